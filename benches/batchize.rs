@@ -0,0 +1,69 @@
+use std::io::BufReader;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use lottr::{bench_batchizer, count_batches, count_tokens, Configuration, Input, TextInput};
+
+const FILTER: &str = r#"^\s*".*[^\x00-\x7f].*"#;
+
+fn content(lines: usize) -> String {
+    (0..lines)
+        .map(|i| match i % 3 {
+            0 => format!("    \"台詞{}\": \"「请原谅我，今天天气不错{}」\",\n", i, i),
+            1 => format!("    \"选项{}\": \"好的\",\n", i),
+            _ => format!(
+                "    \"说明{}\": \"这是一段比较长的说明文字，用来测试分批的速度{}\",\n",
+                i, i
+            ),
+        })
+        .collect()
+}
+
+/// the mtool configuration with the tokenizer and the grouping of the batches
+fn config(tokenizer: &str, grouping: &str) -> Configuration {
+    toml::from_str(&format!(
+        r#"
+trans_type = "replace"
+from = "zho"
+to = "eng"
+capture_regex = ':\s"(.+)"'
+replace_expression = ': "$trans"'
+[batchizer_opt]
+max_tokens = 1024
+tokenizer = "{}"
+grouping = "{}"
+"#,
+        tokenizer, grouping
+    ))
+    .unwrap()
+}
+
+fn bench_batchize(c: &mut Criterion) {
+    let content = content(100_000);
+    let input = TextInput::new(vec![FILTER.to_string()]).unwrap();
+    let textures = input
+        .parse(&mut BufReader::new(content.as_bytes()))
+        .unwrap();
+
+    let mut group = c.benchmark_group("tokenize 100k lines");
+    group.sample_size(10);
+    for tokenizer in ["chars", "cl100k", "o200k"] {
+        let batchizer = bench_batchizer(&config(tokenizer, "off")).unwrap();
+        group.bench_function(tokenizer, |b| {
+            b.iter(|| count_tokens(&batchizer, &textures))
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("batch 100k lines");
+    group.sample_size(10);
+    for grouping in ["off", "first_char"] {
+        let batchizer = bench_batchizer(&config("cl100k", grouping)).unwrap();
+        group.bench_function(grouping, |b| {
+            b.iter(|| count_batches(&batchizer, &textures))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_batchize);
+criterion_main!(benches);
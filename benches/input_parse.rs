@@ -0,0 +1,59 @@
+use std::io::{BufRead, BufReader};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use lottr::{Input, TextInput};
+use regex::Regex;
+
+const FILTER: &str = r#"^\s*".*[^\x00-\x7f].*"#;
+
+fn content(lines: usize) -> String {
+    (0..lines)
+        .map(|i| match i % 4 {
+            0 => format!("    \"台詞{}\": \"请原谅我，今天天气不错{}\",\n", i, i),
+            1 => format!("    \"BGM{}\": \"BGM{}\",\n", i, i),
+            2 => format!("    \"{}\": \"{}\",\n", i, i),
+            _ => format!("    \"选项{}\": \"好的\",\n", i),
+        })
+        .collect()
+}
+
+/// the line by line extraction before the parallel parsing
+fn parse_sequential(content: &str, regex: &Regex) -> usize {
+    let mut reader = BufReader::new(content.as_bytes());
+    let mut buf = String::new();
+    let mut seek = 0;
+    let mut lines = vec![];
+    loop {
+        let size = reader.read_line(&mut buf).unwrap();
+        if size == 0 {
+            break;
+        }
+        if regex.is_match(&buf) {
+            lines.push((seek, size, buf.to_string()));
+        }
+        seek += size;
+        buf.clear();
+    }
+    lines.len()
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let content = content(1_000_000);
+    let regex = Regex::new(FILTER).unwrap();
+    let input = TextInput::new(vec![FILTER.to_string()]).unwrap();
+    let mut group = c.benchmark_group("parse 1m lines");
+    group.sample_size(10);
+    group.bench_function("sequential", |b| {
+        b.iter(|| parse_sequential(&content, &regex))
+    });
+    group.bench_function("parallel", |b| {
+        b.iter(|| {
+            let mut reader = BufReader::new(content.as_bytes());
+            input.parse(&mut reader).unwrap().lines.len()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse);
+criterion_main!(benches);
@@ -0,0 +1,168 @@
+use regex::Regex;
+
+use crate::segment::{join_sentences, split_sentences};
+
+/// the batches whose extracted lines differ by more than a quarter of the batch, at least one
+/// line, are left to the failed range when they are not numbered
+const MAX_DRIFT: usize = 4;
+
+/// recover the translated lines of a batch whose extracted line count mismatches, by the numbers
+/// `(n)` of the reply when every line 1..=n is numbered, else by joining the adjacent lines or
+/// splitting the merged lines on the sentence ends, grouped by the lengths of the source lines.
+/// None if the batch is unrecoverable
+pub fn realign<E>(
+    content: &str,
+    extracted: &[String],
+    sources: &[&str],
+    extract: E,
+) -> Option<Vec<String>>
+where
+    E: Fn(&str) -> Vec<String>,
+{
+    if sources.is_empty() {
+        return None;
+    }
+    if let Some(lines) = by_numbers(content, sources.len(), &extract) {
+        return Some(lines);
+    }
+    if extracted.len().abs_diff(sources.len()) > (sources.len() / MAX_DRIFT).max(1) {
+        return None;
+    }
+    let units = match extracted.len() < sources.len() {
+        true => extracted
+            .iter()
+            .flat_map(|l| split_sentences(l))
+            .filter(|s| !s.trim().is_empty())
+            .collect::<Vec<_>>(),
+        false => extracted.to_vec(),
+    };
+    by_lengths(&units, sources)
+}
+
+/// the segments after every number `(n)` of the reply, the segments of the same number are joined,
+/// e.g. the lines merged on one line `(1) Hi (2) Bye` or a line split `(2) Good\n(2) bye`
+fn by_numbers<E>(content: &str, count: usize, extract: &E) -> Option<Vec<String>>
+where
+    E: Fn(&str) -> Vec<String>,
+{
+    let marker = Regex::new(r"\((\d+)\)").unwrap();
+    let markers = marker
+        .captures_iter(content)
+        .filter_map(|cap| {
+            let n = cap[1].parse::<usize>().ok()?;
+            let m = cap.get(0).unwrap();
+            Some((n, m.start(), m.end()))
+        })
+        .collect::<Vec<_>>();
+    // a number quoted in a translation breaks the order, the reply is then not numbered
+    if markers.is_empty() || markers.windows(2).any(|w| w[1].0 < w[0].0) {
+        return None;
+    }
+    let mut segments = vec![vec![]; count];
+    for (k, &(n, start, _)) in markers.iter().enumerate() {
+        if n == 0 || n > count {
+            return None;
+        }
+        let end = markers.get(k + 1).map(|m| m.1).unwrap_or(content.len());
+        let segment = content[start..end].trim_end();
+        segments[n - 1].extend(extract(segment));
+    }
+    segments
+        .into_iter()
+        .map(|s| match s.is_empty() {
+            true => None,
+            false => Some(join_sentences(&s.iter().collect::<Vec<_>>())),
+        })
+        .collect()
+}
+
+/// group the units in order into a line per source, the share of every line in the characters of
+/// the translation stays the closest to the share of its source
+fn by_lengths(units: &[String], sources: &[&str]) -> Option<Vec<String>> {
+    let (m, n) = (units.len(), sources.len());
+    if m < n {
+        return None;
+    }
+    let share = |lens: Vec<usize>| {
+        let total = lens.iter().sum::<usize>().max(1) as f64;
+        lens.into_iter()
+            .map(|l| l as f64 / total)
+            .collect::<Vec<_>>()
+    };
+    let unit_shares = share(units.iter().map(|u| u.trim().chars().count()).collect());
+    let source_shares = share(sources.iter().map(|s| s.trim().chars().count()).collect());
+    let mut prefix = vec![0.0; m + 1];
+    for (k, s) in unit_shares.iter().enumerate() {
+        prefix[k + 1] = prefix[k] + s;
+    }
+    // cost[j][k], the first j sources take the first k units; from[j][k], the first unit of source j
+    let mut cost = vec![vec![f64::INFINITY; m + 1]; n + 1];
+    let mut from = vec![vec![0; m + 1]; n + 1];
+    cost[0][0] = 0.0;
+    for j in 1..=n {
+        // every source takes at least one unit, and leaves one to every following source
+        for k in j..=m - (n - j) {
+            for s in j - 1..k {
+                let diff = prefix[k] - prefix[s] - source_shares[j - 1];
+                let c = cost[j - 1][s] + diff * diff;
+                if c < cost[j][k] {
+                    cost[j][k] = c;
+                    from[j][k] = s;
+                }
+            }
+        }
+    }
+    let mut lines = vec![String::new(); n];
+    let mut k = m;
+    for j in (1..=n).rev() {
+        let s = from[j][k];
+        lines[j - 1] = join_sentences(&units[s..k].iter().collect::<Vec<_>>());
+        k = s;
+    }
+    Some(lines)
+}
+
+#[cfg(test)]
+mod test {
+    use super::realign;
+
+    fn extract(content: &str) -> Vec<String> {
+        let capture = regex::Regex::new(r"\(\d+\)\s?(.+)").unwrap();
+        capture
+            .captures_iter(content)
+            .map(|cap| cap[1].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_realign() {
+        let sources = ["おはよう。", "今日はいい天気ですね。", "はい。"];
+        let realign = |content: &str| realign(content, &extract(content), &sources, extract);
+        // merged on one line, and a line split in two
+        assert_eq!(
+            realign("(1) Good morning. (2) Nice weather today.\n(3) Yes.").unwrap(),
+            vec!["Good morning.", "Nice weather today.", "Yes."]
+        );
+        assert_eq!(
+            realign("(1) Good morning.\n(2) Nice weather\n(2) today.\n(3) Yes.").unwrap(),
+            vec!["Good morning.", "Nice weather today.", "Yes."]
+        );
+        // not numbered, the merged sentences are split
+        let content = "- 早上好。今天天气真好。\n- 是的。";
+        let lines = content
+            .lines()
+            .map(|l| l.trim_start_matches("- ").to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            super::realign(content, &lines, &sources, |_| vec![]).unwrap(),
+            vec!["早上好。", "今天天气真好。", "是的。"]
+        );
+        // a number missing, the sentences are split
+        assert_eq!(
+            realign("(1) Good morning. Nice weather today.\n(3) Yes.").unwrap(),
+            vec!["Good morning.", "Nice weather today.", "Yes."]
+        );
+        // too few sentences to split
+        assert_eq!(realign("(1) Good morning\n(3) Yes"), None);
+    }
+}
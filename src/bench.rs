@@ -0,0 +1,147 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::{
+    inputs::parse_input,
+    translators::{bench_batchizer, count_batches, count_tokens},
+    Configuration,
+};
+
+/// the timing of a stage over the rounds, the fastest round is reported
+struct Stage {
+    name: &'static str,
+    best: Duration,
+    /// what the stage produced in a round, e.g. the lines parsed
+    count: usize,
+    unit: &'static str,
+}
+
+impl Stage {
+    fn print(&self, lines: usize, bytes: u64) {
+        let secs = self.best.as_secs_f64().max(f64::EPSILON);
+        println!(
+            "{:<10} {:>10.2} ms  {:>12} {:<8} {:>12.0} lines/s  {:>8.2} MB/s",
+            self.name,
+            secs * 1000.0,
+            self.count,
+            self.unit,
+            lines as f64 / secs,
+            bytes as f64 / secs / 1_000_000.0,
+        );
+    }
+}
+
+/// run the stage `rounds` times, the result of the last round is returned with the timing
+fn measure<T, F>(rounds: usize, mut stage: F) -> Result<(T, Duration)>
+where
+    F: FnMut() -> Result<T>,
+{
+    let mut best = Duration::MAX;
+    let mut result = None;
+    for _ in 0..rounds.max(1) {
+        let started = Instant::now();
+        let value = stage()?;
+        best = best.min(started.elapsed());
+        result = Some(value);
+    }
+    Ok((result.unwrap(), best))
+}
+
+/// measure the input parsing, the tokenization and the batching of the file by the
+/// configuration, without any request, the state of the file is neither read nor written
+pub fn bench(cfg: &Configuration, file: &str, rounds: usize) -> Result<()> {
+    let bytes = std::fs::metadata(file)?.len();
+    let (textures, parse) = measure(rounds, || parse_input(cfg, file))?;
+    let lines = textures.lines.len();
+    let sent = textures
+        .lines
+        .iter()
+        .filter(|l| l.needs_translation())
+        .count();
+    let batchizer = bench_batchizer(cfg)?;
+    let (tokens, tokenize) = measure(rounds, || Ok(count_tokens(&batchizer, &textures)))?;
+    let (batches, batch) = measure(rounds, || Ok(count_batches(&batchizer, &textures)))?;
+
+    println!(
+        "{}: {} bytes, {} lines, {} lines to translate, best of {} rounds",
+        file,
+        bytes,
+        lines,
+        sent,
+        rounds.max(1)
+    );
+    let stages = [
+        Stage {
+            name: "parse",
+            best: parse,
+            count: lines,
+            unit: "lines",
+        },
+        Stage {
+            name: "tokenize",
+            best: tokenize,
+            count: tokens,
+            unit: "tokens",
+        },
+        Stage {
+            name: "batch",
+            best: batch,
+            count: batches,
+            unit: "batches",
+        },
+    ];
+    stages.iter().for_each(|stage| stage.print(lines, bytes));
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::measure;
+    use crate::{
+        inputs::parse_input,
+        translators::{bench_batchizer, count_batches, count_tokens},
+        Configuration,
+    };
+
+    #[test]
+    fn test_measure() {
+        let mut rounds = 0;
+        let (value, _) = measure(0, || {
+            rounds += 1;
+            Ok(rounds)
+        })
+        .unwrap();
+        // at least one round, the value of the last round is kept
+        assert_eq!((rounds, value), (1, 1));
+        let (value, _) = measure(3, || {
+            rounds += 1;
+            Ok(rounds)
+        })
+        .unwrap();
+        assert_eq!(value, 4);
+    }
+
+    #[test]
+    fn test_bench_counts() {
+        let dir = crate::utils::TempDir::new("bench");
+        let file = dir.join("a.txt");
+        let file = file.to_str().unwrap();
+        let content = (0..50).map(|i| format!("第{}行\n", i)).collect::<String>();
+        std::fs::write(file, content).unwrap();
+        let cfg: Configuration =
+            toml::from_str(include_str!("../assets/options_text.toml")).unwrap();
+        let textures = parse_input(&cfg, file).unwrap();
+        assert_eq!(textures.lines.len(), 50);
+        // the state of the file is left alone
+        assert!(!cfg.dirs().textures(file).exists());
+        let batchizer = bench_batchizer(&cfg).unwrap();
+        assert!(count_tokens(&batchizer, &textures) >= 50);
+        let batches = count_batches(&batchizer, &textures);
+        assert!(batches >= 1);
+        assert_eq!(
+            crate::translators::estimate_batches(&textures, &cfg).unwrap(),
+            Some(batches)
+        );
+    }
+}
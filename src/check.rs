@@ -0,0 +1,342 @@
+use anyhow::Result;
+use regex::{Regex, RegexSet};
+
+use crate::{
+    console::{self, Tone},
+    i18n::Msg,
+    inputs::{FilterRegex, TransType},
+    outputs::has_trans,
+    paths::OUTPUT_NAME_PLACEHOLDERS,
+    t,
+    translators::{load_prompts, ping_api, Grouping},
+    Configuration,
+};
+
+/// validate the configuration before a translation, every problem is reported at once,
+/// instead of a panic deep inside the translation
+pub async fn check_config(cfg: &Configuration, ping: bool) -> Result<()> {
+    let mut problems = check_options(cfg);
+    if let Some(opt) = &cfg.chatgpt_opt {
+        if ping {
+            for (i, api) in opt.api_pool.iter().enumerate() {
+                match ping_api(api, opt.model.as_deref()).await {
+                    Ok(_) => {
+                        println!(
+                            "{} {}",
+                            console::out(Tone::Ok, "[Check]"),
+                            t!(Msg::CheckOk, i)
+                        )
+                    }
+                    Err(e) => problems.push(format!("api_pool[{}]: {}", i, e)),
+                }
+            }
+        }
+    }
+    if problems.is_empty() {
+        let valid = t!(Msg::CheckValid);
+        println!("{} {}", console::out(Tone::Ok, "[Check]"), valid);
+        return Ok(());
+    }
+    problems
+        .iter()
+        .for_each(|p| eprintln!("{} {}", console::err(Tone::Error, "[Check]"), p));
+    Err(anyhow::anyhow!(t!(Msg::CheckProblems, problems.len())))
+}
+
+/// the problems found without any request
+fn check_options(cfg: &Configuration) -> Vec<String> {
+    let mut problems = vec![];
+    for (i, filter) in cfg.filter_regexen.iter().enumerate() {
+        let regex = check_regex(
+            &mut problems,
+            format!("filter_regexen[{}]", i),
+            filter.regex(),
+        );
+        if let (Some(regex), FilterRegex::Capture { capture, .. }) = (regex, filter) {
+            if *capture >= regex.captures_len() {
+                problems.push(format!(
+                    "filter_regexen[{}] has no capture group {}",
+                    i, capture
+                ));
+            }
+        }
+    }
+    if let Some(regex) = &cfg.capture_regex {
+        if let Some(regex) = check_regex(&mut problems, "capture_regex".to_string(), regex) {
+            if regex.captures_len() < 2 {
+                problems.push("capture_regex has no capture group".to_string());
+            }
+        }
+    }
+    let stages = [
+        ("response_cleanup", &cfg.response_cleanup),
+        ("line_capture", &cfg.line_capture),
+        ("post_replace", &cfg.post_replace),
+        ("output_regexen", &cfg.output_regexen),
+    ];
+    for (stage, regexen) in stages {
+        for (i, desc) in regexen.iter().enumerate() {
+            check_regex(&mut problems, format!("{}[{}]", stage, i), &desc.regex);
+        }
+    }
+    if let Some(regex) = cfg.split.as_ref().and_then(|s| s.chapter_regex.as_ref()) {
+        check_regex(&mut problems, "split.chapter_regex".to_string(), regex);
+    }
+    if let Some(priority) = &cfg.priority {
+        for (i, regex) in priority.regexen.iter().enumerate() {
+            check_regex(&mut problems, format!("priority.regexen[{}]", i), regex);
+        }
+    }
+    if let Grouping::SpeakerTag(regex) = &cfg.batchizer_opt.grouping {
+        check_regex(
+            &mut problems,
+            "batchizer_opt.grouping.speaker_tag".to_string(),
+            regex,
+        );
+    }
+    if let Some(skip) = &cfg.skip {
+        if let Err(e) = RegexSet::new(&skip.regexen) {
+            problems.push(format!("skip.regexen is not valid: {}", e));
+        }
+    }
+
+    if let Some(expr) = &cfg.replace_expression {
+        if !has_trans(expr) {
+            problems.push("replace_expression must contain $trans".to_string());
+        }
+    }
+    if cfg.capture_input && cfg.capture_regex.is_none() {
+        problems.push("capture_input requires capture_regex".to_string());
+    }
+    if cfg.line_capture.is_empty() && cfg.output_regexen.len() < 2 {
+        problems.push(
+            "line_capture requires a regex capturing every translated line, or use a preset"
+                .to_string(),
+        );
+    }
+    if cfg.trans_type == TransType::Replace {
+        if cfg.replace_expression.is_none() {
+            problems.push("trans_type replace requires replace_expression".to_string());
+        }
+        if cfg.capture_regex.is_none() {
+            problems.push("trans_type replace requires capture_regex".to_string());
+        }
+    }
+    if matches!(cfg.trans_type, TransType::Ain | TransType::KeyValue) && cfg.paragraph.is_some() {
+        problems.push(
+            "trans_type ain and key-value translate every line alone, remove paragraph".to_string(),
+        );
+    }
+    if cfg.trans_type == TransType::Tpp {
+        if cfg.paragraph.is_some() || cfg.segment.is_some() {
+            problems.push(
+                "trans_type translator++ translates every row alone, remove paragraph and segment"
+                    .to_string(),
+            );
+        }
+        if cfg.shard_lines.is_some() {
+            problems.push("trans_type translator++ does not support shard_lines".to_string());
+        }
+    }
+    if let Some(template) = &cfg.output_name {
+        let placeholder = Regex::new(r"\{(\w+)\}").unwrap();
+        for cap in placeholder.captures_iter(template) {
+            if !OUTPUT_NAME_PLACEHOLDERS.contains(&&cap[1]) {
+                problems.push(format!(
+                    "output_name has an unknown placeholder {}, the placeholders are {}",
+                    &cap[0],
+                    OUTPUT_NAME_PLACEHOLDERS.join(", ")
+                ));
+            }
+        }
+    }
+    if let Some(qe) = &cfg.qe_opt {
+        if !(1..=5).contains(&qe.min_score) {
+            problems.push("qe_opt.min_score must be 1 to 5".to_string());
+        }
+    }
+    if let Some(length) = &cfg.length_opt {
+        if length.max_length == 0 {
+            problems.push("length_opt.max_length must be greater than 0".to_string());
+        }
+        if length.retries > 0 && cfg.chatgpt_opt.is_none() {
+            problems.push("length_opt.retries needs chatgpt_opt to shorten the lines".to_string());
+        }
+    }
+    if let Some(budget) = &cfg.budget {
+        let priced = budget.prompt_usd_per_million > 0.0 || budget.completion_usd_per_million > 0.0;
+        if budget.max_cost_usd.is_some() && !priced {
+            problems.push(
+                "budget.max_cost_usd needs prompt_usd_per_million or completion_usd_per_million"
+                    .to_string(),
+            );
+        }
+        if budget.max_total_tokens.is_none() && budget.max_cost_usd.is_none() {
+            problems.push("budget needs max_total_tokens or max_cost_usd".to_string());
+        }
+    }
+    for (event, hook) in cfg.hooks.iter().flat_map(|h| h.hooks()) {
+        let Some(hook) = hook else {
+            continue;
+        };
+        if hook.command.is_none() && hook.webhook.is_none() {
+            problems.push(format!("hooks.{} needs a command or a webhook", event));
+        }
+        if let Some(webhook) = &hook.webhook {
+            if !webhook.starts_with("http://") && !webhook.starts_with("https://") {
+                problems.push(format!("hooks.{}.webhook is not a http url", event));
+            }
+        }
+    }
+    if cfg.mtool_opt.as_ref().is_some_and(|v| v.json) {
+        if cfg.trans_type != TransType::Replace {
+            problems.push("mtool_opt.json needs trans_type = \"replace\"".to_string());
+        }
+        if cfg.bilingual.is_some() {
+            problems.push("mtool_opt.json does not support bilingual".to_string());
+        }
+    }
+    if cfg.output_translators.is_empty() {
+        problems.push("output_translators is empty".to_string());
+    }
+    if cfg.batchizer_opt.max_tokens == 0 {
+        problems.push("batchizer_opt.max_tokens must be greater than 0".to_string());
+    }
+
+    match &cfg.chatgpt_opt {
+        Some(opt) => {
+            if opt.api_pool.is_empty() {
+                problems.push("chatgpt_opt.api_pool is empty".to_string());
+            }
+            if opt.stop.len() > 4 {
+                problems.push("chatgpt_opt.stop has more than 4 sequences".to_string());
+            }
+            if opt
+                .adaptive_concurrency
+                .is_some_and(|r| r.min == 0 || r.min > r.max)
+            {
+                problems
+                    .push("chatgpt_opt.adaptive_concurrency must have 0 < min <= max".to_string());
+            }
+            for (i, api) in opt.api_pool.iter().enumerate() {
+                if api.api_key.is_empty() {
+                    problems.push(format!("api_pool[{}].api_key is empty", i));
+                }
+                if api.api_url.is_empty() {
+                    problems.push(format!("api_pool[{}].api_url is empty", i));
+                }
+            }
+            if let Some(path) = &opt.prompt_path {
+                let (from, to) = (cfg.lang_from.to_name(), cfg.lang_to.to_name());
+                if let Err(e) = load_prompts(path, from, to) {
+                    problems.push(format!("prompt file {} is not valid: {}", path, e));
+                }
+                if opt.system_prompt.is_some() {
+                    problems.push(
+                        "chatgpt_opt.system_prompt is ignored when prompt_path is set".to_string(),
+                    );
+                }
+            }
+        }
+        None if cfg.gemini_opt.is_none() => {
+            problems.push("chatgpt_opt or gemini_opt is required".to_string())
+        }
+        None => {}
+    }
+    if let Some(opt) = &cfg.gemini_opt {
+        if opt.api_keys.iter().all(|k| k.is_empty()) {
+            problems.push("gemini_opt.api_keys is empty".to_string());
+        }
+        if opt
+            .adaptive_concurrency
+            .is_some_and(|r| r.min == 0 || r.min > r.max)
+        {
+            problems.push("gemini_opt.adaptive_concurrency must have 0 < min <= max".to_string());
+        }
+        if let Some(path) = &opt.prompt_path {
+            let (from, to) = (cfg.lang_from.to_name(), cfg.lang_to.to_name());
+            if let Err(e) = load_prompts(path, from, to) {
+                problems.push(format!("gemini prompt file {} is not valid: {}", path, e));
+            }
+            if opt.system_prompt.is_some() {
+                problems.push(
+                    "gemini_opt.system_prompt is ignored when prompt_path is set".to_string(),
+                );
+            }
+        }
+    }
+    if let Some(opt) = &cfg.routing_opt {
+        if cfg.gemini_opt.is_some() {
+            problems.push("routing_opt is not supported with gemini_opt".to_string());
+        }
+        if opt.api.api_key.is_empty() || opt.api.api_url.is_empty() {
+            problems.push("routing_opt.api needs api_key and api_url".to_string());
+        }
+        for (i, regex) in opt.regexen.iter().enumerate() {
+            check_regex(&mut problems, format!("routing_opt.regexen[{}]", i), regex);
+        }
+    }
+    if let Some(path) = cfg.refine_opt.as_ref().and_then(|r| r.prompt_path.as_ref()) {
+        let (from, to) = (cfg.lang_from.to_name(), cfg.lang_to.to_name());
+        if let Err(e) = load_prompts(path, from, to) {
+            problems.push(format!("refine prompt file {} is not valid: {}", path, e));
+        }
+    }
+    for (i, o) in cfg.prompt_overrides.iter().enumerate() {
+        let (from, to) = (cfg.lang_from.to_name(), cfg.lang_to.to_name());
+        if let Err(e) = o.prompts(from, to) {
+            problems.push(format!("prompt_overrides[{}]: {}", i, e));
+        }
+        if o.files.is_empty() && o.ranges.is_empty() {
+            problems.push(format!(
+                "prompt_overrides[{}] has neither files nor ranges, set the prompts of the translator instead",
+                i
+            ));
+        }
+        if o.ranges.iter().any(|(start, end)| start > end) {
+            problems.push(format!(
+                "prompt_overrides[{}] has a range whose start > end",
+                i
+            ));
+        }
+    }
+    problems
+}
+
+fn check_regex(problems: &mut Vec<String>, name: String, regex: &str) -> Option<Regex> {
+    match Regex::new(regex) {
+        Ok(regex) => Some(regex),
+        Err(e) => {
+            problems.push(format!("{} is not a valid regex: {}", name, e));
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::check_options;
+    use crate::Configuration;
+
+    #[test]
+    fn test_check_options() {
+        let mut cfg =
+            toml::from_str::<Configuration>(include_str!("../assets/options_mtool.toml")).unwrap();
+        assert!(check_options(&cfg).is_empty());
+        cfg.capture_regex = Some("(unclosed".to_string());
+        cfg.replace_expression = Some("trans".to_string());
+        let problems = check_options(&cfg);
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].starts_with("capture_regex is not a valid regex"));
+        cfg.output_name = Some("{stem}.{lang}.{ext}".to_string());
+        assert!(check_options(&cfg)[2].contains("{lang}"));
+        cfg.hooks =
+            toml::from_str("on_failure = {}\non_run_complete = {webhook = \"discord\"}").ok();
+        let problems = check_options(&cfg);
+        assert_eq!(
+            problems[3],
+            "hooks.on_run_complete.webhook is not a http url"
+        );
+        assert_eq!(problems[4], "hooks.on_failure needs a command or a webhook");
+    }
+}
@@ -0,0 +1,286 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+
+use crate::{translators::ModelProfile, Configuration};
+
+/// the nested includes deeper than this are treated as a cycle
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// the numbered lines `(1) xxx` answered by the translator
+const NUMBERED_OUTPUT: &str = r#"
+[[response_cleanup]]
+usage = {replace = '\n'}
+regex = '\n[^\n\(是]'
+[[line_capture]]
+usage = {capture = 1}
+regex = '\(\d+\)\s?(.+)'
+[[post_replace]]
+usage = {replace = ""}
+regex = '"'
+"#;
+
+/// the output stages of a preset, dropped when the configuration has the output_regexen of an
+/// older version
+const OUTPUT_STAGES: [&str; 3] = ["response_cleanup", "line_capture", "post_replace"];
+
+const MTOOL: &str = r#"
+trans_type = "replace"
+filter_regexen = ['^\s*".*[^\x00-\x7f].*']
+capture_regex = ':\s"(.+)"'
+replace_expression = ': "$trans"'
+"#;
+
+const AIN: &str = r#"
+trans_type = "ain"
+"#;
+
+const KIRIKIRI: &str = r#"
+trans_type = "text"
+filter_regexen = ['^[^;*\[\n]\s*[^\s]+']
+"#;
+
+/// the built-in regexes of the known formats
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum FormatPreset {
+    #[serde(rename = "mtool")]
+    Mtool,
+    #[serde(rename = "ain")]
+    Ain,
+    #[serde(rename = "kirikiri")]
+    Kirikiri,
+    /// only the output stages of the numbered lines
+    #[serde(rename = "numbered")]
+    Numbered,
+}
+
+impl FormatPreset {
+    fn table(&self) -> Table {
+        let format = match self {
+            FormatPreset::Mtool => MTOOL,
+            FormatPreset::Ain => AIN,
+            FormatPreset::Kirikiri => KIRIKIRI,
+            FormatPreset::Numbered => "",
+        };
+        format!("{}{}", format, NUMBERED_OUTPUT)
+            .parse::<Table>()
+            .unwrap()
+    }
+}
+
+/// load the configuration file, with its includes and the selected profile.
+/// `include = "base.toml"` or `include = ["keys.toml", "base.toml"]` loads the files first
+/// (relative to the including file), the later files and the including file override them;
+/// `[profiles.X]` overrides the configuration when selected by `--profile X`,
+/// then the configuration overrides the regexes of its `preset`.
+/// the fixup file, e.g. other output stages or normalize rules, overrides the profile.
+pub fn load_config(
+    path: &str,
+    profile: Option<&str>,
+    fixup: Option<&str>,
+) -> Result<Configuration> {
+    let mut table = load_table(Path::new(path), 0)?;
+    let profiles = table.remove("profiles");
+    if let Some(name) = profile {
+        let overrides = profiles
+            .as_ref()
+            .and_then(|p| p.get(name))
+            .and_then(|p| p.as_table())
+            .ok_or_else(|| anyhow::anyhow!("profile {} is not found in {}", name, path))?;
+        merge(&mut table, overrides.clone());
+    }
+    if let Some(fixup) = fixup {
+        merge(&mut table, load_table(Path::new(fixup), 0)?);
+    }
+    // the output stages of the model profile parse its answer, whatever configured
+    let profile = table
+        .get("chatgpt_opt")
+        .and_then(|o| o.get("model_profile"))
+        .cloned();
+    if let Some(profile) = profile {
+        let profile: ModelProfile = profile.try_into()?;
+        table.remove("output_regexen");
+        merge(&mut table, profile.table());
+    }
+    if let Some(preset) = table.get("preset").cloned() {
+        let preset: FormatPreset = preset.try_into()?;
+        let mut base = preset.table();
+        if table.contains_key("output_regexen") {
+            OUTPUT_STAGES.iter().for_each(|stage| {
+                base.remove(*stage);
+            });
+        }
+        merge(&mut base, table);
+        table = base;
+    }
+    let mut cfg: Configuration = Value::Table(table).try_into()?;
+    if let Some(opt) = cfg.chatgpt_opt.as_mut() {
+        for api in opt.api_pool.iter_mut() {
+            api.api_key = resolve_secret(&api.api_key)?;
+        }
+    }
+    if let Some(opt) = cfg.routing_opt.as_mut() {
+        opt.api.api_key = resolve_secret(&opt.api.api_key)?;
+    }
+    if let Some(key) = cfg.retrieval_opt.as_mut().and_then(|o| o.api_key.as_mut()) {
+        *key = resolve_secret(key)?;
+    }
+    Ok(cfg)
+}
+
+/// resolve a secret at load time, so the configuration can be shared without leaking it:
+/// `env:NAME` reads the environment variable NAME, `keyring:service/user` reads the os keyring
+/// (built with the keyring feature), other values are used as is
+pub fn resolve_secret(value: &str) -> Result<String> {
+    if let Some(name) = value.strip_prefix("env:") {
+        return std::env::var(name)
+            .map_err(|_| anyhow::anyhow!("environment variable {} is not set", name));
+    }
+    if let Some(entry) = value.strip_prefix("keyring:") {
+        let (service, user) = entry
+            .split_once('/')
+            .ok_or_else(|| anyhow::anyhow!("keyring entry must be service/user: {}", entry))?;
+        return keyring_secret(service, user);
+    }
+    Ok(value.to_string())
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_secret(service: &str, user: &str) -> Result<String> {
+    keyring::Entry::new(service, user)
+        .and_then(|entry| entry.get_password())
+        .map_err(|e| anyhow::anyhow!("keyring entry {}/{}: {}", service, user, e))
+}
+
+#[cfg(not(feature = "keyring"))]
+fn keyring_secret(service: &str, user: &str) -> Result<String> {
+    Err(anyhow::anyhow!(
+        "keyring entry {}/{} requires lottr built with the keyring feature",
+        service,
+        user
+    ))
+}
+
+fn load_table(path: &Path, depth: usize) -> Result<Table> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(anyhow::anyhow!(
+            "too deep includes, is there a cycle at {}?",
+            path.display()
+        ));
+    }
+    let content = fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path.display(), e))?;
+    let mut table = content.parse::<Table>()?;
+    let includes = match table.remove("include") {
+        Some(Value::String(include)) => vec![include],
+        Some(Value::Array(includes)) => includes
+            .into_iter()
+            .filter_map(|v| v.as_str().map(String::from))
+            .collect(),
+        Some(_) => return Err(anyhow::anyhow!("include must be a path or a list of paths")),
+        None => vec![],
+    };
+    let dir = path.parent().map(PathBuf::from).unwrap_or_default();
+    let mut base = Table::new();
+    for include in includes {
+        merge(&mut base, load_table(&dir.join(include), depth + 1)?);
+    }
+    merge(&mut base, table);
+    Ok(base)
+}
+
+/// merge the overrides into the table, the nested tables are merged key by key,
+/// the other values (arrays included) are replaced
+fn merge(table: &mut Table, overrides: Table) {
+    for (key, value) in overrides {
+        match (table.get_mut(&key), value) {
+            (Some(Value::Table(inner)), Value::Table(value)) => merge(inner, value),
+            (_, value) => {
+                table.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{load_config, resolve_secret, FormatPreset};
+    use crate::inputs::TransType;
+
+    #[test]
+    fn test_load_config() {
+        let dir = crate::utils::TempDir::new("load_config");
+        let base = std::fs::read_to_string("./assets/options_mtool.toml").unwrap();
+        std::fs::write(dir.join("base.toml"), base).unwrap();
+        std::fs::write(
+            dir.join("game.toml"),
+            r#"
+include = "base.toml"
+from = "eng"
+
+[batchizer_opt]
+max_tokens = 512
+
+[profiles.short]
+batchizer_opt = { max_tokens = 128 }
+"#,
+        )
+        .unwrap();
+        let game = dir.join("game.toml");
+        let game = game.to_str().unwrap();
+        let cfg = load_config(game, None, None).unwrap();
+        assert_eq!(cfg.lang_from, isolang::Language::Eng);
+        assert_eq!(cfg.batchizer_opt.max_tokens, 512);
+        assert!(cfg.chatgpt_opt.is_some());
+        let cfg = load_config(game, Some("short"), None).unwrap();
+        assert_eq!(cfg.batchizer_opt.max_tokens, 128);
+        assert!(load_config(game, Some("long"), None).is_err());
+    }
+
+    #[test]
+    fn test_format_preset() {
+        let dir = crate::utils::TempDir::new("format_preset");
+        let path = dir.join("ain.toml");
+        std::fs::write(
+            &path,
+            r#"
+preset = "ain"
+from = "jpn"
+to = "zho"
+replace_expression = '= "$trans" '
+
+[batchizer_opt]
+max_tokens = 256
+"#,
+        )
+        .unwrap();
+        let cfg = load_config(path.to_str().unwrap(), None, None).unwrap();
+        assert_eq!(cfg.preset, Some(FormatPreset::Ain));
+        assert_eq!(cfg.trans_type, TransType::Ain);
+        assert!(cfg.filter_regexen.is_empty());
+        assert_eq!(cfg.replace_expression.as_deref(), Some(r#"= "$trans" "#));
+        assert_eq!(cfg.line_capture.len(), 1);
+        // the output_regexen of an older configuration replace the stages of the preset
+        let content = std::fs::read_to_string(&path).unwrap();
+        let legacy = "[[output_regexen]]\nusage = {replace = \"\"}\nregex = '\\n'\n\
+                      [[output_regexen]]\nusage = {capture = 0}\nregex = '(.+)'\n";
+        std::fs::write(&path, format!("{}{}", content, legacy)).unwrap();
+        let cfg = load_config(path.to_str().unwrap(), None, None).unwrap();
+        assert!(cfg.line_capture.is_empty() && cfg.response_cleanup.is_empty());
+        assert_eq!(cfg.output_regexen.len(), 2);
+    }
+
+    #[test]
+    fn test_resolve_secret() {
+        std::env::set_var("LOTTR_TEST_API_KEY", "sk-test");
+        assert_eq!(resolve_secret("env:LOTTR_TEST_API_KEY").unwrap(), "sk-test");
+        assert!(resolve_secret("env:LOTTR_TEST_NOT_SET").is_err());
+        assert_eq!(resolve_secret("sk-plain").unwrap(), "sk-plain");
+        assert!(resolve_secret("keyring:lottr").is_err());
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Write as _,
+    fs,
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    i18n::Msg,
+    outputs::{escape_cell, translated_lines},
+    qe::{source, source_capture},
+    review::write_back,
+    t,
+    textures::Textures,
+    translators::load_glossary,
+    Configuration,
+};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConsistencyOptions {
+    /// rewrite the less frequent translations of a source to its most frequent one
+    #[serde(default)]
+    pub harmonize: bool,
+    /// the source terms and their expected translations, merged with refine_opt.glossary
+    #[serde(default)]
+    pub glossary: BTreeMap<String, String>,
+}
+
+/// the different translations of the same source
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variants {
+    pub source: String,
+    /// the translations and the lines translated so, the most frequent first
+    pub variants: Vec<(String, Vec<usize>)>,
+}
+
+/// a line whose source contains a glossary term but whose translation misses the expected one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub line: usize,
+    pub term: String,
+    pub expected: String,
+}
+
+/// scan the translations for the sources translated differently and the glossary violations,
+/// write the report file.consistency.md, with consistency_opt.harmonize rewrite the variants,
+/// returns the count of the harmonized lines
+pub fn check_consistency(cfg: &Configuration, textures: &mut Textures) -> Result<usize> {
+    let Some(opt) = &cfg.consistency_opt else {
+        return Ok(0);
+    };
+    let capture = source_capture(cfg)?;
+    let mut translations = translated_lines(cfg, textures)?;
+    // the duplicated lines share the translation of their first occurrence
+    let sources = textures
+        .lines
+        .iter()
+        .map(|l| {
+            (l.needs_translation() && l.duplicate.is_none()).then(|| source(&capture, &l.content))
+        })
+        .collect::<Vec<_>>();
+    let variants = find_variants(&sources, &translations);
+    let mut glossary = load_glossary(cfg, &textures.name)?;
+    glossary.extend(cfg.refine_opt.iter().flat_map(|r| r.glossary.clone()));
+    glossary.extend(opt.glossary.clone());
+    let violations = find_violations(&glossary, &sources, &translations);
+
+    let path = textures.dirs.consistency(&textures.name);
+    fs::write(&path, render_report(&textures.name, &variants, &violations))?;
+    let found = t!(
+        Msg::Consistency,
+        variants.len(),
+        violations.len(),
+        path.display()
+    );
+    println!("[Consistency] {}", found);
+    if !opt.harmonize || variants.is_empty() {
+        return Ok(0);
+    }
+    let mut edited = HashSet::new();
+    for v in &variants {
+        let (most, _) = &v.variants[0];
+        for (_, lines) in &v.variants[1..] {
+            for &i in lines {
+                translations[i] = Some(most.clone());
+                edited.insert(i);
+            }
+        }
+    }
+    let translator = cfg.translator_selection().primary();
+    let batches = write_back(textures, translator, &translations, &edited);
+    println!(
+        "[Consistency] {}",
+        t!(Msg::Harmonized, edited.len(), batches)
+    );
+    Ok(edited.len())
+}
+
+/// group the translated lines by their source, keep the sources with several translations,
+/// the ties of frequency are broken by the first occurrence
+pub fn find_variants(sources: &[Option<String>], translations: &[Option<String>]) -> Vec<Variants> {
+    let mut groups: Vec<Variants> = vec![];
+    let mut index = HashMap::new();
+    for (i, (source, translation)) in sources.iter().zip(translations).enumerate() {
+        let (Some(source), Some(translation)) = (source, translation) else {
+            continue;
+        };
+        let (source, translation) = (source.trim(), translation.trim());
+        if source.is_empty() || translation.is_empty() {
+            continue;
+        }
+        let g = *index.entry(source).or_insert_with(|| {
+            groups.push(Variants {
+                source: source.to_string(),
+                variants: vec![],
+            });
+            groups.len() - 1
+        });
+        let variants = &mut groups[g].variants;
+        match variants.iter_mut().find(|(t, _)| t == translation) {
+            Some((_, lines)) => lines.push(i),
+            None => variants.push((translation.to_string(), vec![i])),
+        }
+    }
+    groups.retain(|g| g.variants.len() > 1);
+    for g in groups.iter_mut() {
+        // stable, the first occurrence stays first among the same frequency
+        g.variants
+            .sort_by_key(|(_, lines)| std::cmp::Reverse(lines.len()));
+    }
+    groups
+}
+
+/// the lines whose source contains a term of the glossary, and whose translation does not
+/// contain the expected translation, case-insensitive
+pub fn find_violations(
+    glossary: &BTreeMap<String, String>,
+    sources: &[Option<String>],
+    translations: &[Option<String>],
+) -> Vec<Violation> {
+    let mut violations = vec![];
+    for (i, (source, translation)) in sources.iter().zip(translations).enumerate() {
+        let (Some(source), Some(translation)) = (source, translation) else {
+            continue;
+        };
+        let translation = translation.to_lowercase();
+        for (term, expected) in glossary {
+            if source.contains(term.as_str()) && !translation.contains(&expected.to_lowercase()) {
+                violations.push(Violation {
+                    line: i,
+                    term: term.clone(),
+                    expected: expected.clone(),
+                });
+            }
+        }
+    }
+    violations
+}
+
+fn render_report(name: &str, variants: &[Variants], violations: &[Violation]) -> String {
+    let mut report = String::new();
+    let _ = writeln!(report, "# Consistency: {}\n", name);
+    let _ = writeln!(report, "## Variants\n");
+    for v in variants {
+        let _ = writeln!(report, "### {}\n", escape_cell(&v.source));
+        for (translation, lines) in &v.variants {
+            let lines = lines.iter().map(|l| l.to_string()).collect::<Vec<_>>();
+            let _ = writeln!(
+                report,
+                "- {} (lines {})",
+                escape_cell(translation),
+                lines.join(", ")
+            );
+        }
+        report.push('\n');
+    }
+    let _ = writeln!(report, "## Glossary violations\n");
+    if !violations.is_empty() {
+        let _ = writeln!(report, "| line | term | expected |\n|---|---|---|");
+    }
+    for v in violations {
+        let _ = writeln!(
+            report,
+            "| {} | {} | {} |",
+            v.line,
+            escape_cell(&v.term),
+            escape_cell(&v.expected)
+        );
+    }
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::{find_variants, find_violations};
+
+    #[test]
+    fn test_find_variants() {
+        let some = |v: &[&str]| v.iter().map(|s| Some(s.to_string())).collect::<Vec<_>>();
+        let sources = some(&["回復薬", "回復薬", "剣", "回復薬"]);
+        let translations = some(&["Potion", "Healing Potion", "Sword", "Healing Potion"]);
+        let variants = find_variants(&sources, &translations);
+        assert_eq!(variants.len(), 1);
+        assert_eq!(variants[0].source, "回復薬");
+        assert_eq!(
+            variants[0].variants,
+            vec![
+                ("Healing Potion".to_string(), vec![1, 3]),
+                ("Potion".to_string(), vec![0])
+            ]
+        );
+        let glossary = BTreeMap::from([("剣".to_string(), "blade".to_string())]);
+        let violations = find_violations(&glossary, &sources, &translations);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].line, 2);
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    fmt::{self, Display},
+    io::IsTerminal,
+    sync::OnceLock,
+};
+
+/// whether stdout and stderr are painted, decided once by `init` or the first painted text
+static COLORS: OnceLock<(bool, bool)> = OnceLock::new();
+
+/// the tone of a diagnostic
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tone {
+    Error,
+    Warning,
+    Ok,
+}
+
+impl Tone {
+    fn code(&self) -> &'static str {
+        match self {
+            Tone::Error => "\x1b[31m",
+            Tone::Warning => "\x1b[33m",
+            Tone::Ok => "\x1b[32m",
+        }
+    }
+}
+
+/// a text painted by the ansi colors, or written as it is
+pub struct Painted<'a> {
+    text: &'a str,
+    color: Option<&'static str>,
+}
+
+impl Display for Painted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.color {
+            Some(code) => write!(f, "{}{}\x1b[0m", code, self.text),
+            None => f.write_str(self.text),
+        }
+    }
+}
+
+/// set up the console before the first output: the utf-8 code page and the ansi colors of the
+/// windows consoles, so the cjk text is not mojibake, the colors are off when NO_COLOR is set,
+/// for a dumb terminal, and for a stream piped or redirected to a file
+pub fn init() {
+    #[cfg(windows)]
+    let vt = windows::enable_utf8_and_vt();
+    #[cfg(not(windows))]
+    let vt = (true, true);
+    COLORS.get_or_init(|| detect(vt));
+}
+
+fn detect((stdout_vt, stderr_vt): (bool, bool)) -> (bool, bool) {
+    let enabled = std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+        && std::env::var("TERM").ok().is_none_or(|t| t != "dumb");
+    (
+        enabled && stdout_vt && std::io::stdout().is_terminal(),
+        enabled && stderr_vt && std::io::stderr().is_terminal(),
+    )
+}
+
+fn colors() -> (bool, bool) {
+    // without init the windows consoles may not understand the escapes
+    *COLORS.get_or_init(|| detect((cfg!(not(windows)), cfg!(not(windows)))))
+}
+
+/// the text written by println!, painted when stdout is a colored terminal
+pub fn out(tone: Tone, text: &str) -> Painted<'_> {
+    Painted {
+        text,
+        color: colors().0.then(|| tone.code()),
+    }
+}
+
+/// the text written by eprintln!, painted when stderr is a colored terminal
+pub fn err(tone: Tone, text: &str) -> Painted<'_> {
+    Painted {
+        text,
+        color: colors().1.then(|| tone.code()),
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::ffi::c_void;
+
+    const CP_UTF8: u32 = 65001;
+    const STD_OUTPUT_HANDLE: u32 = -11i32 as u32;
+    const STD_ERROR_HANDLE: u32 = -12i32 as u32;
+    const ENABLE_VIRTUAL_TERMINAL_PROCESSING: u32 = 0x0004;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn SetConsoleOutputCP(code_page: u32) -> i32;
+        fn SetConsoleCP(code_page: u32) -> i32;
+        fn GetStdHandle(std_handle: u32) -> *mut c_void;
+        fn GetConsoleMode(handle: *mut c_void, mode: *mut u32) -> i32;
+        fn SetConsoleMode(handle: *mut c_void, mode: u32) -> i32;
+    }
+
+    /// switch the console to utf-8, for the answers typed and the output of the child processes
+    /// too, and enable the escapes of stdout and stderr, false for a stream which is not a
+    /// console or an old console without them
+    pub fn enable_utf8_and_vt() -> (bool, bool) {
+        // SAFETY: the calls only take the handles of the process and plain integers
+        unsafe {
+            SetConsoleOutputCP(CP_UTF8);
+            SetConsoleCP(CP_UTF8);
+            (enable_vt(STD_OUTPUT_HANDLE), enable_vt(STD_ERROR_HANDLE))
+        }
+    }
+
+    unsafe fn enable_vt(std_handle: u32) -> bool {
+        let handle = GetStdHandle(std_handle);
+        let mut mode = 0;
+        if handle.is_null() || GetConsoleMode(handle, &mut mode) == 0 {
+            return false;
+        }
+        mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0
+            || SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Painted, Tone};
+
+    #[test]
+    fn test_painted() {
+        let plain = Painted {
+            text: "[Check]",
+            color: None,
+        };
+        assert_eq!(plain.to_string(), "[Check]");
+        let red = Painted {
+            text: "[Check]",
+            color: Some(Tone::Error.code()),
+        };
+        assert_eq!(red.to_string(), "\x1b[31m[Check]\x1b[0m");
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use anyhow::Result;
+use clap::Subcommand;
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{i18n::Msg, t, translators::Progress};
+
+/// the address of --control and of lottr ctl when not given
+pub const DEFAULT_CONTROL_ADDR: &str = "127.0.0.1:7373";
+
+/// the actions of lottr ctl
+#[derive(Subcommand, Debug, Clone)]
+pub enum CtlAction {
+    /// the workers finish their requests, then take no more batches until resumed;
+    Pause,
+    /// the workers take the batches again;
+    Resume,
+    /// print the progress, whether the run is paused and its concurrency;
+    Status,
+    /// run at most N workers, up to the workers started by max_concurrent or adaptive_concurrency,
+    /// 0 lifts the limit;
+    SetConcurrency { n: usize },
+}
+
+/// the state of the translations of the process changed by lottr ctl, the workers check it
+/// before every batch
+#[derive(Debug, Default)]
+pub struct Control {
+    paused: AtomicBool,
+    /// the workers from it on wait, 0 is no limit
+    concurrency: AtomicUsize,
+    /// the workers started by the current translation
+    workers: AtomicUsize,
+    /// the file and the progress of the current translation
+    progress: Mutex<(String, Progress)>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ControlStatus {
+    pub file: String,
+    pub translated: usize,
+    pub total: usize,
+    pub paused: bool,
+    /// the limit set by set-concurrency, 0 is no limit
+    pub concurrency: usize,
+    pub workers: usize,
+}
+
+impl Control {
+    /// whether the worker waits instead of taking a batch
+    pub fn holds(&self, worker: usize) -> bool {
+        let limit = self.concurrency.load(Ordering::Relaxed);
+        self.paused.load(Ordering::Relaxed) || (limit > 0 && worker >= limit)
+    }
+
+    pub fn start(&self, file: &str, workers: usize, progress: Progress) {
+        self.workers.store(workers, Ordering::Relaxed);
+        self.update(file, progress);
+    }
+
+    pub fn update(&self, file: &str, progress: Progress) {
+        let mut current = self.progress.lock().unwrap();
+        if current.0 != file {
+            current.0 = file.to_string();
+        }
+        current.1 = progress;
+    }
+
+    fn apply(&self, action: &CtlAction) {
+        match action {
+            CtlAction::Pause => self.paused.store(true, Ordering::Relaxed),
+            CtlAction::Resume => self.paused.store(false, Ordering::Relaxed),
+            CtlAction::Status => {}
+            CtlAction::SetConcurrency { n } => self.concurrency.store(*n, Ordering::Relaxed),
+        }
+    }
+
+    pub fn status(&self) -> ControlStatus {
+        let (file, progress) = self.progress.lock().unwrap().clone();
+        ControlStatus {
+            file,
+            translated: progress.translated,
+            total: progress.total,
+            paused: self.paused.load(Ordering::Relaxed),
+            concurrency: self.concurrency.load(Ordering::Relaxed),
+            workers: self.workers.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// serve the control of the run on a local address in the background:
+///   GET /status, POST /pause, POST /resume, POST /concurrency/N -> the status as json
+pub async fn serve_control(control: Arc<Control>, addr: &str) -> Result<()> {
+    let addr: SocketAddr = addr.parse()?;
+    let make_svc = make_service_fn(move |_| {
+        let control = control.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let control = control.clone();
+                async move { Ok::<_, Infallible>(handle(&control, req)) }
+            }))
+        }
+    });
+    // bound before the run goes on, an address in use stops the run
+    let server = Server::try_bind(&addr)?.serve(make_svc);
+    println!("[Control] {}", t!(Msg::ControlServing, addr));
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            eprintln!("[Control] {}", e);
+        }
+    });
+    Ok(())
+}
+
+fn handle(control: &Control, req: Request<Body>) -> Response<Body> {
+    let action = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/status") => Some(CtlAction::Status),
+        (&Method::POST, "/pause") => Some(CtlAction::Pause),
+        (&Method::POST, "/resume") => Some(CtlAction::Resume),
+        (&Method::POST, path) => path
+            .strip_prefix("/concurrency/")
+            .and_then(|n| n.parse().ok())
+            .map(|n| CtlAction::SetConcurrency { n }),
+        _ => None,
+    };
+    let Some(action) = action else {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("not found"))
+            .unwrap();
+    };
+    control.apply(&action);
+    Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Body::from(
+            serde_json::to_string(&control.status()).unwrap_or_default(),
+        ))
+        .unwrap()
+}
+
+/// send the action to the run serving the control on the address, and print its status
+pub async fn ctl(action: &CtlAction, addr: &str) -> Result<()> {
+    let client = reqwest::Client::new();
+    let base = format!("http://{}", addr);
+    let request = match action {
+        CtlAction::Pause => client.post(format!("{}/pause", base)),
+        CtlAction::Resume => client.post(format!("{}/resume", base)),
+        CtlAction::Status => client.get(format!("{}/status", base)),
+        CtlAction::SetConcurrency { n } => client.post(format!("{}/concurrency/{}", base, n)),
+    };
+    let status = request
+        .send()
+        .await
+        .map_err(|e| anyhow::anyhow!(t!(Msg::ControlUnreachable, addr, e)))?
+        .error_for_status()?
+        .json::<ControlStatus>()
+        .await?;
+    println!("{}", render_status(&status));
+    Ok(())
+}
+
+fn render_status(status: &ControlStatus) -> String {
+    let state = match status.paused {
+        true => t!(Msg::ControlPaused),
+        false => t!(Msg::ControlRunning),
+    };
+    let workers = match status.concurrency {
+        0 => status.workers,
+        n => n.min(status.workers),
+    };
+    // the jobs of serve have no file
+    let file = match status.file.is_empty() {
+        true => "-",
+        false => status.file.as_str(),
+    };
+    t!(
+        Msg::ControlStatus,
+        file,
+        state,
+        status.translated,
+        status.total,
+        workers,
+        status.workers
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Control, CtlAction};
+    use crate::translators::Progress;
+
+    #[test]
+    fn test_control() {
+        let control = Control::default();
+        control.start(
+            "a.txt",
+            4,
+            Progress {
+                translated: 10,
+                total: 40,
+            },
+        );
+        assert!(!control.holds(3));
+        control.apply(&CtlAction::SetConcurrency { n: 2 });
+        assert!(control.holds(2) && !control.holds(1));
+        control.apply(&CtlAction::Pause);
+        assert!(control.holds(0));
+        control.apply(&CtlAction::Resume);
+        control.apply(&CtlAction::SetConcurrency { n: 0 });
+        assert!(!control.holds(3));
+        let status = control.status();
+        assert_eq!(
+            (status.file.as_str(), status.translated, status.workers),
+            ("a.txt", 10, 4)
+        );
+    }
+}
@@ -0,0 +1,266 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde_json::Value;
+
+use crate::init::Preset;
+use crate::{i18n::Msg, t};
+
+/// the folders deeper than this are not inspected
+const MAX_DEPTH: usize = 4;
+
+/// the bytes of a file read to recognize its format
+const SAMPLE_BYTES: u64 = 64 * 1024;
+
+/// the engine or the tool a file comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Engine {
+    /// the ManualTransFile.json exported by MTool
+    Mtool,
+    /// a Translator++ project
+    TranslatorPlusPlus,
+    Kirikiri,
+    /// the messages dumped from an ain file
+    Ain,
+    /// the _AutoGeneratedTranslations.txt of XUnity.AutoTranslator
+    AutoTranslator,
+    /// the key=value or tab separated resources
+    KeyValue,
+    /// the data of RPG Maker, exported by MTool or Translator++ first
+    RpgMaker,
+    /// the archives of Wolf RPG Editor, exported by MTool first
+    Wolf,
+    /// the System engine scripts not dumped yet
+    AinBinary,
+    Text,
+}
+
+impl Engine {
+    /// the preset of `lottr init` translating the files, None when they are exported first
+    pub fn preset(&self) -> Option<Preset> {
+        match self {
+            Engine::Mtool => Some(Preset::Mtool),
+            Engine::TranslatorPlusPlus => Some(Preset::Tpp),
+            Engine::Kirikiri => Some(Preset::Kirikiri),
+            Engine::Ain => Some(Preset::Ain),
+            Engine::AutoTranslator | Engine::KeyValue => Some(Preset::KeyValue),
+            Engine::Text => Some(Preset::Text),
+            Engine::RpgMaker | Engine::Wolf | Engine::AinBinary => None,
+        }
+    }
+
+    fn hint(&self) -> &'static str {
+        match self {
+            Engine::Mtool => "the json exported by MTool",
+            Engine::TranslatorPlusPlus => "a Translator++ project, the machine translation column is filled",
+            Engine::Kirikiri => "the kirikiri scripts",
+            Engine::Ain => "the messages dumped from the System engine",
+            Engine::AutoTranslator => "the translations of XUnity.AutoTranslator, set kv_opt.source = \"key\" to translate the untranslated lines",
+            Engine::KeyValue => "the key=value resources, only the values are translated",
+            Engine::RpgMaker => "RPG Maker data, export the texts by MTool (ManualTransFile.json) or open the game in Translator++",
+            Engine::Wolf => "Wolf RPG Editor archives, export the texts by MTool (ManualTransFile.json)",
+            Engine::AinBinary => "a System engine ain, dump the messages by alice-tools: alice ain dump --text",
+            Engine::Text => "plain text, every non-empty line is translated",
+        }
+    }
+}
+
+/// the files of an engine found under the path
+#[derive(Debug, Clone, PartialEq)]
+pub struct Detection {
+    pub engine: Engine,
+    pub files: Vec<PathBuf>,
+}
+
+/// inspect the file, or the files of the folder, the detections of the known engines first, the
+/// plain text last
+pub fn detect(path: &Path) -> Result<Vec<Detection>> {
+    let mut found = BTreeMap::<Engine, Vec<PathBuf>>::new();
+    let mut files = vec![];
+    collect(path, 0, &mut files)?;
+    for file in files {
+        if let Some(engine) = detect_file(&file) {
+            found.entry(engine).or_default().push(file);
+        }
+    }
+    // the text files of a folder of another engine are mostly the readme
+    if found.len() > 1 {
+        found.remove(&Engine::Text);
+    }
+    Ok(found
+        .into_iter()
+        .map(|(engine, files)| Detection { engine, files })
+        .collect())
+}
+
+fn collect(path: &Path, depth: usize, files: &mut Vec<PathBuf>) -> Result<()> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    if depth >= MAX_DEPTH {
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(path)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .collect::<Vec<_>>();
+    entries.sort();
+    for entry in entries {
+        collect(&entry, depth + 1, files)?;
+    }
+    Ok(())
+}
+
+/// the engine of a file by its name and the beginning of its content
+pub fn detect_file(path: &Path) -> Option<Engine> {
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "trans" => return Some(Engine::TranslatorPlusPlus),
+        "ks" => return Some(Engine::Kirikiri),
+        "wolf" => return Some(Engine::Wolf),
+        "rvdata2" | "rvdata" | "rxdata" => return Some(Engine::RpgMaker),
+        "ain" => return Some(Engine::AinBinary),
+        "json" | "txt" | "jam" | "adv" => {}
+        _ => return None,
+    }
+    if name == "_autogeneratedtranslations.txt" {
+        return Some(Engine::AutoTranslator);
+    }
+    let mut sample = vec![];
+    fs::File::open(path)
+        .ok()?
+        .take(SAMPLE_BYTES)
+        .read_to_end(&mut sample)
+        .ok()?;
+    let sample = String::from_utf8_lossy(&sample);
+    match ext.as_str() {
+        "json" => detect_json(path, &sample),
+        _ => Some(detect_text(&sample)),
+    }
+}
+
+fn detect_json(path: &Path, sample: &str) -> Option<Engine> {
+    let rpg_maker = path
+        .parent()
+        .and_then(|p| p.file_name())
+        .is_some_and(|dir| dir.eq_ignore_ascii_case("data"))
+        && ["\"events\"", "\"list\"", "\"code\"", "\"displayName\""]
+            .iter()
+            .any(|key| sample.contains(key));
+    if rpg_maker {
+        return Some(Engine::RpgMaker);
+    }
+    // the whole file is needed to parse it, the sample only of a small one
+    let value = serde_json::from_str::<Value>(sample).ok();
+    let mtool = match value {
+        Some(Value::Object(map)) => !map.is_empty() && map.values().all(Value::is_string),
+        Some(_) => false,
+        // `{"original": "original",` lines of a large export
+        None => {
+            sample.trim_start().starts_with('{')
+                && sample
+                    .lines()
+                    .skip(1)
+                    .take(20)
+                    .filter(|l| !l.trim().is_empty())
+                    .all(|l| l.trim_start().starts_with('"') && l.contains("\": \""))
+        }
+    };
+    mtool.then_some(Engine::Mtool)
+}
+
+fn detect_text(sample: &str) -> Engine {
+    let lines = sample
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .take(200)
+        .collect::<Vec<_>>();
+    let count = |f: &dyn Fn(&str) -> bool| lines.iter().filter(|l| f(l)).count();
+    let half = lines.len().div_ceil(2).max(1);
+    if count(&|l| l.starts_with(";m[") || l.starts_with(";s[")) >= half {
+        return Engine::Ain;
+    }
+    if count(&|l| l.starts_with('@') || l.starts_with('[') || l.starts_with('*')) >= half {
+        return Engine::Kirikiri;
+    }
+    let key_value =
+        |l: &str| !l.starts_with('#') && (l.contains('\t') || l.find('=').is_some_and(|i| i > 0));
+    if count(&key_value) >= half {
+        return Engine::KeyValue;
+    }
+    Engine::Text
+}
+
+/// print the detections and the presets of `lottr init`
+pub fn print_detections(detections: &[Detection]) {
+    if detections.is_empty() {
+        println!("{}", t!(Msg::DetectNone));
+        return;
+    }
+    for detection in detections {
+        let engine = detection.engine;
+        let files = t!(
+            Msg::DetectFiles,
+            format!("{:?}", engine),
+            detection.files.len(),
+            detection.files[0].display()
+        );
+        println!("{}", files);
+        println!("  {}", engine.hint());
+        if let Some(preset) = engine.preset() {
+            println!(
+                "  lottr init --preset {} {}",
+                preset.name(),
+                detection.files[0].display()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{detect, detect_text, Engine};
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(
+            detect_text(";m[0] = \"はい\"\n;m[1] = \"いいえ\"\n"),
+            Engine::Ain
+        );
+        assert_eq!(
+            detect_text("*start\n@bg storage=room\n[cm]\nおはよう[p]\n"),
+            Engine::Kirikiri
+        );
+        assert_eq!(
+            detect_text("menu.start=はじめから\nmenu.load=つづきから\n"),
+            Engine::KeyValue
+        );
+        assert_eq!(
+            detect_text("吾輩は猫である。\n名前はまだ無い。\n"),
+            Engine::Text
+        );
+
+        let dir = crate::utils::TempDir::new("detect");
+        std::fs::create_dir_all(dir.join("www/data")).unwrap();
+        std::fs::write(dir.join("ManualTransFile.json"), r#"{"はい": "はい"}"#).unwrap();
+        std::fs::write(dir.join("www/data/Map001.json"), r#"{"events": []}"#).unwrap();
+        std::fs::write(dir.join("readme.txt"), "readme\n").unwrap();
+        let detections = detect(dir.path()).unwrap();
+        let engines = detections.iter().map(|d| d.engine).collect::<Vec<_>>();
+        assert_eq!(engines, vec![Engine::Mtool, Engine::RpgMaker]);
+        assert_eq!(
+            detections[0].engine.preset(),
+            Some(crate::init::Preset::Mtool)
+        );
+    }
+}
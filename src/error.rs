@@ -0,0 +1,56 @@
+use regex::{Regex, RegexSet};
+use thiserror::Error;
+
+/// the errors of a bad configuration or input, the messages tell what to fix
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{name} is not a valid regex: {source}")]
+    Regex {
+        name: String,
+        #[source]
+        source: regex::Error,
+    },
+    #[error("invalid configuration: {0}")]
+    Config(String),
+    #[error("failed to open {path}: {source}")]
+    Io {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("the prompt file {path} is not valid: {reason}")]
+    Prompt { path: String, reason: String },
+    #[error("the rewritten file {path} is corrupted, it {reason}")]
+    Corrupted { path: String, reason: String },
+    #[error("failed to create the http client: {0}")]
+    Client(#[from] reqwest::Error),
+}
+
+impl Error {
+    pub fn io(path: &str) -> impl FnOnce(std::io::Error) -> Self + '_ {
+        move |source| Error::Io {
+            path: path.to_string(),
+            source,
+        }
+    }
+}
+
+/// compile the regex of the option name
+pub fn new_regex(name: &str, regex: &str) -> Result<Regex, Error> {
+    Regex::new(regex).map_err(|source| Error::Regex {
+        name: name.to_string(),
+        source,
+    })
+}
+
+/// compile the regexen of the option name into a set
+pub fn new_regex_set<I, S>(name: &str, regexen: I) -> Result<RegexSet, Error>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    RegexSet::new(regexen).map_err(|source| Error::Regex {
+        name: name.to_string(),
+        source,
+    })
+}
@@ -0,0 +1,245 @@
+use std::fs;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    paths::ArtifactDirs,
+    textures::{to_ranges, Textures},
+};
+
+/// the chars of the first source line kept as the sample of a batch
+const SAMPLE_CHARS: usize = 80;
+
+/// why a batch is translated again
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailReason {
+    /// the translated lines extracted from the response mismatch the lines of the batch
+    Mismatch,
+    /// scored below qe_opt.min_score with qe_opt.retranslate
+    LowScore,
+    /// the lines changed in the file since the textures
+    Changed,
+    /// the lines left untranslated by an import, or recorded by an older version
+    Untranslated,
+    /// re-queued in the review of the tui
+    Requeued,
+}
+
+impl FailReason {
+    /// found again by every output from the textures, the others are kept until retried
+    fn is_recomputed(self) -> bool {
+        matches!(self, FailReason::Mismatch | FailReason::LowScore)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FailedBatch {
+    /// the lines of the batch, offset to the whole file
+    pub range: (usize, usize),
+    pub reason: FailReason,
+    /// e.g. the sizes of a mismatch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// the start of the first line of the batch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample: Option<String>,
+    /// the ids of the first and the last line, the range is found again by them when the file
+    /// changed, see `TextureLine::id`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ids: Option<(u64, u64)>,
+}
+
+impl FailedBatch {
+    pub fn new(range: (usize, usize), reason: FailReason) -> Self {
+        Self {
+            range,
+            reason,
+            detail: None,
+            sample: None,
+            ids: None,
+        }
+    }
+
+    /// the batch of the range of the textures, the range and the sample are taken from the textures
+    pub fn of(textures: &Textures, (start, end): (usize, usize), reason: FailReason) -> Self {
+        let offset = textures.offset();
+        let mut batch = Self::new((start + offset, end + offset), reason);
+        batch.sample = textures
+            .lines
+            .get(start)
+            .map(|l| l.content.trim().chars().take(SAMPLE_CHARS).collect());
+        batch.ids = textures.range_ids((start, end));
+        batch
+    }
+
+    pub fn with_detail(mut self, detail: String) -> Self {
+        self.detail = Some(detail);
+        self
+    }
+}
+
+/// file.failed.json, the batches to translate again and why, the run with --retry-failed
+/// translates only them
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FailedReport {
+    pub batches: Vec<FailedBatch>,
+}
+
+impl FailedReport {
+    /// the report of the file, or the ranges of file.dignostic_failed_range.json of an older
+    /// version, empty if none
+    pub fn load(dirs: &ArtifactDirs, file: &str) -> Self {
+        if let Some(report) = fs::read(dirs.failed(file))
+            .ok()
+            .and_then(|v| serde_json::from_slice::<FailedReport>(&v).ok())
+        {
+            return report;
+        }
+        let legacy = fs::read(dirs.legacy_failed_range(file))
+            .ok()
+            .and_then(|v| serde_json::from_slice::<Vec<(usize, usize)>>(&v).ok())
+            .unwrap_or_default();
+        Self {
+            batches: legacy
+                .into_iter()
+                .map(|range| FailedBatch::new(range, FailReason::Untranslated))
+                .collect(),
+        }
+    }
+
+    /// move the batches of the old lines of a changed file to the new lines by map, see
+    /// `match_lines`, a batch is found in the old lines by its ids, by its range if it has none,
+    /// and split around its changed lines
+    pub fn relocate(&mut self, old: &Textures, map: &[Option<usize>], new: &Textures) {
+        let index = old.line_index();
+        let old_range = |batch: &FailedBatch| {
+            let ids = batch.ids?;
+            Some((*index.get(&ids.0)?, *index.get(&ids.1)?)).filter(|(s, e)| s <= e)
+        };
+        self.batches = std::mem::take(&mut self.batches)
+            .into_iter()
+            .flat_map(|batch| {
+                let (start, end) = old_range(&batch).unwrap_or(batch.range);
+                to_ranges((start..=end).filter_map(|k| map.get(k).copied().flatten()))
+                    .into_iter()
+                    .map(|range| FailedBatch {
+                        detail: batch.detail.clone(),
+                        ..FailedBatch::of(new, range, batch.reason)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+    }
+
+    /// write the report, or remove it when empty, the file of an older version is replaced
+    pub fn save(&self, dirs: &ArtifactDirs, file: &str) -> Result<()> {
+        let _ = fs::remove_file(dirs.legacy_failed_range(file));
+        let path = dirs.failed(file);
+        if self.batches.is_empty() {
+            let _ = fs::remove_file(path);
+            return Ok(());
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    /// the ranges to translate again, sorted
+    pub fn ranges(&self) -> Vec<(usize, usize)> {
+        let mut ranges = self.batches.iter().map(|b| b.range).collect::<Vec<_>>();
+        ranges.sort();
+        ranges.dedup();
+        ranges
+    }
+
+    /// add the batches, a batch of the same range and reason is kept once
+    pub fn add(&mut self, batches: Vec<FailedBatch>) {
+        self.batches.extend(batches);
+        self.batches.sort_by_key(|b| (b.range, b.reason));
+        self.batches
+            .dedup_by(|a, b| a.range == b.range && a.reason == b.reason);
+    }
+
+    /// replace the batches of the reason
+    pub fn replace(&mut self, reason: FailReason, batches: Vec<FailedBatch>) {
+        self.batches.retain(|b| b.reason != reason);
+        self.add(batches);
+    }
+
+    /// the batches were translated again by --retry-failed, only those the output finds again
+    /// are kept
+    pub fn consume(&mut self) {
+        self.batches.retain(|b| b.reason.is_recomputed());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FailReason, FailedBatch, FailedReport};
+    use crate::{
+        paths::ArtifactDirs,
+        textures::{match_lines, TextureLine, Textures, TEXTURES_VERSION},
+    };
+
+    fn textures(lines: &[&str]) -> Textures {
+        let mut textures = Textures {
+            lines: lines
+                .iter()
+                .map(|l| TextureLine::new(0, l.len(), l.to_string(), false))
+                .collect(),
+            curr_index: 0,
+            version: TEXTURES_VERSION,
+            source: None,
+            name: String::new(),
+            shard: None,
+            dirs: ArtifactDirs::default(),
+        };
+        textures.assign_ids();
+        textures
+    }
+
+    #[test]
+    fn test_relocate() {
+        let old = textures(&["a", "b", "c", "d"]);
+        let mut report = FailedReport::default();
+        report
+            .add(vec![FailedBatch::of(&old, (1, 3), FailReason::Mismatch)
+                .with_detail("2 of 3".to_string())]);
+        // the range is stale, the ids are not
+        report.batches[0].range = (0, 0);
+        let new = textures(&["x", "a", "b", "C", "d"]);
+        report.relocate(&old, &match_lines(&old.lines, &new.lines), &new);
+        assert_eq!(report.ranges(), vec![(2, 2), (4, 4)]);
+        assert_eq!(report.batches[1].ids, new.range_ids((4, 4)));
+        assert_eq!(report.batches[1].detail.as_deref(), Some("2 of 3"));
+    }
+
+    #[test]
+    fn test_failed_report() {
+        let dirs = ArtifactDirs::default();
+        let file = "test_failed_report.txt";
+        std::fs::write(dirs.legacy_failed_range(file), "[[3,5]]").unwrap();
+        let mut report = FailedReport::load(&dirs, file);
+        assert_eq!(report.ranges(), vec![(3, 5)]);
+        report.replace(
+            FailReason::Mismatch,
+            vec![FailedBatch::new((0, 2), FailReason::Mismatch)
+                .with_detail("expected 3 lines, extracted 2".to_string())],
+        );
+        report.save(&dirs, file).unwrap();
+        assert!(!dirs.legacy_failed_range(file).exists());
+        let mut report = FailedReport::load(&dirs, file);
+        assert_eq!(report.ranges(), vec![(0, 2), (3, 5)]);
+        report.consume();
+        assert_eq!(report.batches[0].reason, FailReason::Mismatch);
+        assert_eq!(report.ranges(), vec![(0, 2)]);
+        report.replace(FailReason::Mismatch, vec![]);
+        report.save(&dirs, file).unwrap();
+        assert!(!dirs.failed(file).exists());
+    }
+}
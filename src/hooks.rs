@@ -0,0 +1,208 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    console::{self, Tone},
+    i18n::Msg,
+    t, RunSummary,
+};
+
+/// a command or a webhook taking longer is abandoned
+const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// the commands and the webhooks run on the events of a run, a failed hook is reported and the
+/// run goes on
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HookOptions {
+    /// every batch received from the translator
+    pub on_batch_translated: Option<Hook>,
+    /// the output of a file is written, a paused run too, see budget
+    pub on_run_complete: Option<Hook>,
+    /// the run of a file stops by an error
+    pub on_failure: Option<Hook>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Hook {
+    /// a shell command, the event is in LOTTR_EVENT, the file in LOTTR_FILE and the json payload
+    /// in LOTTR_PAYLOAD
+    pub command: Option<String>,
+    /// the url the json payload is posted to, its `content` is the message of a discord webhook,
+    /// its `text` the message of a slack webhook or of the sendMessage of a telegram bot
+    pub webhook: Option<String>,
+}
+
+impl HookOptions {
+    /// the hooks by the name of their event
+    pub fn hooks(&self) -> [(&'static str, Option<&Hook>); 3] {
+        [
+            ("on_batch_translated", self.on_batch_translated.as_ref()),
+            ("on_run_complete", self.on_run_complete.as_ref()),
+            ("on_failure", self.on_failure.as_ref()),
+        ]
+    }
+
+    /// run in the background, a slow hook does not hold the translation
+    pub fn batch_translated(
+        &self,
+        file: &str,
+        range: (usize, usize),
+        translated: usize,
+        total: usize,
+    ) {
+        let Some(hook) = self.on_batch_translated.clone() else {
+            return;
+        };
+        let message = t!(Msg::HookBatch, file, range.0, range.1, translated, total);
+        let payload = payload(
+            "batch_translated",
+            file,
+            message,
+            json!({"start": range.0, "end": range.1, "translated": translated, "total": total}),
+        );
+        tokio::spawn(async move { hook.run(payload).await });
+    }
+
+    pub async fn run_complete(&self, file: &str, summary: &RunSummary) {
+        let Some(hook) = &self.on_run_complete else {
+            return;
+        };
+        let message = match summary.paused {
+            true => t!(Msg::HookPaused, file, summary.lines),
+            false => t!(
+                Msg::HookComplete,
+                file,
+                summary.lines,
+                summary.batches,
+                summary.failed_batches
+            ),
+        };
+        let summary = serde_json::to_value(summary).unwrap_or_default();
+        hook.run(payload(
+            "run_complete",
+            file,
+            message,
+            json!({"summary": summary}),
+        ))
+        .await;
+    }
+
+    pub async fn failure(&self, file: &str, error: &anyhow::Error) {
+        let Some(hook) = &self.on_failure else {
+            return;
+        };
+        let error = format!("{:#}", error);
+        let message = t!(Msg::HookFailure, file, error);
+        hook.run(payload("failure", file, message, json!({"error": error})))
+            .await;
+    }
+}
+
+/// the payload of every event: the event, the file, the message and the details of the event
+fn payload(event: &str, file: &str, message: String, details: Value) -> Value {
+    let mut payload = json!({
+        "event": event,
+        "file": file,
+        "content": message,
+        "text": message,
+    });
+    if let (Some(payload), Value::Object(details)) = (payload.as_object_mut(), details) {
+        payload.extend(details);
+    }
+    payload
+}
+
+impl Hook {
+    async fn run(&self, payload: Value) {
+        if let Some(command) = &self.command {
+            if let Err(e) = run_command(command, &payload).await {
+                let failed = t!(Msg::HookFailed, command, format!("{:#}", e));
+                eprintln!("{} {}", console::err(Tone::Warning, "[Hook]"), failed);
+            }
+        }
+        if let Some(webhook) = &self.webhook {
+            if let Err(e) = post_webhook(webhook, &payload).await {
+                let failed = t!(Msg::HookFailed, webhook, format!("{:#}", e));
+                eprintln!("{} {}", console::err(Tone::Warning, "[Hook]"), failed);
+            }
+        }
+    }
+}
+
+async fn run_command(command: &str, payload: &Value) -> anyhow::Result<()> {
+    #[cfg(windows)]
+    let mut process = tokio::process::Command::new("cmd");
+    #[cfg(windows)]
+    process.arg("/C").arg(command);
+    #[cfg(not(windows))]
+    let mut process = tokio::process::Command::new("sh");
+    #[cfg(not(windows))]
+    process.arg("-c").arg(command);
+    let field = |name: &str| payload[name].as_str().unwrap_or_default().to_string();
+    process
+        .env("LOTTR_EVENT", field("event"))
+        .env("LOTTR_FILE", field("file"))
+        .env("LOTTR_PAYLOAD", payload.to_string())
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true);
+    let status = tokio::time::timeout(HOOK_TIMEOUT, process.status()).await??;
+    if !status.success() {
+        return Err(anyhow::anyhow!("{}", status));
+    }
+    Ok(())
+}
+
+async fn post_webhook(url: &str, payload: &Value) -> anyhow::Result<()> {
+    let resp = reqwest::Client::new()
+        .post(url)
+        .timeout(HOOK_TIMEOUT)
+        .json(payload)
+        .send()
+        .await?;
+    resp.error_for_status()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{payload, run_command};
+
+    #[test]
+    fn test_payload() {
+        let payload = payload(
+            "failure",
+            "a.txt",
+            "a.txt: boom".to_string(),
+            json!({"error": "boom"}),
+        );
+        assert_eq!(
+            payload,
+            json!({
+                "event": "failure",
+                "file": "a.txt",
+                "content": "a.txt: boom",
+                "text": "a.txt: boom",
+                "error": "boom",
+            })
+        );
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn test_run_command() {
+        let dir = crate::utils::TempDir::new("hook_command");
+        let out = dir.join("hook_command.txt");
+        let payload = payload("run_complete", "a.txt", String::new(), json!({}));
+        let command = format!("echo \"$LOTTR_EVENT $LOTTR_FILE\" > {}", out.display());
+        run_command(&command, &payload).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "run_complete a.txt\n"
+        );
+        assert!(run_command("exit 3", &payload).await.is_err());
+    }
+}
@@ -0,0 +1,464 @@
+use std::{fmt::Display, sync::OnceLock};
+
+use clap::ValueEnum;
+
+/// the language of the messages, chosen once by `--lang` or the locale
+static LANG: OnceLock<Lang> = OnceLock::new();
+
+/// the languages of the cli messages
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum Lang {
+    #[default]
+    En,
+    Zh,
+    Ja,
+}
+
+impl Lang {
+    /// the language of the locale, LC_ALL, LC_MESSAGES or LANG, e.g. zh_CN.UTF-8
+    fn from_locale() -> Lang {
+        let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|v| !v.is_empty())
+            .unwrap_or_default();
+        match locale.get(..2) {
+            Some("zh") => Lang::Zh,
+            Some("ja") => Lang::Ja,
+            _ => Lang::En,
+        }
+    }
+}
+
+/// choose the language of the messages, by the locale when not given, only the first choice of
+/// the process is kept
+pub fn set_lang(lang: Option<Lang>) {
+    LANG.get_or_init(|| lang.unwrap_or_else(Lang::from_locale));
+}
+
+/// the language of the messages, english until chosen
+pub fn lang() -> Lang {
+    LANG.get().copied().unwrap_or_default()
+}
+
+macro_rules! messages {
+    ($($key:ident => [$en:literal, $zh:literal, $ja:literal $(,)?],)*) => {
+        /// the messages of the cli, `{}` takes the next argument, `{n}` the argument n, so a
+        /// translation can put them in another order
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        pub enum Msg {
+            $($key,)*
+        }
+
+        impl Msg {
+            #[cfg(test)]
+            const ALL: &'static [Msg] = &[$(Msg::$key,)*];
+
+            fn texts(&self) -> [&'static str; 3] {
+                match self {
+                    $(Msg::$key => [$en, $zh, $ja],)*
+                }
+            }
+        }
+    };
+}
+
+messages! {
+    Error => ["Error:", "错误：", "エラー："],
+    Cancelled => ["the translation is cancelled", "已取消翻译", "翻訳を中止しました"],
+    TranslateRest => ["translate the rest?", "继续翻译剩余部分？", "残りを翻訳しますか？"],
+    ConfirmLines => ["translate the {} lines?", "翻译这 {} 行？", "この {} 行を翻訳しますか？"],
+    Selection => [
+        "{} lines in the file, {} selected to translate, {} skipped, {} duplicates, {} by the dictionary",
+        "文件共 {} 行，{} 行待翻译，{} 行跳过，{} 行重复，{} 行由词典翻译",
+        "ファイル全体 {} 行、翻訳対象 {} 行、スキップ {} 行、重複 {} 行、辞書で翻訳 {} 行",
+    ],
+    SelectionBatches => [", about {} batches", "，约 {} 个批次", "、約 {} バッチ"],
+    AddRefined => [
+        "add ChatGPTRefined to output_translators to output the refined lines",
+        "在 output_translators 中添加 ChatGPTRefined 才会输出润色后的行",
+        "校正した行を出力するには output_translators に ChatGPTRefined を追加してください",
+    ],
+    AddGemini => [
+        "add Gemini to output_translators to output the Gemini translations",
+        "在 output_translators 中添加 Gemini 才会输出 Gemini 的译文",
+        "Gemini の翻訳を出力するには output_translators に Gemini を追加してください",
+    ],
+    TranslateShard => ["translate shard {}/{}", "翻译分片 {}/{}", "シャード {}/{} を翻訳"],
+    ShardsNoConsistency => [
+        "consistency check is not supported for shards",
+        "分片不支持一致性检查",
+        "シャードでは一貫性チェックに対応していません",
+    ],
+    FailedBatchesHint => [
+        "{} failed batches in {}, add --retry-failed to translate them again",
+        "{1} 中有 {0} 个失败的批次，加上 --retry-failed 重新翻译它们",
+        "{1} に失敗したバッチが {0} 個あります、--retry-failed を付けて再翻訳してください",
+    ],
+    RetryFailed => ["retry {} failed batches", "重试 {} 个失败的批次", "失敗した {} 個のバッチを再試行"],
+    CheckOk => ["api_pool[{}] ok", "api_pool[{}] 正常", "api_pool[{}] 正常"],
+    CheckValid => ["the configuration is valid", "配置有效", "設定は有効です"],
+    CheckProblems => [
+        "{} problems in the configuration",
+        "配置中有 {} 个问题",
+        "設定に {} 件の問題があります",
+    ],
+    AskPreset => [
+        "trans type, mtool / text / kirikiri / ain / key-value / tpp",
+        "翻译类型，mtool / text / kirikiri / ain / key-value / tpp",
+        "翻訳の種類、mtool / text / kirikiri / ain / key-value / tpp",
+    ],
+    AskFrom => ["source language, iso 639-3 code", "源语言，iso 639-3 代码", "原文の言語、iso 639-3 コード"],
+    AskTo => ["target language, iso 639-3 code", "目标语言，iso 639-3 代码", "訳文の言語、iso 639-3 コード"],
+    InitWritten => ["write {} configuration to {}", "已将 {} 配置写入 {}", "{} の設定を {} に書き込みました"],
+    InitNext => [
+        "fill the api_key of [[chatgpt_opt.api_pool]], then run: lottr check-config -c {}",
+        "填写 [[chatgpt_opt.api_pool]] 的 api_key，然后运行：lottr check-config -c {}",
+        "[[chatgpt_opt.api_pool]] の api_key を記入してから実行してください：lottr check-config -c {}",
+    ],
+    DetectNone => ["no known format found", "未发现已知的格式", "既知の形式は見つかりませんでした"],
+    DetectFiles => ["{}: {} files, e.g. {}", "{}：{} 个文件，例如 {}", "{}：{} ファイル、例：{}"],
+    JobsStart => ["{} files, {} jobs", "{} 个文件，{} 个任务", "{} ファイル、{} ジョブ"],
+    Watching => ["watching {}", "正在监视 {}", "{} を監視しています"],
+    WatchTranslate => ["translate {}", "翻译 {}", "{} を翻訳"],
+    WatchError => ["translate {} error: {}", "翻译 {} 出错：{}", "{} の翻訳エラー：{}"],
+    Serving => ["serve on http://{}", "服务地址 http://{}", "http://{} で待ち受けています"],
+    Merged => [
+        "merged {} states into {}, {} batches, resume from line {}",
+        "已将 {} 个状态合并到 {}，{} 个批次，从第 {} 行继续",
+        "{0} 個の状態を {1} に統合しました、{2} バッチ、{3} 行目から再開",
+    ],
+    LoadedShards => ["Loaded {} shards from {}", "从 {1} 加载了 {0} 个分片", "{1} から {0} 個のシャードを読み込みました"],
+    NewShards => [
+        "new {} shards from {}, lines {}",
+        "从 {1} 新建 {0} 个分片，共 {2} 行",
+        "{1} から {0} 個のシャードを作成、{2} 行",
+    ],
+    LoadedTextures => ["Loaded textures from {}", "从 {} 加载了状态", "{} から状態を読み込みました"],
+    NewTextures => ["new textures from {}, lines {}", "从 {} 新建状态，共 {} 行", "{} から状態を作成、{} 行"],
+    SourceChanged => [
+        "{} changed since the textures, {} of {} lines unchanged, {} lines now, {} ranges to translate again",
+        "{0} 自保存状态后已修改，{2} 行中 {1} 行未变，现有 {3} 行，{4} 个范围需要重新翻译",
+        "{0} は状態の保存後に変更されました、{2} 行のうち {1} 行は変更なし、現在 {3} 行、再翻訳する範囲 {4} 個",
+    ],
+    DictionaryLocal => [
+        "dictionary translated {} lines locally",
+        "词典在本地翻译了 {} 行",
+        "辞書でローカルに {} 行を翻訳しました",
+    ],
+    Saving => ["Saving textures...", "正在保存状态...", "状態を保存しています..."],
+    Recovered => [
+        "recovered {} batches from the journal of {}",
+        "从 {1} 的日志恢复了 {0} 个批次",
+        "{1} のジャーナルから {0} 個のバッチを復元しました",
+    ],
+    Migrated => [
+        "migrated {} from version {} to {}, the original is backed up as {}",
+        "已将 {} 从版本 {} 迁移到 {}，原文件备份为 {}",
+        "{0} をバージョン {1} から {2} に移行しました、元のファイルは {3} にバックアップされています",
+    ],
+    Dedup => [
+        "dedup {} duplicated lines of {}",
+        "{1} 行中合并了 {0} 个重复行",
+        "{1} 行のうち重複する {0} 行をまとめました",
+    ],
+    OutputLines => [
+        "output {} lines, {} lines left untranslated",
+        "输出 {} 行，{} 行未翻译",
+        "{} 行を出力、{} 行は未翻訳のまま",
+    ],
+    PatchedInPlace => ["patched {} in place", "已直接修改 {}", "{} を上書きしました"],
+    Backup => ["backup {} to {}", "已将 {} 备份到 {}", "{} を {} にバックアップしました"],
+    InPlaceReplaced => [
+        "{} is not the last in-place output, it is taken as the new original",
+        "{} 不是上次直接修改的输出，已作为新的原文",
+        "{} は前回の上書き出力ではないため、新しい原文として扱います",
+    ],
+    NotSent => [
+        "{} lines not sent to the translator: {}",
+        "{} 行未发送给翻译器：{}",
+        "翻訳に送られなかった行 {} 行：{}",
+    ],
+    ReportShards => [
+        "review report is not supported for shards",
+        "分片不支持审阅报告",
+        "シャードではレビューレポートに対応していません",
+    ],
+    ReviewReport => ["review report: {}", "审阅报告：{}", "レビューレポート：{}"],
+    IndexReport => ["batch index: {}", "批次索引：{}", "バッチ索引：{}"],
+    PreviewReport => ["preview report: {}", "预览报告：{}", "プレビューレポート：{}"],
+    Verified => [
+        "{} keeps the untouched bytes of {}",
+        "{} 保留了 {} 中未翻译的字节",
+        "{0} は {1} の翻訳していないバイトを保持しています",
+    ],
+    MismatchBatch => [
+        "batch range: {}-{}, expected size: {}, but extracted lines size: {}",
+        "批次范围：{}-{}，应有 {} 行，但提取出 {} 行",
+        "バッチ範囲：{}-{}、{} 行のはずが {} 行を抽出しました",
+    ],
+    FailedRange => ["failed range: {}", "失败的范围：{}", "失敗した範囲：{}"],
+    LowScoreRange => ["low score range: {}", "低分的范围：{}", "低スコアの範囲：{}"],
+    RetranslateHint => [
+        "{} batches to translate again in {}, run with --retry-failed",
+        "{1} 中有 {0} 个批次需要重新翻译，加上 --retry-failed 运行",
+        "{1} に再翻訳するバッチが {0} 個あります、--retry-failed を付けて実行してください",
+    ],
+    StyleLine => ["line {}: {}", "第 {} 行：{}", "{} 行目：{}"],
+    StyleMore => ["and {} more", "另有 {} 处", "ほか {} 件"],
+    Split => [
+        "split {} into {} parts, see {}",
+        "已将 {} 拆分为 {} 个部分，见 {}",
+        "{} を {} 個に分割しました、{} を参照",
+    ],
+    FixupNoPrevious => [
+        "no previous output to compare with",
+        "没有可以比较的上次输出",
+        "比較する前回の出力がありません",
+    ],
+    FixupUnchanged => ["the output is unchanged", "输出没有变化", "出力は変わっていません"],
+    FixupChanged => [
+        "{} lines changed in {} places, see {}",
+        "{1} 处共 {0} 行有变化，见 {2}",
+        "{1} か所で {0} 行が変更されました、{2} を参照",
+    ],
+    ExportReview => ["export review to {}, lines {}", "已导出审阅到 {}，共 {} 行", "レビューを {} に書き出しました、{} 行"],
+    InvalidLineId => ["invalid line id: {}", "无效的行号：{}", "無効な行 id：{}"],
+    SourceMismatch => [
+        "line {} source not match, skip",
+        "第 {} 行的原文不匹配，已跳过",
+        "{} 行目の原文が一致しないためスキップしました",
+    ],
+    ImportReview => [
+        "import review from {}, edited lines {}, batches {}",
+        "已从 {} 导入审阅，修改了 {} 行，{} 个批次",
+        "{} からレビューを取り込みました、編集 {} 行、{} バッチ",
+    ],
+    ImportTranslations => [
+        "import translations from {}, filled lines {}, untranslated ranges {}",
+        "已从 {} 导入译文，填入 {} 行，未翻译的范围 {} 个",
+        "{} から翻訳を取り込みました、{} 行を埋め、未翻訳の範囲 {} 個",
+    ],
+    ExportTmx => ["export tmx to {}, units {}", "已导出 tmx 到 {}，共 {} 个单元", "tmx を {} に書き出しました、{} ユニット"],
+    Consistency => [
+        "{} sources translated differently, {} glossary violations, report: {}",
+        "{} 个原文的译法不一致，{} 处违反术语表，报告：{}",
+        "訳の揺れがある原文 {} 件、用語集の違反 {} 件、レポート：{}",
+    ],
+    Harmonized => [
+        "harmonized {} lines in {} batches",
+        "统一了 {1} 个批次中的 {0} 行",
+        "{1} バッチの {0} 行を統一しました",
+    ],
+    LengthLong => ["{} lines longer than {}", "{} 行长于 {}", "{1} より長い行が {0} 行"],
+    LengthError => ["shorten request error: {}", "缩短请求出错：{}", "短縮リクエストのエラー：{}"],
+    LengthShortened => [
+        "shortened {} lines in {} batches",
+        "缩短了 {1} 个批次中的 {0} 行",
+        "{1} バッチの {0} 行を短縮しました",
+    ],
+    LengthStill => [
+        "still too long, left to the line_width wrapping: lines {}",
+        "仍然过长，交给 line_width 换行：第 {} 行",
+        "まだ長すぎるため line_width の折り返しに任せます：{} 行目",
+    ],
+    QeError => ["score request error: {}", "评分请求出错：{}", "採点リクエストのエラー：{}"],
+    QeScored => ["scored {} batches", "评分了 {} 个批次", "{} バッチを採点しました"],
+    GlossaryCandidates => ["{} new candidates", "{} 个新的候选", "新しい候補が {} 件"],
+    GlossaryProposed => [
+        "{} renderings proposed, edit {} before the translation",
+        "提议了 {} 个译名，翻译前请编辑 {}",
+        "訳語を {} 件提案しました、翻訳の前に {} を編集してください",
+    ],
+    RetrievalIndexed => ["indexed {} translated lines", "索引了 {} 行译文", "翻訳済みの {} 行を索引しました"],
+    RetrievalEmbedded => ["embedded {} lines", "嵌入了 {} 行", "{} 行を埋め込みました"],
+    Metrics => ["metrics: {}", "指标：{}", "メトリクス：{}"],
+    NoFailedBatches => ["no failed batches", "没有失败的批次", "失敗したバッチはありません"],
+    ParamsDiffer => [
+        "the parameters differ from the first pass, {} now, {} before",
+        "参数与第一次翻译不同，现在为 {}，之前为 {}",
+        "パラメータが最初の翻訳と異なります、現在 {}、以前 {}",
+    ],
+    Sample => ["sample {} of {} batches", "从 {1} 个批次中抽取 {0} 个", "{1} バッチから {0} 個を抽出"],
+    Capped => [
+        "max_tokens {} is capped to {} by the limits of {}",
+        "受 {2} 的限制，max_tokens {0} 被限制为 {1}",
+        "{2} の制限により max_tokens {0} は {1} に制限されます",
+    ],
+    PriorityDone => [
+        "the priority lines are translated, `lottr output` writes a partial output now",
+        "优先的行已翻译完，现在可以用 `lottr output` 输出部分结果",
+        "優先行の翻訳が終わりました、`lottr output` で途中の出力を書き出せます",
+    ],
+    BudgetPaused => [
+        "the run is paused after {} tokens (${}), the progress is saved, run the same command again to resume, or raise budget.max_total_tokens / budget.max_cost_usd",
+        "已用 {} 个 token（${}），运行暂停，进度已保存，再次运行同一命令即可继续，或调高 budget.max_total_tokens / budget.max_cost_usd",
+        "{} トークン（${}）を使ったため一時停止しました、進捗は保存されています、同じコマンドを再実行すると再開します、または budget.max_total_tokens / budget.max_cost_usd を引き上げてください",
+    ],
+    StartTranslate => [
+        "start translate, batch len: {}, max concurrent {}",
+        "开始翻译，批次数：{}，最大并发 {}",
+        "翻訳を開始、バッチ数：{}、最大同時実行数 {}",
+    ],
+    Mismatch => ["{} mismatch: {}-{}, retry {}", "{} 行数不匹配：{}-{}，重试 {}", "{} 行数の不一致：{}-{}、再試行 {}"],
+    RequestError => ["{} request error: {}", "{} 请求出错：{}", "{} リクエストのエラー：{}"],
+    KeysRejected => [
+        "every api key is rejected, worker {} stops",
+        "所有的 api key 都被拒绝，工作线程 {} 停止",
+        "すべての api key が拒否されました、ワーカー {} を停止します",
+    ],
+    KeyRejected => [
+        "{} is rejected, worker {} moves to {}",
+        "{} 被拒绝，工作线程 {} 改用 {}",
+        "{} が拒否されました、ワーカー {} は {} に切り替えます",
+    ],
+    Refused => ["batch {}-{} is skipped: {}", "批次 {}-{} 已跳过：{}", "バッチ {}-{} をスキップしました：{}"],
+    LeftUntranslated => [
+        "batch {}-{} is left untranslated: {}",
+        "批次 {}-{} 未翻译：{}",
+        "バッチ {}-{} は未翻訳のままです：{}",
+    ],
+    GeminiBlocked => [
+        "batch {}-{} is blocked: {}, relax gemini_opt.safety_threshold",
+        "批次 {}-{} 被拦截：{}，请放宽 gemini_opt.safety_threshold",
+        "バッチ {}-{} がブロックされました：{}、gemini_opt.safety_threshold を緩めてください",
+    ],
+    CacheFailed => [
+        "failed to add the batch {}-{}: {}",
+        "添加批次 {}-{} 失败：{}",
+        "バッチ {}-{} の追加に失敗しました：{}",
+    ],
+    LiveFailed => ["failed to output: {}", "输出失败：{}", "出力に失敗しました：{}"],
+    AdaptiveBudget => ["batch budget {} -> {} tokens", "批次预算 {} -> {} 个 token", "バッチ予算 {} -> {} トークン"],
+    AdaptiveConcurrency => ["concurrency {} -> {}", "并发 {} -> {}", "同時実行数 {} -> {}"],
+    HookBatch => [
+        "{}: batch {}-{} translated, {} of {} lines",
+        "{}：批次 {}-{} 已翻译，{4} 行中已完成 {3} 行",
+        "{}：バッチ {}-{} を翻訳しました、{4} 行のうち {3} 行",
+    ],
+    HookComplete => [
+        "{}: translated {} lines in {} batches, {} failed batches",
+        "{}：翻译了 {} 行，共 {} 个批次，{} 个批次失败",
+        "{}：{} 行を {} バッチで翻訳しました、失敗したバッチ {} 個",
+    ],
+    HookPaused => [
+        "{}: paused over the budget after {} lines",
+        "{}：翻译 {} 行后超出预算，已暂停",
+        "{}：{} 行を翻訳したところで予算を超えたため一時停止しました",
+    ],
+    HookFailure => ["{}: failed, {}", "{}：失败，{}", "{}：失敗しました、{}"],
+    HookFailed => ["{} failed: {}", "{} 执行失败：{}", "{} が失敗しました：{}"],
+    ControlServing => [
+        "control on http://{}, see lottr ctl",
+        "控制接口位于 http://{}，见 lottr ctl",
+        "制御インターフェース http://{}、lottr ctl を参照",
+    ],
+    ControlUnreachable => [
+        "no run serves the control on {}: {}",
+        "{} 上没有运行中的控制接口：{}",
+        "{} で制御を提供している実行はありません：{}",
+    ],
+    ControlPaused => ["paused", "已暂停", "一時停止中"],
+    ControlRunning => ["running", "运行中", "実行中"],
+    ControlStatus => [
+        "{}: {}, {} of {} lines, {} of {} workers",
+        "{}：{}，{3} 行中已完成 {2} 行，{5} 个工作线程中运行 {4} 个",
+        "{}：{}、{3} 行のうち {2} 行、ワーカー {5} 個のうち {4} 個",
+    ],
+    PreviewRequest => ["the request of batch {}-{}:", "批次 {}-{} 的请求：", "バッチ {}-{} のリクエスト："],
+    PreviewResponse => ["the response:", "回复：", "応答："],
+    PreviewParsed => [
+        "{} lines parsed from the response for {} lines:",
+        "从回复中解析出 {} 行，应有 {} 行：",
+        "応答から {} 行を解析しました、{} 行のはず：",
+    ],
+}
+
+/// the message in the language chosen, see `t!`
+pub fn text(msg: Msg) -> &'static str {
+    msg.texts()[lang() as usize]
+}
+
+/// fill the placeholders of the message by the arguments
+pub fn fill(template: &str, args: &[&dyn Display]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut next = 0;
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        let Some(close) = rest[open..].find('}').map(|c| open + c) else {
+            break;
+        };
+        out.push_str(&rest[..open]);
+        let index = match &rest[open + 1..close] {
+            "" => {
+                next += 1;
+                Some(next - 1)
+            }
+            n => n.parse::<usize>().ok(),
+        };
+        match index.and_then(|i| args.get(i)) {
+            Some(arg) => out.push_str(&arg.to_string()),
+            None => out.push_str(&rest[open..=close]),
+        }
+        rest = &rest[close + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// the message of the key in the language chosen, with its placeholders filled, e.g.
+/// `t!(Msg::OutputLines, written, skipped)`
+#[macro_export]
+macro_rules! t {
+    ($msg:expr $(, $arg:expr)* $(,)?) => {
+        $crate::i18n::fill(
+            $crate::i18n::text($msg),
+            &[$(&$arg as &dyn std::fmt::Display),*],
+        )
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use super::{fill, Msg};
+
+    /// the arguments a message uses
+    fn placeholders(template: &str) -> BTreeSet<usize> {
+        let args = (0..8).map(|i| format!("<{}>", i)).collect::<Vec<_>>();
+        let args = args
+            .iter()
+            .map(|a| a as &dyn std::fmt::Display)
+            .collect::<Vec<_>>();
+        let filled = fill(template, &args);
+        (0..8)
+            .filter(|i| filled.contains(&format!("<{}>", i)))
+            .collect()
+    }
+
+    #[test]
+    fn test_fill() {
+        assert_eq!(
+            fill("output {} lines, {} left", &[&3, &"a"]),
+            "output 3 lines, a left"
+        );
+        assert_eq!(
+            fill("{1} 中有 {0} 个", &[&3, &"a.json"]),
+            "a.json 中有 3 个"
+        );
+        // the missing arguments are kept as they are
+        assert_eq!(fill("{} and {}", &[&1]), "1 and {}");
+        assert_eq!(fill("{x} {", &[&1]), "{x} {");
+    }
+
+    #[test]
+    fn test_catalog() {
+        for msg in Msg::ALL {
+            let [en, zh, ja] = msg.texts();
+            let expected = placeholders(en);
+            assert_eq!(placeholders(zh), expected, "{:?} zh", msg);
+            assert_eq!(placeholders(ja), expected, "{:?} ja", msg);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::{
+    fs,
+    io::{BufRead, IsTerminal, Write},
+    path::Path,
+};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use isolang::Language;
+
+use crate::{i18n::Msg, t, Configuration};
+
+/// the starter configurations, with the regexes known to work for the formats
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Preset {
+    /// the json exported by MTool, {"original": "original"}
+    Mtool,
+    /// plain text, every non-empty line is translated
+    Text,
+    /// kirikiri scripts (.ks)
+    Kirikiri,
+    /// the json of ain (.ain) games
+    Ain,
+    /// the key=value or tab separated resources, e.g. the Unity localization tables
+    KeyValue,
+    /// a Translator++ project (.trans)
+    Tpp,
+}
+
+impl Preset {
+    fn template(&self) -> &'static str {
+        match self {
+            Preset::Mtool => include_str!("../assets/options_mtool.toml"),
+            Preset::Text => include_str!("../assets/options_text.toml"),
+            Preset::Kirikiri => include_str!("../assets/options_ks.toml"),
+            Preset::Ain => include_str!("../assets/options_ain.toml"),
+            Preset::KeyValue | Preset::Tpp => include_str!("../assets/options_text.toml"),
+        }
+    }
+
+    /// the trans_type replacing the one of the template
+    fn trans_type(&self) -> Option<&'static str> {
+        match self {
+            Preset::KeyValue => Some("key-value"),
+            Preset::Tpp => Some("translator++"),
+            _ => None,
+        }
+    }
+
+    /// the name of the --preset value
+    pub fn name(&self) -> String {
+        self.to_possible_value()
+            .map(|v| v.get_name().to_string())
+            .unwrap_or_default()
+    }
+}
+
+pub struct InitOptions {
+    pub preset: Option<Preset>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub file: Option<String>,
+    pub force: bool,
+}
+
+/// write a starter configuration to path, the missing options are asked in the terminal
+pub fn init(path: &str, options: InitOptions) -> Result<()> {
+    if Path::new(path).exists() && !options.force {
+        return Err(anyhow::anyhow!(
+            "{} already exists, use --force to overwrite it",
+            path
+        ));
+    }
+    let interactive = std::io::stdin().is_terminal();
+    let preset = match options.preset {
+        Some(preset) => preset,
+        None if interactive => {
+            let answer = ask(&t!(Msg::AskPreset), "mtool")?;
+            Preset::from_str(&answer, true).map_err(|e| anyhow::anyhow!(e))?
+        }
+        None => return Err(anyhow::anyhow!("Please specify a --preset")),
+    };
+    let from = match options.from {
+        Some(from) => from,
+        None if interactive => ask(&t!(Msg::AskFrom), "jpn")?,
+        None => "jpn".to_string(),
+    };
+    let to = match options.to {
+        Some(to) => to,
+        None if interactive => ask(&t!(Msg::AskTo), "zho")?,
+        None => "zho".to_string(),
+    };
+    let content = generate(preset, &from, &to, options.file.as_deref())?;
+    fs::write(path, content)?;
+    println!("{}", t!(Msg::InitWritten, format!("{:?}", preset), path));
+    println!("{}", t!(Msg::InitNext, path));
+    Ok(())
+}
+
+/// the template of the preset, with the languages and the input file replaced
+fn generate(preset: Preset, from: &str, to: &str, file: Option<&str>) -> Result<String> {
+    for lang in [from, to] {
+        if Language::from_639_3(lang).is_none() {
+            return Err(anyhow::anyhow!("{} is not an iso 639-3 code", lang));
+        }
+    }
+    let mut content = String::new();
+    for line in preset.template().lines() {
+        let line = match file {
+            _ if line.starts_with("from = ") => format!("from = \"{}\"", from),
+            _ if line.starts_with("to = ") => format!("to = \"{}\"", to),
+            _ if line.starts_with("trans_type = ") && preset.trans_type().is_some() => {
+                format!(
+                    "trans_type = \"{}\"",
+                    preset.trans_type().unwrap_or_default()
+                )
+            }
+            Some(file) if line.starts_with("# file = ") => format!("file = '{}'", file),
+            _ => line.to_string(),
+        };
+        content.push_str(&line);
+        content.push('\n');
+    }
+    toml::from_str::<Configuration>(&content)?;
+    Ok(content)
+}
+
+fn ask(question: &str, default: &str) -> Result<String> {
+    print!("{} [{}]: ", question, default);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    let answer = answer.trim();
+    Ok(if answer.is_empty() { default } else { answer }.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{generate, Preset};
+
+    #[test]
+    fn test_generate() {
+        let content = generate(Preset::Kirikiri, "jpn", "eng", Some("game.ks")).unwrap();
+        assert!(content.contains("\nto = \"eng\"\n"));
+        assert!(content.contains("\nfile = 'game.ks'\n"));
+        assert!(generate(Preset::Mtool, "jpn", "english", None).is_err());
+        let content = generate(Preset::Tpp, "jpn", "eng", None).unwrap();
+        assert!(content.contains("\ntrans_type = \"translator++\"\n"));
+    }
+}
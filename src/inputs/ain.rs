@@ -0,0 +1,121 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use super::input::LineSyntax;
+
+/// the message and the string lines of a script decompiled from the System engine (AliceSoft),
+/// `;m[12] = "..."` is a message, `;s[3] = "..."` is a string, the string literals are escaped by
+/// backslashes
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AinOptions {
+    /// translate the strings too, they are mostly the names of the files and the functions, only
+    /// the messages are translated by default
+    #[serde(default)]
+    pub strings: bool,
+}
+
+/// the prefix of the literal lines, group 1 is m or s
+const LITERAL: &str = r#"^;([ms])\[\d+\]\s*=\s*""#;
+
+pub struct AinSyntax {
+    literal: Regex,
+    strings: bool,
+}
+
+impl AinSyntax {
+    pub fn new(opt: &AinOptions) -> Self {
+        Self {
+            literal: Regex::new(LITERAL).unwrap(),
+            strings: opt.strings,
+        }
+    }
+}
+
+impl LineSyntax for AinSyntax {
+    /// the unescaped text of the literal, and the span between its quotes, None for the other
+    /// lines, the empty literals and the strings unless they are translated
+    fn extract_line(&self, line: &str) -> Option<(String, (usize, usize))> {
+        let caps = self.literal.captures(line)?;
+        if &caps[1] == "s" && !self.strings {
+            return None;
+        }
+        let start = caps.get(0)?.end();
+        let end = start + closing_quote(&line[start..])?;
+        let text = unescape(&line[start..end]);
+        if text.trim().is_empty() {
+            return None;
+        }
+        Some((text, (start, end)))
+    }
+
+    fn escape(&self, text: &str) -> String {
+        escape(text)
+    }
+}
+
+/// the index of the first quote not escaped
+fn closing_quote(s: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => return Some(i),
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+pub fn unescape(s: &str) -> String {
+    let mut text = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => text.push('\n'),
+            Some('t') => text.push('\t'),
+            Some(c) => text.push(c),
+            None => text.push('\\'),
+        }
+    }
+    text
+}
+
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str(r#"\""#),
+            '\\' => escaped.push_str(r#"\\"#),
+            '\n' => escaped.push_str(r#"\n"#),
+            '\t' => escaped.push_str(r#"\t"#),
+            '\r' => {}
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::{escape, AinOptions, AinSyntax};
+    use crate::inputs::LineSyntax;
+
+    #[test]
+    fn test_ain_syntax() {
+        let syntax = AinSyntax::new(&AinOptions::default());
+        let line = r#";m[300] = "「\"勇者\"は\\来た」" "#;
+        let (text, (start, end)) = syntax.extract_line(line).unwrap();
+        assert_eq!(text, r#"「"勇者"は\来た」"#);
+        assert_eq!(&line[start..end], r#"「\"勇者\"は\\来た」"#);
+        assert_eq!(escape(&text), &line[start..end]);
+        assert!(syntax.extract_line(r#";s[4] = "勇者""#).is_none());
+        assert!(syntax.extract_line(r#";m[5] = """#).is_none());
+        assert!(syntax.extract_line(r#"m[6] = "勇者""#).is_none());
+        let syntax = AinSyntax::new(&AinOptions { strings: true });
+        assert_eq!(syntax.extract_line(r#";s[4] = "勇者""#).unwrap().0, "勇者");
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+
+use regex::RegexSet;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{new_regex_set, Error};
+
+/// translate the trivial lines without the api, they are never sent, e.g. the numbers, the
+/// abbreviations of the status and the sound effects
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DictionaryOptions {
+    /// keep the lines of only numbers and punctuation, and the abbreviations like HP, OK, Lv.5,
+    /// as they are, default is true
+    #[serde(default = "default_builtin")]
+    pub builtin: bool,
+    /// the policy of the onomatopoeia lines, e.g. ドカーン！, ゴロゴロ, どきどき: translate sends
+    /// them, romaji writes their romaji, source keeps them as they are
+    #[serde(default)]
+    pub sound_effects: SoundEffectPolicy,
+    /// the lines matching one of them are sound effects too, e.g. '^[ぁ-んァ-ヶー]+っ?[♡♥]$'
+    #[serde(default)]
+    pub sound_effect_regexen: Vec<String>,
+    /// the translations of the whole lines, the surrounding whitespace is ignored
+    #[serde(default)]
+    pub entries: HashMap<String, String>,
+    /// a toml file of more entries, `"はい" = "Yes"`
+    pub path: Option<String>,
+}
+
+fn default_builtin() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SoundEffectPolicy {
+    #[default]
+    Translate,
+    Romaji,
+    Source,
+}
+
+/// the abbreviations of the games kept untranslated
+const ABBREVIATIONS: &[&str] = &[
+    "OK", "HP", "MP", "SP", "TP", "EP", "EXP", "Exp", "Lv", "LV", "ATK", "DEF", "MAT", "MDF",
+    "AGI", "LUK", "SPD", "INT", "STR", "VIT", "DEX", "MAX", "Max", "MIN", "G", "BGM", "SE", "NPC",
+    "CG", "FPS", "ON", "OFF", "On", "Off",
+];
+
+pub struct Dictionary {
+    entries: HashMap<String, String>,
+    builtin: bool,
+    sound_effects: SoundEffectPolicy,
+    sound_effect_regexen: RegexSet,
+}
+
+impl Dictionary {
+    pub fn new(opt: &DictionaryOptions) -> Result<Self, Error> {
+        let mut entries = HashMap::new();
+        if let Some(path) = &opt.path {
+            let content = std::fs::read_to_string(path).map_err(Error::io(path))?;
+            let table = toml::from_str::<HashMap<String, String>>(&content)
+                .map_err(|e| Error::Config(format!("dictionary {} is not valid: {}", path, e)))?;
+            entries.extend(table);
+        }
+        entries.extend(opt.entries.clone());
+        Ok(Self {
+            entries: entries
+                .into_iter()
+                .map(|(k, v)| (k.trim().to_string(), v))
+                .collect(),
+            builtin: opt.builtin,
+            sound_effects: opt.sound_effects,
+            sound_effect_regexen: new_regex_set(
+                "dictionary.sound_effect_regexen",
+                &opt.sound_effect_regexen,
+            )?,
+        })
+    }
+
+    /// the translation of the line, None if it is sent to the translator
+    pub fn translate(&self, content: &str) -> Option<String> {
+        let text = content.trim();
+        if text.is_empty() {
+            return None;
+        }
+        if let Some(translation) = self.entries.get(text) {
+            return Some(content.replacen(text, translation, 1));
+        }
+        if self.builtin && is_trivial(text) {
+            return Some(content.to_string());
+        }
+        if self.sound_effects == SoundEffectPolicy::Translate || !self.is_sound_effect(text) {
+            return None;
+        }
+        match self.sound_effects {
+            SoundEffectPolicy::Romaji => Some(content.replacen(text, &romanize(text), 1)),
+            _ => Some(content.to_string()),
+        }
+    }
+
+    fn is_sound_effect(&self, text: &str) -> bool {
+        is_sound_effect(text) || self.sound_effect_regexen.is_match(text)
+    }
+}
+
+/// no words but the abbreviations
+fn is_trivial(text: &str) -> bool {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().any(char::is_alphabetic))
+        .all(|word| {
+            let letters = word.trim_matches(|c: char| c.is_ascii_digit());
+            ABBREVIATIONS.contains(&letters)
+        })
+}
+
+/// the sound effect marks after the katakana
+const SOUND_MARKS: &[char] = &['！', '!', '？', '?', '…', '～', '〜', '~', '・', 'ッ', 'っ'];
+
+/// katakana ended by an exclamation or a long sound, or kana of a repeated syllable, so the loan
+/// words like ゲーム and the words like はい！ are not taken
+fn is_sound_effect(text: &str) -> bool {
+    let kana = text.trim_end_matches(SOUND_MARKS);
+    let chars = kana.chars().collect::<Vec<_>>();
+    if chars.is_empty() || chars.len() > 10 {
+        return false;
+    }
+    let repeated = (1..=chars.len() / 2).any(|n| chars[..n] == chars[n..2 * n]);
+    if chars.iter().all(|c| is_katakana(*c)) {
+        let marked = kana.len() < text.len() || kana.ends_with(['ー', 'ン']) && chars.len() <= 4;
+        return marked || repeated;
+    }
+    // only the hiragana repeated as a whole, いいえ is a word
+    let whole = (1..=chars.len() / 2)
+        .any(|n| chars.len() % n == 0 && chars.chunks(n).all(|chunk| chunk == &chars[..n]));
+    chars.iter().all(|c| is_hiragana(*c) || *c == 'ー') && whole
+}
+
+fn is_katakana(c: char) -> bool {
+    ('\u{30A1}'..='\u{30FA}').contains(&c) || c == 'ー'
+}
+
+fn is_hiragana(c: char) -> bool {
+    ('\u{3041}'..='\u{3096}').contains(&c)
+}
+
+/// the kana of ROMAJI in the same order
+const KANA: &str =
+    "アイウエオカキクケコガギグゲゴサシスセソザジズゼゾタチツテトダヂヅデドナニヌネノ\
+    ハヒフヘホバビブベボパピプペポマミムメモヤユヨラリルレロワヲンヴ";
+
+const ROMAJI: [&str; 72] = [
+    "a", "i", "u", "e", "o", "ka", "ki", "ku", "ke", "ko", "ga", "gi", "gu", "ge", "go", "sa",
+    "shi", "su", "se", "so", "za", "ji", "zu", "ze", "zo", "ta", "chi", "tsu", "te", "to", "da",
+    "ji", "zu", "de", "do", "na", "ni", "nu", "ne", "no", "ha", "hi", "fu", "he", "ho", "ba", "bi",
+    "bu", "be", "bo", "pa", "pi", "pu", "pe", "po", "ma", "mi", "mu", "me", "mo", "ya", "yu", "yo",
+    "ra", "ri", "ru", "re", "ro", "wa", "wo", "n", "vu",
+];
+
+fn kana_romaji(c: char) -> Option<&'static str> {
+    KANA.chars().position(|k| k == c).map(|i| ROMAJI[i])
+}
+
+/// the romaji of the kana sound effect, ドカーン！ is Dokaan!
+fn romanize(text: &str) -> String {
+    let mut romaji = String::new();
+    // ッ doubles the consonant of the next kana
+    let mut double = false;
+    // the hiragana are read as their katakana
+    let katakana = text.chars().map(|c| match is_hiragana(c) {
+        true => char::from_u32(c as u32 + 0x60).unwrap_or(c),
+        false => c,
+    });
+    for c in katakana {
+        if let Some(kana) = kana_romaji(c) {
+            if double && !kana.starts_with(['a', 'i', 'u', 'e', 'o', 'n']) {
+                romaji.push_str(&kana[..1]);
+            }
+            double = false;
+            romaji.push_str(kana);
+            continue;
+        }
+        let small_vowel = match c {
+            'ァ' => Some("a"),
+            'ィ' => Some("i"),
+            'ゥ' => Some("u"),
+            'ェ' => Some("e"),
+            'ォ' => Some("o"),
+            'ャ' => Some("ya"),
+            'ュ' => Some("yu"),
+            'ョ' => Some("yo"),
+            _ => None,
+        };
+        match c {
+            'ッ' => double = true,
+            'ー' => {
+                let vowel = romaji.chars().last().filter(|v| "aiueo".contains(*v));
+                romaji.extend(vowel);
+            }
+            _ if small_vowel.is_some() => {
+                let small = small_vowel.unwrap_or_default();
+                // キャ kya, シャ sha, ファ fa
+                match romaji.pop() {
+                    Some('i')
+                        if romaji.ends_with("sh")
+                            || romaji.ends_with("ch")
+                            || romaji.ends_with('j') =>
+                    {
+                        romaji.push_str(&small[small.len() - 1..])
+                    }
+                    Some('i') if small.starts_with('y') => romaji.push_str(small),
+                    Some(v) if "aiueo".contains(v) => romaji.push_str(&small[small.len() - 1..]),
+                    Some(v) => {
+                        romaji.push(v);
+                        romaji.push_str(small);
+                    }
+                    None => romaji.push_str(small),
+                }
+            }
+            '！' => romaji.push('!'),
+            '？' => romaji.push('?'),
+            '…' => romaji.push_str("..."),
+            '～' | '〜' => romaji.push('~'),
+            '・' => romaji.push(' '),
+            _ => romaji.push(c),
+        }
+    }
+    let mut chars = romaji.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => romaji,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{romanize, Dictionary, DictionaryOptions};
+
+    #[test]
+    fn test_dictionary() {
+        let opt: DictionaryOptions =
+            toml::from_str("sound_effects = \"romaji\"\nentries = {\"はい\" = \"Yes\"}").unwrap();
+        let dictionary = Dictionary::new(&opt).unwrap();
+        assert_eq!(dictionary.translate(" はい\n").as_deref(), Some(" Yes\n"));
+        assert_eq!(dictionary.translate("100/100").as_deref(), Some("100/100"));
+        assert_eq!(dictionary.translate("HP+10").as_deref(), Some("HP+10"));
+        assert_eq!(dictionary.translate("Lv.5").as_deref(), Some("Lv.5"));
+        assert_eq!(dictionary.translate("……！").as_deref(), Some("……！"));
+        assert_eq!(
+            dictionary.translate("ドカーン！").as_deref(),
+            Some("Dokaan!")
+        );
+        assert_eq!(
+            dictionary.translate("ゴロゴロ").as_deref(),
+            Some("Gorogoro")
+        );
+        assert_eq!(dictionary.translate("ゲーム"), None);
+        assert_eq!(dictionary.translate("HPが足りない"), None);
+        assert_eq!(dictionary.translate("Hello"), None);
+        assert_eq!(
+            dictionary.translate("どきどき").as_deref(),
+            Some("Dokidoki")
+        );
+        assert_eq!(dictionary.translate("ふふっ").as_deref(), Some("Fufu"));
+        assert_eq!(dictionary.translate("いいえ！"), None);
+
+        let opt: DictionaryOptions =
+            toml::from_str("sound_effects = \"source\"\nsound_effect_regexen = ['♡$']").unwrap();
+        let dictionary = Dictionary::new(&opt).unwrap();
+        assert_eq!(
+            dictionary.translate("ドカーン！").as_deref(),
+            Some("ドカーン！")
+        );
+        assert_eq!(dictionary.translate("あんっ♡").as_deref(), Some("あんっ♡"));
+        let dictionary = Dictionary::new(&DictionaryOptions {
+            sound_effects: Default::default(),
+            ..opt
+        })
+        .unwrap();
+        assert_eq!(dictionary.translate("ドカーン！"), None);
+
+        assert_eq!(romanize("ガチャッ"), "Gacha");
+        assert_eq!(romanize("シャキーン"), "Shakiin");
+        assert_eq!(romanize("バッタン"), "Battan");
+        assert_eq!(romanize("ファー"), "Faa");
+    }
+}
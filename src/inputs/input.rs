@@ -2,112 +2,699 @@ use std::io::BufRead;
 use std::io::BufReader;
 use std::io::Read;
 
+use crate::error::{new_regex, new_regex_set, Error};
+use crate::failed::{FailReason, FailedBatch, FailedReport};
+use crate::i18n::Msg;
+use crate::outputs::prepare_in_place;
+use crate::paths::ArtifactDirs;
+use crate::segment::{split_line, SegmentOptions};
+use crate::t;
+use crate::textures::Shard;
+use crate::textures::ShardsIndex;
+use crate::textures::SourceStamp;
 use crate::textures::TextureLine;
+use crate::textures::TexturePart;
 use crate::textures::Textures;
+use crate::textures::TEXTURES_VERSION;
+use crate::Configuration;
+
+use super::ain::AinSyntax;
+use super::key_value::KeyValueSyntax;
+use super::tpp::TppInput;
+use super::{Dictionary, SkipRules};
 use anyhow::Result;
-use regex::Regex;
+use rayon::prelude::*;
+use regex::{Regex, RegexSet};
 use serde::Deserialize;
 use serde::Serialize;
 
-pub fn input(trans_type: TransType, file: &str, regexen: Vec<String>) -> Result<Textures> {
-    let textures = match trans_type {
-        TransType::Text | TransType::Replace => TextInput::new(regexen).read(file)?,
+/// the input of the trans_type
+pub fn new_input(cfg: &Configuration) -> Result<TextInput, Error> {
+    let skip = skip_rules(cfg)?;
+    let dictionary = dictionary(cfg)?;
+    match cfg.trans_type {
+        TransType::Text | TransType::Replace => Ok(TextInput::new(cfg.filter_regexen.clone())?
+            .with_capture(cfg.input_capture_regex())?
+            .with_paragraph(cfg.paragraph.clone())
+            .with_segment(cfg.segment.clone())
+            .with_dedup(cfg.dedup)
+            .with_skip(skip)
+            .with_dictionary(dictionary)
+            .with_dirs(cfg.dirs())),
+        TransType::Ain | TransType::KeyValue => Ok(TextInput::new(cfg.filter_regexen.clone())?
+            .with_syntax(line_syntax(cfg))
+            .with_segment(cfg.segment.clone())
+            .with_dedup(cfg.dedup)
+            .with_skip(skip)
+            .with_dictionary(dictionary)
+            .with_dirs(cfg.dirs())),
+        TransType::Tpp => Err(Error::Config(
+            "trans_type translator++ reads the project json, not the lines".to_string(),
+        )),
+    }
+}
+
+/// the skip rules, and the lines of only protected_terms
+pub fn skip_rules(cfg: &Configuration) -> Result<Option<SkipRules>, Error> {
+    if cfg.skip.is_none() && cfg.protected_terms.iter().all(|t| t.is_empty()) {
+        return Ok(None);
+    }
+    let options = cfg.skip.clone().unwrap_or_default();
+    Ok(Some(
+        SkipRules::new(&options, cfg.lang_from, cfg.lang_to)?.with_protected(&cfg.protected_terms),
+    ))
+}
+
+/// the dictionary of the trivial lines
+pub fn dictionary(cfg: &Configuration) -> Result<Option<Dictionary>, Error> {
+    cfg.dictionary.as_ref().map(Dictionary::new).transpose()
+}
+
+/// the syntax of the trans_types whose text is quoted or escaped in the line
+pub fn line_syntax(cfg: &Configuration) -> Option<Box<dyn LineSyntax>> {
+    match cfg.trans_type {
+        TransType::Ain => Some(Box::new(AinSyntax::new(
+            &cfg.ain_opt.clone().unwrap_or_default(),
+        ))),
+        TransType::KeyValue => Some(Box::new(KeyValueSyntax::new(
+            &cfg.kv_opt.clone().unwrap_or_default(),
+        ))),
+        _ => None,
+    }
+}
+
+/// the input of the Translator++ projects
+fn tpp_input(cfg: &Configuration) -> Result<TppInput, Error> {
+    Ok(TppInput {
+        opt: cfg.tpp_opt.clone().unwrap_or_default(),
+        dedup: cfg.dedup,
+        skip: skip_rules(cfg)?,
+        dictionary: dictionary(cfg)?,
+        dirs: cfg.dirs(),
+    })
+}
+
+pub fn input(cfg: &Configuration, file: &str) -> Result<Textures> {
+    // the file rewritten by the last in-place run is read as the original the state is stamped
+    // with, not as a changed file
+    if cfg.in_place {
+        prepare_in_place(file)?;
+    }
+    match cfg.trans_type {
+        TransType::Tpp => tpp_input(cfg)?.read(file),
+        _ => new_input(cfg)?.read(file),
+    }
+}
+
+/// parse the file without the state, neither loaded nor saved, see the bench command
+pub fn parse(cfg: &Configuration, file: &str) -> Result<Textures> {
+    let mut reader = BufReader::new(std::fs::File::open(file).map_err(Error::io(file))?);
+    match cfg.trans_type {
+        TransType::Tpp => tpp_input(cfg)?.parse(&mut reader),
+        _ => new_input(cfg)?.parse(&mut reader),
+    }
+}
+
+/// split the file into shards of `shard_lines` lines, every shard is saved as
+/// file.textures.{index}.json, so the whole file never needs to be held in memory.
+/// the shards are only created once, file.shards.json records them.
+pub fn input_shards<I: Input>(input: &I, file: &str, shard_lines: usize) -> Result<ShardsIndex> {
+    let dirs = input.dirs();
+    let index_path = dirs.shards_index(file);
+    if let Ok(index) = std::fs::read_to_string(&index_path) {
+        let index = serde_json::from_str::<ShardsIndex>(&index)?;
+        let loaded = t!(Msg::LoadedShards, index.shards, index_path.display());
+        println!("{}", loaded);
+        return Ok(index);
+    }
+    let mut reader = BufReader::new(std::fs::File::open(file)?);
+    let mut index = ShardsIndex {
+        shards: 0,
+        lines: 0,
+    };
+    let new_shard = |index: &ShardsIndex| Textures {
+        lines: Vec::with_capacity(shard_lines),
+        curr_index: 0,
+        version: TEXTURES_VERSION,
+        source: None,
+        name: file.to_string(),
+        shard: Some(Shard {
+            index: index.shards,
+            offset: index.lines,
+        }),
+        dirs: dirs.clone(),
     };
-    Ok(textures)
+    let mut shard = new_shard(&index);
+    input.parse_each(&mut reader, |texture_line| {
+        // the sentences of a line are kept in the same shard
+        let line_end = texture_line
+            .sentence
+            .is_none_or(|(index, count)| index + 1 == count);
+        shard.lines.push(texture_line);
+        if shard.lines.len() >= shard_lines && line_end {
+            shard.assign_ids();
+            if input.dedup() {
+                shard.dedup();
+            }
+            shard.save()?;
+            index.shards += 1;
+            index.lines += shard.lines.len();
+            shard = new_shard(&index);
+        }
+        Ok(())
+    })?;
+    if !shard.lines.is_empty() {
+        // the ids count the occurrences in the shard, like the shards of the older states
+        shard.assign_ids();
+        if input.dedup() {
+            shard.dedup();
+        }
+        shard.save()?;
+        index.shards += 1;
+        index.lines += shard.lines.len();
+    }
+    std::fs::write(&index_path, serde_json::to_string(&index)?)?;
+    println!("{}", t!(Msg::NewShards, index.shards, file, index.lines));
+    Ok(index)
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum TransType {
     #[serde(rename = "text")]
     Text,
     #[serde(rename = "replace")]
     Replace,
+    /// the messages of the System engine scripts, see AinOptions
+    #[serde(rename = "ain")]
+    Ain,
+    /// the rows of a Translator++ project (.trans), see TppOptions
+    #[serde(rename = "translator++")]
+    Tpp,
+    /// the values of the `key=value` or `key<tab>text` resources, see KeyValueOptions
+    #[serde(rename = "key-value")]
+    KeyValue,
+}
+
+/// the rules of a format whose text is quoted or escaped in the line, the text is read unescaped
+/// and the translation is escaped again into its span
+pub trait LineSyntax: Send + Sync {
+    /// the text and its span in the line, None for the lines without text
+    fn extract_line(&self, line: &str) -> Option<(String, (usize, usize))>;
+    fn escape(&self, text: &str) -> String;
 }
 
-pub trait Input {
+/// the lines of a chunk are read sequentially, then extracted in parallel
+const PARSE_CHUNK_LINES: usize = 64 * 1024;
+
+pub trait Input: Sync {
     fn read(&self, file_path: &str) -> Result<Textures> {
-        match Textures::load(file_path) {
-            Ok(textures) => {
-                println!("Loaded textures from {}.textures.json", file_path);
-                Ok(textures)
+        let dirs = self.dirs();
+        match Textures::load(file_path, &dirs) {
+            Ok(mut textures) => {
+                let state = dirs.textures(file_path);
+                println!("{}", t!(Msg::LoadedTextures, state.display()));
+                let Some(stamp) = textures.source else {
+                    // saved before the stamp, the file is taken as unchanged
+                    textures.source = SourceStamp::of(file_path).ok();
+                    return Ok(textures);
+                };
+                let now = stamp.refresh(file_path).map_err(Error::io(file_path))?;
+                if now.hash == stamp.hash {
+                    textures.source = Some(now);
+                    return Ok(textures);
+                }
+                self.reparse(file_path, textures)
+            }
+            // a state that can not be read is not replaced, its translations would be lost
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(Error::io(&dirs.textures(file_path).display().to_string())(e).into())
             }
             Err(_) => {
                 let file = std::fs::OpenOptions::new()
                     .read(true)
                     .open(file_path)
-                    .unwrap_or_else(|_| panic!("Failed to open file: {}", file_path));
+                    .map_err(Error::io(file_path))?;
                 let mut reader = BufReader::new(file);
                 let mut textures = self.parse(&mut reader)?;
-                println!(
-                    "new textures from {}, lines {}",
-                    file_path,
-                    textures.lines.len()
-                );
+                let lines = textures.lines.len();
+                println!("{}", t!(Msg::NewTextures, file_path, lines));
                 textures.name.push_str(file_path);
+                textures.source = SourceStamp::of(file_path).ok();
+                textures.dirs = dirs;
                 Ok(textures)
             }
         }
     }
+    /// parse the changed file again, keep the translations of the unchanged lines, and add the
+    /// changed ones to file.failed.json to be translated again by --retry-failed,
+    /// the failed batches already there are moved to the new lines
+    fn reparse(&self, file_path: &str, old: Textures) -> Result<Textures> {
+        let dirs = self.dirs();
+        let file = std::fs::File::open(file_path).map_err(Error::io(file_path))?;
+        let mut textures = self.parse(&mut BufReader::new(file))?;
+        textures.name.push_str(file_path);
+        textures.source = Some(SourceStamp::of(file_path).map_err(Error::io(file_path))?);
+        textures.dirs = dirs.clone();
+        let (old_len, new_len) = (old.lines.len(), textures.lines.len());
+        let (map, requeue) = textures.reconcile(&old);
+        let unchanged = map.iter().flatten().count();
+        let changed = t!(
+            Msg::SourceChanged,
+            file_path,
+            unchanged,
+            old_len,
+            new_len,
+            requeue.len()
+        );
+        println!("{}", changed);
+        let mut report = FailedReport::load(&dirs, file_path);
+        report.relocate(&old, &map, &textures);
+        let changed = requeue
+            .into_iter()
+            .map(|range| FailedBatch::of(&textures, range, FailReason::Changed))
+            .collect();
+        report.add(changed);
+        report.save(&dirs, file_path)?;
+        textures.save()?;
+        Ok(textures)
+    }
     fn parse<R: Read>(&self, reader: &mut BufReader<R>) -> Result<Textures> {
         let mut texture_lines = Vec::new();
-        let mut buf = String::new();
+        self.parse_each(reader, |texture_line| {
+            texture_lines.push(texture_line);
+            Ok(())
+        })?;
+        let mut textures = Textures {
+            lines: texture_lines,
+            curr_index: 0,
+            version: TEXTURES_VERSION,
+            source: None,
+            name: String::new(),
+            shard: None,
+            dirs: ArtifactDirs::default(),
+        };
+        textures.assign_ids();
+        let local = textures.lines.iter().filter(|l| l.local.is_some()).count();
+        if local > 0 {
+            println!("{}", t!(Msg::DictionaryLocal, local));
+        }
+        if self.dedup() {
+            textures.dedup();
+        }
+        Ok(textures)
+    }
+    /// read the lines chunk by chunk, extract the lines of a chunk in parallel,
+    /// then call f with every extracted line in order, without holding the whole file
+    fn parse_each<R: Read, F: FnMut(TextureLine) -> Result<()>>(
+        &self,
+        reader: &mut BufReader<R>,
+        mut f: F,
+    ) -> Result<()> {
+        let mut joiner = self.paragraph().map(ParagraphJoiner::new);
+        let segment = self.segment();
+        let skip = self.skip();
+        let dictionary = self.dictionary();
+        let mut f = |mut texture_line: TextureLine| {
+            if let Some(skip) = skip {
+                let reason = skip.reason(&texture_line.content);
+                texture_line.skip = reason.is_some();
+                texture_line.skip_reason = reason.map(str::to_string);
+            }
+            if let Some(dictionary) = dictionary.filter(|_| !texture_line.skip) {
+                texture_line.local = dictionary.translate(&texture_line.content);
+            }
+            f(texture_line)
+        };
+        let mut f = |texture_line: TextureLine| match segment {
+            Some(segment) => split_line(texture_line, segment)
+                .into_iter()
+                .try_for_each(&mut f),
+            None => f(texture_line),
+        };
         let mut seek = 0;
         loop {
-            let line = reader.read_line(&mut buf);
-            match line {
-                Ok(0) => {
+            // (seek, line) of the chunk, the seek is counted before the extraction
+            let mut chunk = Vec::with_capacity(PARSE_CHUNK_LINES);
+            while chunk.len() < PARSE_CHUNK_LINES {
+                let mut buf = String::new();
+                let size = reader.read_line(&mut buf)?;
+                if size == 0 {
                     break;
                 }
-                Ok(size) => {
-                    if let Some(value) = self.extract_line(&buf) {
-                        let texture_line = TextureLine::new(seek, size, value, false);
-                        texture_lines.push(texture_line);
-                    }
-                    seek += size;
-                    buf.clear();
+                chunk.push((seek, buf));
+                seek += size;
+            }
+            if chunk.is_empty() {
+                break;
+            }
+            let extracted = chunk
+                .par_iter()
+                .map(|(seek, line)| {
+                    self.extract_fields(line)
+                        .into_iter()
+                        .map(|(value, span)| {
+                            let mut texture_line =
+                                TextureLine::new(*seek, line.len(), value, false);
+                            texture_line.span = span;
+                            texture_line
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+            for texture_line in extracted.into_iter().flatten() {
+                match &mut joiner {
+                    Some(joiner) => joiner.push(texture_line, &mut f)?,
+                    None => f(texture_line)?,
                 }
-                Err(e) => return Err(e.into()),
             }
         }
-        Ok(Textures {
-            lines: texture_lines,
-            curr_index: 0,
-            name: String::new(),
-        })
+        if let Some(joiner) = joiner {
+            joiner.finish(&mut f)?;
+        }
+        Ok(())
+    }
+    /// the content to translate, and its span in the line if it is not the whole line
+    fn extract_line(&self, line: &str) -> Option<(String, Option<(usize, usize)>)>;
+    /// the contents to translate of a line with several fields, every one a texture line of its
+    /// span, in the order of the line
+    fn extract_fields(&self, line: &str) -> Vec<(String, Option<(usize, usize)>)> {
+        self.extract_line(line).into_iter().collect()
+    }
+    /// join the consecutive lines into paragraphs
+    fn paragraph(&self) -> Option<&ParagraphOptions> {
+        None
+    }
+    /// split the long lines into sentences
+    fn segment(&self) -> Option<&SegmentOptions> {
+        None
+    }
+    /// translate the identical lines only once
+    fn dedup(&self) -> bool {
+        false
+    }
+    /// where the textures of the input are saved
+    fn dirs(&self) -> ArtifactDirs {
+        ArtifactDirs::default()
+    }
+    /// mark the lines never sent to the translator
+    fn skip(&self) -> Option<&SkipRules> {
+        None
+    }
+    /// translate the trivial lines locally
+    fn dictionary(&self) -> Option<&Dictionary> {
+        None
+    }
+}
+
+/// join consecutive non-empty lines into paragraphs, novels translated line by line lose coherence
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParagraphOptions {
+    /// the delimiter between the joined lines, empty for CJK
+    #[serde(default)]
+    pub delimiter: String,
+    /// close a paragraph at a sentence boundary once it has max_chars characters
+    pub max_chars: Option<usize>,
+}
+
+const SENTENCE_ENDS: [char; 11] = ['。', '！', '？', '」', '』', '…', '.', '!', '?', '"', '”'];
+
+pub fn is_sentence_end(c: char) -> bool {
+    SENTENCE_ENDS.contains(&c)
+}
+
+struct ParagraphJoiner<'a> {
+    options: &'a ParagraphOptions,
+    curr: Option<TextureLine>,
+}
+
+impl<'a> ParagraphJoiner<'a> {
+    fn new(options: &'a ParagraphOptions) -> Self {
+        Self {
+            options,
+            curr: None,
+        }
+    }
+
+    fn push<F: FnMut(TextureLine) -> Result<()>>(
+        &mut self,
+        line: TextureLine,
+        f: &mut F,
+    ) -> Result<()> {
+        // the lines with a captured span are never joined
+        if line.span.is_some() {
+            if let Some(curr) = self.curr.take() {
+                f(curr)?;
+            }
+            return f(line);
+        }
+        let Some(mut curr) = self.curr.take() else {
+            self.curr = Some(line);
+            return Ok(());
+        };
+        let adjacent = curr.seek + curr.size == line.seek;
+        let full = self.options.max_chars.is_some_and(|max| {
+            curr.content.chars().count() >= max
+                && curr
+                    .content
+                    .trim_end()
+                    .chars()
+                    .last()
+                    .is_some_and(is_sentence_end)
+        });
+        if !adjacent || full {
+            self.curr = Some(line);
+            return f(curr);
+        }
+        if curr.parts.is_empty() {
+            let content = curr.content.trim_end_matches(['\r', '\n']).to_string();
+            curr.parts.push(TexturePart {
+                seek: curr.seek,
+                size: curr.size,
+                start: 0,
+                end: content.len(),
+            });
+            curr.content = content;
+        }
+        curr.content.push_str(&self.options.delimiter);
+        let start = curr.content.len();
+        curr.content
+            .push_str(line.content.trim_end_matches(['\r', '\n']));
+        curr.parts.push(TexturePart {
+            seek: line.seek,
+            size: line.size,
+            start,
+            end: curr.content.len(),
+        });
+        curr.size = line.seek + line.size - curr.seek;
+        self.curr = Some(curr);
+        Ok(())
+    }
+
+    fn finish<F: FnMut(TextureLine) -> Result<()>>(self, f: &mut F) -> Result<()> {
+        match self.curr {
+            Some(curr) => f(curr),
+            None => Ok(()),
+        }
+    }
+}
+
+/// a filter regex of the input, example: '^;m\[\d+\]' or {regex = '^;m\[\d+\] = "(.+)"', capture = 1},
+/// with capture, only the captured group becomes the content of the texture line,
+/// the output splices the translation into the span of the group.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FilterRegex {
+    Match(String),
+    Capture { regex: String, capture: usize },
+}
+
+impl FilterRegex {
+    pub fn regex(&self) -> &str {
+        match self {
+            FilterRegex::Match(regex) => regex,
+            FilterRegex::Capture { regex, .. } => regex,
+        }
+    }
+}
+
+impl From<String> for FilterRegex {
+    fn from(regex: String) -> Self {
+        FilterRegex::Match(regex)
     }
-    fn extract_line(&self, line: &str) -> Option<String>;
 }
 
 pub struct TextInput {
-    pub regexen: Vec<Regex>,
+    pub paragraph: Option<ParagraphOptions>,
+    pub segment: Option<SegmentOptions>,
+    pub dedup: bool,
+    pub skip: Option<SkipRules>,
+    pub dictionary: Option<Dictionary>,
+    /// match all the regexen in one pass
+    pub set: RegexSet,
+    /// the regexen and the capture index, only compiled for the captures
+    pub regexen: Vec<(Regex, Option<usize>)>,
+    /// capture the group 1 of the lines which matched a regex without capture index
+    pub capture: Option<Regex>,
+    /// extract the text by the syntax of the format, the filter_regexen only narrow the lines
+    pub syntax: Option<Box<dyn LineSyntax>>,
+    /// where the textures are saved
+    pub dirs: ArtifactDirs,
 }
 
 impl TextInput {
-    pub fn new(regexen: Vec<String>) -> Self {
+    pub fn new<T: Into<FilterRegex>>(regexen: Vec<T>) -> Result<Self, Error> {
+        let regexen = regexen.into_iter().map(Into::into).collect::<Vec<_>>();
+        let set = new_regex_set("filter_regexen", regexen.iter().map(|re| re.regex()))?;
         let regexen = regexen
-            .into_iter()
-            .map(|re| Regex::new(&re).unwrap())
-            .collect::<Vec<_>>();
-        Self { regexen }
+            .iter()
+            .map(|re| match re {
+                FilterRegex::Match(regex) => Ok((new_regex("filter_regexen", regex)?, None)),
+                FilterRegex::Capture { regex, capture } => {
+                    Ok((new_regex("filter_regexen", regex)?, Some(*capture)))
+                }
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Self {
+            set,
+            regexen,
+            capture: None,
+            syntax: None,
+            paragraph: None,
+            segment: None,
+            dedup: false,
+            skip: None,
+            dictionary: None,
+            dirs: ArtifactDirs::default(),
+        })
     }
-}
 
-impl Input for TextInput {
-    fn extract_line(&self, line: &str) -> Option<String> {
+    pub fn with_paragraph(mut self, paragraph: Option<ParagraphOptions>) -> Self {
+        self.paragraph = paragraph;
+        self
+    }
+
+    pub fn with_segment(mut self, segment: Option<SegmentOptions>) -> Self {
+        self.segment = segment;
+        self
+    }
+
+    pub fn with_dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    pub fn with_skip(mut self, skip: Option<SkipRules>) -> Self {
+        self.skip = skip;
+        self
+    }
+
+    pub fn with_dictionary(mut self, dictionary: Option<Dictionary>) -> Self {
+        self.dictionary = dictionary;
+        self
+    }
+
+    pub fn with_syntax(mut self, syntax: Option<Box<dyn LineSyntax>>) -> Self {
+        self.syntax = syntax;
+        self
+    }
+
+    pub fn with_dirs(mut self, dirs: ArtifactDirs) -> Self {
+        self.dirs = dirs;
+        self
+    }
+
+    pub fn with_capture(mut self, capture_regex: Option<&str>) -> Result<Self, Error> {
+        self.capture = capture_regex
+            .map(|re| new_regex("capture_regex", re))
+            .transpose()?;
+        Ok(self)
+    }
+
+    fn capture_span(
+        regex: &Regex,
+        index: usize,
+        line: &str,
+    ) -> Option<(String, Option<(usize, usize)>)> {
+        regex
+            .captures(line)
+            .and_then(|caps| caps.get(index))
+            .map(|m| (m.as_str().to_string(), Some((m.start(), m.end()))))
+    }
+
+    /// every group of the capture_regex matched in the line, e.g. both texts of
+    /// `name "X" text "Y"`, the groups not matched or empty are left out
+    fn capture_fields(regex: &Regex, line: &str) -> Vec<(String, Option<(usize, usize)>)> {
+        let Some(caps) = regex.captures(line) else {
+            return vec![];
+        };
+        caps.iter()
+            .skip(1)
+            .flatten()
+            .filter(|m| !m.is_empty())
+            .map(|m| (m.as_str().to_string(), Some((m.start(), m.end()))))
+            .collect()
+    }
+
+    /// the contents of the line, every group of the capture_regex when fields, only the group 1
+    /// otherwise
+    fn extract(&self, line: &str, fields: bool) -> Vec<(String, Option<(usize, usize)>)> {
+        if let Some(syntax) = &self.syntax {
+            if !self.regexen.is_empty() && !self.set.is_match(line) {
+                return vec![];
+            }
+            return syntax
+                .extract_line(line)
+                .map(|(text, span)| (text, Some(span)))
+                .into_iter()
+                .collect();
+        }
         if self.regexen.is_empty() {
             if line.trim().is_empty() {
-                None
-            } else {
-                Some(line.to_string())
+                return vec![];
             }
         } else {
-            for regex in &self.regexen {
-                if regex.is_match(line) {
-                    return Some(line.to_string());
-                }
+            // the first matched regex decides the content
+            let Some(index) = self.set.matches(line).iter().next() else {
+                return vec![];
+            };
+            if let (regex, Some(capture)) = &self.regexen[index] {
+                return Self::capture_span(regex, *capture, line)
+                    .into_iter()
+                    .collect();
             }
-            None
         }
+        match &self.capture {
+            Some(capture) if fields => Self::capture_fields(capture, line),
+            Some(capture) => Self::capture_span(capture, 1, line).into_iter().collect(),
+            None => vec![(line.to_string(), None)],
+        }
+    }
+}
+
+impl Input for TextInput {
+    fn extract_line(&self, line: &str) -> Option<(String, Option<(usize, usize)>)> {
+        self.extract(line, false).into_iter().next()
+    }
+    fn extract_fields(&self, line: &str) -> Vec<(String, Option<(usize, usize)>)> {
+        self.extract(line, true)
+    }
+    fn paragraph(&self) -> Option<&ParagraphOptions> {
+        self.paragraph.as_ref()
+    }
+    fn segment(&self) -> Option<&SegmentOptions> {
+        self.segment.as_ref()
+    }
+    fn dedup(&self) -> bool {
+        self.dedup
+    }
+    fn skip(&self) -> Option<&SkipRules> {
+        self.skip.as_ref()
+    }
+    fn dictionary(&self) -> Option<&Dictionary> {
+        self.dictionary.as_ref()
+    }
+    fn dirs(&self) -> ArtifactDirs {
+        self.dirs.clone()
     }
 }
 
@@ -115,6 +702,49 @@ impl Input for TextInput {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_invalid_input() {
+        let err = TextInput::new(vec!["(unclosed".to_string()]).err().unwrap();
+        assert!(err
+            .to_string()
+            .starts_with("filter_regexen is not a valid regex"));
+        let err = TextInput::new(Vec::<String>::new())
+            .unwrap()
+            .read("./assets/not_exists.txt")
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("failed to open ./assets/not_exists.txt"));
+    }
+
+    #[test]
+    fn test_sound_effect_input() {
+        let mut cfg: Configuration =
+            toml::from_str(include_str!("../../assets/options_text.toml")).unwrap();
+        cfg.dictionary = Some(
+            toml::from_str("sound_effects = \"romaji\"\nsound_effect_regexen = ['♡$']").unwrap(),
+        );
+        let mut reader = BufReader::new("ドカーン！\nあんっ♡\nこんにちは\n".as_bytes());
+        let textures = TextInput::new(Vec::<String>::new())
+            .unwrap()
+            .with_dictionary(dictionary(&cfg).unwrap())
+            .parse(&mut reader)
+            .unwrap();
+        let local = textures
+            .lines
+            .iter()
+            .map(|line| line.local.as_deref())
+            .collect::<Vec<_>>();
+        // the line of a sound_effect_regexen is written in romaji too
+        assert_eq!(local, vec![Some("Dokaan!\n"), Some("An♡\n"), None]);
+
+        cfg.dictionary = Some(toml::from_str("sound_effect_regexen = ['(']").unwrap());
+        let err = dictionary(&cfg).err().unwrap();
+        assert!(err
+            .to_string()
+            .starts_with("dictionary.sound_effect_regexen is not a valid regex"));
+    }
+
     #[test]
     fn test_mtool_input() {
         let content = r#"
@@ -129,6 +759,7 @@ mod tests {
         let mut reader = BufReader::new(content.as_bytes());
         let re = r#"^\s*".*[^\x00-\x7f].*"#;
         let textures = TextInput::new(vec![re.to_string()])
+            .unwrap()
             .parse(&mut reader)
             .unwrap();
         textures.lines.iter().for_each(|line| {
@@ -137,6 +768,145 @@ mod tests {
         assert_eq!(textures.lines.len(), 3);
     }
 
+    #[test]
+    fn test_input_shards() {
+        let dir = crate::utils::TempDir::new("input_shards");
+        let file = dir.join("input_shards.txt");
+        let file = file.to_str().unwrap();
+        let dirs = ArtifactDirs::default();
+        std::fs::write(file, "a\nb\n\nc\nd\ne\n").unwrap();
+        let input = TextInput::new(Vec::<String>::new()).unwrap();
+        let index = input_shards(&input, file, 2).unwrap();
+        assert_eq!(
+            index,
+            ShardsIndex {
+                shards: 3,
+                lines: 5
+            }
+        );
+        let shard = Textures::load_shard(file, 1, &dirs).unwrap();
+        assert_eq!(shard.offset(), 2);
+        assert_eq!(shard.lines[0].content, "c\n");
+        assert_eq!(shard.lines[0].seek, 5);
+    }
+
+    #[test]
+    fn test_parse_seek_across_chunks() {
+        let content = (0..PARSE_CHUNK_LINES + 10)
+            .map(|i| {
+                if i % 3 == 0 {
+                    "你好\n".to_string()
+                } else {
+                    format!("line {}\n", i)
+                }
+            })
+            .collect::<String>();
+        let mut reader = BufReader::new(content.as_bytes());
+        let textures = TextInput::new(vec![r"[^\x00-\x7f]".to_string()])
+            .unwrap()
+            .parse(&mut reader)
+            .unwrap();
+        assert_eq!(textures.lines.len(), (PARSE_CHUNK_LINES + 10).div_ceil(3));
+        for line in &textures.lines {
+            assert_eq!(&content[line.seek..line.seek + line.size], "你好\n");
+        }
+    }
+
+    #[test]
+    fn test_capture_input() {
+        let content = r#"
+;s[2227] = "角色1"
+;m[293] = "好的"
+;m[300] = "请原谅我"
+"#;
+        let mut reader = BufReader::new(content.as_bytes());
+        let regexen: Vec<FilterRegex> = vec![
+            FilterRegex::Capture {
+                regex: r#"^;m\[\d+\]\s=\s"(.+)""#.to_string(),
+                capture: 1,
+            },
+            FilterRegex::Match(r#"^;s\[\d+\]"#.to_string()),
+        ];
+        let textures = TextInput::new(regexen).unwrap().parse(&mut reader).unwrap();
+        let contents = textures
+            .lines
+            .iter()
+            .map(|l| l.content.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(contents, vec![";s[2227] = \"角色1\"\n", "好的", "请原谅我"]);
+        let line = &textures.lines[2];
+        let (start, end) = line.span.unwrap();
+        assert_eq!(&content[line.seek + start..line.seek + end], "请原谅我");
+        assert_eq!(textures.lines[0].span, None);
+    }
+
+    #[test]
+    fn test_capture_regex_input() {
+        let content = ";m[293] = \"好的\"\n;m[300] = \"请原谅我\"\n";
+        let mut reader = BufReader::new(content.as_bytes());
+        let textures = TextInput::new(vec![r#"^;m\[\d+\]"#.to_string()])
+            .unwrap()
+            .with_capture(Some(r#"=\s"(.+)""#))
+            .unwrap()
+            .parse(&mut reader)
+            .unwrap();
+        assert_eq!(textures.lines[1].content, "请原谅我");
+        assert_eq!(textures.lines[1].span, Some((11, 23)));
+        // the fields of a line are captured one by one
+        let content = "name \"勇者\" text \"行くよ\"\nname \"\" text \"はい\"\n";
+        let mut reader = BufReader::new(content.as_bytes());
+        let textures = TextInput::new(Vec::<String>::new())
+            .unwrap()
+            .with_capture(Some(r#"^name "(.*)" text "(.*)""#))
+            .unwrap()
+            .parse(&mut reader)
+            .unwrap();
+        let fields = textures
+            .lines
+            .iter()
+            .map(|l| (l.seek, l.content.as_str(), l.span.unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fields,
+            vec![
+                (0, "勇者", (6, 12)),
+                (0, "行くよ", (20, 29)),
+                (31, "はい", (14, 20))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_paragraph_input() {
+        let content = "第一行，\n第二行。\n\n第三行。\n第四行。\n第五行\n";
+        let mut reader = BufReader::new(content.as_bytes());
+        let textures = TextInput::new(Vec::<String>::new())
+            .unwrap()
+            .with_paragraph(Some(ParagraphOptions {
+                delimiter: "".to_string(),
+                max_chars: Some(6),
+            }))
+            .parse(&mut reader)
+            .unwrap();
+        let contents = textures
+            .lines
+            .iter()
+            .map(|l| l.content.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            contents,
+            vec!["第一行，第二行。", "第三行。第四行。", "第五行\n"]
+        );
+        let paragraph = &textures.lines[0];
+        assert_eq!(paragraph.parts.len(), 2);
+        assert_eq!(paragraph.seek, 0);
+        assert_eq!(paragraph.size, "第一行，\n第二行。\n".len());
+        let part = paragraph.parts[1];
+        assert_eq!(&paragraph.content[part.start..part.end], "第二行。");
+        assert_eq!(&content[part.seek..part.seek + part.size], "第二行。\n");
+        assert!(textures.lines[2].parts.is_empty());
+    }
+
     #[test]
     fn test_text_input() {
         let content = r#"
@@ -147,11 +917,15 @@ mod tests {
         let mut reader = BufReader::new(content.as_bytes());
         let re = r#"^\s*.*[^\x00-\x7f].*"#;
         let textures = TextInput::new(vec![re.to_string()])
+            .unwrap()
             .parse(&mut reader)
             .unwrap();
         assert_eq!(textures.lines.len(), 1);
         let mut reader = BufReader::new(content.as_bytes());
-        let textures = TextInput::new(vec![]).parse(&mut reader).unwrap();
+        let textures = TextInput::new(Vec::<String>::new())
+            .unwrap()
+            .parse(&mut reader)
+            .unwrap();
         assert_eq!(textures.lines.len(), 3);
     }
 
@@ -187,6 +961,7 @@ Hello.
         let mut reader = BufReader::new(content.as_bytes());
         let re = r#"^[^;*\[\n]\s*[^\s]+"#;
         let textures = TextInput::new(vec![re.to_string()])
+            .unwrap()
             .parse(&mut reader)
             .unwrap();
         assert_eq!(textures.lines.len(), 3);
@@ -208,6 +983,7 @@ Hello.
         let mut reader = BufReader::new(content.as_bytes());
         let re = r#"^;m\[\d+\]\s=\s".+""#;
         let textures = TextInput::new(vec![re.to_string()])
+            .unwrap()
             .parse(&mut reader)
             .unwrap();
         assert_eq!(textures.lines.len(), 3);
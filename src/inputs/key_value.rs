@@ -0,0 +1,144 @@
+use serde::{Deserialize, Serialize};
+
+use super::input::LineSyntax;
+
+/// the resource files of `key=value` or `key<tab>text` lines, e.g. the Unity localization tables
+/// or the `_AutoGeneratedTranslations.txt` of XUnity.AutoTranslator
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct KeyValueOptions {
+    /// the separator of the key and the value, by default a tab if the line has one, otherwise the
+    /// first `=` not escaped by a backslash, e.g. ": " for the managed text of Naninovel
+    pub separator: Option<String>,
+    /// value: translate the values; key: translate the keys into the empty values, for the
+    /// untranslated lines of AutoTranslator `original=`
+    #[serde(default)]
+    pub source: KeyValueSource,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyValueSource {
+    #[default]
+    Value,
+    Key,
+}
+
+/// the comments and the section headers are kept as they are
+const COMMENTS: [&str; 4] = ["#", "//", ";", "["];
+
+pub struct KeyValueSyntax {
+    separator: Option<String>,
+    source: KeyValueSource,
+}
+
+impl KeyValueSyntax {
+    pub fn new(opt: &KeyValueOptions) -> Self {
+        Self {
+            separator: opt.separator.clone().filter(|s| !s.is_empty()),
+            source: opt.source,
+        }
+    }
+
+    /// the span of the separator in the line
+    fn split(&self, line: &str) -> Option<(usize, usize)> {
+        if let Some(separator) = &self.separator {
+            return line
+                .find(separator.as_str())
+                .map(|i| (i, i + separator.len()));
+        }
+        if let Some(i) = line.find('\t') {
+            return Some((i, i + 1));
+        }
+        let mut escaped = false;
+        for (i, c) in line.char_indices() {
+            match c {
+                '\\' if !escaped => escaped = true,
+                '=' if !escaped => return Some((i, i + 1)),
+                _ => escaped = false,
+            }
+        }
+        None
+    }
+
+    /// the backslashes escape the separator `=` and the line breaks
+    fn escapes(&self) -> bool {
+        self.separator.as_deref().is_none_or(|s| s == "=")
+    }
+}
+
+impl LineSyntax for KeyValueSyntax {
+    fn extract_line(&self, line: &str) -> Option<(String, (usize, usize))> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let trimmed = line.trim_start();
+        if COMMENTS.iter().any(|c| trimmed.starts_with(c)) {
+            return None;
+        }
+        let (sep_start, sep_end) = self.split(line)?;
+        let key = line[..sep_start].trim();
+        let value_start = sep_end + (line[sep_end..].len() - line[sep_end..].trim_start().len());
+        let value_end = line.trim_end().len().max(value_start);
+        let value = &line[value_start..value_end];
+        let text = match self.source {
+            KeyValueSource::Value => value,
+            // only the untranslated keys
+            KeyValueSource::Key if value.is_empty() => key,
+            KeyValueSource::Key => return None,
+        };
+        if text.trim().is_empty() {
+            return None;
+        }
+        let text = match self.escapes() {
+            true => text.replace("\\=", "="),
+            false => text.to_string(),
+        };
+        Some((text, (value_start, value_end)))
+    }
+
+    fn escape(&self, text: &str) -> String {
+        let text = text.replace(['\r', '\n'], "\\n").replace('\t', "\\t");
+        match self.escapes() {
+            true => text.replace('=', "\\="),
+            false => text,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{KeyValueOptions, KeyValueSource, KeyValueSyntax};
+    use crate::inputs::LineSyntax;
+
+    #[test]
+    fn test_key_value_syntax() {
+        let syntax = KeyValueSyntax::new(&KeyValueOptions::default());
+        let line = "menu.start = はじめから\\nもう一度 \r\n";
+        let (text, (start, end)) = syntax.extract_line(line).unwrap();
+        assert_eq!(text, "はじめから\\nもう一度");
+        assert_eq!(&line[start..end], "はじめから\\nもう一度");
+        assert_eq!(
+            syntax.extract_line("title\tタイトル\n").unwrap().0,
+            "タイトル"
+        );
+        assert_eq!(syntax.extract_line("a\\=b=1\\=2\n").unwrap().0, "1=2");
+        assert_eq!(syntax.escape("1=2\n3"), "1\\=2\\n3");
+        assert!(syntax.extract_line("# comment=x\n").is_none());
+        assert!(syntax.extract_line("[Section]\n").is_none());
+        assert!(syntax.extract_line("empty=\n").is_none());
+
+        let syntax = KeyValueSyntax::new(&KeyValueOptions {
+            separator: None,
+            source: KeyValueSource::Key,
+        });
+        let line = "こんにちは=\n";
+        let (text, (start, end)) = syntax.extract_line(line).unwrap();
+        assert_eq!((text.as_str(), start, end), ("こんにちは", 16, 16));
+        assert!(syntax.extract_line("こんにちは=Hello\n").is_none());
+
+        let syntax = KeyValueSyntax::new(&KeyValueOptions {
+            separator: Some(": ".to_string()),
+            source: KeyValueSource::Value,
+        });
+        assert_eq!(syntax.extract_line("Intro: a=b\n").unwrap().0, "a=b");
+        assert_eq!(syntax.escape("a=b"), "a=b");
+    }
+}
@@ -1,3 +1,33 @@
+mod ain;
+mod dictionary;
 mod input;
+mod key_value;
+mod skip;
+mod tpp;
+pub use ain::AinOptions;
+#[cfg(test)]
+pub use ain::AinSyntax;
+pub use dictionary::Dictionary;
+pub use dictionary::DictionaryOptions;
 pub use input::input as in_put;
+pub use input::input_shards;
+pub use input::is_sentence_end;
+pub use input::line_syntax;
+pub use input::new_input;
+pub use input::parse as parse_input;
+pub use input::FilterRegex;
+pub use input::Input;
+pub use input::LineSyntax;
+pub use input::ParagraphOptions;
+pub use input::TextInput;
 pub use input::TransType;
+pub use key_value::KeyValueOptions;
+#[cfg(test)]
+pub use key_value::KeyValueSyntax;
+pub use skip::SkipOptions;
+pub use skip::SkipRules;
+pub use tpp::cell as tpp_cell;
+pub use tpp::from_line as tpp_from_line;
+pub use tpp::TppInput;
+pub use tpp::TppOptions;
+pub use tpp::TppProject;
@@ -0,0 +1,212 @@
+use isolang::Language;
+use regex::RegexSet;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{new_regex_set, Error};
+
+/// the lines matching any of the rules are kept in the textures, but never sent to the translator
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SkipOptions {
+    /// skip the lines equal to one of the strings, the surrounding whitespace is ignored
+    #[serde(default)]
+    pub strings: Vec<String>,
+    /// skip the lines matching one of the regexen
+    #[serde(default)]
+    pub regexen: Vec<String>,
+    /// skip the lines of only numbers and punctuation
+    #[serde(default)]
+    pub numeric: bool,
+    /// skip the lines already written in the script of the target language
+    #[serde(default)]
+    pub translated: bool,
+    /// skip the lines detected in a language other than the source language, by whatlang,
+    /// the lines of an unreliable detection (mostly short lines) are kept
+    #[serde(default)]
+    pub detect_language: bool,
+}
+
+pub struct SkipRules {
+    strings: Vec<String>,
+    set: RegexSet,
+    numeric: bool,
+    /// the target language, only when the translated lines are skipped
+    lang_to: Option<Language>,
+    /// the source language, only when the language is detected
+    lang_from: Option<Language>,
+    /// the protected_terms, a line of only them passes through untranslated
+    protected: Vec<String>,
+}
+
+impl SkipRules {
+    pub fn new(
+        options: &SkipOptions,
+        lang_from: Language,
+        lang_to: Language,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            strings: options
+                .strings
+                .iter()
+                .map(|s| s.trim().to_string())
+                .collect(),
+            set: new_regex_set("skip.regexen", &options.regexen)?,
+            numeric: options.numeric,
+            lang_to: Some(lang_to).filter(|_| options.translated),
+            lang_from: Some(lang_from).filter(|_| options.detect_language),
+            protected: vec![],
+        })
+    }
+
+    pub fn with_protected(mut self, terms: &[String]) -> Self {
+        self.protected = terms.iter().filter(|t| !t.is_empty()).cloned().collect();
+        self
+    }
+
+    pub fn is_skipped(&self, content: &str) -> bool {
+        self.reason(content).is_some()
+    }
+
+    /// why the line is skipped: filter, numeric, language or protected
+    pub fn reason(&self, content: &str) -> Option<&'static str> {
+        let content = content.trim();
+        if self.strings.iter().any(|s| s == content) || self.set.is_match(content) {
+            return Some("filter");
+        }
+        if self.numeric && is_numeric(content) {
+            return Some("numeric");
+        }
+        if self.lang_to.is_some_and(|lang| in_script_of(content, lang))
+            || self
+                .lang_from
+                .is_some_and(|lang| in_other_language(content, lang))
+        {
+            return Some("language");
+        }
+        if self.is_protected(content) {
+            return Some("protected");
+        }
+        None
+    }
+
+    fn is_protected(&self, content: &str) -> bool {
+        if self.protected.is_empty() {
+            return false;
+        }
+        let mut rest = content.to_string();
+        for term in &self.protected {
+            rest = rest.replace(term.as_str(), "");
+        }
+        rest.len() < content.len() && !rest.chars().any(char::is_alphanumeric)
+    }
+}
+
+fn is_numeric(content: &str) -> bool {
+    content.chars().any(|c| c.is_numeric())
+        && content
+            .chars()
+            .all(|c| c.is_numeric() || c.is_whitespace() || c.is_ascii_punctuation())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Script {
+    Han,
+    Kana,
+    Hangul,
+    Cyrillic,
+    Latin,
+    Other,
+}
+
+fn script_of(c: char) -> Script {
+    match c as u32 {
+        0x3040..=0x30FF | 0x31F0..=0x31FF | 0xFF66..=0xFF9F => Script::Kana,
+        0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF | 0x20000..=0x3FFFD => Script::Han,
+        0x1100..=0x11FF | 0x3130..=0x318F | 0xAC00..=0xD7A3 => Script::Hangul,
+        0x0400..=0x052F => Script::Cyrillic,
+        0x0041..=0x005A | 0x0061..=0x007A | 0x00C0..=0x024F => Script::Latin,
+        _ => Script::Other,
+    }
+}
+
+/// whether the letters of the content are written in the script of the language,
+/// a rough guess by the scripts, chinese is han without kana, japanese has kana
+pub fn in_script_of(content: &str, lang: Language) -> bool {
+    let scripts = content
+        .chars()
+        .filter(|c| c.is_alphabetic())
+        .map(script_of)
+        .collect::<Vec<_>>();
+    if scripts.is_empty() {
+        return false;
+    }
+    let has = |script| scripts.contains(&script);
+    match lang.to_639_3() {
+        "zho" => has(Script::Han) && !has(Script::Kana) && !has(Script::Hangul),
+        "jpn" => has(Script::Kana),
+        "kor" => has(Script::Hangul),
+        "rus" | "ukr" | "bel" | "bul" | "srp" | "mkd" => {
+            scripts.iter().all(|s| *s == Script::Cyrillic)
+        }
+        _ => scripts.iter().all(|s| *s == Script::Latin),
+    }
+}
+
+/// whether the content is reliably detected in a language other than lang
+pub fn in_other_language(content: &str, lang: Language) -> bool {
+    let Some(info) = whatlang::detect(content) else {
+        return false;
+    };
+    let code = match info.lang().code() {
+        // whatlang detects chinese as mandarin
+        "cmn" => "zho",
+        code => code,
+    };
+    info.is_reliable() && code != lang.to_639_3()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_skip_rules() {
+        let options = SkipOptions {
+            strings: vec!["……".to_string()],
+            regexen: vec![r"^\\[a-z]+$".to_string()],
+            numeric: true,
+            translated: true,
+            detect_language: false,
+        };
+        let rules = SkipRules::new(&options, Language::Jpn, Language::Zho).unwrap();
+        assert!(rules.is_skipped(" ……\n"));
+        assert!(rules.is_skipped("\\wait"));
+        assert!(rules.is_skipped("100, 200.5%"));
+        assert!(rules.is_skipped("今天天气不错"));
+        assert!(!rules.is_skipped("今日はいい天気ですね"));
+        assert!(!rules.is_skipped("……！"));
+        let rules = SkipRules::new(&options, Language::Jpn, Language::Eng).unwrap();
+        assert!(rules.is_skipped("Hello, world."));
+        assert!(!rules.is_skipped("Hello, 世界."));
+
+        let rules = SkipRules::new(&options, Language::Jpn, Language::Zho)
+            .unwrap()
+            .with_protected(&["ピノ".to_string()]);
+        assert_eq!(rules.reason("……"), Some("filter"));
+        assert_eq!(rules.reason("100"), Some("numeric"));
+        assert_eq!(rules.reason("今天天气不错"), Some("language"));
+        assert_eq!(rules.reason("ピノ！"), Some("protected"));
+        assert_eq!(rules.reason("ピノ、待って"), None);
+    }
+
+    #[test]
+    fn test_detect_language() {
+        let options = SkipOptions {
+            detect_language: true,
+            ..Default::default()
+        };
+        let rules = SkipRules::new(&options, Language::Jpn, Language::Zho).unwrap();
+        assert!(rules.is_skipped("This line has already been translated into English."));
+        assert!(!rules.is_skipped("今日はいい天気ですね、一緒に散歩に行きましょうか。"));
+        assert!(!rules.is_skipped("OK"));
+    }
+}
@@ -0,0 +1,229 @@
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+
+use anyhow::Result;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::Error;
+use crate::paths::ArtifactDirs;
+use crate::textures::TextureLine;
+
+use super::{Dictionary, Input, SkipRules};
+
+/// the project of Translator++ (.trans), every row of a file is [original, translations...],
+/// the translation fills one column of the rows, the other cells are kept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TppOptions {
+    /// the column of the translation, 1 initial, 2 machine translation, 3 better translation,
+    /// 4 best translation, default is 2
+    #[serde(default = "default_column")]
+    pub column: usize,
+    /// translate the rows whose column is filled already, they are kept by default
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+fn default_column() -> usize {
+    2
+}
+
+impl Default for TppOptions {
+    fn default() -> Self {
+        Self {
+            column: default_column(),
+            overwrite: false,
+        }
+    }
+}
+
+/// the project json, saved gzipped again if it was read gzipped
+pub struct TppProject {
+    pub value: Value,
+    gzip: bool,
+}
+
+impl TppProject {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let gzip = bytes.starts_with(&[0x1f, 0x8b]);
+        let value = match gzip {
+            true => {
+                let mut json = vec![];
+                GzDecoder::new(bytes).read_to_end(&mut json)?;
+                serde_json::from_slice(&json)?
+            }
+            false => serde_json::from_slice(bytes)?,
+        };
+        let project = Self { value, gzip };
+        if project.files().is_none() {
+            return Err(
+                Error::Config("not a Translator++ project, no project.files".to_string()).into(),
+            );
+        }
+        Ok(project)
+    }
+
+    pub fn read(path: &str) -> Result<Self> {
+        Self::from_bytes(&std::fs::read(path).map_err(Error::io(path))?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_vec(&self.value)?;
+        let bytes = match self.gzip {
+            true => {
+                let mut encoder = GzEncoder::new(vec![], Compression::default());
+                encoder.write_all(&json)?;
+                encoder.finish()?
+            }
+            false => json,
+        };
+        std::fs::write(path, bytes).map_err(Error::io(&path.to_string_lossy()))?;
+        Ok(())
+    }
+
+    fn files(&self) -> Option<&serde_json::Map<String, Value>> {
+        self.value
+            .get("project")
+            .unwrap_or(&self.value)
+            .get("files")?
+            .as_object()
+    }
+
+    /// the rows of every file in the order of the file paths, the index of a row is its seek
+    pub fn rows(&self) -> Vec<&Vec<Value>> {
+        self.files()
+            .into_iter()
+            .flat_map(|files| files.values())
+            .filter_map(|file| file.get("data")?.as_array())
+            .flatten()
+            .map(|row| row.as_array().map_or(&EMPTY_ROW, |r| r))
+            .collect()
+    }
+
+    pub fn rows_mut(&mut self) -> Vec<Option<&mut Vec<Value>>> {
+        let value = match self.value.get("project").is_some() {
+            true => &mut self.value["project"],
+            false => &mut self.value,
+        };
+        value
+            .get_mut("files")
+            .and_then(|files| files.as_object_mut())
+            .into_iter()
+            .flat_map(|files| files.values_mut())
+            .filter_map(|file| file.get_mut("data")?.as_array_mut())
+            .flatten()
+            .map(|row| row.as_array_mut())
+            .collect()
+    }
+}
+
+static EMPTY_ROW: Vec<Value> = Vec::new();
+
+/// the text of a cell, None for an empty cell
+pub fn cell(row: &[Value], column: usize) -> Option<&str> {
+    row.get(column)
+        .and_then(|c| c.as_str())
+        .filter(|s| !s.trim().is_empty())
+}
+
+/// the line breaks of a cell are sent as \n, a line of the batch is a row
+pub fn to_line(cell: &str) -> String {
+    cell.replace("\r\n", "\n").replace('\n', "\\n")
+}
+
+pub fn from_line(line: &str) -> String {
+    line.replace("\\n", "\n")
+}
+
+pub struct TppInput {
+    pub opt: TppOptions,
+    pub dedup: bool,
+    pub skip: Option<SkipRules>,
+    pub dictionary: Option<Dictionary>,
+    pub dirs: ArtifactDirs,
+}
+
+impl Input for TppInput {
+    /// the rows are read from the project json, not line by line
+    fn parse_each<R: Read, F: FnMut(TextureLine) -> Result<()>>(
+        &self,
+        reader: &mut BufReader<R>,
+        mut f: F,
+    ) -> Result<()> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        let project = TppProject::from_bytes(&bytes)?;
+        for (seek, row) in project.rows().into_iter().enumerate() {
+            let Some(original) = cell(row, 0) else {
+                continue;
+            };
+            let content = to_line(original);
+            let filled = !self.opt.overwrite && cell(row, self.opt.column).is_some();
+            let reason = match filled {
+                true => Some("filled"),
+                false => self.skip.as_ref().and_then(|s| s.reason(&content)),
+            };
+            let skip = reason.is_some();
+            let mut line = TextureLine::new(seek, 1, content, skip);
+            line.skip_reason = reason.map(str::to_string);
+            if let Some(dictionary) = self.dictionary.as_ref().filter(|_| !skip) {
+                line.local = dictionary.translate(&line.content);
+            }
+            f(line)?;
+        }
+        Ok(())
+    }
+    fn extract_line(&self, _: &str) -> Option<(String, Option<(usize, usize)>)> {
+        None
+    }
+    fn dedup(&self) -> bool {
+        self.dedup
+    }
+    fn dirs(&self) -> ArtifactDirs {
+        self.dirs.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::BufReader;
+
+    use super::{TppInput, TppOptions, TppProject};
+    use crate::inputs::Input;
+
+    #[test]
+    fn test_tpp_input() {
+        let json = r#"{"project": {"gameEngine": "rmmv", "files": {
+            "data/Map001.json": {"data": [["はい", null, null], ["", null], ["行くよ\nまた明日", null, null]]},
+            "data/Actors.json": {"data": [["勇者", "Hero", "Hero"], ["魔王"]]}
+        }}}"#;
+        let input = TppInput {
+            opt: TppOptions::default(),
+            dedup: false,
+            skip: None,
+            dictionary: None,
+            dirs: Default::default(),
+        };
+        let textures = input.parse(&mut BufReader::new(json.as_bytes())).unwrap();
+        let lines = textures
+            .lines
+            .iter()
+            .map(|l| (l.seek, l.content.as_str(), l.skip))
+            .collect::<Vec<_>>();
+        // the files in the order of their paths
+        assert_eq!(
+            lines,
+            vec![
+                (0, "勇者", true),
+                (1, "魔王", false),
+                (2, "はい", false),
+                (4, "行くよ\\nまた明日", false)
+            ]
+        );
+        let mut project = TppProject::from_bytes(json.as_bytes()).unwrap();
+        let rows = project.rows_mut();
+        assert_eq!(rows.len(), 5);
+        assert!(TppProject::from_bytes(b"{\"files\": 1}").is_err());
+    }
+}
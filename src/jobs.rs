@@ -0,0 +1,198 @@
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use tokio::sync::{watch, Semaphore};
+
+use crate::{
+    i18n::Msg, run_file, t, translators::Progress, watch::should_translate, Configuration,
+};
+
+struct JobState {
+    file: String,
+    progress: watch::Receiver<Progress>,
+    started: Option<Instant>,
+    elapsed: Option<Duration>,
+    error: Option<String>,
+}
+
+/// translate the files with a bounded number of parallel jobs, all the jobs share one requests
+/// limiter, the progress of the running jobs is printed every 10 seconds, and a summary at last.
+pub async fn run_jobs(
+    cfg: &Configuration,
+    paths: &[String],
+    jobs: usize,
+    max_requests: Option<usize>,
+    exts: &[String],
+) -> Result<()> {
+    let files = collect_files(paths, exts)?;
+    if files.is_empty() {
+        return Err(anyhow::anyhow!("No files to translate"));
+    }
+    let mut cfg = cfg.clone();
+    let max_requests = max_requests.or_else(|| {
+        cfg.chatgpt_opt
+            .as_ref()
+            .map(|opt| opt.max_concurrent.max(1) as usize)
+    });
+    cfg.request_limiter = max_requests.map(|n| Arc::new(Semaphore::new(n)));
+    let cfg = Arc::new(cfg);
+
+    let mut states = vec![];
+    let mut senders = VecDeque::new();
+    for (i, file) in files.iter().enumerate() {
+        let (tx, rx) = watch::channel(Progress::default());
+        states.push(JobState {
+            file: file.to_string_lossy().to_string(),
+            progress: rx,
+            started: None,
+            elapsed: None,
+            error: None,
+        });
+        senders.push_back((i, tx));
+    }
+    let states = Arc::new(Mutex::new(states));
+    let queue = Arc::new(Mutex::new(senders));
+    println!("{}", t!(Msg::JobsStart, files.len(), jobs));
+
+    let mut handles = vec![];
+    for _ in 0..jobs.clamp(1, files.len()) {
+        let queue = queue.clone();
+        let states = states.clone();
+        let cfg = cfg.clone();
+        handles.push(tokio::spawn(async move {
+            loop {
+                let Some((i, progress)) = queue.lock().unwrap().pop_front() else {
+                    break;
+                };
+                let file = {
+                    let mut states = states.lock().unwrap();
+                    states[i].started = Some(Instant::now());
+                    states[i].file.clone()
+                };
+                let result = run_file(&cfg, &file, Some(&progress)).await;
+                let mut states = states.lock().unwrap();
+                states[i].elapsed = states[i].started.map(|s| s.elapsed());
+                if let Err(e) = result {
+                    states[i].error = Some(e.to_string());
+                }
+            }
+        }));
+    }
+
+    let reporter = {
+        let states = states.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                for state in states.lock().unwrap().iter() {
+                    if state.started.is_some() && state.elapsed.is_none() {
+                        let progress = *state.progress.borrow();
+                        println!(
+                            "[Job] {}: {}/{}",
+                            state.file, progress.translated, progress.total
+                        );
+                    }
+                }
+            }
+        })
+    };
+    for handle in handles {
+        handle.await?;
+    }
+    reporter.abort();
+
+    let states = states.lock().unwrap();
+    println!("{}", summary_table(&states));
+    let failed = states.iter().filter(|s| s.error.is_some()).count();
+    if failed > 0 {
+        return Err(anyhow::anyhow!(
+            "{} of {} files failed",
+            failed,
+            states.len()
+        ));
+    }
+    Ok(())
+}
+
+fn summary_table(states: &[JobState]) -> String {
+    let width = states
+        .iter()
+        .map(|s| s.file.len())
+        .max()
+        .unwrap_or(4)
+        .max(4);
+    let mut table = format!(
+        "{:<width$}  {:<6}  {:>13}  {:>8}\n",
+        "file",
+        "status",
+        "lines",
+        "seconds",
+        width = width
+    );
+    for state in states {
+        let progress = *state.progress.borrow();
+        let status = match (&state.error, state.elapsed) {
+            (Some(_), _) => "failed",
+            (None, Some(_)) => "done",
+            (None, None) => "-",
+        };
+        table.push_str(&format!(
+            "{:<width$}  {:<6}  {:>13}  {:>8.1}\n",
+            state.file,
+            status,
+            format!("{}/{}", progress.translated, progress.total),
+            state.elapsed.map(|e| e.as_secs_f64()).unwrap_or(0.0),
+            width = width
+        ));
+        if let Some(error) = &state.error {
+            table.push_str(&format!("  error: {}\n", error));
+        }
+    }
+    table
+}
+
+/// expand the directories recursively, skip the files written by lottr itself
+fn collect_files(paths: &[String], exts: &[String]) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut stack = paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+    stack.reverse();
+    while let Some(path) = stack.pop() {
+        if path.is_dir() {
+            let mut entries = std::fs::read_dir(&path)?
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .collect::<Vec<_>>();
+            entries.sort();
+            entries.reverse();
+            stack.extend(entries);
+        } else if should_translate(Path::new(&path), exts) {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_collect_files() {
+        let dir = crate::utils::TempDir::new("collect_files");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a.json"), "{}").unwrap();
+        std::fs::write(dir.join("a.json.textures.json"), "{}").unwrap();
+        std::fs::write(dir.join("sub/b.json"), "{}").unwrap();
+        std::fs::write(dir.join("sub/c.txt"), "").unwrap();
+        let files = collect_files(
+            &[dir.path().to_string_lossy().to_string()],
+            &["json".to_string()],
+        )
+        .unwrap();
+        assert_eq!(files, vec![dir.join("a.json"), dir.join("sub/b.json")]);
+    }
+}
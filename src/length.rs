@@ -0,0 +1,159 @@
+use std::{collections::HashSet, sync::Arc};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use crate::{
+    console::{self, Tone},
+    i18n::Msg,
+    outputs::translated_lines,
+    qe::{source, source_capture},
+    review::write_back,
+    segment::WidthMode,
+    t,
+    textures::Textures,
+    translators::{complete, ChatCompletionMessage, ChatCompletionRole},
+    Configuration,
+};
+
+/// the shortening prompt of the model, {{from}}, {{to}} and {{max}} are replaced
+const SHORTEN_PROMPT: &str = "You are shortening the {{to}} translation of a {{from}} line \
+of a game or an application, it must fit in {{max}} {{unit}}. Keep the meaning and the tone, \
+drop the less important words, use the shorter synonyms and abbreviations. \
+Reply only the shortened translation.";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LengthOptions {
+    /// the longest translation of a line, measured by mode
+    pub max_length: usize,
+    /// display, a full-width character counts 2, or chars
+    #[serde(default)]
+    pub mode: WidthMode,
+    /// how many times the model is asked to shorten a line too long, 0 only reports them
+    #[serde(default = "default_retries")]
+    pub retries: usize,
+}
+
+fn default_retries() -> usize {
+    2
+}
+
+/// the length of the translation measured by the mode, without the line breaks
+pub fn measure(text: &str, mode: WidthMode) -> usize {
+    text.chars()
+        .filter(|c| *c != '\n')
+        .map(|c| mode.char_width(c))
+        .sum()
+}
+
+/// ask the model of chatgpt_opt to shorten the translations longer than length_opt.max_length,
+/// the lines still too long are left to the line_width wrapping of the output,
+/// returns the count of the shortened lines
+pub async fn enforce_length(textures: &mut Textures, cfg: &Configuration) -> Result<usize> {
+    let Some(opt) = &cfg.length_opt else {
+        return Ok(0);
+    };
+    let capture = source_capture(cfg)?;
+    let mut translations = translated_lines(cfg, textures)?;
+    let mut long = (0..textures.lines.len())
+        .filter(|&i| textures.lines[i].needs_translation())
+        .filter(|&i| {
+            translations[i]
+                .as_deref()
+                .is_some_and(|t| measure(t, opt.mode) > opt.max_length)
+        })
+        .collect::<Vec<_>>();
+    if long.is_empty() {
+        return Ok(0);
+    }
+    println!(
+        "[Length] {}",
+        t!(Msg::LengthLong, long.len(), opt.max_length)
+    );
+    let mut edited = HashSet::new();
+    if let Some(chatgpt) = &cfg.chatgpt_opt {
+        let limiter = Arc::new(Semaphore::new(chatgpt.max_concurrent.max(1) as usize));
+        let unit = match opt.mode {
+            WidthMode::Display => "columns, a full-width character counts 2",
+            WidthMode::Chars => "characters",
+        };
+        let prompt = SHORTEN_PROMPT
+            .replace("{{from}}", cfg.lang_from.to_name())
+            .replace("{{to}}", cfg.lang_to.to_name())
+            .replace("{{max}}", &opt.max_length.to_string())
+            .replace("{{unit}}", unit);
+        for _ in 0..opt.retries {
+            if long.is_empty() {
+                break;
+            }
+            let mut tasks = JoinSet::new();
+            for (n, &i) in long.iter().enumerate() {
+                let api = chatgpt.api_pool[n % chatgpt.api_pool.len()].clone();
+                let model = chatgpt.model.clone();
+                let limiter = limiter.clone();
+                let translation = translations[i].clone().unwrap_or_default();
+                let messages = vec![
+                    ChatCompletionMessage::new(ChatCompletionRole::System, &prompt),
+                    ChatCompletionMessage::new(
+                        ChatCompletionRole::User,
+                        &format!(
+                            "source: {}\ntranslation: {}",
+                            source(&capture, &textures.lines[i].content),
+                            translation
+                        ),
+                    ),
+                ];
+                tasks.spawn(async move {
+                    let _permit = limiter.acquire().await;
+                    (i, complete(&api, model.as_deref(), messages).await)
+                });
+            }
+            while let Some(result) = tasks.join_next().await {
+                let (i, reply) = result?;
+                let reply = match reply {
+                    Ok(reply) => reply.trim().to_string(),
+                    Err(e) => {
+                        let error = t!(Msg::LengthError, format!("{:?}", e));
+                        eprintln!("{} {}", console::err(Tone::Error, "[Length]"), error);
+                        continue;
+                    }
+                };
+                let current = translations[i].as_deref().unwrap_or_default();
+                // a longer or an empty reply is ignored
+                if !reply.is_empty() && measure(&reply, opt.mode) < measure(current, opt.mode) {
+                    translations[i] = Some(reply);
+                    edited.insert(i);
+                }
+            }
+            long.retain(|&i| {
+                translations[i]
+                    .as_deref()
+                    .is_some_and(|t| measure(t, opt.mode) > opt.max_length)
+            });
+        }
+    }
+    let translator = cfg.translator_selection().primary();
+    let batches = write_back(textures, translator, &translations, &edited);
+    println!(
+        "[Length] {}",
+        t!(Msg::LengthShortened, edited.len(), batches)
+    );
+    if !long.is_empty() {
+        let lines = long.iter().map(|i| (i + 1).to_string()).collect::<Vec<_>>();
+        println!("[Length] {}", t!(Msg::LengthStill, lines.join(", ")));
+    }
+    Ok(edited.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::measure;
+    use crate::segment::WidthMode;
+
+    #[test]
+    fn test_measure() {
+        assert_eq!(measure("はい、OK\n", WidthMode::Display), 8);
+        assert_eq!(measure("はい、OK\n", WidthMode::Chars), 5);
+    }
+}
@@ -1,19 +1,80 @@
-use std::fs;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use anyhow::Result;
-use clap::Parser;
-use inputs::in_put;
-use inputs::TransType;
+use clap::{Parser, Subcommand};
+use config::FormatPreset;
+use consistency::ConsistencyOptions;
+use control::{Control, CtlAction, DEFAULT_CONTROL_ADDR};
+use failed::FailedReport;
+use hooks::HookOptions;
+use inputs::{in_put, input_shards, new_input};
+use inputs::{
+    AinOptions, DictionaryOptions, FilterRegex, KeyValueOptions, ParagraphOptions, SkipOptions,
+    TppOptions, TransType,
+};
 use isolang::Language;
-use outputs::out_put;
+use length::LengthOptions;
+use normalize::{NormalizeOptions, Normalizer};
+use outputs::{
+    fixup, out_put, output_shards, BilingualMode, MergeStrategy, OutputReport, SplitOptions,
+    TranslatorSelection,
+};
+use paths::ArtifactDirs;
+use qe::QeOptions;
+use ruby::RubyMode;
+use segment::{SegmentOptions, WidthMode};
 use serde::{Deserialize, Serialize};
-use translators::{translate, ChatGPTOptions};
+use textures::StateFormat;
+use tokio::sync::Semaphore;
+use translators::{
+    build_glossary, refine, report_clients, translate, BudgetOptions, ChatGPTOptions,
+    ClientMetrics, GeminiOptions, Grouping, PriorityOptions, Progress, PromptOverride,
+    RefineOptions, RetrievalOptions, RoutingOptions, StyleOptions, TokenizerKind, Translator,
+};
 
+mod align;
+mod bench;
+mod check;
+mod config;
+mod consistency;
+mod console;
+mod control;
+mod detect;
+mod error;
+mod failed;
+mod hooks;
+mod i18n;
+mod init;
 mod inputs;
+mod jobs;
+mod length;
+mod normalize;
 mod outputs;
+mod paths;
+mod qe;
+mod review;
+mod ruby;
+mod segment;
+mod selection;
+#[cfg(feature = "selftest")]
+mod selftest;
+mod server;
+mod state_merge;
 mod textures;
 mod translators;
+#[cfg(feature = "tui")]
+mod tui;
 mod utils;
+mod watch;
+
+pub use console::{err as paint_err, init as init_console, Tone};
+pub use error::Error;
+pub use i18n::{set_lang, text as message, Lang, Msg};
+pub use inputs::{Input, TextInput, TppInput};
+pub use translators::{bench_batchizer, count_batches, count_tokens};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RegexDescription {
@@ -32,6 +93,9 @@ pub enum RegexUsage {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Configuration {
     pub file: Option<String>,
+    /// the built-in regexes of a format: mtool, ain, kirikiri or numbered (only the output stages),
+    /// the options written in the configuration override the preset;
+    pub preset: Option<FormatPreset>,
     /// iso 639-3 code, see https://en.wikipedia.org/wiki/List_of_ISO_639-1_codes
     #[serde(rename = "from")]
     pub lang_from: Language,
@@ -40,28 +104,275 @@ pub struct Configuration {
     pub lang_to: Language,
     pub trans_type: TransType,
     /// filter the input lines by regex, only the lines that match the regex will be translated, if
-    /// empty, all lines will be translated
-    pub filter_regexen: Vec<String>,
+    /// empty, all lines will be translated; an entry can capture a group by {regex = '', capture = 1}
+    #[serde(default)]
+    pub filter_regexen: Vec<FilterRegex>,
     /// capture the text by regex, and replace the text by replace_expression;
     pub capture_regex: Option<String>,
-    /// replace the text by replace_expression, must contain flag $trans, $trans will be replaced
-    /// by the translated text, example: [: "$trans"];
+    /// apply capture_regex in the input, every group matched is translated, e.g. both texts of
+    /// `name "X" text "Y"`, and the translation is spliced into the span of the group;
+    #[serde(default)]
+    pub capture_input: bool,
+    /// join consecutive non-empty lines into paragraphs, for text trans_type,
+    /// example: {delimiter = "", max_chars = 300};
+    pub paragraph: Option<ParagraphOptions>,
+    /// split the lines longer than max_chars into sentences, the translation is re-wrapped to
+    /// line_width, example: {max_chars = 120, line_width = 36};
+    pub segment: Option<SegmentOptions>,
+    /// translate the identical lines only once, the translation is shared by all of them;
+    #[serde(default)]
+    pub dedup: bool,
+    /// the ruby markup ([ruby text=...], |base《reading》, <ruby>) is replaced by its base before
+    /// sent, "drop" outputs the translation without the readings, "keep" wraps the bases kept
+    /// in the translation in their markup again;
+    pub ruby: Option<RubyMode>,
+    /// the lines never sent to the translator, they stay untranslated in the output,
+    /// example: {strings = ["……"], regexen = ['^\\w+$'], numeric = true, translated = true,
+    /// detect_language = true};
+    pub skip: Option<SkipOptions>,
+    /// translate the trivial lines without the translator, the numbers, the punctuation and the
+    /// abbreviations like HP, OK are kept, the onomatopoeia lines are sent, written in romaji or
+    /// kept by sound_effects = "translate" | "romaji" | "source", example: {builtin = true,
+    /// sound_effects = "romaji", sound_effect_regexen = ['♡$'],
+    /// entries = {"はい" = "Yes"}, path = "dictionary.toml"};
+    pub dictionary: Option<DictionaryOptions>,
+    /// the terms pass through the translator untranslated, a line of only them is not sent,
+    /// example: ["Pino", "Geppetto"];
+    #[serde(default)]
+    pub protected_terms: Vec<String>,
+    /// replace the text by replace_expression, must contain flag $trans, $trans or ${trans} will be
+    /// replaced by the translated text, $1 or ${name} by a group of capture_regex, example:
+    /// [: "$trans"] or [name "${name}" text "$trans"];
     pub replace_expression: Option<String>,
+    /// the regexen run in order over the whole answer of a batch before its lines are captured,
+    /// e.g. the lines broken by the translator joined back;
+    #[serde(default)]
+    pub response_cleanup: Vec<RegexDescription>,
+    /// the regexen splitting the answer of a batch into its translated lines, the group of every
+    /// match of a capture is a line, e.g. {usage = {capture = 1}, regex = '\(\d+\)\s?(.+)'};
+    #[serde(default)]
+    pub line_capture: Vec<RegexDescription>,
+    /// the regexen run in order over every captured line, a capture keeps the group of its first
+    /// match;
+    #[serde(default)]
+    pub post_replace: Vec<RegexDescription>,
+    /// the stages of an older version: the matches of the first regex are replaced by `\n`, the
+    /// group 1 of the second one is a line, the quotes are removed, used when line_capture is empty;
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub output_regexen: Vec<RegexDescription>,
     pub chatgpt_opt: Option<ChatGPTOptions>,
+    /// send the batches containing the keywords or matching the regexen to a designated api,
+    /// e.g. a local model for the explicit content, the others keep the api pool of chatgpt_opt,
+    /// example: {keywords = ["..."], regexen = [], api = {api_key = "sk-local", api_url = "..."}};
+    pub routing_opt: Option<RoutingOptions>,
+    /// the prompts of the files matching the globs or the line ranges, instead of the prompts of
+    /// the translator, the first override matching a batch is used, example: [{files = ["*menu*"],
+    /// system_prompt = "..."}, {ranges = [[120, 300]], prompt_path = "..."}];
+    #[serde(default)]
+    pub prompt_overrides: Vec<PromptOverride>,
+    /// translate by google gemini instead of chatgpt_opt, the translations are marked Gemini,
+    /// output_translators = ["Gemini"] outputs them;
+    pub gemini_opt: Option<GeminiOptions>,
     pub specify_range: Option<Vec<(usize, usize)>>,
     pub batchizer_opt: BatchizerOptions,
     pub mtool_opt: Option<MToolOptions>,
+    /// the literals translated by the ain trans_type, example: {strings = true};
+    pub ain_opt: Option<AinOptions>,
+    /// the column of the Translator++ project filled by the translator++ trans_type,
+    /// example: {column = 2, overwrite = false};
+    pub tpp_opt: Option<TppOptions>,
+    /// the separator and the side translated by the key-value trans_type,
+    /// example: {separator = "=", source = "value"};
+    pub kv_opt: Option<KeyValueOptions>,
+    /// share the translations with the runs of the other files of the game, even the runs at the
+    /// same time, by a sqlite file, a batch whose lines are all in it is not sent;
+    pub shared_cache: Option<String>,
+    /// the proofreading prompt and the glossary of the refine command;
+    pub refine_opt: Option<RefineOptions>,
+    /// score the translated batches after the translation, the low-score batches are flagged;
+    pub qe_opt: Option<QeOptions>,
+    /// send the most similar lines translated before with every batch, by the embeddings of
+    /// openai or the local ones, example: {provider = "local", top_k = 5, min_similarity = 0.75};
+    pub retrieval_opt: Option<RetrievalOptions>,
+    /// the formality, the pronouns and the honorifics of the translation, rendered into the system
+    /// prompt, the honorifics are validated in the output, example: {formality = "formal",
+    /// first_person = "I", second_person = "you", honorifics = "keep"};
+    pub style: Option<StyleOptions>,
+    /// report the sources translated differently and the glossary violations after the translation,
+    /// example: {harmonize = true, glossary = { "回復薬" = "Potion" }};
+    pub consistency_opt: Option<ConsistencyOptions>,
+    /// ask the model to shorten the translations longer than max_length after the translation,
+    /// e.g. the ui strings overflowing their buttons, example: {max_length = 24, mode = "display"};
+    pub length_opt: Option<LengthOptions>,
+    /// the glossary file built by the glossary command, sent with the batches containing its terms,
+    /// default is file.glossary.toml if it exists;
+    pub glossary_path: Option<PathBuf>,
+    /// rewrite the original file in place instead of writing file.translated_xxx.ext,
+    /// the original file will be backed up as file.bak before the first overwrite, a file replaced
+    /// since the last overwrite is the new original and is backed up again
+    #[serde(default)]
+    pub in_place: bool,
+    /// keep the translated file up to date during the translation, it is rewritten with every save
+    /// of the textures, only the batches translated since the last save are extracted again;
+    #[serde(default)]
+    pub live_output: bool,
+    /// compare the translated file with the original after the output, the bytes not translated
+    /// must be kept and a json file must still parse, a corrupted output does not replace the
+    /// translated file;
+    #[serde(default)]
+    pub verify_output: bool,
+    /// clean up the punctuation width, the ellipses, the quotes, the digits, the number grouping and
+    /// the dates of the translation before output, example: {width = true, ellipsis = true,
+    /// quotes = "corner", digits = "half", grouping = "myriad", dates = "cjk"};
+    pub normalize: Option<NormalizeOptions>,
+    /// output the original text together with the translation, for proofreading;
+    pub bilingual: Option<BilingualMode>,
+    /// write a markdown review report file.review_xxx.md after output, and file.index.html, a
+    /// page of every batch linked to its anchor in the report, neither is written for the shards;
+    #[serde(default)]
+    pub report: bool,
+    /// the translators whose translations are output, in priority order, default is ["ChatGPT"];
+    #[serde(default = "default_output_translators")]
+    pub output_translators: Vec<Translator>,
+    /// how a line translated by several translators is picked, priority or best;
+    #[serde(default)]
+    pub merge: MergeStrategy,
+    /// split a large file into shards of shard_lines lines, translated shard by shard,
+    /// every shard is saved as file.textures.{index}.json;
+    pub shard_lines: Option<usize>,
+    /// write the translated files and the review reports into output_dir instead of beside the file;
+    pub output_dir: Option<PathBuf>,
+    /// keep the textures, the shards and the diagnostics in state_dir instead of beside the file;
+    pub state_dir: Option<PathBuf>,
+    /// how the textures are saved, json, json-gz or messagepack, the compact ones for the large
+    /// files, a state of another format is still read and converted on the next save;
+    #[serde(default)]
+    pub state_format: StateFormat,
+    /// the path template of the translated file, e.g. `{stem}.{to}.{ext}` or `{dir}/translated/{name}`,
+    /// see paths::output_name for the placeholders;
+    pub output_name: Option<String>,
+    /// split the translated file into parts by a chapter regex or a max line count, with an index
+    /// file, example: {chapter_regex = '^(第.+章|Chapter \d+)', max_lines = 2000};
+    pub split: Option<SplitOptions>,
+    /// translate the batches of the lines in the ranges or matching the regexen first, e.g. the
+    /// menus and the system messages, example: {ranges = [[0, 120]], regexen = ['^\[MENU\]']};
+    pub priority: Option<PriorityOptions>,
+    /// write the requests, the latency, the tokens and the errors of every worker at the end of
+    /// the run in the prometheus text format, for the textfile collector of node_exporter;
+    pub metrics_file: Option<PathBuf>,
+    /// pause the run once its tokens or its cost cross a limit, the progress is saved and the
+    /// run is resumed by running it again, example:
+    /// {max_cost_usd = 5.0, prompt_usd_per_million = 2.5, completion_usd_per_million = 10.0};
+    pub budget: Option<BudgetOptions>,
+    /// run a shell command or post a json payload to a webhook on the events of the run,
+    /// on_batch_translated, on_run_complete and on_failure, example:
+    /// [hooks.on_run_complete] webhook = "https://discord.com/api/webhooks/...";
+    pub hooks: Option<HookOptions>,
+    /// limit the concurrent requests across all the jobs, set at runtime by the batch command
+    #[serde(skip)]
+    pub request_limiter: Option<Arc<Semaphore>>,
+    /// pause the workers or limit them by lottr ctl, set at runtime by --control
+    #[serde(skip)]
+    pub control: Option<Arc<Control>>,
+    /// translate only n batches spread across the file and write file.preview.md instead of the
+    /// output, set at runtime by --sample
+    #[serde(skip)]
+    pub sample: Option<usize>,
+    /// translate only the first batch and print its request and its parsed response, set at
+    /// runtime by --preview
+    #[serde(skip)]
+    pub preview: bool,
+    /// translate only the batches of file.failed.json, set at runtime by --retry-failed
+    #[serde(skip)]
+    pub retry_failed: bool,
+    /// print every request and response of the translation, set at runtime by --verbose
+    #[serde(skip)]
+    pub verbose: bool,
+}
+
+fn default_output_translators() -> Vec<Translator> {
+    vec![Translator::ChatGPT]
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+impl Configuration {
+    /// the capture regex used by the input, only when capture_input is enabled
+    pub fn input_capture_regex(&self) -> Option<&str> {
+        self.capture_regex.as_deref().filter(|_| self.capture_input)
+    }
+
+    /// the path of the translated file, file.translated_xxx.ext unless output_name is set
+    pub fn output_path(&self, file: &str, translator: Translator) -> PathBuf {
+        match &self.output_name {
+            Some(template) => paths::output_name(
+                template,
+                file,
+                self.output_dir.as_deref(),
+                [
+                    ("from", self.lang_from.to_639_3()),
+                    ("to", self.lang_to.to_639_3()),
+                    ("translator", &format!("{:?}", translator)),
+                ],
+            ),
+            None => self.dirs().translated(file, translator),
+        }
+    }
+
+    /// the translators of the output
+    pub fn translator_selection(&self) -> TranslatorSelection {
+        TranslatorSelection {
+            translators: self.output_translators.clone(),
+            merge: self.merge,
+            ruby: self.ruby,
+            normalize: self
+                .normalize
+                .clone()
+                .map(|n| Normalizer::new(n, self.lang_to)),
+        }
+    }
+
+    /// the directories of the derived files
+    pub fn dirs(&self) -> ArtifactDirs {
+        ArtifactDirs {
+            output_dir: self.output_dir.clone(),
+            state_dir: self.state_dir.clone(),
+            state_format: self.state_format,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 pub struct MToolOptions {
     pub line_width: Option<usize>,
+    /// how line_width is measured, display: display columns, a full-width character counts 2;
+    /// chars: every character counts 1;
+    #[serde(default)]
+    pub width_mode: WidthMode,
+    /// build the translated file as a json object instead of splicing the translated lines into
+    /// the original, always valid json, the entries not translated keep their value;
+    #[serde(default)]
+    pub json: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BatchizerOptions {
     pub max_tokens: usize,
+    /// cl100k, o200k, p50k or chars (approximated by the characters, for the backends which are
+    /// not OpenAI), chosen by the model of chatgpt_opt if not specified;
+    pub tokenizer: Option<TokenizerKind>,
+    /// the expected completion tokens per prompt token, the batches are capped so the completion
+    /// fits the output limit of the model, default is 1.5;
+    pub expansion_ratio: Option<f32>,
+    /// the count of lines of the previous batch sent again before every batch, as context only,
+    /// they keep the sentences split at the batch boundaries continuous, default is 0;
+    #[serde(default)]
+    pub overlap: usize,
+    /// keep the consecutive lines together in a batch: "off", "first_char" (default) or
+    /// { speaker_tag = "regex" }, a group can extend the batch beyond max_tokens;
+    #[serde(default)]
+    pub grouping: Grouping,
+    /// the hard ceiling of a batch extended by a group, always wins over the grouping,
+    /// default is max_tokens * 2;
+    pub group_max_tokens: Option<usize>,
 }
 
 #[derive(Parser, Debug)]
@@ -71,15 +382,415 @@ pub struct Arguments {
     #[arg(global = true)]
     pub file: Option<String>,
     /// Configuration file, It's Required;
-    #[arg(short, long, default_value = "default.toml")]
+    #[arg(short, long, default_value = "default.toml", global = true)]
     pub config: String,
+    /// the profile of the configuration file, [profiles.X] overrides the configuration;
+    #[arg(short, long, global = true)]
+    pub profile: Option<String>,
     /// just output the result from file.textures.json, without translate;
     #[arg(short = 'j', long = "outputonly", default_value_t = false)]
     pub output_only: bool,
+    /// output again like outputonly, with the output stages, normalize and the other output options
+    /// of this toml file merged over the configuration, the lines changed against the previous
+    /// translated file are printed and written to file.fixup.md;
+    #[arg(long)]
+    pub fixup: Option<String>,
+    /// rewrite the original file in place, the original file will be backed up as file.bak;
+    #[arg(long = "in-place", default_value_t = false)]
+    pub in_place: bool,
+    /// write a markdown review report and the html index of the batches beside the input file
+    /// after output;
+    #[arg(long, default_value_t = false)]
+    pub report: bool,
+    /// rewrite the translated file with every save during the translation, see live_output;
+    #[arg(long = "live-output", default_value_t = false)]
+    pub live_output: bool,
+    /// verify the translated file against the original after the output, see verify_output;
+    #[arg(long = "verify-output", default_value_t = false)]
+    pub verify_output: bool,
+    /// translate only these lines to translate, counted from 1, instead of the failed batches of
+    /// the last run, e.g. 100-250,3000-3100 or 3000- to the end;
+    #[arg(long)]
+    pub range: Option<String>,
+    /// translate only the batches recorded in file.failed.json: the mismatched, the low scored,
+    /// the changed and the re-queued ones;
+    #[arg(long = "retry-failed", default_value_t = false, global = true)]
+    pub retry_failed: bool,
+    /// translate from this line to translate, counted from 1, e.g. a sample run of the first 50
+    /// lines with --to-line 50;
+    #[arg(long = "from-line")]
+    pub from_line: Option<usize>,
+    /// translate to this line to translate, inclusive;
+    #[arg(long = "to-line")]
+    pub to_line: Option<usize>,
+    /// translate only N batches spread across the file, and write a preview report file.preview.md
+    /// with the estimated tokens of the whole file, instead of the output;
+    #[arg(long)]
+    pub sample: Option<usize>,
+    /// ask before the translation, after the summary of the lines selected to translate;
+    #[arg(long, default_value_t = false)]
+    pub confirm: bool,
+    /// translate the first batch, print the messages sent and the parsed response, then ask
+    /// whether to translate the rest;
+    #[arg(long, default_value_t = false)]
+    pub preview: bool,
+    /// serve the control of lottr ctl on this local address during the run, default is
+    /// 127.0.0.1:7373;
+    #[arg(long, num_args = 0..=1, default_missing_value = DEFAULT_CONTROL_ADDR)]
+    pub control: Option<String>,
+    /// print the summary of the run as json, for the pipelines wrapping lottr;
+    #[arg(long = "json-summary", default_value_t = false, global = true)]
+    pub json_summary: bool,
+    /// print every request and response of the translation;
+    #[arg(short, long, default_value_t = false, global = true)]
+    pub verbose: bool,
+    /// the language of the messages, en, zh or ja, default is the language of the locale;
+    #[arg(long, value_enum, global = true)]
+    pub lang: Option<Lang>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// export the lines and translations to a csv file (id, source, translation) for reviewing;
+    ExportReview {
+        /// the csv file, default is file.review.csv;
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// merge the edited translations in the review csv back, then output;
+    ImportReview {
+        /// the csv file exported by export-review, default is file.review.csv;
+        csv: Option<String>,
+    },
+    /// pre-fill the untranslated lines with a previous work, e.g. continuing a partial
+    /// translation, the next run only sends the lines left untranslated;
+    Import {
+        /// a tmx, a csv of source,translation or the review csv, a Translator++ project, or a
+        /// translated file of the same format and lines;
+        path: String,
+    },
+    /// export the source and translation pairs as a tmx translation memory for the cat tools;
+    ExportTmx {
+        /// the tmx file, default is file.tmx;
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// proofread the translated lines with the source by a second pass, then output,
+    /// output_translators = ["ChatGPTRefined", "ChatGPT"] selects the refined lines;
+    Refine,
+    /// report the sources translated differently and the glossary violations, then output,
+    /// with consistency_opt.harmonize the variants are rewritten to the most frequent one;
+    Consistency,
+    /// extract the probable proper nouns, propose their renderings by the model once, and write
+    /// them to the glossary file to be edited before the translation;
+    Glossary {
+        /// the glossary file, default is glossary_path or file.glossary.toml;
+        #[arg(short, long)]
+        output: Option<String>,
+        /// the terms found fewer times are not proposed;
+        #[arg(long, default_value_t = 3)]
+        min_count: usize,
+    },
+    /// merge the translations of two states of the same file, e.g. the ranges translated by
+    /// two people or by different backends, into one state, even of two revisions of the file;
+    Merge {
+        /// the state file, file.textures.json;
+        state: String,
+        /// the other state file, merge the result again for more states;
+        other: String,
+        /// the merged state, default is the first state;
+        #[arg(short, long)]
+        output: Option<String>,
+        /// the state kept when several translated the same lines with the same translator;
+        #[arg(long, value_enum, default_value_t = state_merge::MergePreference::Priority)]
+        prefer: state_merge::MergePreference,
+    },
+    /// review the failed batches in a terminal ui, fix them by hand or re-queue them;
+    #[cfg(feature = "tui")]
+    Tui,
+    /// watch the directory, translate the new or modified files;
+    Watch {
+        /// the directory to watch;
+        dir: String,
+        /// only the files with these extensions will be translated, e.g. -e json -e txt;
+        #[arg(short, long)]
+        ext: Vec<String>,
+    },
+    /// translate the files, or the files in the directories, several files at the same time;
+    Batch {
+        /// the files or directories;
+        #[arg(required = true)]
+        paths: Vec<String>,
+        /// how many files are translated at the same time;
+        #[arg(short, long, default_value_t = 2)]
+        jobs: usize,
+        /// limit the concurrent requests across all the files, default is chatgpt_opt.max_concurrent;
+        #[arg(short, long)]
+        max_requests: Option<usize>,
+        /// only the files with these extensions will be translated, e.g. -e json -e txt;
+        #[arg(short, long)]
+        ext: Vec<String>,
+    },
+    /// write a starter configuration to the config path, for a preset of the known formats,
+    /// the options not given are asked in the terminal;
+    Init {
+        /// the format of the files to translate;
+        #[arg(long, value_enum)]
+        preset: Option<init::Preset>,
+        /// the source language, iso 639-3 code;
+        #[arg(long)]
+        from: Option<String>,
+        /// the target language, iso 639-3 code;
+        #[arg(long)]
+        to: Option<String>,
+        /// overwrite the existing configuration;
+        #[arg(long, default_value_t = false)]
+        force: bool,
+    },
+    /// inspect a game folder or a file, suggest the preset of the files found, with --write the
+    /// starter configuration of the first one is written to the config path;
+    Detect {
+        /// the game folder or the file;
+        path: String,
+        /// write the starter configuration like init;
+        #[arg(long, default_value_t = false)]
+        write: bool,
+        /// the source language, iso 639-3 code;
+        #[arg(long)]
+        from: Option<String>,
+        /// the target language, iso 639-3 code;
+        #[arg(long)]
+        to: Option<String>,
+        /// overwrite the existing configuration;
+        #[arg(long, default_value_t = false)]
+        force: bool,
+    },
+    /// validate the configuration: the regexes, the required options, the prompt file,
+    /// and send a minimal request with every api of the api pool;
+    CheckConfig {
+        /// do not send any request;
+        #[arg(long, default_value_t = false)]
+        offline: bool,
+    },
+    /// measure the input parsing, the tokenization and the batching of the file, without any
+    /// request, to compare the configurations or the builds on a large file;
+    Bench {
+        /// the times every stage is run, the fastest is reported;
+        #[arg(short, long, default_value_t = 3)]
+        rounds: usize,
+    },
+    /// pause, resume or throttle a run started with --control, or print its status;
+    Ctl {
+        #[command(subcommand)]
+        action: CtlAction,
+        /// the address of --control;
+        #[arg(short, long, default_value = DEFAULT_CONTROL_ADDR)]
+        addr: String,
+    },
+    /// serve a http api for translating: POST /translate, GET /progress/:job;
+    Serve {
+        /// the address to listen;
+        #[arg(short, long, default_value = "127.0.0.1:8080")]
+        addr: String,
+    },
+    /// translate the sample files of every trans_type by a mock translator and compare the output
+    /// with the golden files, to verify the build;
+    #[cfg(feature = "selftest")]
+    Selftest,
 }
 
-pub async fn start(args: Arguments) -> Result<()> {
-    let mut cfg = { toml::from_str::<Configuration>(&fs::read_to_string(args.config)?)? };
+/// the result of a run, printed as json by --json-summary
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RunSummary {
+    /// the batches translated in this run
+    pub batches: usize,
+    /// the batches failed to extract in the output, recorded in file.failed.json
+    pub failed_batches: usize,
+    /// the lines translated in this run
+    pub lines: usize,
+    /// the lines written translated, and the lines left untranslated in the output
+    pub written_lines: usize,
+    pub skipped_lines: usize,
+    /// the batches scored below qe_opt.min_score
+    pub low_score_batches: usize,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub duration_secs: f64,
+    /// the requests, the latency, the tokens and the errors of every worker
+    pub clients: Vec<ClientMetrics>,
+    /// the run paused over the budget, it is resumed by running it again
+    pub paused: bool,
+}
+
+impl RunSummary {
+    fn add(&mut self, other: &RunSummary) {
+        self.batches += other.batches;
+        self.lines += other.lines;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.paused |= other.paused;
+        for client in &other.clients {
+            match self
+                .clients
+                .iter_mut()
+                .find(|c| c.worker == client.worker && c.api == client.api)
+            {
+                Some(c) => c.add(client),
+                None => self.clients.push(client.clone()),
+            }
+        }
+    }
+
+    fn with_output(mut self, report: OutputReport) -> Self {
+        self.failed_batches = report.failed_batches;
+        self.written_lines = report.written;
+        self.skipped_lines = report.skipped;
+        self.low_score_batches = report.low_score_batches;
+        self
+    }
+}
+
+/// the exit code of a run: 0 ok, 2 partial with diagnostics or paused over the budget, 3 bad
+/// configuration or input, 1 other errors
+pub fn exit_code(result: &Result<RunSummary>) -> i32 {
+    match result {
+        Ok(summary) if summary.failed_batches > 0 || summary.paused => 2,
+        Ok(_) => 0,
+        Err(e) if e.is::<Error>() => 3,
+        Err(_) => 1,
+    }
+}
+
+pub async fn start(args: Arguments) -> Result<RunSummary> {
+    let started = std::time::Instant::now();
+    set_lang(args.lang);
+    let mut summary = run(args).await?;
+    summary.duration_secs = started.elapsed().as_secs_f64();
+    Ok(summary)
+}
+
+async fn run(args: Arguments) -> Result<RunSummary> {
+    if let Some(Command::Init {
+        preset,
+        from,
+        to,
+        force,
+    }) = args.command
+    {
+        let options = init::InitOptions {
+            preset,
+            from,
+            to,
+            file: args.file,
+            force,
+        };
+        init::init(&args.config, options)?;
+        return Ok(RunSummary::default());
+    }
+    if let Some(Command::Detect {
+        path,
+        write,
+        from,
+        to,
+        force,
+    }) = args.command
+    {
+        let detections = detect::detect(Path::new(&path))?;
+        detect::print_detections(&detections);
+        if write {
+            let Some((preset, file)) = detections
+                .iter()
+                .find_map(|d| Some((d.engine.preset()?, &d.files[0])))
+            else {
+                return Err(anyhow::anyhow!("no format to write a configuration for"));
+            };
+            let options = init::InitOptions {
+                preset: Some(preset),
+                from,
+                to,
+                file: Some(file.to_string_lossy().to_string()),
+                force,
+            };
+            init::init(&args.config, options)?;
+        }
+        return Ok(RunSummary::default());
+    }
+    if let Some(Command::Merge {
+        state,
+        other,
+        output,
+        prefer,
+    }) = args.command
+    {
+        state_merge::merge_states(&[state, other], output, prefer)?;
+        return Ok(RunSummary::default());
+    }
+    #[cfg(feature = "selftest")]
+    if let Some(Command::Selftest) = args.command {
+        selftest::selftest()?;
+        return Ok(RunSummary::default());
+    }
+    if let Some(Command::Ctl { action, addr }) = &args.command {
+        control::ctl(action, addr).await?;
+        return Ok(RunSummary::default());
+    }
+    let cfg = config::load_config(&args.config, args.profile.as_deref(), args.fixup.as_deref())
+        .map_err(|e| Error::Config(format!("{:#}", e)))?;
+    // the files of batch and watch notify their failures one by one
+    let per_file = matches!(
+        args.command,
+        Some(Command::Batch { .. } | Command::Watch { .. })
+    );
+    let hooks = cfg.hooks.clone().filter(|_| !per_file);
+    let file = args.file.clone().or_else(|| cfg.file.clone());
+    let result = run_config(args, cfg).await;
+    if let (Some(hooks), Err(e)) = (hooks, &result) {
+        hooks.failure(file.as_deref().unwrap_or_default(), e).await;
+    }
+    result
+}
+
+async fn run_config(args: Arguments, mut cfg: Configuration) -> Result<RunSummary> {
+    cfg.in_place = cfg.in_place || args.in_place;
+    cfg.report = cfg.report || args.report;
+    cfg.live_output = cfg.live_output || args.live_output;
+    cfg.verify_output = cfg.verify_output || args.verify_output;
+    cfg.verbose = args.verbose;
+    cfg.retry_failed = args.retry_failed;
+    cfg.dirs().create()?;
+
+    let range = cli_range(&args)?;
+    if range.is_some() && args.retry_failed {
+        return Err(
+            Error::Config("--retry-failed is not supported with --range".to_string()).into(),
+        );
+    }
+
+    if let Some(addr) = &args.control {
+        let control = Arc::new(Control::default());
+        control::serve_control(control.clone(), addr).await?;
+        cfg.control = Some(control);
+    }
+
+    let done = match &args.command {
+        Some(Command::Watch { dir, ext }) => Some(watch::watch(&cfg, dir, ext).await),
+        Some(Command::Serve { addr }) => Some(server::serve(&cfg, addr).await),
+        Some(Command::CheckConfig { offline }) => Some(check::check_config(&cfg, !offline).await),
+        Some(Command::Batch {
+            paths,
+            jobs,
+            max_requests,
+            ext,
+        }) => Some(jobs::run_jobs(&cfg, paths, *jobs, *max_requests, ext).await),
+        _ => None,
+    };
+    if let Some(done) = done {
+        return done.map(|_| RunSummary::default());
+    }
+    // only the translation of a single file is sampled
+    cfg.sample = args.sample.filter(|n| *n > 0);
 
     let file = match args.file {
         Some(v) => v,
@@ -91,31 +802,330 @@ pub async fn start(args: Arguments) -> Result<()> {
         },
     };
 
-    cfg.specify_range = {
-        match fs::OpenOptions::new()
-            .read(true)
-            .open(format!("{}.dignostic_failed_range.json", file))
-        {
-            Ok(v) => match serde_json::from_reader::<_, Vec<(usize, usize)>>(v) {
-                Ok(v) => {
-                    println!("load specify range");
-                    Some(v)
-                }
-                _ => None,
-            },
-            _ => None,
+    if let Some(Command::Bench { rounds }) = args.command {
+        bench::bench(&cfg, &file, rounds)?;
+        return Ok(RunSummary::default());
+    }
+
+    if let Some(shard_lines) = cfg.shard_lines {
+        if args.command.is_some() || cfg.sample.is_some() || args.preview {
+            return Err(anyhow::anyhow!(
+                "The command is not supported with shard_lines"
+            ));
         }
-    };
-    // input
-    let textures = in_put(cfg.trans_type, &file, cfg.filter_regexen.clone())?;
+        if args.fixup.is_some() {
+            let report = fixup(&cfg, &file, || {
+                let index = input_shards(&new_input(&cfg)?, &file, shard_lines)?;
+                output_shards(&cfg, &file, index.shards)
+            })?;
+            return Ok(RunSummary::default().with_output(report));
+        }
+        return run_shards(&cfg, &file, shard_lines, args.output_only, range).await;
+    }
+
+    // input, before the failed range, a changed file adds its changed lines to it
+    let textures = in_put(&cfg, &file)?;
+    cfg.specify_range = range.or_else(|| load_specify_range(&cfg, &file));
+    cfg.specify_range = cfg
+        .specify_range
+        .map(|ranges| shard_ranges(&ranges, 0, textures.lines.len()));
+
+    match args.command {
+        Some(Command::ExportReview { output }) => {
+            review::export_review(&cfg, &textures, output)?;
+            return Ok(RunSummary::default());
+        }
+        Some(Command::ExportTmx { output }) => {
+            review::export_tmx(&cfg, &textures, output)?;
+            return Ok(RunSummary::default());
+        }
+        Some(Command::Import { path }) => {
+            let mut textures = textures;
+            review::import_translations(&cfg, &mut textures, &path)?;
+            textures.save()?;
+            return Ok(RunSummary::default());
+        }
+        Some(Command::ImportReview { csv }) => {
+            let mut textures = textures;
+            review::import_review(&cfg, &mut textures, csv)?;
+            textures.save()?;
+            let report = out_put(&cfg, &textures)?;
+            return Ok(RunSummary::default().with_output(report));
+        }
+        Some(Command::Refine) => {
+            if !cfg.output_translators.contains(&Translator::ChatGPTRefined) {
+                println!("{}", t!(Msg::AddRefined));
+            }
+            let mut textures_mut = textures.clone();
+            let summary = refine(textures, &mut textures_mut, &cfg, None).await?;
+            report_clients(&summary.clients, cfg.metrics_file.as_deref())?;
+            let report = out_put(&cfg, &textures_mut)?;
+            return Ok(summary.with_output(report));
+        }
+        Some(Command::Consistency) => {
+            let mut textures = textures;
+            if cfg.consistency_opt.is_none() {
+                cfg.consistency_opt = Some(ConsistencyOptions::default());
+            }
+            if consistency::check_consistency(&cfg, &mut textures)? > 0 {
+                textures.save()?;
+            }
+            let report = out_put(&cfg, &textures)?;
+            return Ok(RunSummary::default().with_output(report));
+        }
+        Some(Command::Glossary { output, min_count }) => {
+            build_glossary(&cfg, &textures, output, min_count).await?;
+            return Ok(RunSummary::default());
+        }
+        #[cfg(feature = "tui")]
+        Some(Command::Tui) => {
+            tui::review(&cfg, textures)?;
+            return Ok(RunSummary::default());
+        }
+        #[cfg(feature = "selftest")]
+        Some(Command::Selftest) => {}
+        Some(
+            Command::Watch { .. }
+            | Command::Serve { .. }
+            | Command::Batch { .. }
+            | Command::CheckConfig { .. }
+            | Command::Init { .. }
+            | Command::Detect { .. }
+            | Command::Merge { .. }
+            | Command::Ctl { .. }
+            | Command::Bench { .. },
+        )
+        | None => {}
+    }
+
+    if args.fixup.is_some() {
+        let report = fixup(&cfg, &file, || out_put(&cfg, &textures))?;
+        return Ok(RunSummary::default().with_output(report));
+    }
 
     if args.output_only {
-        return out_put(&cfg, &textures);
+        let report = out_put(&cfg, &textures)?;
+        return Ok(RunSummary::default().with_output(report));
+    }
+
+    if !selection::review_selection(&cfg, &textures, args.confirm)? {
+        println!("{}", t!(Msg::Cancelled));
+        return Ok(RunSummary::default());
+    }
+
+    if cfg.sample.is_some() {
+        let mut textures_mut = textures.clone();
+        return translate(textures, &mut textures_mut, &cfg, None).await;
+    }
+
+    if args.preview {
+        cfg.preview = true;
+        let mut textures_mut = textures.clone();
+        let summary = translate(textures, &mut textures_mut, &cfg, None).await?;
+        if !selection::ask(&t!(Msg::TranslateRest))? {
+            return Ok(summary);
+        }
+        cfg.preview = false;
+        // the previewed batch is kept, the rest goes on from the resume index
+        return translate_and_output(&cfg, textures_mut, None).await;
     }
 
+    translate_and_output(&cfg, textures, None).await
+}
+
+/// translate the file and output, the state is kept in file.textures.json
+pub async fn run_file(
+    cfg: &Configuration,
+    file: &str,
+    progress: Option<&tokio::sync::watch::Sender<Progress>>,
+) -> Result<RunSummary> {
+    let mut cfg = cfg.clone();
+    let result = match in_put(&cfg, file) {
+        Ok(textures) => {
+            cfg.specify_range = load_specify_range(&cfg, file);
+            translate_and_output(&cfg, textures, progress).await
+        }
+        Err(e) => Err(e),
+    };
+    if let (Some(hooks), Err(e)) = (&cfg.hooks, &result) {
+        hooks.failure(file, e).await;
+    }
+    result
+}
+
+async fn translate_and_output(
+    cfg: &Configuration,
+    textures: textures::Textures,
+    progress: Option<&tokio::sync::watch::Sender<Progress>>,
+) -> Result<RunSummary> {
     let mut textures_mut = textures.clone();
-    translate(textures, &mut textures_mut, &cfg).await?;
-    out_put(&cfg, &textures_mut)
+    let summary = translate(textures, &mut textures_mut, cfg, progress).await?;
+    report_clients(&summary.clients, cfg.metrics_file.as_deref())?;
+    consume_failed(cfg, &textures_mut.name, &summary)?;
+    // the requests after the translation wait for the resumed run
+    if !summary.paused {
+        let scored = qe::score_batches(&mut textures_mut, cfg).await?;
+        let shortened = length::enforce_length(&mut textures_mut, cfg).await?;
+        let harmonized = consistency::check_consistency(cfg, &mut textures_mut)?;
+        if scored + shortened + harmonized > 0 {
+            textures_mut.save()?;
+        }
+    }
+    let report = out_put(cfg, &textures_mut)?;
+    let summary = summary.with_output(report);
+    if let Some(hooks) = &cfg.hooks {
+        hooks.run_complete(&textures_mut.name, &summary).await;
+    }
+    Ok(summary)
+}
+
+/// translate a large file shard by shard, only one shard is held in memory at a time
+async fn run_shards(
+    cfg: &Configuration,
+    file: &str,
+    shard_lines: usize,
+    output_only: bool,
+    range: Option<Vec<(usize, usize)>>,
+) -> Result<RunSummary> {
+    let index = input_shards(&new_input(cfg)?, file, shard_lines)?;
+    let mut summary = RunSummary::default();
+    if !output_only {
+        let specify_range = range.or_else(|| load_specify_range(cfg, file));
+        for i in 0..index.shards {
+            let textures = textures::Textures::load_shard(file, i, &cfg.dirs())?;
+            let mut cfg = cfg.clone();
+            cfg.specify_range = specify_range
+                .as_ref()
+                .map(|ranges| shard_ranges(ranges, textures.offset(), textures.lines.len()));
+            if cfg.specify_range.as_ref().is_some_and(|r| r.is_empty()) {
+                continue;
+            }
+            println!("{}", t!(Msg::TranslateShard, i + 1, index.shards));
+            // the shards share the budget of the run
+            cfg.budget = cfg
+                .budget
+                .map(|b| b.remaining(summary.prompt_tokens, summary.completion_tokens));
+            let mut textures_mut = textures.clone();
+            summary.add(&translate(textures, &mut textures_mut, &cfg, None).await?);
+            if summary.paused {
+                break;
+            }
+            let scored = qe::score_batches(&mut textures_mut, &cfg).await?;
+            if scored + length::enforce_length(&mut textures_mut, &cfg).await? > 0 {
+                textures_mut.save()?;
+            }
+        }
+        report_clients(&summary.clients, cfg.metrics_file.as_deref())?;
+        consume_failed(cfg, file, &summary)?;
+    }
+    if cfg.consistency_opt.is_some() {
+        println!("{}", t!(Msg::ShardsNoConsistency));
+    }
+    let report = output_shards(cfg, file, index.shards)?;
+    let summary = summary.with_output(report);
+    if let Some(hooks) = cfg.hooks.as_ref().filter(|_| !output_only) {
+        hooks.run_complete(file, &summary).await;
+    }
+    Ok(summary)
+}
+
+/// map the ranges of the whole file into the ranges of the shard
+fn shard_ranges(ranges: &[(usize, usize)], offset: usize, len: usize) -> Vec<(usize, usize)> {
+    if len == 0 {
+        return vec![];
+    }
+    ranges
+        .iter()
+        .filter_map(|(start, end)| {
+            let start = (*start).max(offset);
+            let end = (*end).min(offset + len - 1);
+            (start <= end).then(|| (start - offset, end - offset))
+        })
+        .collect()
+}
+
+/// the ranges of --range, --from-line and --to-line, counted from 0 and inclusive like
+/// specify_range, the ends past the last line are clamped after the input
+fn cli_range(args: &Arguments) -> Result<Option<Vec<(usize, usize)>>, Error> {
+    let mut ranges = match &args.range {
+        Some(expression) => parse_range(expression)?,
+        None => vec![],
+    };
+    if args.from_line.is_some() || args.to_line.is_some() {
+        let from = args.from_line.unwrap_or(1);
+        let to = args.to_line.unwrap_or(usize::MAX);
+        if from == 0 || from > to {
+            return Err(Error::Config(format!(
+                "--from-line {} --to-line {} is not a valid range",
+                from, to
+            )));
+        }
+        ranges.push((from - 1, to - 1));
+    }
+    Ok((!ranges.is_empty()).then_some(ranges))
+}
+
+/// parse `100-250,3000-3100`, `42` or `3000-`, the lines are counted from 1
+fn parse_range(expression: &str) -> Result<Vec<(usize, usize)>, Error> {
+    let invalid = |part: &str| {
+        Error::Config(format!(
+            "--range {}: {} is not a valid range",
+            expression, part
+        ))
+    };
+    let mut ranges = expression
+        .split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let (start, end) = part.split_once('-').unwrap_or((part, part));
+            let start = start.trim().parse::<usize>().map_err(|_| invalid(part))?;
+            let end = match end.trim() {
+                "" => usize::MAX,
+                end => end.parse::<usize>().map_err(|_| invalid(part))?,
+            };
+            if start == 0 || start > end {
+                return Err(invalid(part));
+            }
+            Ok((start - 1, end - 1))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    if ranges.is_empty() {
+        return Err(invalid(expression));
+    }
+    ranges.sort();
+    Ok(ranges)
+}
+
+/// the ranges of file.failed.json with --retry-failed, otherwise they are only reported, a file
+/// without failed batches is translated from its resume index
+fn load_specify_range(cfg: &Configuration, file: &str) -> Option<Vec<(usize, usize)>> {
+    let report = FailedReport::load(&cfg.dirs(), file);
+    if report.is_empty() {
+        return None;
+    }
+    if !cfg.retry_failed {
+        let failed = cfg.dirs().failed(file);
+        let hint = t!(
+            Msg::FailedBatchesHint,
+            report.batches.len(),
+            failed.display()
+        );
+        println!("{}", hint);
+        return None;
+    }
+    println!("{}", t!(Msg::RetryFailed, report.batches.len()));
+    Some(report.ranges())
+}
+
+/// the batches of file.failed.json are translated, those failed again are recorded by the output
+fn consume_failed(cfg: &Configuration, file: &str, summary: &RunSummary) -> Result<()> {
+    if !cfg.retry_failed || summary.paused {
+        return Ok(());
+    }
+    let mut report = FailedReport::load(&cfg.dirs(), file);
+    report.consume();
+    report.save(&cfg.dirs(), file)
 }
 
 pub struct Timer {
@@ -145,19 +1155,89 @@ impl Timer {
 
 #[cfg(test)]
 mod test {
-    use crate::{Configuration, MToolOptions};
+    use clap::Parser;
+
+    use crate::{Arguments, Command, Configuration, MToolOptions, WidthMode};
+
+    #[test]
+    fn test_exit_code() {
+        let ok = crate::RunSummary::default();
+        assert_eq!(crate::exit_code(&Ok(ok.clone())), 0);
+        let partial = crate::RunSummary {
+            failed_batches: 1,
+            ..ok
+        };
+        assert_eq!(crate::exit_code(&Ok(partial)), 2);
+        let config = crate::Error::Config("max_tokens must be greater than 0".to_string());
+        assert_eq!(crate::exit_code(&Err(config.into())), 3);
+        assert_eq!(crate::exit_code(&Err(anyhow::anyhow!("timeout"))), 1);
+        let args = Arguments::parse_from(["lottr", "check-config", "--json-summary"]);
+        assert!(args.json_summary);
+    }
+
+    #[test]
+    fn test_shard_ranges() {
+        let ranges = vec![(0, 4), (8, 12), (30, 40)];
+        assert_eq!(crate::shard_ranges(&ranges, 0, 10), vec![(0, 4), (8, 9)]);
+        assert_eq!(crate::shard_ranges(&ranges, 10, 10), vec![(0, 2)]);
+        assert_eq!(crate::shard_ranges(&ranges, 20, 10), vec![]);
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(
+            crate::parse_range("3000-3100, 100-250").unwrap(),
+            vec![(99, 249), (2999, 3099)]
+        );
+        assert_eq!(
+            crate::parse_range("42,50-").unwrap(),
+            vec![(41, 41), (49, usize::MAX - 1)]
+        );
+        assert!(crate::parse_range("0-10").is_err());
+        assert!(crate::parse_range("20-10").is_err());
+        assert!(crate::parse_range("a-b").is_err());
+        let args = Arguments::parse_from(["lottr", "a.txt", "--to-line", "50"]);
+        assert_eq!(crate::cli_range(&args).unwrap(), Some(vec![(0, 49)]));
+    }
+
+    #[test]
+    fn arguments_parse() {
+        let args = Arguments::try_parse_from(["lottr", "a.txt", "-c", "b.toml"]).unwrap();
+        assert_eq!(args.file, Some("a.txt".to_string()));
+        assert!(args.command.is_none());
+        let args =
+            Arguments::try_parse_from(["lottr", "import-review", "a.csv", "-c", "b.toml"]).unwrap();
+        assert!(
+            matches!(args.command, Some(Command::ImportReview { csv }) if csv.as_deref() == Some("a.csv"))
+        );
+        let args = Arguments::try_parse_from([
+            "lottr",
+            "merge",
+            "a.textures.json",
+            "b.textures.json",
+            "--prefer",
+            "recent",
+        ])
+        .unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Merge { state, other, prefer, .. })
+                if state == "a.textures.json" && other == "b.textures.json" && prefer == crate::state_merge::MergePreference::Recent
+        ));
+    }
 
     #[test]
     fn options_deserialize() {
         let str = include_str!("../assets/options_mtool.toml");
         let config: Configuration = toml::from_str(str).unwrap();
-        config.output_regexen.iter().for_each(|x| {
-            println!("{:?}", x);
-        });
+        assert_eq!(config.line_capture.len(), 1);
+        assert!(config.output_regexen.is_empty());
         assert_eq!(
             config.mtool_opt,
             Some(MToolOptions {
-                line_width: Some(36)
+                line_width: Some(36),
+                width_mode: WidthMode::Display,
+                json: false,
             })
         );
         assert_eq!(config.lang_to.to_name(), "Chinese");
@@ -1,5 +1,5 @@
 use clap::Parser;
-use lottr::{start, Arguments};
+use lottr::{exit_code, init_console, message, paint_err, start, Arguments, Msg, Tone};
 
 #[tokio::main]
 async fn main() {
@@ -8,6 +8,17 @@ async fn main() {
     //     input: Some("./assets/haha.txt".to_string()),
     //     template: "./assets/options_01.toml".to_string(),
     // };
+    init_console();
     let args = Arguments::parse();
-    start(args).await.unwrap();
+    let json_summary = args.json_summary;
+    let result = start(args).await;
+    match &result {
+        Ok(summary) if json_summary => match serde_json::to_string(summary) {
+            Ok(json) => println!("{}", json),
+            Err(e) => eprintln!("{} {}", paint_err(Tone::Error, message(Msg::Error)), e),
+        },
+        Ok(_) => {}
+        Err(e) => eprintln!("{} {:#}", paint_err(Tone::Error, message(Msg::Error)), e),
+    }
+    std::process::exit(exit_code(&result));
 }
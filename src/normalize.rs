@@ -0,0 +1,494 @@
+use isolang::Language;
+use regex::{Captures, Match, Regex};
+use serde::{Deserialize, Serialize};
+
+/// the half-width punctuation and its full-width form
+const PUNCTUATION: [(char, char); 7] = [
+    (',', '，'),
+    ('.', '。'),
+    ('!', '！'),
+    ('?', '？'),
+    (':', '：'),
+    (';', '；'),
+    (')', '）'),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum QuoteStyle {
+    /// 「」 and 『』
+    #[serde(rename = "corner")]
+    Corner,
+    /// “” and ‘’
+    #[serde(rename = "curly")]
+    Curly,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DigitWidth {
+    /// ０１２
+    #[serde(rename = "full")]
+    Full,
+    /// 012
+    #[serde(rename = "half")]
+    Half,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum NumberGrouping {
+    /// 12,345,678, the 万 and 亿 of the amounts are multiplied out
+    #[serde(rename = "western")]
+    Western,
+    /// the round amounts in 万 and 亿 (億 for japanese), e.g. 1,200,000 as 120万
+    #[serde(rename = "myriad")]
+    Myriad,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DateStyle {
+    /// 2024-03-05
+    #[serde(rename = "iso")]
+    Iso,
+    /// 2024年3月5日
+    #[serde(rename = "cjk")]
+    Cjk,
+}
+
+/// the cleanup of the translated text, applied before the output is formatted
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NormalizeOptions {
+    /// the punctuation is full-width for a chinese or japanese target, half-width for the others,
+    /// the full-width letters and digits are always half-width
+    #[serde(default = "default_true")]
+    pub width: bool,
+    /// collapse the repeated ellipses into …… for a chinese or japanese target, ... for the others
+    #[serde(default = "default_true")]
+    pub ellipsis: bool,
+    /// rewrite the quotes, the straight quotes are paired
+    pub quotes: Option<QuoteStyle>,
+    /// the width of the digits, after the width of the punctuation
+    #[serde(default)]
+    pub digits: Option<DigitWidth>,
+    /// the grouping of the numbers of five or more digits
+    #[serde(default)]
+    pub grouping: Option<NumberGrouping>,
+    /// rewrite the dates of the year, the month and the day
+    #[serde(default)]
+    pub dates: Option<DateStyle>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// the normalization of the target language
+#[derive(Debug, Clone)]
+pub struct Normalizer {
+    options: NormalizeOptions,
+    cjk: bool,
+    /// 亿, or 億 for japanese
+    hundred_million: &'static str,
+    numbers: NumberRegexen,
+}
+
+impl PartialEq for Normalizer {
+    fn eq(&self, other: &Self) -> bool {
+        self.options == other.options
+            && self.cjk == other.cjk
+            && self.hundred_million == other.hundred_million
+    }
+}
+
+#[derive(Debug, Clone)]
+struct NumberRegexen {
+    /// 2024-03-05, 2024/3/5 or 2024.3.5
+    date: Regex,
+    /// 2024年3月5日
+    cjk_date: Regex,
+    /// 1.5万, 3亿 or 1億2000万
+    myriad: Regex,
+    /// 12345678 or 12,345,678
+    number: Regex,
+}
+
+impl Default for NumberRegexen {
+    fn default() -> Self {
+        Self {
+            date: Regex::new(r"(\d{4})[-/.](\d{1,2})[-/.](\d{1,2})").unwrap(),
+            cjk_date: Regex::new(r"(\d{4})年(\d{1,2})月(\d{1,2})日").unwrap(),
+            myriad: Regex::new(r"(\d+(?:\.\d+)?)(?:亿|億)(?:(\d+)[万萬])?|(\d+(?:\.\d+)?)[万萬]")
+                .unwrap(),
+            number: Regex::new(r"\d{1,3}(?:,\d{3})+|\d{5,}").unwrap(),
+        }
+    }
+}
+
+impl Normalizer {
+    pub fn new(options: NormalizeOptions, lang_to: Language) -> Self {
+        Self {
+            options,
+            cjk: matches!(lang_to, Language::Zho | Language::Jpn),
+            hundred_million: if lang_to == Language::Jpn {
+                "億"
+            } else {
+                "亿"
+            },
+            numbers: NumberRegexen::default(),
+        }
+    }
+
+    pub fn normalize(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        // before the width, the dots of an ellipsis are not sentence ends
+        if self.options.ellipsis {
+            text = collapse_ellipses(&text, if self.cjk { "……" } else { "..." });
+        }
+        if self.options.width {
+            text = if self.cjk {
+                to_full_width(&text)
+            } else {
+                to_half_width(&text)
+            };
+        }
+        if let Some(style) = self.options.dates {
+            text = self.rewrite_dates(&text, style);
+        }
+        if let Some(grouping) = self.options.grouping {
+            text = self.group_numbers(&text, grouping);
+        }
+        if let Some(width) = self.options.digits {
+            text = text
+                .chars()
+                .map(|c| match width {
+                    DigitWidth::Full if c.is_ascii_digit() => {
+                        char::from_u32(c as u32 + 0xFEE0).unwrap_or(c)
+                    }
+                    DigitWidth::Half => half_alphanumeric(c),
+                    _ => c,
+                })
+                .collect();
+        }
+        if let Some(style) = self.options.quotes {
+            text = rewrite_quotes(&text, style);
+        }
+        text
+    }
+
+    fn rewrite_dates(&self, text: &str, style: DateStyle) -> String {
+        let date = |caps: &Captures| {
+            let year = &caps[1];
+            let (month, day) = (caps[2].parse::<u32>().ok()?, caps[3].parse::<u32>().ok()?);
+            if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+                return None;
+            }
+            Some(match style {
+                DateStyle::Iso => format!("{}-{:02}-{:02}", year, month, day),
+                DateStyle::Cjk => format!("{}年{}月{}日", year, month, day),
+            })
+        };
+        let text = replace_isolated(&self.numbers.date, text, date);
+        match style {
+            DateStyle::Iso => replace_isolated(&self.numbers.cjk_date, &text, date),
+            DateStyle::Cjk => text,
+        }
+    }
+
+    fn group_numbers(&self, text: &str, grouping: NumberGrouping) -> String {
+        match grouping {
+            NumberGrouping::Western => {
+                let text = replace_isolated(&self.numbers.myriad, text, |caps| {
+                    let value = match (caps.get(1), caps.get(3)) {
+                        (Some(yi), _) => {
+                            let wan = caps.get(2).map_or(Some(0), |w| scale(w.as_str(), 4))?;
+                            scale(yi.as_str(), 8)? + wan
+                        }
+                        (None, Some(wan)) => scale(wan.as_str(), 4)?,
+                        _ => return None,
+                    };
+                    Some(western(value))
+                });
+                replace_isolated(&self.numbers.number, &text, |caps| {
+                    Some(western(caps[0].replace(',', "").parse().ok()?))
+                })
+            }
+            NumberGrouping::Myriad => replace_isolated(&self.numbers.number, text, |caps| {
+                let value: u128 = caps[0].replace(',', "").parse().ok()?;
+                if !value.is_multiple_of(10_000) {
+                    return None;
+                }
+                let (yi, wan) = (value / 100_000_000, value / 10_000 % 10_000);
+                let mut myriad = String::new();
+                if yi > 0 {
+                    myriad.push_str(&format!("{}{}", yi, self.hundred_million));
+                }
+                if wan > 0 {
+                    myriad.push_str(&format!("{}万", wan));
+                }
+                Some(myriad)
+            }),
+        }
+    }
+}
+
+/// replace the matches not next to another digit or number by f, f returns None to keep one
+fn replace_isolated<F>(regex: &Regex, text: &str, f: F) -> String
+where
+    F: Fn(&Captures) -> Option<String>,
+{
+    let isolated = |m: Match| {
+        let before = text[..m.start()].chars().last();
+        let mut after = text[m.end()..].chars();
+        // a comma or a dot after is a punctuation unless followed by a digit
+        let after = match after.next() {
+            Some('.' | ',') => after.next(),
+            c => c,
+        };
+        !before.is_some_and(|c| c.is_ascii_digit() || matches!(c, '.' | ',' | '-' | '/'))
+            && !after.is_some_and(|c| c.is_ascii_digit())
+    };
+    regex
+        .replace_all(text, |caps: &Captures| {
+            let m = caps.get(0).unwrap();
+            match isolated(m) {
+                true => f(caps).unwrap_or_else(|| m.as_str().to_string()),
+                false => m.as_str().to_string(),
+            }
+        })
+        .to_string()
+}
+
+/// the number times 10^zeros, None if the decimals go below 1
+fn scale(number: &str, zeros: u32) -> Option<u128> {
+    let (int, frac) = number.split_once('.').unwrap_or((number, ""));
+    let frac = frac.trim_end_matches('0');
+    if frac.len() > zeros as usize {
+        return None;
+    }
+    let int: u128 = int.parse().ok()?;
+    let frac: u128 = match frac {
+        "" => 0,
+        f => f.parse::<u128>().ok()? * 10u128.pow(zeros - f.len() as u32),
+    };
+    int.checked_mul(10u128.pow(zeros))?.checked_add(frac)
+}
+
+/// 12,345,678
+fn western(value: u128) -> String {
+    let digits = value.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    grouped
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}' | '\u{3400}'..='\u{9fff}' | '\u{f900}'..='\u{faff}'
+        | '\u{3000}'..='\u{303f}' | '\u{ff01}'..='\u{ff60}')
+}
+
+/// the runs of …, ‥ and three or more of the same . 。 ・ are replaced by one ellipsis
+fn collapse_ellipses(text: &str, ellipsis: &str) -> String {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut result = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        while let Some(&c) = chars.get(i) {
+            match c {
+                '…' | '‥' => i += 1,
+                '.' | '。' | '・' => {
+                    let n = chars[i..].iter().take_while(|&&x| x == c).count();
+                    if n < 3 {
+                        break;
+                    }
+                    i += n;
+                }
+                _ => break,
+            }
+        }
+        if i > start {
+            result.push_str(ellipsis);
+        } else {
+            result.push(chars[i]);
+            i += 1;
+        }
+    }
+    result
+}
+
+/// the full-width letters and digits of U+FF01..U+FF5E as ascii
+fn half_alphanumeric(c: char) -> char {
+    match c {
+        '０'..='９' | 'Ａ'..='Ｚ' | 'ａ'..='ｚ' => {
+            char::from_u32(c as u32 - 0xFEE0).unwrap_or(c)
+        }
+        _ => c,
+    }
+}
+
+/// the half-width punctuation after a cjk character becomes full-width, the spaces after it are dropped
+fn to_full_width(text: &str) -> String {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut result = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = half_alphanumeric(chars[i]);
+        let prev_cjk = result.chars().last().is_some_and(is_cjk);
+        let next = chars.get(i + 1).copied();
+        let full = match c {
+            '(' if next.is_some_and(is_cjk) => Some('（'),
+            // not a decimal point or an abbreviation
+            '.' if next.is_some_and(|n| n.is_ascii_alphanumeric()) => None,
+            _ if prev_cjk => PUNCTUATION.iter().find(|(h, _)| *h == c).map(|(_, f)| *f),
+            _ => None,
+        };
+        match full {
+            Some(full) => {
+                result.push(full);
+                i += 1;
+                while chars.get(i) == Some(&' ') {
+                    i += 1;
+                }
+            }
+            None => {
+                result.push(c);
+                i += 1;
+            }
+        }
+    }
+    result
+}
+
+/// the full-width punctuation becomes half-width, followed by a space before a word
+fn to_half_width(text: &str) -> String {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut result = String::with_capacity(text.len());
+    for (i, &c) in chars.iter().enumerate() {
+        let c = half_alphanumeric(c);
+        if c == '\u{3000}' {
+            result.push(' ');
+            continue;
+        }
+        if c == '（' {
+            if result.chars().last().is_some_and(char::is_alphanumeric) {
+                result.push(' ');
+            }
+            result.push('(');
+            continue;
+        }
+        match PUNCTUATION.iter().find(|(_, f)| *f == c) {
+            Some((half, _)) => {
+                result.push(*half);
+                if chars.get(i + 1).is_some_and(|n| n.is_alphanumeric()) {
+                    result.push(' ');
+                }
+            }
+            None => result.push(c),
+        }
+    }
+    result
+}
+
+/// rewrite the quotes to the style, the straight double quotes are paired as open and close,
+/// the single curly quotes between letters are apostrophes
+fn rewrite_quotes(text: &str, style: QuoteStyle) -> String {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut open = true;
+    let mut result = String::with_capacity(text.len());
+    for (i, &c) in chars.iter().enumerate() {
+        let apostrophe = i > 0
+            && chars[i - 1].is_alphabetic()
+            && chars.get(i + 1).is_some_and(|n| n.is_alphabetic());
+        let quote = match (style, c) {
+            (_, '"') => {
+                open = !open;
+                match style {
+                    QuoteStyle::Corner if !open => '「',
+                    QuoteStyle::Corner => '」',
+                    QuoteStyle::Curly if !open => '“',
+                    QuoteStyle::Curly => '”',
+                }
+            }
+            (QuoteStyle::Corner, '“') => '「',
+            (QuoteStyle::Corner, '”') => '」',
+            (QuoteStyle::Corner, '‘') if !apostrophe => '『',
+            (QuoteStyle::Corner, '’') if !apostrophe => '』',
+            (QuoteStyle::Curly, '「') => '“',
+            (QuoteStyle::Curly, '」') => '”',
+            (QuoteStyle::Curly, '『') => '‘',
+            (QuoteStyle::Curly, '』') => '’',
+            _ => c,
+        };
+        result.push(quote);
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use isolang::Language;
+
+    use super::{DateStyle, DigitWidth, NormalizeOptions, Normalizer, NumberGrouping, QuoteStyle};
+
+    #[test]
+    fn test_normalize() {
+        let options = NormalizeOptions {
+            width: true,
+            ellipsis: true,
+            quotes: Some(QuoteStyle::Corner),
+            digits: None,
+            grouping: None,
+            dates: None,
+        };
+        let zho = Normalizer::new(options.clone(), Language::Zho);
+        assert_eq!(
+            zho.normalize("你好, 皮诺!他说\"等等。。。\"(笑)版本1.5，ＡＢＣ"),
+            "你好，皮诺！他说「等等……」（笑）版本1.5，ABC"
+        );
+        let eng = Normalizer::new(
+            NormalizeOptions {
+                quotes: Some(QuoteStyle::Curly),
+                ..options
+            },
+            Language::Eng,
+        );
+        assert_eq!(
+            eng.normalize("「Wait…………」，Pino！Don’t（really）"),
+            "“Wait...”, Pino! Don’t (really)"
+        );
+    }
+
+    #[test]
+    fn test_normalize_numbers() {
+        let options = NormalizeOptions {
+            width: false,
+            ellipsis: false,
+            quotes: None,
+            digits: None,
+            grouping: Some(NumberGrouping::Western),
+            dates: Some(DateStyle::Iso),
+        };
+        let eng = Normalizer::new(options.clone(), Language::Eng);
+        assert_eq!(
+            eng.normalize("On 2024年3月5日, 1.5万 coins and 1億2000万 yen, 12345678 or 3.14159, id 2024/13/01"),
+            "On 2024-03-05, 15,000 coins and 120,000,000 yen, 12,345,678 or 3.14159, id 2024/13/01"
+        );
+        let jpn = Normalizer::new(
+            NormalizeOptions {
+                digits: Some(DigitWidth::Full),
+                grouping: Some(NumberGrouping::Myriad),
+                dates: Some(DateStyle::Cjk),
+                ..options
+            },
+            Language::Jpn,
+        );
+        assert_eq!(
+            jpn.normalize("2024-03-05に1,200,000円と300000000円、12345円"),
+            "２０２４年３月５日に１２０万円と３億円、１２３４５円"
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+
+use super::output::RewriteOutput;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum BilingualMode {
+    /// original and translation on the same line, separated by the delimiter
+    #[serde(rename = "inline")]
+    Inline(String),
+    /// original line followed by the translated line
+    #[serde(rename = "alternate")]
+    Alternate,
+    /// <ruby>original<rt>translation</rt></ruby>
+    #[serde(rename = "ruby")]
+    Ruby,
+}
+
+/// wrap a RewriteOutput, emit the original text and the translation together,
+/// the lines that not be translated are kept as they are.
+pub struct BilingualOutput<T: RewriteOutput> {
+    inner: T,
+    mode: BilingualMode,
+}
+
+impl<T: RewriteOutput> BilingualOutput<T> {
+    pub fn new(inner: T, mode: BilingualMode) -> Self {
+        Self { inner, mode }
+    }
+}
+
+impl<T: RewriteOutput> RewriteOutput for BilingualOutput<T> {
+    fn extract_lines(&self, content: &str) -> Vec<String> {
+        self.inner.extract_lines(content)
+    }
+    /// only the captured value is bilingual when the translation replaces it, e.g. a json line
+    fn format_line(&self, raw: &str, content: &str) -> String {
+        let join = |source: &str, translated: &str| self.join_span(source, translated);
+        if let Some(line) = self.inner.format_joined(raw, content, &join) {
+            return line;
+        }
+        let translated = self.inner.format_line(raw, content);
+        let (raw_body, newline) = split_newline(raw);
+        let (tran_body, _) = split_newline(&translated);
+        let newline = if newline.is_empty() { "\n" } else { newline };
+        match &self.mode {
+            BilingualMode::Inline(delimiter) => {
+                format!("{}{}{}{}", raw_body, delimiter, tran_body, newline)
+            }
+            BilingualMode::Alternate => {
+                format!("{}{}{}{}", raw_body, newline, tran_body, newline)
+            }
+            BilingualMode::Ruby => {
+                format!("<ruby>{}<rt>{}</rt></ruby>{}", raw_body, tran_body, newline)
+            }
+        }
+    }
+    fn format_span(&self, raw: &str, content: &str) -> String {
+        self.join_span(raw, &self.inner.format_span(raw, content))
+    }
+}
+
+impl<T: RewriteOutput> BilingualOutput<T> {
+    /// a span can not hold a line break, the alternate mode separates by a space
+    fn join_span(&self, source: &str, translated: &str) -> String {
+        match &self.mode {
+            BilingualMode::Inline(delimiter) => format!("{}{}{}", source, delimiter, translated),
+            BilingualMode::Alternate => format!("{} {}", source, translated),
+            BilingualMode::Ruby => format!("<ruby>{}<rt>{}</rt></ruby>", source, translated),
+        }
+    }
+}
+
+fn split_newline(line: &str) -> (&str, &str) {
+    let body = line.trim_end_matches(['\r', '\n']);
+    (body, &line[body.len()..])
+}
+
+#[cfg(test)]
+mod test {
+    use crate::outputs::{pipeline::OutputPipeline, replace::ReplaceOutput, text::TextOutput};
+
+    use super::*;
+
+    #[test]
+    fn test_bilingual_format_line() {
+        let text = || {
+            TextOutput::new(OutputPipeline::from_output_regexen(r"\n", r"\(\d+\)\s?(.+)").unwrap())
+        };
+        let output = BilingualOutput::new(text(), BilingualMode::Inline(" | ".to_string()));
+        assert_eq!(
+            output.format_line("你好。\n", "Hello."),
+            "你好。 | Hello.\n"
+        );
+        let output = BilingualOutput::new(text(), BilingualMode::Alternate);
+        assert_eq!(
+            output.format_line("你好。\r\n", "Hello."),
+            "你好。\r\nHello.\r\n"
+        );
+        let output = BilingualOutput::new(text(), BilingualMode::Ruby);
+        assert_eq!(
+            output.format_line("你好。", "Hello."),
+            "<ruby>你好。<rt>Hello.</rt></ruby>\n"
+        );
+    }
+
+    #[test]
+    fn test_bilingual_replace_json() {
+        let replace = || {
+            let pipeline = OutputPipeline::from_output_regexen(r"\n", r"\(\d+\)\s?(.+)").unwrap();
+            ReplaceOutput::new(pipeline, r#": "$trans""#, r#":\s"(.+)""#).unwrap()
+        };
+        let raw = [
+            "{\n",
+            "  \"你好\": \"你好\",\n",
+            "  \"再见\\\"\": \"再见\\\"\"\n",
+            "}\n",
+        ];
+        let translations = [None, Some("Hello"), Some("Bye \"$1\""), None];
+        let modes = [
+            BilingualMode::Inline(" | ".to_string()),
+            BilingualMode::Alternate,
+            BilingualMode::Ruby,
+        ];
+        for mode in modes {
+            let output = BilingualOutput::new(replace(), mode);
+            let json = raw
+                .iter()
+                .zip(translations)
+                .map(|(raw, tran)| match tran {
+                    Some(tran) => output.format_line(raw, tran),
+                    None => raw.to_string(),
+                })
+                .collect::<String>();
+            let value = serde_json::from_str::<serde_json::Value>(&json).unwrap();
+            let bye = value["再见\""].as_str().unwrap();
+            assert!(bye.contains("再见\""), "{}", json);
+            assert!(bye.contains("Bye \"$1\""), "{}", json);
+        }
+        let output = BilingualOutput::new(replace(), BilingualMode::Inline(" | ".to_string()));
+        assert_eq!(
+            output.format_line("  \"你好\": \"你好\",\n", "Hello"),
+            "  \"你好\": \"你好 | Hello\",\n"
+        );
+    }
+}
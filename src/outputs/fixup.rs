@@ -0,0 +1,198 @@
+use std::{fmt::Write as _, fs, path::Path};
+
+use anyhow::Result;
+
+use crate::{error::Error, i18n::Msg, t, Configuration};
+
+use super::output::OutputReport;
+
+/// the changed middle of two files is diffed line by line up to this many cells, a larger one is
+/// reported as a single change
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// the hunks printed, the others are only in the report file
+const PRINTED_HUNKS: usize = 5;
+
+/// a run of changed lines, line counts from 1 in the previous file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hunk {
+    pub line: usize,
+    pub old: Vec<String>,
+    pub new: Vec<String>,
+}
+
+/// output again with the output options of the fixup file merged over the configuration, e.g. new
+/// output stages or normalize rules, then compare the translated file with the one it replaced,
+/// the changes are printed and written to file.fixup.md
+pub fn fixup<F>(config: &Configuration, name: &str, write: F) -> Result<OutputReport>
+where
+    F: FnOnce() -> Result<OutputReport>,
+{
+    let target = match config.in_place {
+        true => Path::new(name).to_path_buf(),
+        false => config.output_path(name, config.translator_selection().primary()),
+    };
+    // the first output has nothing to compare
+    let previous = fs::read(&target)
+        .map(|b| String::from_utf8_lossy(&b).to_string())
+        .ok();
+    let report = write()?;
+    let Some(previous) = previous else {
+        println!("[Fixup] {}", t!(Msg::FixupNoPrevious));
+        return Ok(report);
+    };
+    let current = fs::read(&target).map_err(Error::io(&target.to_string_lossy()))?;
+    let hunks = diff_lines(&previous, &String::from_utf8_lossy(&current));
+    let changed = hunks
+        .iter()
+        .map(|h| h.old.len().max(h.new.len()))
+        .sum::<usize>();
+    if hunks.is_empty() {
+        println!("[Fixup] {}", t!(Msg::FixupUnchanged));
+        return Ok(report);
+    }
+    for hunk in hunks.iter().take(PRINTED_HUNKS) {
+        print!("{}", render_hunk(hunk));
+    }
+    let path = config.dirs().fixup(name);
+    fs::write(&path, render_report(name, &hunks)).map_err(Error::io(&path.to_string_lossy()))?;
+    let changed = t!(Msg::FixupChanged, changed, hunks.len(), path.display());
+    println!("[Fixup] {}", changed);
+    Ok(report)
+}
+
+/// the changed lines of new against old, the files of the same line count are compared line by
+/// line, the translations replace the lines in place
+pub fn diff_lines(old: &str, new: &str) -> Vec<Hunk> {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+    if old.len() == new.len() {
+        return group(
+            old.iter()
+                .zip(&new)
+                .enumerate()
+                .filter(|(_, (o, n))| o != n)
+                .map(|(i, (o, n))| (i, Some(*o), Some(*n))),
+        );
+    }
+    let prefix = old.iter().zip(&new).take_while(|(o, n)| o == n).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(o, n)| o == n)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+    if old_mid.len() * new_mid.len() > MAX_DIFF_CELLS {
+        return vec![Hunk {
+            line: prefix + 1,
+            old: old_mid.iter().map(|l| l.to_string()).collect(),
+            new: new_mid.iter().map(|l| l.to_string()).collect(),
+        }];
+    }
+    let ops = lcs_ops(old_mid, new_mid);
+    group(ops.into_iter().map(|(i, o, n)| (i + prefix, o, n)))
+}
+
+/// the deleted (old only) and inserted (new only) lines of the longest common subsequence, by the
+/// index of the old line they are at
+fn lcs_ops<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(usize, Option<&'a str>, Option<&'a str>)> {
+    let (n, m) = (old.len(), new.len());
+    // lcs[i][j], the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = match old[i] == new[j] {
+                true => lcs[i + 1][j + 1] + 1,
+                false => lcs[i + 1][j].max(lcs[i][j + 1]),
+            };
+        }
+    }
+    let mut ops = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if j < m && (i == n || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            ops.push((i, None, Some(new[j])));
+            j += 1;
+        } else {
+            ops.push((i, Some(old[i]), None));
+            i += 1;
+        }
+    }
+    ops
+}
+
+/// group the changed lines of adjacent old lines into hunks
+fn group<'a>(ops: impl Iterator<Item = (usize, Option<&'a str>, Option<&'a str>)>) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = vec![];
+    // the old line after the last hunk
+    let mut next = usize::MAX;
+    for (i, old, new) in ops {
+        let hunk = match hunks.last_mut() {
+            Some(hunk) if i == next => hunk,
+            _ => {
+                hunks.push(Hunk {
+                    line: i + 1,
+                    old: vec![],
+                    new: vec![],
+                });
+                hunks.last_mut().unwrap()
+            }
+        };
+        hunk.old.extend(old.map(String::from));
+        hunk.new.extend(new.map(String::from));
+        next = hunk.line - 1 + hunk.old.len();
+    }
+    hunks
+}
+
+fn render_hunk(hunk: &Hunk) -> String {
+    let mut text = format!("@@ line {}\n", hunk.line);
+    for line in &hunk.old {
+        let _ = writeln!(text, "- {}", line);
+    }
+    for line in &hunk.new {
+        let _ = writeln!(text, "+ {}", line);
+    }
+    text
+}
+
+fn render_report(name: &str, hunks: &[Hunk]) -> String {
+    let mut report = format!("# Fixup: {}\n\n", name);
+    for hunk in hunks {
+        let _ = writeln!(report, "```diff\n{}```\n", render_hunk(hunk));
+    }
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::{diff_lines, Hunk};
+
+    #[test]
+    fn test_diff_lines() {
+        let hunk = |line, old: &[&str], new: &[&str]| Hunk {
+            line,
+            old: old.iter().map(|l| l.to_string()).collect(),
+            new: new.iter().map(|l| l.to_string()).collect(),
+        };
+        assert_eq!(
+            diff_lines("a\nb\nc\nd\n", "a\nB\nc\nD\n"),
+            vec![hunk(2, &["b"], &["B"]), hunk(4, &["d"], &["D"])]
+        );
+        // a line wrapped in two
+        assert_eq!(
+            diff_lines("a\nbb\nc\n", "a\nb\nb\nc\n"),
+            vec![hunk(2, &["bb"], &["b", "b"])]
+        );
+        assert_eq!(
+            diff_lines("a\nb\nc\n", "a\nc\n"),
+            vec![hunk(2, &["b"], &[])]
+        );
+        assert!(diff_lines("a\nb\n", "a\nb\n").is_empty());
+    }
+}
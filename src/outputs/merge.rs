@@ -0,0 +1,176 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    normalize::Normalizer,
+    ruby::{RubyMode, RubyParser},
+    textures::Textures,
+    translators::Translator,
+};
+
+/// how a line translated by several translators is picked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum MergeStrategy {
+    /// the first translator of the priority list which translated the line
+    #[default]
+    #[serde(rename = "priority")]
+    Priority,
+    /// the translation which looks best, a non-empty translation different from the source
+    /// beats an empty or untranslated one, the priority list breaks the ties
+    #[serde(rename = "best")]
+    Best,
+}
+
+/// the translators whose translations are output, in priority order
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranslatorSelection {
+    pub translators: Vec<Translator>,
+    pub merge: MergeStrategy,
+    /// with keep, the ruby markup of the source is restored in the translation
+    pub ruby: Option<RubyMode>,
+    /// the cleanup of the translation, before the ruby markup is restored
+    pub normalize: Option<Normalizer>,
+}
+
+impl From<Translator> for TranslatorSelection {
+    fn from(translator: Translator) -> Self {
+        Self {
+            translators: vec![translator],
+            merge: MergeStrategy::Priority,
+            ruby: None,
+            normalize: None,
+        }
+    }
+}
+
+impl TranslatorSelection {
+    /// the translator naming the output files
+    pub fn primary(&self) -> Translator {
+        self.translators
+            .first()
+            .copied()
+            .unwrap_or(Translator::ChatGPT)
+    }
+
+    /// the translation of every line merged from the translators, indexed like lines,
+    /// failed is called with (batch_range, expected, extracted) for the batches whose size
+    /// mismatches and whose lines are not translated by another translator
+    pub fn select<E, F>(
+        &self,
+        textures: &Textures,
+        extract: E,
+        mut failed: F,
+    ) -> Vec<Option<String>>
+    where
+        E: Fn(&str) -> Vec<String>,
+        F: FnMut((usize, usize), usize, usize),
+    {
+        let mut failures = vec![];
+        let aligned = self
+            .translators
+            .iter()
+            .map(|translator| {
+                textures.align_translated(*translator, &extract, |range, expected, extracted| {
+                    failures.push((range, expected, extracted))
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut result = (0..textures.lines.len())
+            .map(|i| {
+                let mut candidates = aligned.iter().filter_map(|a| a[i].as_ref());
+                match self.merge {
+                    MergeStrategy::Priority => candidates.next().cloned(),
+                    MergeStrategy::Best => {
+                        let source = textures.lines[i].content.trim();
+                        let mut best: Option<(u8, &String)> = None;
+                        for candidate in candidates {
+                            let score = score(candidate, source);
+                            if best.is_none_or(|(s, _)| score > s) {
+                                best = Some((score, candidate));
+                            }
+                        }
+                        best.map(|(_, c)| c.clone())
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+        if let Some(normalizer) = &self.normalize {
+            for translation in result.iter_mut().flatten() {
+                *translation = normalizer.normalize(translation);
+            }
+        }
+        if self.ruby == Some(RubyMode::Keep) {
+            let parser = RubyParser::default();
+            for (translation, line) in result.iter_mut().zip(&textures.lines) {
+                if let Some(translation) = translation {
+                    *translation = parser.restore(translation, &line.content);
+                }
+            }
+        }
+        failures.sort_by_key(|(range, _, _)| *range);
+        failures.dedup_by_key(|(range, _, _)| *range);
+        for (range, expected, extracted) in failures {
+            if textures
+                .batch_lines(range)
+                .into_iter()
+                .any(|i| result[i].is_none())
+            {
+                failed(range, expected, extracted);
+            }
+        }
+        result
+    }
+}
+
+fn score(translation: &str, source: &str) -> u8 {
+    match translation.trim() {
+        "" => 0,
+        t if t == source => 1,
+        _ => 2,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MergeStrategy, TranslatorSelection};
+    use crate::{
+        textures::{TextureLine, Textures, TranslatedLine, TEXTURES_VERSION},
+        translators::Translator,
+    };
+
+    #[test]
+    fn test_select() {
+        let mut textures = Textures {
+            lines: vec![
+                TextureLine::new(0, 4, "你好\n".to_string(), false),
+                TextureLine::new(4, 4, "再见\n".to_string(), false),
+            ],
+            curr_index: 0,
+            version: TEXTURES_VERSION,
+            source: None,
+            name: String::new(),
+            shard: None,
+            dirs: Default::default(),
+        };
+        textures.update(TranslatedLine::new(
+            Translator::ChatGPT,
+            "Hello\n再见".to_string(),
+            0,
+            1,
+        ));
+        let extract = |c: &str| c.lines().map(String::from).collect::<Vec<_>>();
+        let mut selection = TranslatorSelection::from(Translator::ChatGPT);
+        let mut failed = vec![];
+        let result = selection.select(&textures, extract, |r, _, _| failed.push(r));
+        assert_eq!(
+            result,
+            vec![Some("Hello".to_string()), Some("再见".to_string())]
+        );
+        assert!(failed.is_empty());
+        selection.merge = MergeStrategy::Best;
+        let result = selection.select(&textures, extract, |_, _, _| {});
+        assert_eq!(result[1].as_deref(), Some("再见"));
+        let result = selection.select(&textures, |_| vec![], |r, _, _| failed.push(r));
+        assert_eq!(result, vec![None, None]);
+        assert_eq!(failed, vec![(0, 1)]);
+    }
+}
@@ -1,5 +1,30 @@
+mod bilingual;
+mod fixup;
+mod merge;
+mod mtool;
 mod output;
+mod pipeline;
 mod replace;
+mod report;
+mod split;
+mod syntax;
 mod text;
+mod tpp;
+mod verify;
 
+pub use bilingual::BilingualMode;
+pub use fixup::fixup;
+pub use merge::MergeStrategy;
+pub use merge::TranslatorSelection;
+pub use output::live_output;
 pub use output::output as out_put;
+pub use output::output_shards;
+pub use output::prepare_in_place;
+pub use output::translated_lines;
+pub use output::LineExtractor;
+pub use output::OutputCache;
+pub use output::OutputReport;
+pub use replace::has_trans;
+pub(crate) use report::escape_cell;
+pub use report::write_preview;
+pub use split::SplitOptions;
@@ -0,0 +1,192 @@
+use std::{collections::HashMap, fmt, fs, path::Path};
+
+use anyhow::Result;
+use serde::{
+    de::{MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::Value;
+
+use crate::{error::Error, textures::Textures, Configuration};
+
+use super::{
+    output::{join_line, mismatch, save_failed, LineExtractor, OutputReport},
+    replace::wrap_value,
+};
+
+/// the entries of a json object in the order of the file
+#[derive(Debug, Default, PartialEq)]
+struct Entries(Vec<(String, Value)>);
+
+impl<'de> Deserialize<'de> for Entries {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EntriesVisitor;
+        impl<'de> Visitor<'de> for EntriesVisitor {
+            type Value = Entries;
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a json object")
+            }
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Entries, A::Error> {
+                let mut entries = vec![];
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(Entries(entries))
+            }
+        }
+        deserializer.deserialize_map(EntriesVisitor)
+    }
+}
+
+impl Serialize for Entries {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in &self.0 {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+/// the key of an entry line of the MTool file, `"key": "value",`
+fn entry_key(line: &str) -> Option<String> {
+    let entry = line.trim().trim_end_matches(',');
+    let Entries(entries) = serde_json::from_str(&format!("{{{}}}", entry)).ok()?;
+    entries.into_iter().next().map(|(key, _)| key)
+}
+
+/// write the MTool file as a json object built by serde_json instead of splicing the lines, the
+/// keys in the order of the original, the value of a translated entry replaced, the others passed
+/// through, so the output is valid json whatever the layout of the original. the failed batches
+/// are recorded unless live
+pub fn output_json(
+    config: &Configuration,
+    textures: &Textures,
+    target: &Path,
+    live: bool,
+) -> Result<OutputReport> {
+    let name = &textures.name;
+    if target == Path::new(name) {
+        return Err(Error::Config(format!(
+            "output_name points to the input file {}, use in_place to rewrite it",
+            name
+        ))
+        .into());
+    }
+    if let Some(parent) = target.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(Error::io(&parent.to_string_lossy()))?;
+    }
+    let original = fs::read(name).map_err(Error::io(name))?;
+    let Entries(mut entries) = serde_json::from_slice(&original)
+        .map_err(|e| Error::Config(format!("{} is not a json object: {}", name, e)))?;
+    let opt = config.mtool_opt.clone().unwrap_or_default();
+    let line_width = opt
+        .line_width
+        .or(config.segment.as_ref().and_then(|v| v.line_width));
+
+    let extractor = LineExtractor::new(config)?;
+    let mut failed = vec![];
+    let tran_lines = config.translator_selection().select(
+        textures,
+        |content| extractor.extract(content),
+        |range, expected, extracted| {
+            if !live {
+                failed.push(mismatch(textures, range, expected, extracted));
+            }
+        },
+    );
+    let mut report = OutputReport::default();
+    let mut translations = HashMap::new();
+    let mut i = 0;
+    while i < textures.lines.len() {
+        let line = &textures.lines[i];
+        let (range, translation) = join_line(textures, &tran_lines, i);
+        i += range.len().max(1);
+        let Some(translation) = translation else {
+            if range
+                .into_iter()
+                .any(|k| textures.lines[k].needs_translation())
+            {
+                report.skipped += 1;
+            }
+            continue;
+        };
+        // the key is read from the original line, the content of a sentence is a part of it
+        let key = original
+            .get(line.seek..line.seek + line.size)
+            .and_then(|bytes| entry_key(&String::from_utf8_lossy(bytes)));
+        match key {
+            Some(key) => {
+                report.written += 1;
+                translations.insert(key, wrap_value(&translation, line_width, opt.width_mode));
+            }
+            None => report.skipped += 1,
+        }
+    }
+    for (key, value) in entries.iter_mut() {
+        if let Some(translation) = translations.remove(key.as_str()) {
+            *value = Value::String(translation);
+        }
+    }
+
+    let mut tmp = target.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(&Entries(entries))?)
+        .map_err(Error::io(&target.to_string_lossy()))?;
+    fs::rename(&tmp, target)?;
+    report.failed_batches = failed.len();
+    if !live {
+        save_failed(&textures.dirs, name, failed)?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        inputs::{Input, TextInput},
+        textures::TranslatedLine,
+        translators::Translator,
+        Configuration,
+    };
+
+    use super::{entry_key, output_json};
+
+    #[test]
+    fn test_output_json() {
+        assert_eq!(
+            entry_key("  \"請\\\"原\\\"諒\": \"x\",\n"),
+            Some("請\"原\"諒".to_string())
+        );
+        let dir = crate::utils::TempDir::new("output_json");
+        let path = dir.join("ManualTransFile.json");
+        // the last entry has no trailing comma
+        std::fs::write(
+            &path,
+            "{\n  \"はい\": \"はい\",\n  \"b\": \"b\",\n  \"「また」\": \"「また」\"\n}",
+        )
+        .unwrap();
+        let name = path.to_str().unwrap();
+        let mut textures = TextInput::new(vec![r#"^\s*".*[^\x00-\x7f].*"#.to_string()])
+            .unwrap()
+            .read(name)
+            .unwrap();
+        textures.update(TranslatedLine::new(
+            Translator::ChatGPT,
+            "(1) 好的\n(2) 又见".to_string(),
+            0,
+            1,
+        ));
+        let config: Configuration =
+            toml::from_str(include_str!("../../assets/options_mtool.toml")).unwrap();
+        let target = dir.join("ManualTransFile.translated.json");
+        let report = output_json(&config, &textures, &target, true).unwrap();
+        assert_eq!((report.written, report.skipped), (2, 0));
+        let output = std::fs::read_to_string(&target).unwrap();
+        assert_eq!(
+            output,
+            "{\n  \"はい\": \"好的\",\n  \"b\": \"b\",\n  \"「また」\": \"又见\"\n}"
+        );
+    }
+}
@@ -1,293 +1,1190 @@
 use std::{
+    collections::HashMap,
     fs,
     io::{Read, Write},
+    path::Path,
+    sync::Mutex,
 };
 
 use anyhow::Result;
-use regex::Regex;
+use serde::Serialize;
 
 use crate::{
-    inputs::TransType, textures::Textures, translators::Translator, Configuration,
-    RegexDescription, RegexUsage,
+    console::{self, Tone},
+    error::Error,
+    failed::{FailReason, FailedBatch, FailedReport},
+    i18n::Msg,
+    inputs::{is_sentence_end, line_syntax, TransType},
+    paths::ArtifactDirs,
+    qe,
+    segment::join_sentences,
+    t,
+    textures::{fnv, TextureLine, Textures},
+    Configuration,
 };
 
-use super::{replace::ReplaceOutput, text::TextOutput};
+use super::{
+    bilingual::BilingualOutput,
+    merge::TranslatorSelection,
+    mtool::output_json,
+    pipeline::OutputPipeline,
+    replace::ReplaceOutput,
+    report::{render_skipped, write_index, write_report},
+    split::split_output,
+    syntax::SyntaxOutput,
+    text::TextOutput,
+    tpp::output_project,
+    verify::{verify_rewrite, Splice},
+};
+
+/// what an output wrote, counted by the original lines
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct OutputReport {
+    /// the lines replaced by their translation
+    pub written: usize,
+    /// the lines to be translated but kept original, untranslated or in a failed batch
+    pub skipped: usize,
+    /// the batches failed to extract, see file.failed.json
+    pub failed_batches: usize,
+    /// the batches scored below qe_opt.min_score
+    pub low_score_batches: usize,
+}
+
+/// where the translated textures come from
+enum OutputSource<'a> {
+    Whole(&'a Textures),
+    /// the count of shards of a large file, they are loaded one by one
+    Shards(usize),
+    /// the textures of a running translation, the batches output before are not extracted again
+    Live(&'a Textures, &'a OutputCache),
+}
+
+/// the extracted lines of the batches already output, by the content of the batch, kept across
+/// the live outputs of a run
+#[derive(Debug, Default)]
+pub struct OutputCache(Mutex<HashMap<String, Vec<String>>>);
+
+impl OutputCache {
+    fn extract<E>(&self, content: &str, extract: E) -> Vec<String>
+    where
+        E: Fn(&str) -> Vec<String>,
+    {
+        if let Some(lines) = self.0.lock().unwrap().get(content) {
+            return lines.clone();
+        }
+        let lines = extract(content);
+        self.0
+            .lock()
+            .unwrap()
+            .insert(content.to_string(), lines.clone());
+        lines
+    }
+}
 
-pub fn output(config: &Configuration, textures: &Textures) -> Result<()> {
-    match config.trans_type {
+pub fn output(config: &Configuration, textures: &Textures) -> Result<OutputReport> {
+    write_output(config, &textures.name, OutputSource::Whole(textures))
+}
+
+/// update the translated file during the translation, only the batches translated since the last
+/// live output are extracted, the file is replaced at once so a reader never sees it half written.
+/// the review report, the scores, the style check, the split and the failed ranges are left to the
+/// output at the end of the run
+pub fn live_output(
+    config: &Configuration,
+    textures: &Textures,
+    cache: &OutputCache,
+) -> Result<OutputReport> {
+    write_output(config, &textures.name, OutputSource::Live(textures, cache))
+}
+
+/// output a large file from its shards, see `input_shards`
+pub fn output_shards(config: &Configuration, file: &str, shards: usize) -> Result<OutputReport> {
+    write_output(config, file, OutputSource::Shards(shards))
+}
+
+fn write_output(config: &Configuration, name: &str, source: OutputSource) -> Result<OutputReport> {
+    let live = matches!(source, OutputSource::Live(..));
+    if config.in_place && !live {
+        prepare_in_place(name)?;
+    }
+    let mut report = match config.trans_type {
         TransType::Text => {
-            if config.output_regexen.len() < 2 {
-                return Err(anyhow::anyhow!("Please specify at least 2 regexes for MTool output! \n The MTool output need 2 regexes, one for the replace, and one for the capture."));
-            }
-            let output = TextOutput::new(
-                &config.output_regexen[0].regex,
-                &config.output_regexen[1].regex,
-            );
-            output.output(Translator::ChatGPT, textures);
+            let mut output = TextOutput::new(OutputPipeline::new(config)?);
+            output.set_line_width(config.segment.as_ref().and_then(|v| v.line_width));
+            rewrite(config, output, name, &source)?
         }
-        TransType::Replace => {
-            if config.output_regexen.len() < 2 {
-                return Err(anyhow::anyhow!("Please specify at least 2 regexes for MTool output! \n The MTool output need 2 regexes, one for the replace, and one for the capture."));
+        TransType::Replace if config.mtool_opt.as_ref().is_some_and(|v| v.json) => match &source {
+            OutputSource::Whole(textures) | OutputSource::Live(textures, _) => {
+                let translator = config.translator_selection().primary();
+                let target = config.output_path(name, translator);
+                output_json(config, textures, &target, live)?
+            }
+            OutputSource::Shards(_) => {
+                return Err(Error::Config(
+                    "mtool_opt.json does not support shard_lines".to_string(),
+                )
+                .into())
             }
-            if config.replace_expression.is_none() || config.capture_regex.is_none() {
+        },
+        TransType::Replace => {
+            let (Some(replace_expression), Some(capture_regex)) =
+                (&config.replace_expression, &config.capture_regex)
+            else {
                 return Err(anyhow::anyhow!(
                     "Please specify a replace expression and a capture regex for output!"
                 ));
-            }
+            };
             let mut output = ReplaceOutput::new(
-                &config.output_regexen[0].regex,
-                &config.output_regexen[1].regex,
-                config.replace_expression.as_ref().unwrap(),
-                config.capture_regex.as_ref().unwrap(),
-            );
-            let line_width = config.mtool_opt.as_ref().and_then(|v| v.line_width);
+                OutputPipeline::new(config)?,
+                replace_expression,
+                capture_regex,
+            )?;
+            let line_width = config
+                .mtool_opt
+                .as_ref()
+                .and_then(|v| v.line_width)
+                .or(config.segment.as_ref().and_then(|v| v.line_width));
             output.set_line_width(line_width);
-            output.output(Translator::ChatGPT, textures);
+            output.set_width_mode(
+                config
+                    .mtool_opt
+                    .as_ref()
+                    .map(|v| v.width_mode)
+                    .unwrap_or_default(),
+            );
+            rewrite(config, output, name, &source)?
+        }
+        TransType::Ain | TransType::KeyValue => {
+            let Some(syntax) = line_syntax(config) else {
+                unreachable!("the trans_type has a syntax");
+            };
+            let output = SyntaxOutput::new(OutputPipeline::new(config)?, syntax);
+            rewrite(config, output, name, &source)?
         }
+        TransType::Tpp => match &source {
+            OutputSource::Whole(textures) | OutputSource::Live(textures, _) => {
+                let translator = config.translator_selection().primary();
+                output_project(config, textures, &config.output_path(name, translator))?
+            }
+            OutputSource::Shards(_) => {
+                return Err(Error::Config(
+                    "trans_type translator++ does not support shard_lines".to_string(),
+                )
+                .into())
+            }
+        },
+    };
+    if live {
+        let written = t!(Msg::OutputLines, report.written, report.skipped);
+        println!("[Live] {}", written);
+        return Ok(report);
     }
-    Ok(())
+    report.low_score_batches = flag_low_scores(config, name, &source)?;
+    check_style(config, name, &source)?;
+    write_skipped(config, name, &source)?;
+    if config.in_place {
+        let translator = config.translator_selection().primary();
+        fs::rename(config.output_path(name, translator), name)?;
+        record_in_place(name)?;
+        println!("{}", t!(Msg::PatchedInPlace, name));
+    }
+    if let Some(split) = &config.split {
+        let translator = config.translator_selection().primary();
+        let target = match config.in_place {
+            true => Path::new(name).to_path_buf(),
+            false => config.output_path(name, translator),
+        };
+        split_output(split, &target)?;
+    }
+    println!("{}", t!(Msg::OutputLines, report.written, report.skipped));
+    Ok(report)
 }
 
-pub trait Output {
-    fn output(&self, translator: Translator, textures: &Textures);
+/// print the batches scored below qe_opt.min_score, with qe_opt.retranslate they are recorded in
+/// file.failed.json, so the run with --retry-failed translates them again
+fn flag_low_scores(config: &Configuration, name: &str, source: &OutputSource) -> Result<usize> {
+    let Some(qe) = &config.qe_opt else {
+        return Ok(0);
+    };
+    let dirs = config.dirs();
+    let low_scores = |textures: &Textures| {
+        let offset = textures.offset();
+        qe::low_score_ranges(textures, qe.min_score)
+            .into_iter()
+            .map(|(start, end)| {
+                FailedBatch::of(
+                    textures,
+                    (start - offset, end - offset),
+                    FailReason::LowScore,
+                )
+                .with_detail(format!("scored below {}", qe.min_score))
+            })
+            .collect::<Vec<_>>()
+    };
+    let batches = match source {
+        OutputSource::Whole(textures) | OutputSource::Live(textures, _) => low_scores(textures),
+        OutputSource::Shards(shards) => (0..*shards)
+            .map(|i| Ok(low_scores(&Textures::load_shard(name, i, &dirs)?)))
+            .collect::<Result<Vec<_>>>()?
+            .concat(),
+    };
+    let count = batches.len();
+    if count > 0 {
+        let ranges = batches.iter().map(|b| b.range).collect::<Vec<_>>();
+        let ranges = t!(Msg::LowScoreRange, format!("{:?}", ranges));
+        println!("{} {}", console::out(Tone::Warning, "[QE]"), ranges);
+    }
+    if qe.retranslate {
+        let mut report = FailedReport::load(&dirs, name);
+        report.replace(FailReason::LowScore, batches);
+        report.save(&dirs, name)?;
+    }
+    Ok(count)
 }
 
-#[allow(dead_code)]
-pub struct SimpleTextOutput {
-    regex_chain: Vec<(RegexUsage, Regex)>,
+/// write file.skipped.txt, the lines not sent to the translator and why, to audit that no line is
+/// dropped by mistake
+fn write_skipped(config: &Configuration, name: &str, source: &OutputSource) -> Result<usize> {
+    let skipped = match source {
+        OutputSource::Whole(textures) | OutputSource::Live(textures, _) => render_skipped(textures),
+        OutputSource::Shards(shards) => (0..*shards)
+            .map(|i| {
+                Ok(render_skipped(&Textures::load_shard(
+                    name,
+                    i,
+                    &config.dirs(),
+                )?))
+            })
+            .collect::<Result<Vec<_>>>()?
+            .concat(),
+    };
+    let count = skipped.lines().count();
+    if count > 0 {
+        let path = config.dirs().skipped(name);
+        fs::write(&path, skipped)?;
+        println!("{}", t!(Msg::NotSent, count, path.display()));
+    }
+    Ok(count)
 }
 
-#[allow(dead_code)]
-impl SimpleTextOutput {
-    pub fn new(clear_rules: Vec<RegexDescription>) -> Self {
-        let regex_chain = clear_rules
-            .into_iter()
-            .map(|r| {
-                let regex = Regex::new(&r.regex).unwrap();
-                (r.usage, regex)
+/// print the translated lines violating the honorifics of the style
+fn check_style(config: &Configuration, name: &str, source: &OutputSource) -> Result<usize> {
+    let Some(style) = &config.style else {
+        return Ok(0);
+    };
+    let capture = qe::source_capture(config)?;
+    let check = |textures: &Textures| -> Result<Vec<(usize, String)>> {
+        let translations = translated_lines(config, textures)?;
+        Ok(textures
+            .lines
+            .iter()
+            .zip(translations)
+            .enumerate()
+            .filter_map(|(i, (line, translation))| {
+                let source = qe::source(&capture, &line.content);
+                style
+                    .check(&source, &translation?)
+                    .map(|v| (i + textures.offset(), v))
             })
-            .collect::<Vec<_>>();
-        Self { regex_chain }
+            .collect())
+    };
+    let violations = match source {
+        OutputSource::Whole(textures) | OutputSource::Live(textures, _) => check(textures)?,
+        OutputSource::Shards(shards) => (0..*shards)
+            .map(|i| check(&Textures::load_shard(name, i, &config.dirs())?))
+            .collect::<Result<Vec<_>>>()?
+            .concat(),
+    };
+    for (line, violation) in violations.iter().take(10) {
+        let violation = t!(Msg::StyleLine, line, violation);
+        println!("{} {}", console::out(Tone::Warning, "[Style]"), violation);
+    }
+    if violations.len() > 10 {
+        let more = t!(Msg::StyleMore, violations.len() - 10);
+        println!("{} {}", console::out(Tone::Warning, "[Style]"), more);
     }
+    Ok(violations.len())
+}
 
-    pub fn clear(&self, content: &str) -> String {
-        let mut content = content.to_string();
-        for (usage, regex) in &self.regex_chain {
-            match usage {
-                RegexUsage::Replace(replace) => {
-                    content = regex.replace_all(&content, replace).to_string();
-                }
-                RegexUsage::Capture(index) => {
-                    let captures = regex.captures(&content);
-                    if let Some(capture) = captures {
-                        content = capture.get(*index).unwrap().as_str().to_string();
-                    }
-                }
+fn rewrite<T: RewriteOutput>(
+    config: &Configuration,
+    output: T,
+    name: &str,
+    source: &OutputSource,
+) -> Result<OutputReport> {
+    let selection = config.translator_selection();
+    let translator = selection.primary();
+    if config.report {
+        match source {
+            OutputSource::Whole(textures) => {
+                let report = write_report(&output, translator, textures)?;
+                let min_score = config.qe_opt.as_ref().map(|qe| qe.min_score);
+                write_index(&output, translator, textures, min_score, &report)?;
             }
+            OutputSource::Shards(_) => println!("{}", t!(Msg::ReportShards)),
+            OutputSource::Live(..) => {}
         }
-        content
+    }
+    let target = config.output_path(name, translator);
+    if target == Path::new(name) {
+        return Err(Error::Config(format!(
+            "output_name points to the input file {}, use in_place to rewrite it",
+            name
+        ))
+        .into());
+    }
+    if let Some(parent) = target.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(Error::io(&parent.to_string_lossy()))?;
+    }
+    match &config.bilingual {
+        Some(mode) => rewrite_source(
+            &BilingualOutput::new(output, mode.clone()),
+            &selection,
+            name,
+            source,
+            &target,
+            &config.dirs(),
+            config.verify_output,
+        ),
+        None => rewrite_source(
+            &output,
+            &selection,
+            name,
+            source,
+            &target,
+            &config.dirs(),
+            config.verify_output,
+        ),
     }
 }
 
-impl Output for SimpleTextOutput {
-    fn output(&self, translator: Translator, textures: &Textures) {
-        let mut output_file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(format!("{}.translated_{:?}.txt", textures.name, translator))
-            .expect("Failed to open file");
-        let mut i = 0;
-        while i < textures.lines.len() {
-            let line = &textures.lines[i];
-            if let Some(translated) = line.translated.iter().find(|t| t.translator == translator) {
-                let content = translated.content.as_str();
-                let content = self.clear(content);
-                let _ = output_file
-                    .write(content.as_bytes())
-                    .expect("Failed to write to file");
-                if i != translated.batch_range.0 {
-                    eprintln!(
-                        "batch_range.0: {}, expected: {}",
-                        translated.batch_range.0, i
-                    );
-                }
-                i = translated.batch_range.1 + 1; // todo window
-            } else {
-                i += 1;
+fn rewrite_source<T: RewriteOutput>(
+    output: &T,
+    selection: &TranslatorSelection,
+    name: &str,
+    source: &OutputSource,
+    target: &Path,
+    dirs: &ArtifactDirs,
+    verify: bool,
+) -> Result<OutputReport> {
+    let new_rewriter = || -> Result<Rewriter<T>> {
+        let rewriter = Rewriter::new(output, selection, name, target, dirs)?;
+        Ok(match verify {
+            true => rewriter.with_verify(),
+            false => rewriter,
+        })
+    };
+    match source {
+        OutputSource::Whole(textures) => {
+            let mut rewriter = new_rewriter()?;
+            rewriter.feed(textures)?;
+            rewriter.finish()
+        }
+        OutputSource::Shards(shards) => {
+            let mut rewriter = new_rewriter()?;
+            for index in 0..*shards {
+                rewriter.feed(&Textures::load_shard(name, index, dirs)?)?;
             }
+            rewriter.finish()
+        }
+        OutputSource::Live(textures, cache) => {
+            let mut rewriter =
+                Rewriter::new(output, selection, name, target, dirs)?.with_cache(cache);
+            rewriter.feed(textures)?;
+            rewriter.finish()
         }
     }
 }
 
+/// split the translated content of a batch into lines, by the output stages of the configuration
+pub struct LineExtractor(TextOutput);
+
+impl LineExtractor {
+    pub fn new(config: &Configuration) -> Result<Self> {
+        Ok(Self(TextOutput::new(OutputPipeline::new(config)?)))
+    }
+
+    pub fn extract(&self, content: &str) -> Vec<String> {
+        self.0.extract_lines(content)
+    }
+}
+
+/// extract the translated lines of every batch, indexed like textures.lines, merged from the
+/// output_translators, lines whose batch can not be aligned (extracted size mismatch) are None.
+pub fn translated_lines(
+    config: &Configuration,
+    textures: &Textures,
+) -> Result<Vec<Option<String>>> {
+    let extractor = LineExtractor::new(config)?;
+    Ok(config
+        .translator_selection()
+        .select(textures, |c| extractor.extract(c), |_, _, _| {}))
+}
+
+/// make sure the file to be rewritten is the original one, the first time back it up as file.bak,
+/// after that restore it from file.bak, because the textures seek offsets point into the original.
+/// the file is only restored while it is the last in-place output, see `record_in_place`, a file
+/// replaced since, e.g. by an update of the game, is the new original and is backed up again.
+pub fn prepare_in_place(file: &str) -> Result<()> {
+    let bak = format!("{}.bak", file);
+    if !Path::new(&bak).exists() {
+        fs::copy(file, &bak)?;
+        println!("{}", t!(Msg::Backup, file, bak));
+        return Ok(());
+    }
+    let content = fs::read(file)?;
+    if fs::read(&bak)? == content {
+        return Ok(());
+    }
+    let output = match fs::read_to_string(in_place_hash(file)) {
+        Ok(hash) => u64::from_str_radix(hash.trim(), 16).ok() == Some(fnv(&content)),
+        // the backups of the older runs have no hash, the file is taken as their output
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => true,
+        Err(e) => return Err(e.into()),
+    };
+    if output {
+        fs::copy(&bak, file)?;
+    } else {
+        fs::copy(file, &bak)?;
+        match fs::remove_file(in_place_hash(file)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        println!("{}", t!(Msg::InPlaceReplaced, file));
+    }
+    Ok(())
+}
+
+/// record the hash of the in-place output, the file is restored from file.bak while it matches
+fn record_in_place(file: &str) -> Result<()> {
+    let hash = fnv(&fs::read(file)?);
+    fs::write(in_place_hash(file), format!("{:016x}", hash))?;
+    Ok(())
+}
+
+/// file.bak.hash, beside the backup
+fn in_place_hash(file: &str) -> String {
+    format!("{}.bak.hash", file)
+}
+
 pub trait RewriteOutput {
     fn extract_lines(&self, content: &str) -> Vec<String>;
     fn format_line(&self, raw: &str, content: &str) -> String;
+    /// format the translation spliced into the span of a captured group
+    fn format_span(&self, _raw: &str, content: &str) -> String {
+        content.to_string()
+    }
+    /// the line with the source of the captured group and its translation joined by join, e.g. a
+    /// bilingual value of a json line, None when the translation replaces the whole line
+    fn format_joined(
+        &self,
+        _raw: &str,
+        _content: &str,
+        _join: &dyn Fn(&str, &str) -> String,
+    ) -> Option<String> {
+        None
+    }
 }
 
-impl<T> Output for T
-where
-    T: RewriteOutput,
-{
-    fn output(&self, translator: Translator, textures: &Textures) {
+/// copy the original file to the translated file, splice the translated lines into their seek,
+/// the textures can be fed one by one (shards), as long as they are in the order of the file.
+/// the translated file is written as target.tmp, and only replaces the target when finished,
+/// so a failed write never leaves a half-written output.
+pub struct Rewriter<'a, T: RewriteOutput> {
+    output: &'a T,
+    selection: TranslatorSelection,
+    /// the failed batches are recorded in the report of the file
+    name: String,
+    dirs: ArtifactDirs,
+    target: std::path::PathBuf,
+    tmp: std::path::PathBuf,
+    report: OutputReport,
+    reader: std::io::BufReader<fs::File>,
+    writer: std::io::BufWriter<fs::File>,
+    buf: [u8; 8192],
+    last_read_at: usize,
+    pre_read_at: usize,
+    failed: Vec<FailedBatch>,
+    /// set by a live output, the failed batches are then neither printed nor saved
+    cache: Option<&'a OutputCache>,
+    /// the splices written, kept when the output is verified against the original
+    splices: Option<Vec<Splice>>,
+}
+
+impl<'a, T: RewriteOutput> Rewriter<'a, T> {
+    pub fn new(
+        output: &'a T,
+        selection: &TranslatorSelection,
+        name: &str,
+        target: &Path,
+        dirs: &ArtifactDirs,
+    ) -> Result<Self, Error> {
         let original_file = std::fs::OpenOptions::new()
             .read(true)
-            .open(&textures.name)
-            .unwrap_or_else(|_| panic!("Failed to open file {}", &textures.name));
-        let ext = std::path::Path::new(&textures.name)
-            .extension()
-            .unwrap()
-            .to_str()
-            .unwrap();
+            .open(name)
+            .map_err(Error::io(name))?;
+        let mut tmp = target.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = std::path::PathBuf::from(tmp);
         let rewritten_file = std::fs::OpenOptions::new()
             .create(true)
             .write(true)
             .truncate(true)
-            .open(format!(
-                "{}.translated_{:?}.{}",
-                textures.name, translator, ext
-            ))
-            .unwrap_or_else(|_| panic!("Failed to open file {}", &textures.name));
-        let mut reader = std::io::BufReader::new(original_file);
-        let mut buf: [u8; 8192] = [0; 8192];
-        let mut last_read_at = 0;
-        let mut pre_read_at = 0;
-
-        let mut writer = std::io::BufWriter::new(rewritten_file);
-        let mut i = 0;
+            .open(&tmp)
+            .map_err(Error::io(&tmp.to_string_lossy()))?;
+        Ok(Self {
+            output,
+            selection: selection.clone(),
+            name: name.to_string(),
+            dirs: dirs.clone(),
+            target: target.to_path_buf(),
+            tmp,
+            report: OutputReport::default(),
+            reader: std::io::BufReader::new(original_file),
+            writer: std::io::BufWriter::new(rewritten_file),
+            buf: [0; 8192],
+            last_read_at: 0,
+            pre_read_at: 0,
+            failed: vec![],
+            cache: None,
+            splices: None,
+        })
+    }
+
+    /// reuse the lines extracted by the last live output
+    pub fn with_cache(mut self, cache: &'a OutputCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// verify the untouched bytes of the rewritten file before it replaces the target
+    pub fn with_verify(mut self) -> Self {
+        self.splices = Some(vec![]);
+        self
+    }
 
-        let mut dignostic_failed_range = vec![];
+    pub fn feed(&mut self, textures: &Textures) -> std::io::Result<()> {
+        let tran_lines = self.extract_batches(textures);
+        let mut i = 0;
         while i < textures.lines.len() {
-            let line = &textures.lines[i];
-            if let Some(translated) = line.translated.iter().find(|t| t.translator == translator) {
-                // check translated lines equals to raw lines
-                let content = translated.content.as_str();
-                let tran_lines = self.extract_lines(content);
-                // dignostic
-                if tran_lines.len() != translated.batch_range.1 - translated.batch_range.0 + 1 {
-                    dignostic_failed_range
-                        .push((translated.batch_range.0, translated.batch_range.1));
-                    i = translated.batch_range.1 + 1;
-                    eprintln!(
-                        "[Dignostic] batch range: {}-{}, expected size: {}, but extracted lines size: {}",
-                        translated.batch_range.0,
-                        translated.batch_range.1,
-                        translated.batch_range.1 - translated.batch_range.0 + 1,
-                        tran_lines.len()
-                    );
-                    // let mut tran_lines_iter = tran_lines.iter();
-                    // let mut raw_lines_iter =
-                    // textures.lines[translated.batch_range.0..=translated.batch_range.1].iter();
-                    // loop {
-                    //     let tran_line = tran_lines_iter.next();
-                    //     let raw_line = raw_lines_iter.next();
-                    //     if tran_line.is_none() || raw_line.is_none() {
-                    //         break;
-                    //     }
-                    //     eprintln!(
-                    //         "[Dignostic] raw: {}\n[Dignostic] tran: {}",
-                    //         raw_line.unwrap().content,
-                    //         tran_line.unwrap(),
-                    //     );
-                    // }
-                    continue;
+            let raw_line = &textures.lines[i];
+            let (range, tran_line) = join_line(textures, &tran_lines, i);
+            i += range.len().max(1);
+            let Some(tran_line) = tran_line else {
+                if range
+                    .into_iter()
+                    .any(|k| textures.lines[k].needs_translation())
+                {
+                    self.report.skipped += 1;
+                }
+                continue;
+            };
+            self.report.written += 1;
+            if !raw_line.parts.is_empty() {
+                self.write_parts(raw_line, &tran_line)?;
+                continue;
+            }
+            match raw_line.span {
+                Some((start, end)) => {
+                    let fmt = self.output.format_span(&raw_line.content, &tran_line);
+                    self.splice(raw_line.seek + start, raw_line.seek + end, &fmt, false)?;
                 }
-                // write
-                for (j, tran_line) in tran_lines.iter().enumerate() {
-                    let raw_line = &textures.lines[i + j];
-                    // check before not writed
-                    if raw_line.seek > pre_read_at {
-                        reader
-                            .seek_relative((pre_read_at - last_read_at) as i64)
-                            .unwrap();
-                        last_read_at = pre_read_at;
-                        let mut size = raw_line.seek - pre_read_at;
-                        while size > 0 {
-                            let buf_slice = if size > buf.len() {
-                                &mut buf
-                            } else {
-                                &mut buf[..size]
-                            };
-                            let read_size = reader.read(buf_slice).unwrap();
-                            last_read_at += read_size;
-                            size -= read_size;
-                            let _ = writer.write(&buf_slice[..read_size]).unwrap();
-                        }
-                    }
-                    // write translated lines
-                    let fmt = self.format_line(&raw_line.content, tran_line);
-                    let _ = writer.write(fmt.as_bytes()).unwrap();
-                    pre_read_at = raw_line.seek + raw_line.size;
+                None => {
+                    let fmt = self.output.format_line(&raw_line.content, &tran_line);
+                    self.splice(raw_line.seek, raw_line.seek + raw_line.size, &fmt, true)?;
                 }
-                // skip the batch
-                i = translated.batch_range.1 + 1;
-            } else {
-                i += 1;
             }
         }
-        reader
-            .seek_relative((pre_read_at - last_read_at) as i64)
-            .unwrap();
-        // println!(
-        //     "pre read at: {} last read at: {}",
-        //     pre_read_at, last_read_at
-        // );
+        Ok(())
+    }
+
+    /// copy the original bytes until start, then write fmt instead of the bytes until end, a
+    /// whole line keeps the line ending of the original line, CRLF, LF or none at the end
+    fn splice(&mut self, start: usize, end: usize, fmt: &str, line: bool) -> std::io::Result<()> {
+        if start > self.pre_read_at {
+            self.copy_until(start)?;
+        }
+        // read the replaced bytes instead of skipping them, for their line ending
+        self.reader
+            .seek_relative(start as i64 - self.last_read_at as i64)?;
+        let mut replaced = vec![0; end - start];
+        self.reader.read_exact(&mut replaced)?;
+        self.last_read_at = end;
+        let fmt = match line {
+            true => with_line_ending(fmt, line_ending(&replaced)),
+            false => fmt.to_string(),
+        };
+        self.writer.write_all(fmt.as_bytes())?;
+        if let Some(splices) = &mut self.splices {
+            splices.push(Splice {
+                start,
+                end,
+                written: fmt.len(),
+            });
+        }
+        self.pre_read_at = end;
+        Ok(())
+    }
+
+    /// extract the translated lines of every batch, indexed like textures.lines,
+    /// the lines of the batches failed to extract are None
+    fn extract_batches(&mut self, textures: &Textures) -> Vec<Option<String>> {
+        let failed = &mut self.failed;
+        let (output, cache) = (self.output, self.cache);
+        self.selection.select(
+            textures,
+            |content| match cache {
+                Some(cache) => cache.extract(content, |c| output.extract_lines(c)),
+                None => output.extract_lines(content),
+            },
+            |(start, end), expected, extracted| {
+                if cache.is_none() {
+                    failed.push(mismatch(textures, (start, end), expected, extracted));
+                }
+            },
+        )
+    }
+
+    /// split the translation of a paragraph back across its original lines
+    fn write_parts(&mut self, raw_line: &TextureLine, tran_line: &str) -> std::io::Result<()> {
+        let weights = raw_line
+            .parts
+            .iter()
+            .map(|p| raw_line.content[p.start..p.end].chars().count())
+            .collect::<Vec<_>>();
+        let pieces = split_paragraph(tran_line, &weights);
+        for (part, piece) in raw_line.parts.iter().zip(pieces) {
+            let raw = &raw_line.content[part.start..part.end];
+            let fmt = self.output.format_line(raw, &piece);
+            self.splice(part.seek, part.seek + part.size, &fmt, true)?;
+        }
+        Ok(())
+    }
+
+    /// skip the replaced bytes, then copy the original bytes until the seek
+    fn copy_until(&mut self, seek: usize) -> std::io::Result<()> {
+        self.reader
+            .seek_relative((self.pre_read_at - self.last_read_at) as i64)?;
+        self.last_read_at = self.pre_read_at;
+        let mut size = seek - self.pre_read_at;
+        while size > 0 {
+            let len = size.min(self.buf.len());
+            let read_size = self.reader.read(&mut self.buf[..len])?;
+            if read_size == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            self.last_read_at += read_size;
+            size -= read_size;
+            self.writer.write_all(&self.buf[..read_size])?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<OutputReport> {
+        self.reader
+            .seek_relative((self.pre_read_at - self.last_read_at) as i64)?;
         loop {
-            let size = reader.read(&mut buf).unwrap();
+            let size = self.reader.read(&mut self.buf)?;
             if size == 0 {
                 break;
             }
-            let _ = writer.write(&buf[..size]).unwrap();
+            self.writer.write_all(&self.buf[..size])?;
+        }
+        // the file must be closed before renamed on windows
+        let file = self.writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        drop(file);
+        // a corrupted output is left as target.tmp for inspection, the target is not replaced
+        if let Some(splices) = &self.splices {
+            verify_rewrite(Path::new(&self.name), &self.tmp, splices)?;
+            let verified = t!(Msg::Verified, self.target.display(), self.name);
+            println!("[Verify] {}", verified);
         }
-        if dignostic_failed_range.is_empty() {
-            let _ = std::fs::remove_file(format!("{}.dignostic_failed_range.json", textures.name));
-        } else {
-            // try deledte dignostic file
-            println!("[Dignostic] failed range: {:?}", dignostic_failed_range);
-            let writer = std::fs::OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(format!("{}.dignostic_failed_range.json", textures.name))
-                .expect("Failed to create file");
-            let writer = std::io::BufWriter::new(writer);
-            serde_json::to_writer(writer, &dignostic_failed_range).unwrap();
+        fs::rename(&self.tmp, &self.target)?;
+        self.report.failed_batches = self.failed.len();
+        if self.cache.is_none() {
+            save_failed(&self.dirs, &self.name, self.failed)?;
         }
+        Ok(self.report)
     }
 }
 
+/// the range of the lines joined into the line at i, and their joined translation, None if a line
+/// to translate is not translated
+pub(super) fn join_line(
+    textures: &Textures,
+    tran_lines: &[Option<String>],
+    i: usize,
+) -> (std::ops::Range<usize>, Option<String>) {
+    // the sentences of a long line are translated separately, joined back before written
+    let count = textures.lines[i]
+        .sentence
+        .map(|(_, count)| count)
+        .unwrap_or(1);
+    let end = (i + count).min(tran_lines.len());
+    // a skipped sentence is kept as it is
+    let sentences = (i..end)
+        .map(|k| match &textures.lines[k] {
+            line if count > 1 && line.skip => Some(&line.content),
+            _ => tran_lines[k].as_ref(),
+        })
+        .collect::<Option<Vec<_>>>();
+    (i..end, sentences.map(|s| join_sentences(&s)))
+}
+
+/// the batch of the textures whose extracted lines mismatch its lines
+pub(super) fn mismatch(
+    textures: &Textures,
+    (start, end): (usize, usize),
+    expected: usize,
+    extracted: usize,
+) -> FailedBatch {
+    let offset = textures.offset();
+    let mismatch = t!(
+        Msg::MismatchBatch,
+        start + offset,
+        end + offset,
+        expected,
+        extracted
+    );
+    eprintln!(
+        "{} {}",
+        console::err(Tone::Warning, "[Dignostic]"),
+        mismatch
+    );
+    let detail = format!("expected {} lines, extracted {}", expected, extracted);
+    FailedBatch::of(textures, (start, end), FailReason::Mismatch).with_detail(detail)
+}
+
+/// record the mismatched batches of the output in file.failed.json
+pub(super) fn save_failed(dirs: &ArtifactDirs, name: &str, failed: Vec<FailedBatch>) -> Result<()> {
+    if !failed.is_empty() {
+        let ranges = failed.iter().map(|b| b.range).collect::<Vec<_>>();
+        let ranges = t!(Msg::FailedRange, format!("{:?}", ranges));
+        println!("{} {}", console::out(Tone::Warning, "[Dignostic]"), ranges);
+    }
+    let mut report = FailedReport::load(dirs, name);
+    report.replace(FailReason::Mismatch, failed);
+    report.save(dirs, name)?;
+    if !report.is_empty() {
+        let failed = dirs.failed(name);
+        let hint = t!(Msg::RetranslateHint, report.batches.len(), failed.display());
+        println!("[Dignostic] {}", hint);
+    }
+    Ok(())
+}
+
+/// the line ending of the bytes of a line: CRLF, LF, or none for the last line of a file
+fn line_ending(line: &[u8]) -> &'static str {
+    if line.ends_with(b"\r\n") {
+        "\r\n"
+    } else if line.ends_with(b"\n") {
+        "\n"
+    } else {
+        ""
+    }
+}
+
+/// the formatted line with the line ending, the line breaks inside a wrapped line follow it
+fn with_line_ending(fmt: &str, ending: &str) -> String {
+    let body = fmt.trim_end_matches(['\r', '\n']);
+    let body = match ending {
+        "\r\n" => body.replace("\r\n", "\n").replace('\n', "\r\n"),
+        _ => body.to_string(),
+    };
+    format!("{}{}", body, ending)
+}
+
+/// split the translation into pieces in proportion to the weights,
+/// every cut is moved to the nearest sentence end if there is one close enough.
+pub fn split_paragraph(translation: &str, weights: &[usize]) -> Vec<String> {
+    let chars = translation.chars().collect::<Vec<_>>();
+    let total = chars.len();
+    let total_weight = weights.iter().sum::<usize>().max(1);
+    let tolerance = total / weights.len().max(1) / 2;
+    let mut pieces = Vec::with_capacity(weights.len());
+    let mut from = 0;
+    let mut acc = 0;
+    for (k, weight) in weights.iter().enumerate() {
+        if k == weights.len() - 1 {
+            pieces.push(chars[from..].iter().collect::<String>().trim().to_string());
+            break;
+        }
+        acc += weight;
+        let target = (total * acc / total_weight).clamp(from, total);
+        let cut = (from + 1..=total)
+            .filter(|&i| is_sentence_end(chars[i - 1]))
+            .min_by_key(|&i| i.abs_diff(target))
+            .filter(|&i| i.abs_diff(target) <= tolerance)
+            .unwrap_or(target);
+        pieces.push(
+            chars[from..cut]
+                .iter()
+                .collect::<String>()
+                .trim()
+                .to_string(),
+        );
+        from = cut;
+    }
+    pieces
+}
+
 #[cfg(test)]
 mod test {
+    use std::path::Path;
+
     use regex::Regex;
 
-    use crate::{RegexDescription, RegexUsage};
+    use super::{
+        in_place_hash, prepare_in_place, record_in_place, split_paragraph, ArtifactDirs,
+        OutputCache, OutputPipeline, OutputReport, RewriteOutput, Rewriter, Textures,
+    };
+    use crate::translators::Translator;
 
-    use super::SimpleTextOutput;
+    /// the numbered lines `(1) xxx`
+    fn numbered() -> OutputPipeline {
+        OutputPipeline::from_output_regexen(r"\n[^\n\(]", r"\(\d+\)\s?(.+)").unwrap()
+    }
+
+    /// feed the whole textures to a rewriter of the ChatGPT translations
+    fn rewrite<T: RewriteOutput>(output: &T, textures: &Textures, target: &Path) -> OutputReport {
+        let selection = Translator::ChatGPT.into();
+        let dirs = ArtifactDirs::default();
+        let mut rewriter =
+            Rewriter::new(output, &selection, &textures.name, target, &dirs).unwrap();
+        rewriter.feed(textures).unwrap();
+        rewriter.finish().unwrap()
+    }
 
     #[test]
-    fn test_clear() {
-        let output = SimpleTextOutput::new(vec![RegexDescription {
-            usage: RegexUsage::Replace("".to_string()),
-            regex: r"(翻译为[:：]\n?|^\d+\.\s?|是否违规.*)".to_string(),
-        }]);
-        let content = "翻译为:\n1. \"......\"\n2. 我非常兴奋。心跳加速，听到自己心脏跳动的声音。无论如何想要吸烟，于是在口袋里掏找着。\n3. 握住火柴盒的手指微微颤抖着。含上烟，小心地搓动火柴。火柴点燃了香烟，意识到烟深深地吸入。\n4. \"呼——\"\n5. 心静如水。充分体验错觉。然后我从豪华的皮革沙发上微微坐起，向右伸手，拿起桌子上的水晶球。\n6. 握着平球大小的小水晶球。慢慢地注入体内魔力，观影用的巨大窗户一下子就打开了。\n7. \"......\"\n8. 原来是一个明亮的黄色房间，明亮的黄色墙纸和宽广的地毯铺满了整个房间。\n是否违规: 否";
-        println!("before \n{}", content);
-        let content = output.clear(content);
-        println!("after \n{}", content);
-        let content = "(1) 在各种意义上，刊登了展示夫妻关系良好的短篇小说。\n是否违规：否\n\n(2) 顺带一提，在第24集之后，我想“年龄差25岁？”但是，她和桜井之间的年龄差为23岁。\n是否违规：否\n\n(3) 我不戴戒指是因为平日要做家务。\n是否违规：否\n\n(4) 由于外表和性格等原因，他在某些方面比女儿更受欢迎。是的，他是可爱本的对象。\n是否违规：否";
-        let output = SimpleTextOutput::new(vec![
-            RegexDescription {
-                usage: RegexUsage::Replace("".to_string()),
-                regex: r"(是否违规.+|\(\d+\)\s?)".to_string(),
-            },
-            RegexDescription {
-                usage: RegexUsage::Replace("\n".to_string()),
-                regex: r"\n{2,}".to_string(),
-            },
-        ]);
-        println!("before \n{}", content);
-        let content = output.clear(content);
-        println!("after \n{}", content);
+    fn test_split_paragraph() {
+        let pieces = split_paragraph("Hello there. How are you? Fine.", &[6, 7, 3]);
+        assert_eq!(pieces, vec!["Hello there.", "How are you?", "Fine."]);
+        let pieces = split_paragraph("第一句。第二句。", &[4, 4]);
+        assert_eq!(pieces, vec!["第一句。", "第二句。"]);
+        let pieces = split_paragraph("abcdef", &[1, 1]);
+        assert_eq!(pieces, vec!["abc", "def"]);
+    }
+
+    #[test]
+    fn test_rewrite_span() {
+        use crate::{
+            inputs::{Input, TextInput},
+            outputs::replace::ReplaceOutput,
+            textures::TranslatedLine,
+            translators::Translator,
+        };
+        let dir = crate::utils::TempDir::new("rewrite_span");
+        let file = dir.join("rewrite_span.txt");
+        let file = file.to_str().unwrap();
+        std::fs::write(
+            file,
+            ";m[1] = \"好的\"\n;s[2] = \"角色\"\n;m[3] = \"再见\"\n",
+        )
+        .unwrap();
+        let mut textures = TextInput::new(vec![r#"^;m\[\d+\]"#.to_string()])
+            .unwrap()
+            .with_capture(Some(r#"=\s"(.+)""#))
+            .unwrap()
+            .read(file)
+            .unwrap();
+        textures.update(TranslatedLine::new(
+            Translator::ChatGPT,
+            "(1) OK\n(2) Bye".to_string(),
+            0,
+            1,
+        ));
+        let output = ReplaceOutput::new(numbered(), "= \"$trans\"", r#"=\s"(.+)""#).unwrap();
+        let translated = ArtifactDirs::default().translated(file, Translator::ChatGPT);
+        rewrite(&output, &textures, &translated);
+        assert_eq!(
+            std::fs::read_to_string(&translated).unwrap(),
+            ";m[1] = \"OK\"\n;s[2] = \"角色\"\n;m[3] = \"Bye\"\n"
+        );
+        // both fields of a line are spliced
+        std::fs::write(file, "name \"勇者\" text \"行くよ\"\n").unwrap();
+        let mut textures = TextInput::new(Vec::<String>::new())
+            .unwrap()
+            .with_capture(Some(r#"^name "(.*)" text "(.*)""#))
+            .unwrap()
+            .read(file)
+            .unwrap();
+        textures.update(TranslatedLine::new(
+            Translator::ChatGPT,
+            "(1) Hero\n(2) Let's go".to_string(),
+            0,
+            1,
+        ));
+        rewrite(&output, &textures, &translated);
+        assert_eq!(
+            std::fs::read_to_string(&translated).unwrap(),
+            "name \"Hero\" text \"Let's go\"\n"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_sentences() {
+        use crate::{
+            inputs::{Input, TextInput},
+            outputs::text::TextOutput,
+            segment::SegmentOptions,
+            textures::TranslatedLine,
+            translators::Translator,
+        };
+        let dir = crate::utils::TempDir::new("rewrite_sentences");
+        let file = dir.join("rewrite_sentences.txt");
+        let file = file.to_str().unwrap();
+        std::fs::write(file, "前言\n第一句。第二句。\n").unwrap();
+        let mut textures = TextInput::new(Vec::<String>::new())
+            .unwrap()
+            .with_segment(Some(SegmentOptions {
+                max_chars: 4,
+                line_width: None,
+            }))
+            .read(file)
+            .unwrap();
+        assert_eq!(textures.lines.len(), 3);
+        textures.update(TranslatedLine::new(
+            Translator::ChatGPT,
+            "(1) Foreword\n(2) First.\n(3) Second.".to_string(),
+            0,
+            2,
+        ));
+        let mut output = TextOutput::new(numbered());
+        output.set_line_width(Some(10));
+        let translated = ArtifactDirs::default().translated(file, Translator::ChatGPT);
+        let report = rewrite(&output, &textures, &translated);
+        assert_eq!(
+            std::fs::read_to_string(&translated).unwrap(),
+            "Foreword\nFirst.\nSecond.\n"
+        );
+        assert_eq!(
+            report,
+            OutputReport {
+                written: 2,
+                skipped: 0,
+                failed_batches: 0,
+                low_score_batches: 0
+            }
+        );
+        assert!(!std::path::Path::new(&format!("{}.tmp", translated.display())).exists());
+    }
+
+    #[test]
+    fn test_rewrite_report() {
+        use crate::{
+            inputs::{Input, TextInput},
+            outputs::text::TextOutput,
+            textures::TranslatedLine,
+            translators::Translator,
+        };
+        let dir = crate::utils::TempDir::new("rewrite_report");
+        let file = dir.join("rewrite_report.txt");
+        let file = file.to_str().unwrap();
+        std::fs::write(file, "一\n二\n三\n四\n五\n").unwrap();
+        let mut textures = TextInput::new(Vec::<String>::new())
+            .unwrap()
+            .read(file)
+            .unwrap();
+        textures.update(TranslatedLine::new(
+            Translator::ChatGPT,
+            "(1) One\n(2) Two".to_string(),
+            0,
+            1,
+        ));
+        // a batch of 2 lines extracted as 1, the 5th line is not translated yet
+        textures.update(TranslatedLine::new(
+            Translator::ChatGPT,
+            "(1) Three".to_string(),
+            2,
+            3,
+        ));
+        let output = TextOutput::new(numbered());
+        let translated = ArtifactDirs::default().translated(file, Translator::ChatGPT);
+        std::fs::write(&translated, "old").unwrap();
+        let tmp = format!("{}.tmp", translated.display());
+
+        // the target is kept until the rewriter finishes
+        let mut rewriter = Rewriter::new(
+            &output,
+            &Translator::ChatGPT.into(),
+            file,
+            &translated,
+            &ArtifactDirs::default(),
+        )
+        .unwrap();
+        rewriter.feed(&textures).unwrap();
+        drop(rewriter);
+        assert_eq!(std::fs::read_to_string(&translated).unwrap(), "old");
+
+        let report = rewrite(&output, &textures, &translated);
+        assert_eq!(
+            std::fs::read_to_string(&translated).unwrap(),
+            "One\nTwo\n三\n四\n五\n"
+        );
+        assert_eq!((report.written, report.skipped), (2, 3));
+        assert_eq!(report.failed_batches, 1);
+        assert!(!Path::new(&tmp).exists());
+    }
+
+    #[test]
+    fn test_rewrite_line_endings() {
+        use crate::{
+            inputs::{Input, TextInput},
+            outputs::text::TextOutput,
+            textures::TranslatedLine,
+            translators::Translator,
+        };
+        let dir = crate::utils::TempDir::new("rewrite_line_endings");
+        let file = dir.join("rewrite_line_endings.txt");
+        let file = file.to_str().unwrap();
+        // CRLF and LF mixed, no line ending at the end
+        std::fs::write(file, "一\r\n\r\n二\n三").unwrap();
+        let mut textures = TextInput::new(Vec::<String>::new())
+            .unwrap()
+            .read(file)
+            .unwrap();
+        textures.update(TranslatedLine::new(
+            Translator::ChatGPT,
+            "(1) One two\n(2) Two\n(3) Six".to_string(),
+            0,
+            2,
+        ));
+        let mut output = TextOutput::new(numbered());
+        output.set_line_width(Some(4));
+        let translated = ArtifactDirs::default().translated(file, Translator::ChatGPT);
+        // the splices of the line endings are verified against the original
+        let mut rewriter = Rewriter::new(
+            &output,
+            &Translator::ChatGPT.into(),
+            file,
+            &translated,
+            &ArtifactDirs::default(),
+        )
+        .unwrap()
+        .with_verify();
+        rewriter.feed(&textures).unwrap();
+        rewriter.finish().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&translated).unwrap(),
+            "One\r\ntwo\r\n\r\nTwo\nSix"
+        );
+    }
+
+    #[test]
+    fn test_live_rewrite() {
+        use crate::{
+            inputs::{Input, TextInput},
+            outputs::text::TextOutput,
+            textures::TranslatedLine,
+            translators::Translator,
+        };
+        let dir = crate::utils::TempDir::new("live_rewrite");
+        let file = dir.join("live_rewrite.txt");
+        let file = file.to_str().unwrap();
+        std::fs::write(file, "一\n二\n三\n四\n").unwrap();
+        let mut textures = TextInput::new(Vec::<String>::new())
+            .unwrap()
+            .read(file)
+            .unwrap();
+        let output = TextOutput::new(numbered());
+        let selection = Translator::ChatGPT.into();
+        let dirs = ArtifactDirs::default();
+        let translated = dirs.translated(file, Translator::ChatGPT);
+        let cache = OutputCache::default();
+        let live = |textures: &Textures| {
+            let mut rewriter = Rewriter::new(&output, &selection, file, &translated, &dirs)
+                .unwrap()
+                .with_cache(&cache);
+            rewriter.feed(textures).unwrap();
+            rewriter.finish().unwrap()
+        };
+        textures.update(TranslatedLine::new(
+            Translator::ChatGPT,
+            "(1) One\n(2) Two".to_string(),
+            0,
+            1,
+        ));
+        assert_eq!(live(&textures).written, 2);
+        // a mismatched batch is left to the output at the end
+        textures.update(TranslatedLine::new(
+            Translator::ChatGPT,
+            "(1) Three".to_string(),
+            2,
+            3,
+        ));
+        let report = live(&textures);
+        assert_eq!((report.written, report.skipped), (2, 2));
+        assert_eq!(
+            std::fs::read_to_string(&translated).unwrap(),
+            "One\nTwo\n三\n四\n"
+        );
+        assert_eq!(cache.0.lock().unwrap().len(), 2);
+        assert!(!dirs.failed(file).exists());
+    }
+
+    #[test]
+    fn test_prepare_in_place() {
+        let dir = crate::utils::TempDir::new("prepare_in_place");
+        let file = dir.join("prepare_in_place.txt");
+        let file = file.to_str().unwrap();
+        let bak = format!("{}.bak", file);
+        std::fs::write(file, "original").unwrap();
+        prepare_in_place(file).unwrap();
+        assert_eq!(std::fs::read_to_string(&bak).unwrap(), "original");
+        // simulate a patched file, it should be restored from the backup
+        std::fs::write(file, "patched").unwrap();
+        record_in_place(file).unwrap();
+        prepare_in_place(file).unwrap();
+        assert_eq!(std::fs::read_to_string(file).unwrap(), "original");
+        // an updated source is not the patched file, it is backed up instead of restored
+        std::fs::write(file, "updated").unwrap();
+        prepare_in_place(file).unwrap();
+        assert_eq!(std::fs::read_to_string(file).unwrap(), "updated");
+        assert_eq!(std::fs::read_to_string(&bak).unwrap(), "updated");
+        assert!(!std::path::Path::new(&in_place_hash(file)).exists());
+    }
+
+    #[test]
+    fn test_in_place_twice() {
+        use crate::{
+            config, inputs::in_put, outputs::out_put, textures::TranslatedLine,
+            translators::Translator,
+        };
+        let dir = crate::utils::TempDir::new("in_place_twice");
+        let config_path = dir.join("config.toml");
+        let config = "trans_type = \"text\"\npreset = \"numbered\"\nfrom = \"jpn\"\nto = \"eng\"\n\
+            in_place = true\n[batchizer_opt]\nmax_tokens = 256\n";
+        std::fs::write(&config_path, config).unwrap();
+        let cfg = config::load_config(config_path.to_str().unwrap(), None, None).unwrap();
+        let file = dir.join("a.txt");
+        std::fs::write(&file, "一\n二\n").unwrap();
+        let file = file.to_str().unwrap();
+        let mut textures = in_put(&cfg, file).unwrap();
+        textures.update(TranslatedLine::new(
+            Translator::ChatGPT,
+            "(1) One\n(2) Two".to_string(),
+            0,
+            1,
+        ));
+        textures.save().unwrap();
+        out_put(&cfg, &textures).unwrap();
+        assert_eq!(std::fs::read_to_string(file).unwrap(), "One\nTwo\n");
+        // the translated file is not taken as a changed source, the translations are kept
+        let textures = in_put(&cfg, file).unwrap();
+        assert_eq!(textures.lines[0].translated.len(), 1);
+        assert!(!cfg.dirs().failed(file).exists());
+        out_put(&cfg, &textures).unwrap();
+        assert_eq!(std::fs::read_to_string(file).unwrap(), "One\nTwo\n");
+        // an updated source replaces the backup and is parsed as a changed file
+        std::fs::write(file, "一\n三\n").unwrap();
+        let textures = in_put(&cfg, file).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(format!("{}.bak", file)).unwrap(),
+            "一\n三\n"
+        );
+        assert_eq!(textures.lines[1].content.trim(), "三");
+        assert!(textures.lines[1].translated.is_empty());
     }
 
     #[test]
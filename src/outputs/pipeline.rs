@@ -0,0 +1,170 @@
+use regex::Regex;
+
+use crate::{
+    error::{new_regex, Error},
+    Configuration, RegexDescription, RegexUsage,
+};
+
+/// the regexen of a stage of the output, run in order
+pub struct RegexChain(Vec<(RegexUsage, Regex)>);
+
+impl RegexChain {
+    /// the regexen are named name[i] in the errors
+    pub fn new(name: &str, regexen: &[RegexDescription]) -> Result<Self, Error> {
+        let chain = regexen
+            .iter()
+            .enumerate()
+            .map(|(i, r)| {
+                let regex = new_regex(&format!("{}[{}]", name, i), &r.regex)?;
+                Ok((r.usage.clone(), regex))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Self(chain))
+    }
+
+    /// a replace replaces every match, a capture keeps the group of the first match, the text is
+    /// kept when it does not match
+    pub fn apply(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for (usage, regex) in &self.0 {
+            match usage {
+                RegexUsage::Replace(replace) => {
+                    text = regex.replace_all(&text, replace.as_str()).to_string();
+                }
+                RegexUsage::Capture(index) => {
+                    let captures = regex.captures(&text);
+                    if let Some(capture) = captures.and_then(|c| c.get(*index)) {
+                        text = capture.as_str().to_string();
+                    }
+                }
+            }
+        }
+        text
+    }
+
+    /// a replace replaces every match of every piece, a capture splits every piece into the
+    /// groups of its matches, the pieces without a match are dropped
+    pub fn split(&self, text: &str) -> Vec<String> {
+        let mut pieces = vec![text.to_string()];
+        for (usage, regex) in &self.0 {
+            pieces = match usage {
+                RegexUsage::Replace(replace) => pieces
+                    .iter()
+                    .map(|p| regex.replace_all(p, replace.as_str()).to_string())
+                    .collect(),
+                RegexUsage::Capture(index) => pieces
+                    .iter()
+                    .flat_map(|p| {
+                        regex
+                            .captures_iter(p)
+                            .filter_map(|c| c.get(*index).map(|m| m.as_str().to_string()))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect(),
+            };
+        }
+        pieces
+    }
+}
+
+/// how the answer of a batch becomes its translated lines: response_cleanup runs over the whole
+/// answer, line_capture splits it into the lines, post_replace runs over every line
+pub struct OutputPipeline {
+    response_cleanup: RegexChain,
+    line_capture: RegexChain,
+    post_replace: RegexChain,
+}
+
+impl OutputPipeline {
+    /// the named stages of the configuration, or its output_regexen of an older version
+    pub fn new(config: &Configuration) -> Result<Self, Error> {
+        if !config.line_capture.is_empty() {
+            return Ok(Self {
+                response_cleanup: RegexChain::new("response_cleanup", &config.response_cleanup)?,
+                line_capture: RegexChain::new("line_capture", &config.line_capture)?,
+                post_replace: RegexChain::new("post_replace", &config.post_replace)?,
+            });
+        }
+        match config.output_regexen.as_slice() {
+            [cleanup, capture, ..] => Self::from_output_regexen(&cleanup.regex, &capture.regex),
+            _ => Err(Error::Config(
+                "line_capture is empty, add a regex capturing every translated line, e.g. \
+                 {usage = {capture = 1}, regex = '\\(\\d+\\)\\s?(.+)'}, or use a preset"
+                    .to_string(),
+            )),
+        }
+    }
+
+    /// the stages of output_regexen: the matches of the first regex are replaced by `\n`, the
+    /// group 1 of the second regex is a line, the quotes are removed from the lines
+    pub fn from_output_regexen(cleanup: &str, capture: &str) -> Result<Self, Error> {
+        let stage = |usage, regex: &str| RegexDescription {
+            usage,
+            regex: regex.to_string(),
+        };
+        Ok(Self {
+            response_cleanup: RegexChain::new(
+                "output_regexen[0]",
+                &[stage(RegexUsage::Replace("\\n".to_string()), cleanup)],
+            )?,
+            line_capture: RegexChain::new(
+                "output_regexen[1]",
+                &[stage(RegexUsage::Capture(1), capture)],
+            )?,
+            post_replace: RegexChain::new(
+                "post_replace",
+                &[stage(RegexUsage::Replace(String::new()), "\"")],
+            )?,
+        })
+    }
+
+    pub fn extract_lines(&self, content: &str) -> Vec<String> {
+        let content = self.response_cleanup.apply(content);
+        self.line_capture
+            .split(&content)
+            .iter()
+            .map(|line| self.post_replace.apply(line))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::OutputPipeline;
+    use crate::{RegexDescription, RegexUsage};
+
+    #[test]
+    fn test_output_pipeline() {
+        let stage = |usage, regex: &str| RegexDescription {
+            usage,
+            regex: regex.to_string(),
+        };
+        let mut config: crate::Configuration =
+            toml::from_str(include_str!("../../assets/options_text.toml")).unwrap();
+        // the numbered lines of a markdown list, the bold marks dropped
+        config.response_cleanup = vec![stage(RegexUsage::Replace(String::new()), r"(?m)^```.*\n")];
+        config.line_capture = vec![
+            stage(RegexUsage::Capture(1), r"(?m)^- (.+)$"),
+            stage(RegexUsage::Capture(1), r"^\(\d+\)\s?(.+)"),
+        ];
+        config.post_replace = vec![stage(RegexUsage::Replace(String::new()), r"\*\*")];
+        let pipeline = OutputPipeline::new(&config).unwrap();
+        let lines = pipeline.extract_lines("```\n- (1) **Hello**\n- (2) Bye\n- note\n```\n");
+        assert_eq!(lines, vec!["Hello", "Bye"]);
+        // the positional output_regexen of an older version
+        config.line_capture.clear();
+        config.output_regexen = vec![
+            stage(RegexUsage::Replace(String::new()), r"\n[^\n\(]"),
+            stage(RegexUsage::Capture(0), r"\(\d+\)\s?(.+)"),
+        ];
+        let pipeline = OutputPipeline::new(&config).unwrap();
+        let lines = pipeline.extract_lines("(1) \"Good\" night\n(2) Hi");
+        assert_eq!(lines, vec!["Good night", "Hi"]);
+        config.output_regexen.clear();
+        assert!(OutputPipeline::new(&config)
+            .err()
+            .unwrap()
+            .to_string()
+            .contains("line_capture is empty"));
+    }
+}
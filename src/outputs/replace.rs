@@ -1,72 +1,111 @@
 use regex::Regex;
 
-use super::{output::RewriteOutput, text::TextOutput};
+use crate::{
+    error::{new_regex, Error},
+    segment::{wrap, WidthMode},
+};
+
+use super::{output::RewriteOutput, pipeline::OutputPipeline, text::TextOutput};
 
 pub struct ReplaceOutput {
     text_output: TextOutput,
     line_width: Option<usize>,
+    width_mode: WidthMode,
     replace_expression: String,
     capture_regex: Regex,
 }
 
 impl ReplaceOutput {
     pub fn new(
-        replace_rule: &str,
-        capture_rule: &str,
+        pipeline: OutputPipeline,
         replace_expression: &str,
         capture_regex: &str,
-    ) -> Self {
-        Self {
-            text_output: TextOutput::new(replace_rule, capture_rule),
+    ) -> Result<Self, Error> {
+        if !has_trans(replace_expression) {
+            return Err(Error::Config(
+                "replace_expression must contain $trans".to_string(),
+            ));
+        }
+        Ok(Self {
+            text_output: TextOutput::new(pipeline),
             line_width: None,
+            width_mode: WidthMode::default(),
             replace_expression: replace_expression.to_string(),
-            capture_regex: Regex::new(capture_regex).unwrap(),
-        }
+            capture_regex: new_regex("capture_regex", capture_regex)?,
+        })
     }
 
     pub fn set_line_width(&mut self, line_width: Option<usize>) {
         self.line_width = line_width;
     }
+
+    pub fn set_width_mode(&mut self, width_mode: WidthMode) {
+        self.width_mode = width_mode;
+    }
 }
 
 impl RewriteOutput for ReplaceOutput {
     fn extract_lines(&self, content: &str) -> Vec<String> {
         self.text_output.extract_lines(content)
     }
+    /// $trans or ${trans} is the translation, $1 or ${name} a group of the capture_regex
     fn format_line(&self, raw: &str, content: &str) -> String {
-        let content = escape_json_string(content, self.line_width);
-        let content = self.replace_expression.replace("$trans", &content);
-        let content = self.capture_regex.replace(raw, content);
-        content.to_string()
+        let content = escape_json_string(content, self.line_width, self.width_mode);
+        self.replace_trans(raw, &content)
+    }
+    fn format_span(&self, _: &str, content: &str) -> String {
+        escape_json_string(content, self.line_width, self.width_mode)
     }
+    /// the group 1 is the source, kept escaped as it is in the line
+    fn format_joined(
+        &self,
+        raw: &str,
+        content: &str,
+        join: &dyn Fn(&str, &str) -> String,
+    ) -> Option<String> {
+        let source = self.capture_regex.captures(raw)?.get(1)?.as_str();
+        let content = escape_json_string(content, self.line_width, self.width_mode);
+        Some(self.replace_trans(raw, &join(source, &content)))
+    }
+}
+
+impl ReplaceOutput {
+    /// replace the capture of the line by the replace_expression with $trans as the value
+    fn replace_trans(&self, raw: &str, value: &str) -> String {
+        // a $ of the translation is not a group
+        let value = value.replace('$', "$$");
+        let expression = self
+            .replace_expression
+            .replace("${trans}", &value)
+            .replace("$trans", &value);
+        self.capture_regex.replace(raw, expression).to_string()
+    }
+}
+
+/// the replace_expression has the flag of the translation, $trans or ${trans}
+pub fn has_trans(replace_expression: &str) -> bool {
+    replace_expression.contains("$trans") || replace_expression.contains("${trans}")
+}
+
+/// wrap the string to line_width measured by width_mode, the lines joined by \n
+pub(super) fn wrap_value(s: &str, line_width: Option<usize>, width_mode: WidthMode) -> String {
+    wrap(s, line_width.unwrap_or(3000), width_mode).join("\n")
 }
 
-fn escape_json_string(s: &str, line_width: Option<usize>) -> String {
-    let line_width = line_width.unwrap_or(3000);
+/// wrap the string to line_width measured by width_mode, then escape it as a json string
+fn escape_json_string(s: &str, line_width: Option<usize>, width_mode: WidthMode) -> String {
     let mut escaped = String::new();
-    let mut line_len = 0;
-    for c in s.chars() {
-        line_len += 1;
+    for c in wrap_value(s, line_width, width_mode).chars() {
         match c {
             '"' => escaped.push_str(r#"\""#),
             '\\' => escaped.push_str(r#"\\"#),
             '\x08' => escaped.push_str(r#"\b"#),
             '\x0c' => escaped.push_str(r#"\f"#),
-            '\n' => {
-                line_len = 0;
-                escaped.push_str(r#"\n"#);
-            }
-            '\r' => {
-                line_len = 0;
-                escaped.push_str(r#"\r"#);
-            }
+            '\n' => escaped.push_str(r#"\n"#),
+            '\r' => escaped.push_str(r#"\r"#),
             '\t' => escaped.push_str(r#"\t"#),
             _ => escaped.push(c),
         }
-        if line_len >= line_width {
-            line_len = 0;
-            escaped.push_str(r#"\n"#);
-        }
     }
     escaped
 }
@@ -78,13 +117,21 @@ mod test {
     #[test]
     fn escape_json_string_test() {
         let s = r#"hello\world"#;
-        let escaped = escape_json_string(s, Some(5));
+        let escaped = escape_json_string(s, Some(5), WidthMode::Display);
         assert_eq!(escaped, "hello\\n\\\\worl\\nd");
+        let escaped = escape_json_string("你好，世界", Some(6), WidthMode::Display);
+        assert_eq!(escaped, "你好，\\n世界");
+        let escaped = escape_json_string("你好，世界", Some(3), WidthMode::Chars);
+        assert_eq!(escaped, "你好，\\n世界");
+    }
+
+    fn pipeline() -> OutputPipeline {
+        OutputPipeline::from_output_regexen(r#""(.*)""#, r#""(.*)""#).unwrap()
     }
 
     #[test]
     fn test_format_line_for_mtool() {
-        let output = ReplaceOutput::new(r#""(.*)""#, r#""(.*)""#, r#": "$trans""#, r#":\s"(.+)""#);
+        let output = ReplaceOutput::new(pipeline(), r#": "$trans""#, r#":\s"(.+)""#).unwrap();
         let line = output.format_line(r#""请翻译": "待翻译","#, "翻译完成");
         assert_eq!(line, r#""请翻译": "翻译完成","#);
         let content = r#" "请原\"谅\"我": "请原\"谅\"我", "#;
@@ -92,11 +139,34 @@ mod test {
         assert_eq!(line, r#" "请原\"谅\"我": "翻译完成", "#);
     }
 
+    #[test]
+    fn test_format_line_with_groups() {
+        let output = ReplaceOutput::new(
+            pipeline(),
+            r#"${tag}"$2" text "${trans}""#,
+            r#"(?P<tag>name )"(.+?)" text "(.+)""#,
+        )
+        .unwrap();
+        let line = output.format_line(r#"name "勇者" text "5$ください""#, "$5, please");
+        assert_eq!(line, r#"name "勇者" text "$5, please""#);
+    }
+
     #[test]
     fn test_format_line_for_ain() {
-        let output = ReplaceOutput::new(r#""(.*)""#, r#""(.*)""#, r#"= "$trans""#, r#"=\s"(.+)""#);
+        let output = ReplaceOutput::new(pipeline(), r#"= "$trans""#, r#"=\s"(.+)""#).unwrap();
         let content = r#";m[300] = "请原谅我""#;
         let line = output.format_line(content, "翻译完成");
         assert_eq!(line, r#";m[300] = "翻译完成""#);
     }
+
+    #[test]
+    fn test_format_line_width_mode() {
+        let mut output = ReplaceOutput::new(pipeline(), r#": "$trans""#, r#":\s"(.+)""#).unwrap();
+        output.set_line_width(Some(4));
+        let line = output.format_line(r#""原文": "原文","#, "你好世界");
+        assert_eq!(line, r#""原文": "你好\n世界","#);
+        output.set_width_mode(WidthMode::Chars);
+        let line = output.format_line(r#""原文": "原文","#, "你好世界");
+        assert_eq!(line, r#""原文": "你好世界","#);
+    }
 }
@@ -0,0 +1,529 @@
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use crate::{i18n::Msg, t, textures::Textures, translators::Translator, Configuration, RunSummary};
+
+use super::output::{translated_lines, RewriteOutput};
+
+/// write a markdown review report beside the input file, list every translated batch with
+/// the source lines, the extracted translated lines, warnings, token usage and the api key.
+pub fn write_report<T: RewriteOutput>(
+    output: &T,
+    translator: Translator,
+    textures: &Textures,
+) -> Result<PathBuf> {
+    let path = textures.dirs.report(&textures.name, translator);
+    fs::write(&path, render_report(output, translator, textures))?;
+    println!("{}", t!(Msg::ReviewReport, path.display()));
+    Ok(path)
+}
+
+pub fn render_report<T: RewriteOutput>(
+    output: &T,
+    translator: Translator,
+    textures: &Textures,
+) -> String {
+    let mut report = String::new();
+    let _ = writeln!(report, "# Review: {}\n", textures.name);
+    let mut i = 0;
+    while i < textures.lines.len() {
+        let line = &textures.lines[i];
+        let Some(translated) = line.translated.iter().find(|t| t.translator == translator) else {
+            i += 1;
+            continue;
+        };
+        let (start, end) = translated.batch_range;
+        let tran_lines = output.extract_lines(&translated.content);
+        let batch_lines = textures.batch_lines(translated.batch_range);
+        // the stable anchor of the batch, the rows of the index link to it
+        let _ = writeln!(report, "<a id=\"batch-{}-{}\"></a>\n", start, end);
+        let _ = writeln!(report, "## Batch {}-{}\n", start, end);
+        if let Some(api) = &translated.api {
+            let _ = writeln!(report, "- api: `{}`", api);
+        }
+        if let Some(params) = &translated.params {
+            let _ = writeln!(
+                report,
+                "- params: model {}, temperature {:?}, top_p {:?}, seed {:?}",
+                params.model, params.temperature, params.top_p, params.seed
+            );
+        }
+        if let Some(score) = translated.score {
+            let _ = writeln!(report, "- score: {}", score);
+        }
+        if let Some(usage) = &translated.usage {
+            let _ = writeln!(
+                report,
+                "- tokens: prompt {}, completion {}",
+                usage.prompt_tokens, usage.completion_tokens
+            );
+        }
+        if tran_lines.len() != batch_lines.len() {
+            let _ = writeln!(
+                report,
+                "- **warning**: expected {} lines, but extracted {} lines",
+                batch_lines.len(),
+                tran_lines.len()
+            );
+        }
+        let _ = writeln!(report, "\n| # | source | translation |\n|---|---|---|");
+        for (n, j) in batch_lines.into_iter().enumerate() {
+            let tran = tran_lines.get(n).map(|s| s.as_str()).unwrap_or("");
+            let _ = writeln!(
+                report,
+                "| {} | {} | {} |",
+                j,
+                escape_cell(&textures.lines[j].content),
+                escape_cell(tran)
+            );
+        }
+        report.push('\n');
+        i = end + 1;
+    }
+    report
+}
+
+/// the characters of the source and the translation shown in a row of the index
+const INDEX_PREVIEW_CHARS: usize = 60;
+
+/// the status of a range of the index
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RangeStatus {
+    Translated,
+    /// the translated lines extracted differ from the lines of the batch
+    Mismatch,
+    /// scored below qe_opt.min_score
+    LowScore,
+    Refused,
+    /// the lines to translate without a translation
+    Untranslated,
+}
+
+impl RangeStatus {
+    fn name(&self) -> &'static str {
+        match self {
+            RangeStatus::Translated => "translated",
+            RangeStatus::Mismatch => "mismatch",
+            RangeStatus::LowScore => "low score",
+            RangeStatus::Refused => "refused",
+            RangeStatus::Untranslated => "untranslated",
+        }
+    }
+
+    fn class(&self) -> &'static str {
+        match self {
+            RangeStatus::Translated => "ok",
+            RangeStatus::Mismatch | RangeStatus::Refused => "error",
+            RangeStatus::LowScore | RangeStatus::Untranslated => "warning",
+        }
+    }
+}
+
+/// a row of the index, a translated batch or a range left untranslated
+struct IndexRow {
+    start: usize,
+    end: usize,
+    status: RangeStatus,
+    source: String,
+    translation: String,
+    retries: u32,
+    tokens: u32,
+}
+
+/// write file.index.html beside the review report, a row for every batch with its status, the
+/// previews of the source and the translation, the retries and a link to the anchor of the batch
+/// in the report, to browse a large file without opening it. it is written with the report, so
+/// not for the shards, and the anchors resolve where the report is rendered as markdown
+pub fn write_index<T: RewriteOutput>(
+    output: &T,
+    translator: Translator,
+    textures: &Textures,
+    min_score: Option<u8>,
+    report: &Path,
+) -> Result<PathBuf> {
+    let path = textures.dirs.index(&textures.name);
+    let report = report.file_name().unwrap_or_default().to_string_lossy();
+    let rows = index_rows(output, translator, textures, min_score);
+    fs::write(&path, render_index(&textures.name, &rows, &report))?;
+    println!("{}", t!(Msg::IndexReport, path.display()));
+    Ok(path)
+}
+
+fn index_rows<T: RewriteOutput>(
+    output: &T,
+    translator: Translator,
+    textures: &Textures,
+    min_score: Option<u8>,
+) -> Vec<IndexRow> {
+    let translation_of = |i: usize| {
+        textures.lines[i]
+            .translated
+            .iter()
+            .find(|t| t.translator == translator)
+    };
+    let mut rows = vec![];
+    let mut i = 0;
+    while i < textures.lines.len() {
+        let Some(translated) = translation_of(i) else {
+            // the lines to translate up to the next translated batch
+            let pending = (i..textures.lines.len())
+                .take_while(|&j| translation_of(j).is_none())
+                .filter(|&j| textures.lines[j].needs_translation())
+                .collect::<Vec<_>>();
+            let next = (i..textures.lines.len())
+                .find(|&j| translation_of(j).is_some())
+                .unwrap_or(textures.lines.len());
+            if let (Some(&start), Some(&end)) = (pending.first(), pending.last()) {
+                let sources = pending.iter().map(|&j| textures.lines[j].content.as_str());
+                rows.push(IndexRow {
+                    start,
+                    end,
+                    status: RangeStatus::Untranslated,
+                    source: preview(sources),
+                    translation: String::new(),
+                    retries: 0,
+                    tokens: 0,
+                });
+            }
+            i = next;
+            continue;
+        };
+        let (start, end) = translated.batch_range;
+        let tran_lines = output.extract_lines(&translated.content);
+        let batch_lines = textures.batch_lines(translated.batch_range);
+        let status = if translated.refused.is_some() {
+            RangeStatus::Refused
+        } else if tran_lines.len() != batch_lines.len() {
+            RangeStatus::Mismatch
+        } else if min_score.is_some_and(|min| translated.score.is_some_and(|s| s < min)) {
+            RangeStatus::LowScore
+        } else {
+            RangeStatus::Translated
+        };
+        let translation = match &translated.refused {
+            Some(reason) => reason.clone(),
+            None => preview(tran_lines.iter().map(|l| l.as_str())),
+        };
+        rows.push(IndexRow {
+            start,
+            end,
+            status,
+            source: preview(
+                batch_lines
+                    .iter()
+                    .map(|&j| textures.lines[j].content.as_str()),
+            ),
+            translation,
+            retries: translated.retries,
+            tokens: translated
+                .usage
+                .map_or(0, |u| u.prompt_tokens + u.completion_tokens),
+        });
+        i = end + 1;
+    }
+    rows
+}
+
+fn render_index(name: &str, rows: &[IndexRow], report: &str) -> String {
+    let mut html = String::new();
+    let _ = writeln!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>",
+        escape_html(name)
+    );
+    html.push_str(
+        "<style>\n\
+         body { font-family: sans-serif; margin: 2em; }\n\
+         table { border-collapse: collapse; width: 100%; }\n\
+         th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; vertical-align: top; }\n\
+         .ok { color: #2a7a2a; }\n\
+         .warning { color: #a66a00; }\n\
+         .error { color: #c0392b; }\n\
+         </style>\n</head>\n<body>\n",
+    );
+    let _ = writeln!(html, "<h1>{}</h1>", escape_html(name));
+    let statuses = [
+        RangeStatus::Translated,
+        RangeStatus::Mismatch,
+        RangeStatus::LowScore,
+        RangeStatus::Refused,
+        RangeStatus::Untranslated,
+    ];
+    let counts = statuses
+        .iter()
+        .map(|status| {
+            let count = rows.iter().filter(|r| r.status == *status).count();
+            format!(
+                "<span class=\"{}\">{} {}</span>",
+                status.class(),
+                count,
+                status.name()
+            )
+        })
+        .collect::<Vec<_>>();
+    let _ = writeln!(html, "<p>{} ranges: {}</p>", rows.len(), counts.join(", "));
+    html.push_str(
+        "<table>\n<tr><th>lines</th><th>status</th><th>source</th><th>translation</th>\
+         <th>retries</th><th>tokens</th></tr>\n",
+    );
+    for row in rows {
+        // the anchor `<a id="batch-start-end">` of the batch in the markdown report
+        let lines = match row.status {
+            RangeStatus::Untranslated => format!("{}-{}", row.start, row.end),
+            _ => format!(
+                "<a href=\"{}#batch-{}-{}\">{}-{}</a>",
+                escape_html(report),
+                row.start,
+                row.end,
+                row.start,
+                row.end
+            ),
+        };
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td class=\"{}\">{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            lines,
+            row.status.class(),
+            row.status.name(),
+            escape_html(&row.source),
+            escape_html(&row.translation),
+            row.retries,
+            row.tokens
+        );
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+/// the first line shortened to INDEX_PREVIEW_CHARS, with the count of the other lines
+fn preview<'a>(mut lines: impl Iterator<Item = &'a str>) -> String {
+    let Some(first) = lines.next() else {
+        return String::new();
+    };
+    let first = first.trim();
+    let mut preview = first.chars().take(INDEX_PREVIEW_CHARS).collect::<String>();
+    if first.chars().count() > INDEX_PREVIEW_CHARS {
+        preview.push('…');
+    }
+    match lines.count() {
+        0 => preview,
+        more => format!("{} (+{})", preview, more),
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// write file.preview.md after a sample run, list the sampled batches with the source lines and
+/// the translations as output, and the tokens of the whole file estimated from the sample.
+pub fn write_preview(
+    cfg: &Configuration,
+    textures: &Textures,
+    ranges: &[(usize, usize)],
+    batches: usize,
+    summary: &RunSummary,
+) -> Result<PathBuf> {
+    let translations = translated_lines(cfg, textures)?;
+    let mut preview = String::new();
+    let _ = writeln!(
+        preview,
+        "# Preview: {}
+",
+        textures.name
+    );
+    let _ = writeln!(
+        preview,
+        "- sampled batches: {} of {}",
+        summary.batches, batches
+    );
+    let _ = writeln!(
+        preview,
+        "- tokens: prompt {}, completion {}",
+        summary.prompt_tokens, summary.completion_tokens
+    );
+    if summary.batches > 0 {
+        let scale = |tokens: u64| tokens * batches as u64 / summary.batches as u64;
+        let _ = writeln!(
+            preview,
+            "- estimated tokens of the whole file: prompt {}, completion {}",
+            scale(summary.prompt_tokens),
+            scale(summary.completion_tokens)
+        );
+    }
+    preview.push('\n');
+    for &(start, end) in ranges {
+        let _ = writeln!(preview, "## Batch {}-{}\n", start, end);
+        let _ = writeln!(preview, "| # | source | translation |\n|---|---|---|");
+        for i in textures.batch_lines((start, end)) {
+            let translation = translations[i].as_deref().unwrap_or("");
+            let _ = writeln!(
+                preview,
+                "| {} | {} | {} |",
+                i,
+                escape_cell(&textures.lines[i].content),
+                escape_cell(translation)
+            );
+        }
+        preview.push('\n');
+    }
+    let path = textures.dirs.preview(&textures.name);
+    fs::write(&path, preview)?;
+    println!("{}", t!(Msg::PreviewReport, path.display()));
+    Ok(path)
+}
+
+/// the lines not sent to the translator and why, a `line<TAB>reason<TAB>content` line for each
+pub fn render_skipped(textures: &Textures) -> String {
+    let offset = textures.offset();
+    let mut skipped = String::new();
+    for (i, line) in textures.lines.iter().enumerate() {
+        if let Some(reason) = line.unsent_reason(offset) {
+            let _ = writeln!(
+                skipped,
+                "{}\t{}\t{}",
+                i + offset,
+                reason,
+                line.content.trim().replace('\n', "\\n")
+            );
+        }
+    }
+    skipped
+}
+
+/// a cell of a markdown table, the pipes are escaped and the line breaks kept as <br>
+pub(crate) fn escape_cell(s: &str) -> String {
+    s.trim().replace('|', "\\|").replace('\n', "<br>")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        outputs::{pipeline::OutputPipeline, text::TextOutput},
+        textures::{TextureLine, Textures, TokenUsage, TranslatedLine, TEXTURES_VERSION},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_render_report() {
+        let mut textures = Textures {
+            lines: vec![
+                TextureLine::new(0, 4, "你好\n".to_string(), false),
+                TextureLine::new(4, 4, "再见\n".to_string(), false),
+                TextureLine::new(8, 4, "谢谢\n".to_string(), false),
+            ],
+            curr_index: 0,
+            version: TEXTURES_VERSION,
+            source: None,
+            name: "test.txt".to_string(),
+            shard: None,
+            dirs: Default::default(),
+        };
+        let mut translated =
+            TranslatedLine::new(Translator::ChatGPT, "(1) Hello\n(2) Bye|".to_string(), 0, 1);
+        translated.usage = Some(TokenUsage {
+            prompt_tokens: 10,
+            completion_tokens: 5,
+        });
+        translated.api = Some("***abcd".to_string());
+        textures.update(translated);
+        textures.update(TranslatedLine::new(
+            Translator::ChatGPT,
+            "(1) Thanks\n(2) More".to_string(),
+            2,
+            2,
+        ));
+        let output = TextOutput::new(
+            OutputPipeline::from_output_regexen(r"\n[^\n\(]", r"\(\d+\)\s?(.+)").unwrap(),
+        );
+        let report = render_report(&output, Translator::ChatGPT, &textures);
+        assert!(report.contains("<a id=\"batch-0-1\"></a>\n\n## Batch 0-1"));
+        assert!(report.contains("- api: `***abcd`"));
+        assert!(report.contains("| 1 | 再见 | Bye\\| |"));
+        assert!(report.contains("expected 1 lines, but extracted 2 lines"));
+    }
+
+    #[test]
+    fn test_render_index() {
+        let mut textures = Textures {
+            lines: vec![
+                TextureLine::new(0, 4, "<你好>\n".to_string(), false),
+                TextureLine::new(4, 4, "再见\n".to_string(), false),
+                TextureLine::new(8, 4, "谢谢\n".to_string(), false),
+                TextureLine::new(12, 4, "早安\n".to_string(), false),
+            ],
+            curr_index: 0,
+            version: TEXTURES_VERSION,
+            source: None,
+            name: "test.txt".to_string(),
+            shard: None,
+            dirs: Default::default(),
+        };
+        let mut translated =
+            TranslatedLine::new(Translator::ChatGPT, "(1) Hello\n(2) Bye".to_string(), 0, 1);
+        translated.retries = 2;
+        translated.score = Some(2);
+        textures.update(translated);
+        textures.update(TranslatedLine::new(
+            Translator::ChatGPT,
+            "(1) Thanks\n(2) More".to_string(),
+            2,
+            2,
+        ));
+        let output = TextOutput::new(
+            OutputPipeline::from_output_regexen(r"\n[^\n\(]", r"\(\d+\)\s?(.+)").unwrap(),
+        );
+        let rows = index_rows(&output, Translator::ChatGPT, &textures, Some(3));
+        let statuses = rows.iter().map(|r| r.status).collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            vec![
+                RangeStatus::LowScore,
+                RangeStatus::Mismatch,
+                RangeStatus::Untranslated
+            ]
+        );
+        assert_eq!(rows[0].source, "<你好> (+1)");
+        assert_eq!(rows[0].retries, 2);
+        assert_eq!((rows[2].start, rows[2].end), (3, 3));
+        let html = render_index(&textures.name, &rows, "test.review_ChatGPT.md");
+        assert!(html.contains("<a href=\"test.review_ChatGPT.md#batch-0-1\">0-1</a>"));
+        assert!(html.contains("<td>&lt;你好&gt; (+1)</td><td>Hello (+1)</td><td>2</td>"));
+        assert!(html.contains("<tr><td>3-3</td><td class=\"warning\">untranslated</td>"));
+    }
+
+    #[test]
+    fn test_render_skipped() {
+        let mut lines = vec![
+            TextureLine::new(0, 4, "你好\n".to_string(), false),
+            TextureLine::new(4, 4, "100\n".to_string(), true),
+            TextureLine::new(8, 4, "你好\n".to_string(), false),
+            TextureLine::new(12, 4, "HP\n".to_string(), false),
+        ];
+        lines[1].skip_reason = Some("numeric".to_string());
+        lines[3].local = Some("HP\n".to_string());
+        let mut textures = Textures {
+            lines,
+            curr_index: 0,
+            version: TEXTURES_VERSION,
+            source: None,
+            name: "test.txt".to_string(),
+            shard: None,
+            dirs: Default::default(),
+        };
+        textures.dedup();
+        assert_eq!(
+            render_skipped(&textures),
+            "1\tnumeric\t100\n2\tduplicate of 0\t你好\n3\tdictionary\tHP\n"
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{new_regex, Error};
+use crate::i18n::Msg;
+use crate::t;
+
+/// split the translated file into parts, e.g. a part per chapter of a web novel
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SplitOptions {
+    /// a line of the translated file matching the regex starts a new part, and titles it
+    pub chapter_regex: Option<String>,
+    /// a part longer than max_lines is split again
+    pub max_lines: Option<usize>,
+}
+
+/// a part of the translated file, the lines start..end
+#[derive(Debug, Clone, PartialEq)]
+struct Part {
+    title: String,
+    start: usize,
+    end: usize,
+}
+
+/// split the translated file into target_stem.001.ext, target_stem.002.ext, ..., and write the
+/// index of the parts target_stem.index.md, the translated file itself is kept
+pub fn split_output(opt: &SplitOptions, target: &Path) -> Result<Vec<PathBuf>> {
+    let chapter = opt
+        .chapter_regex
+        .as_deref()
+        .map(|r| new_regex("split.chapter_regex", r))
+        .transpose()?;
+    let content = fs::read_to_string(target).map_err(Error::io(&target.to_string_lossy()))?;
+    let lines = content.split_inclusive('\n').collect::<Vec<_>>();
+    let parts = split_parts(&lines, chapter.as_ref(), opt.max_lines);
+    let stem = target
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let ext = target
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    let width = parts.len().to_string().len().max(3);
+    let mut index = format!("# {}\n\n", stem);
+    let mut paths = vec![];
+    for (i, part) in parts.iter().enumerate() {
+        let name = format!("{}.{:0width$}{}", stem, i + 1, ext, width = width);
+        let path = target.with_file_name(&name);
+        fs::write(&path, lines[part.start..part.end].concat())
+            .map_err(Error::io(&path.to_string_lossy()))?;
+        index.push_str(&format!(
+            "{}. [{}](<{}>) lines {}-{}\n",
+            i + 1,
+            part.title,
+            name,
+            part.start + 1,
+            part.end
+        ));
+        paths.push(path);
+    }
+    let index_path = target.with_file_name(format!("{}.index.md", stem));
+    fs::write(&index_path, index).map_err(Error::io(&index_path.to_string_lossy()))?;
+    let split = t!(
+        Msg::Split,
+        target.display(),
+        parts.len(),
+        index_path.display()
+    );
+    println!("{}", split);
+    Ok(paths)
+}
+
+/// the parts at the chapter lines, then at every max_lines lines of a chapter,
+/// the lines before the first chapter are a part of their own
+fn split_parts(lines: &[&str], chapter: Option<&Regex>, max_lines: Option<usize>) -> Vec<Part> {
+    let mut starts = vec![0];
+    if let Some(chapter) = chapter {
+        starts.extend(
+            (1..lines.len()).filter(|&i| chapter.is_match(lines[i].trim_end_matches(['\r', '\n']))),
+        );
+    }
+    starts.push(lines.len());
+    let title_of = |i: usize| {
+        lines[i..]
+            .iter()
+            .map(|l| l.trim())
+            .find(|l| !l.is_empty())
+            .unwrap_or_default()
+            .to_string()
+    };
+    let mut parts = vec![];
+    for window in starts.windows(2) {
+        let (start, end) = (window[0], window[1]);
+        if start == end {
+            continue;
+        }
+        let title = title_of(start);
+        let size = max_lines.filter(|&n| n > 0).unwrap_or(end - start);
+        let pieces = (end - start).div_ceil(size);
+        for (n, piece_start) in (start..end).step_by(size).enumerate() {
+            parts.push(Part {
+                title: match pieces {
+                    1 => title.clone(),
+                    _ => format!("{} ({}/{})", title, n + 1, pieces),
+                },
+                start: piece_start,
+                end: (piece_start + size).min(end),
+            });
+        }
+    }
+    parts
+}
+
+#[cfg(test)]
+mod test {
+    use regex::Regex;
+
+    use super::{split_parts, Part};
+
+    #[test]
+    fn test_split_parts() {
+        let lines = [
+            "Preface\n",
+            "Chapter 1\n",
+            "a\n",
+            "b\n",
+            "c\n",
+            "Chapter 2\n",
+            "d\n",
+        ];
+        let chapter = Regex::new("^Chapter").unwrap();
+        let part = |title: &str, start, end| Part {
+            title: title.to_string(),
+            start,
+            end,
+        };
+        assert_eq!(
+            split_parts(&lines, Some(&chapter), Some(3)),
+            vec![
+                part("Preface", 0, 1),
+                part("Chapter 1 (1/2)", 1, 4),
+                part("Chapter 1 (2/2)", 4, 5),
+                part("Chapter 2", 5, 7),
+            ]
+        );
+        assert_eq!(split_parts(&lines, None, None), vec![part("Preface", 0, 7)]);
+    }
+}
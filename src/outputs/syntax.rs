@@ -0,0 +1,60 @@
+use crate::inputs::LineSyntax;
+
+use super::{output::RewriteOutput, pipeline::OutputPipeline, text::TextOutput};
+
+/// splice the translation into the span of the text, escaped again by the syntax of the format
+pub struct SyntaxOutput {
+    text_output: TextOutput,
+    syntax: Box<dyn LineSyntax>,
+}
+
+impl SyntaxOutput {
+    pub fn new(pipeline: OutputPipeline, syntax: Box<dyn LineSyntax>) -> Self {
+        Self {
+            text_output: TextOutput::new(pipeline),
+            syntax,
+        }
+    }
+}
+
+impl RewriteOutput for SyntaxOutput {
+    fn extract_lines(&self, content: &str) -> Vec<String> {
+        self.text_output.extract_lines(content)
+    }
+    fn format_line(&self, raw: &str, content: &str) -> String {
+        match self.syntax.extract_line(raw) {
+            Some((_, (start, end))) => {
+                let escaped = self.syntax.escape(content);
+                format!("{}{}{}", &raw[..start], escaped, &raw[end..])
+            }
+            None => raw.to_string(),
+        }
+    }
+    fn format_span(&self, _: &str, content: &str) -> String {
+        self.syntax.escape(content)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::inputs::{AinOptions, AinSyntax, KeyValueOptions, KeyValueSyntax};
+
+    fn pipeline() -> OutputPipeline {
+        OutputPipeline::from_output_regexen(r#""(.*)""#, r#""(.*)""#).unwrap()
+    }
+
+    #[test]
+    fn test_format_line_for_syntax() {
+        let syntax = Box::new(AinSyntax::new(&AinOptions::default()));
+        let output = SyntaxOutput::new(pipeline(), syntax);
+        let line = output.format_line(";m[300] = \"请原谅我\"\n", "\"翻译\"完成");
+        assert_eq!(line, ";m[300] = \"\\\"翻译\\\"完成\"\n");
+        assert_eq!(output.format_span("", "a\\b"), r#"a\\b"#);
+
+        let syntax = Box::new(KeyValueSyntax::new(&KeyValueOptions::default()));
+        let output = SyntaxOutput::new(pipeline(), syntax);
+        let line = output.format_line("menu.start=はじめから\r\n", "从头=开始");
+        assert_eq!(line, "menu.start=从头\\=开始\r\n");
+    }
+}
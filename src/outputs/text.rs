@@ -1,33 +1,85 @@
 use regex::Regex;
 
-use super::output::RewriteOutput;
+use crate::segment::{wrap, WidthMode};
+
+use super::{output::RewriteOutput, pipeline::OutputPipeline};
 
 pub struct TextOutput {
-    pub replace_rule: Regex,
-    pub capture_rule: Regex,
+    pipeline: OutputPipeline,
+    /// the context lines `(c1) xxx` of the batch overlap, which the translator may echo back
+    context_rule: Regex,
+    line_width: Option<usize>,
 }
 
 impl TextOutput {
-    pub fn new(replace_rule: &str, capture_rule: &str) -> Self {
-        let replace_rule = Regex::new(replace_rule).unwrap();
-        let capture_rule = Regex::new(capture_rule).unwrap();
+    pub fn new(pipeline: OutputPipeline) -> Self {
         Self {
-            replace_rule,
-            capture_rule,
+            pipeline,
+            context_rule: Regex::new(r"(?m)^\s*\(c\d+\).*$").unwrap(),
+            line_width: None,
         }
     }
+
+    pub fn set_line_width(&mut self, line_width: Option<usize>) {
+        self.line_width = line_width;
+    }
 }
 
 impl RewriteOutput for TextOutput {
     fn extract_lines(&self, content: &str) -> Vec<String> {
-        let mut lines = vec![];
-        let content = self.replace_rule.replace_all(content, "\\n").to_string();
-        self.capture_rule.captures_iter(&content).for_each(|cap| {
-            lines.push(cap[1].to_string().replace('\"', ""));
-        });
-        lines
+        let content = self.context_rule.replace_all(content, "");
+        self.pipeline.extract_lines(&content)
     }
     fn format_line(&self, _: &str, translated_line: &str) -> String {
-        format!("{}\n", translated_line)
+        match self.line_width {
+            Some(line_width) => format!(
+                "{}\n",
+                wrap(translated_line, line_width, WidthMode::Display).join("\n")
+            ),
+            None => format!("{}\n", translated_line),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_extract_lines_drop_context() {
+        let pipeline =
+            OutputPipeline::from_output_regexen(r"\n[^\n\(]", r"\(\d+\)\s?(.+)").unwrap();
+        let output = TextOutput::new(pipeline);
+        let lines = output.extract_lines("(c1) Bye\n(c2) Thanks\n(1) Good night\n(2) Hi");
+        assert_eq!(lines, vec!["Good night", "Hi"]);
+    }
+
+    #[test]
+    fn test_extract_lines_stages() {
+        let mut table = include_str!("../../assets/options_text.toml")
+            .parse::<toml::Table>()
+            .unwrap();
+        let stages = r#"
+[[response_cleanup]]
+usage = {replace = ""}
+regex = '(?m)^(翻译为[:：]|是否违规.*)\n?'
+[[response_cleanup]]
+usage = {replace = "\n"}
+regex = '\n{2,}'
+[[line_capture]]
+usage = {capture = 1}
+regex = '(?m)^\(\d+\)\s?(.+)$'
+[[post_replace]]
+usage = {replace = "$1…"}
+regex = '(.+?)\.{3}$'
+"#;
+        table.extend(stages.parse::<toml::Table>().unwrap());
+        let cfg: crate::Configuration = toml::Value::Table(table).try_into().unwrap();
+        let output = TextOutput::new(OutputPipeline::new(&cfg).unwrap());
+        let content = "翻译为:\n(1) 在各种意义上。\n是否违规：否\n\n(2) 顺带一提...\n是否违规：否\n\n\n(3) \"我\"";
+        assert_eq!(
+            output.extract_lines(content),
+            vec!["在各种意义上。", "顺带一提…", "\"我\""]
+        );
     }
 }
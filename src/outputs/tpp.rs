@@ -0,0 +1,118 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::Result;
+use serde_json::Value;
+
+use crate::{
+    error::Error,
+    inputs::{tpp_from_line, TppProject},
+    textures::Textures,
+    Configuration,
+};
+
+use super::output::{translated_lines, OutputReport};
+
+/// fill the column of tpp_opt with the translations of the rows, a row not translated keeps its
+/// column, the rest of the project is written back as it was read
+pub fn output_project(
+    config: &Configuration,
+    textures: &Textures,
+    target: &Path,
+) -> Result<OutputReport> {
+    let column = config.tpp_opt.clone().unwrap_or_default().column;
+    if target == Path::new(&textures.name) {
+        return Err(Error::Config(format!(
+            "output_name points to the input file {}, use in_place to rewrite it",
+            textures.name
+        ))
+        .into());
+    }
+    if let Some(parent) = target.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(Error::io(&parent.to_string_lossy()))?;
+    }
+    let mut report = OutputReport::default();
+    let mut translations = HashMap::new();
+    for (line, translation) in textures
+        .lines
+        .iter()
+        .zip(translated_lines(config, textures)?)
+    {
+        match translation {
+            Some(translation) => {
+                report.written += 1;
+                translations.insert(line.seek, translation);
+            }
+            None if line.needs_translation() => report.skipped += 1,
+            None => {}
+        }
+    }
+    let mut project = TppProject::read(&textures.name)?;
+    for (seek, row) in project.rows_mut().into_iter().enumerate() {
+        let (Some(row), Some(translation)) = (row, translations.remove(&seek)) else {
+            continue;
+        };
+        if row.len() <= column {
+            row.resize(column + 1, Value::Null);
+        }
+        row[column] = Value::String(tpp_from_line(&translation));
+    }
+    project.write(target)?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        inputs::{Input, TppInput, TppOptions, TppProject},
+        textures::TranslatedLine,
+        translators::Translator,
+        Configuration,
+    };
+
+    use super::output_project;
+
+    #[test]
+    fn test_output_project() {
+        let dir = crate::utils::TempDir::new("output_project");
+        let path = dir.join("game.trans");
+        std::fs::write(
+            &path,
+            r#"{"project": {"files": {"data/Map001.json": {"data": [["はい", null, null], ["行くよ\nまた明日"], ["勇者", null, "Hero"]], "tags": []}}}}"#,
+        )
+        .unwrap();
+        let name = path.to_str().unwrap();
+        let input = TppInput {
+            opt: TppOptions::default(),
+            dedup: false,
+            skip: None,
+            dictionary: None,
+            dirs: Default::default(),
+        };
+        let mut textures = input
+            .parse(&mut std::io::BufReader::new(
+                std::fs::File::open(name).unwrap(),
+            ))
+            .unwrap();
+        textures.name = name.to_string();
+        textures.lines[0].translated.push(TranslatedLine::new(
+            Translator::ChatGPT,
+            "(1) 好的\n(2) 走吧\\n明天见".to_string(),
+            0,
+            1,
+        ));
+        let config: Configuration =
+            toml::from_str(include_str!("../../assets/options_text.toml")).unwrap();
+        let target = dir.join("game.translated.trans");
+        let report = output_project(&config, &textures, &target).unwrap();
+        assert_eq!((report.written, report.skipped), (2, 0));
+        let project = TppProject::read(target.to_str().unwrap()).unwrap();
+        let rows = project.rows();
+        assert_eq!(rows[0][2], "好的");
+        assert_eq!(rows[1][2], "走吧\n明天见");
+        assert_eq!(rows[2][2], "Hero");
+        assert_eq!(
+            project.value["project"]["files"]["data/Map001.json"]["tags"],
+            serde_json::json!([])
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use std::{fs, path::Path};
+
+use crate::error::Error;
+
+/// the bytes of the original file replaced by the rewriter, and the size of what was written
+/// instead
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Splice {
+    pub start: usize,
+    pub end: usize,
+    pub written: usize,
+}
+
+/// compare the rewritten file with the original outside the splices, every byte not replaced by
+/// a translation must be kept as it is, and a json original must still parse, so a bug of the
+/// seek bookkeeping is caught before the file is loaded into the game
+pub fn verify_rewrite(original: &Path, rewritten: &Path, splices: &[Splice]) -> Result<(), Error> {
+    let source = fs::read(original).map_err(Error::io(&original.to_string_lossy()))?;
+    let output = fs::read(rewritten).map_err(Error::io(&rewritten.to_string_lossy()))?;
+    let corrupted = |reason: String| Error::Corrupted {
+        path: rewritten.to_string_lossy().to_string(),
+        reason,
+    };
+    let tail = Splice {
+        start: source.len(),
+        end: source.len(),
+        written: 0,
+    };
+    let (mut at, mut out_at) = (0, 0);
+    for splice in splices.iter().chain(std::iter::once(&tail)) {
+        if splice.start < at || splice.end < splice.start || splice.end > source.len() {
+            return Err(corrupted(format!(
+                "the replaced bytes {}..{} overlap or exceed the original",
+                splice.start, splice.end
+            )));
+        }
+        let kept = &source[at..splice.start];
+        let Some(copied) = output.get(out_at..out_at + kept.len()) else {
+            return Err(corrupted(format!(
+                "ends at byte {}, the original bytes from {} are missing",
+                output.len(),
+                at
+            )));
+        };
+        if let Some(k) = kept.iter().zip(copied).position(|(a, b)| a != b) {
+            return Err(corrupted(format!(
+                "byte {} differs from byte {} of the original, which is not translated",
+                out_at + k,
+                at + k
+            )));
+        }
+        out_at += kept.len() + splice.written;
+        at = splice.end;
+    }
+    if out_at != output.len() {
+        return Err(corrupted(format!(
+            "has {} bytes, expected {}",
+            output.len(),
+            out_at
+        )));
+    }
+    if serde_json::from_slice::<serde_json::Value>(&source).is_ok() {
+        if let Err(e) = serde_json::from_slice::<serde_json::Value>(&output) {
+            return Err(corrupted(format!("is not valid json: {}", e)));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{verify_rewrite, Splice};
+
+    #[test]
+    fn test_verify_rewrite() {
+        let dir = crate::utils::TempDir::new("verify_rewrite");
+        let (original, rewritten) = (
+            &dir.join("test_verify_rewrite.json"),
+            &dir.join("test_verify_rewrite.translated.json"),
+        );
+        std::fs::write(original, "{\"一\": \"一\",\n\"二\": \"二\"}").unwrap();
+        // "一" and "二" of the values are 3 bytes each
+        let splices = [
+            Splice {
+                start: 9,
+                end: 12,
+                written: 3,
+            },
+            Splice {
+                start: 23,
+                end: 26,
+                written: 3,
+            },
+        ];
+        std::fs::write(rewritten, "{\"一\": \"One\",\n\"二\": \"Two\"}").unwrap();
+        assert!(verify_rewrite(original, rewritten, &splices).is_ok());
+        // a quote lost by a bad seek
+        std::fs::write(rewritten, "{\"一\": \"One,\n\"二\": \"Two\"}").unwrap();
+        let err = verify_rewrite(original, rewritten, &splices).unwrap_err();
+        assert!(err.to_string().contains("byte 12 differs from byte 12"));
+        // the untouched bytes are intact, but the translation breaks the json
+        std::fs::write(rewritten, "{\"一\": \"On\"e\",\n\"二\": \"Two\"}").unwrap();
+        let splices = [
+            Splice {
+                written: 4,
+                ..splices[0]
+            },
+            splices[1],
+        ];
+        let err = verify_rewrite(original, rewritten, &splices).unwrap_err();
+        assert!(err.to_string().contains("is not valid json"));
+    }
+}
@@ -0,0 +1,327 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use regex::Regex;
+
+use crate::{textures::StateFormat, translators::Translator};
+
+/// the suffixes of the files derived from an input file, and of the other files written beside
+/// it, file.bak of in_place and the temporary files, e.g. to tell them from the input files
+const DERIVED_SUFFIX: &str = concat!(
+    r"\.(",
+    // the textures, the shards and their journals
+    r"textures(\.\d+)?\.(json|journal\.jsonl)|shards\.json|embeddings\.json",
+    r"|failed\.json|dignostic_failed_range\.json",
+    // the translated files and the review reports of the translators
+    r"|translated_(ChatGPT|ChatGPTRefined|Gemini)(\.[^.]+)?|review_(ChatGPT|ChatGPTRefined|Gemini)\.md",
+    r"|review\.csv|index\.html|preview\.md|consistency\.md|fixup\.md|skipped\.txt",
+    r"|glossary\.toml|tmx|bak|bak\.hash|tmp",
+    r")$"
+);
+
+/// the directories of the files derived from an input file, next to the input by default.
+/// the derived file names are the input file name with a suffix, so a name with dots,
+/// without an extension or not in utf-8 is kept as it is.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ArtifactDirs {
+    /// the translated files and the review reports
+    pub output_dir: Option<PathBuf>,
+    /// the textures, the shards and the diagnostics, which are kept between runs
+    pub state_dir: Option<PathBuf>,
+    /// how the textures and the shards are saved
+    pub state_format: StateFormat,
+}
+
+impl ArtifactDirs {
+    /// whether the file is derived from an input file, its name is the name of the input with
+    /// the suffix of an artifact, see DERIVED_SUFFIX
+    pub fn is_derived(path: &Path) -> bool {
+        static SUFFIX: OnceLock<Regex> = OnceLock::new();
+        let suffix = SUFFIX.get_or_init(|| Regex::new(DERIVED_SUFFIX).unwrap());
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        suffix.find(&name).is_some_and(|m| m.start() > 0)
+    }
+
+    /// file.textures.json
+    pub fn textures(&self, file: &str) -> PathBuf {
+        derived(self.state_dir.as_deref(), file, ".textures.json")
+    }
+
+    /// file.textures.{index}.json
+    pub fn shard(&self, file: &str, index: usize) -> PathBuf {
+        derived(
+            self.state_dir.as_deref(),
+            file,
+            format!(".textures.{}.json", index),
+        )
+    }
+
+    /// file.shards.json
+    pub fn shards_index(&self, file: &str) -> PathBuf {
+        derived(self.state_dir.as_deref(), file, ".shards.json")
+    }
+
+    /// file.embeddings.json
+    pub fn embeddings(&self, file: &str) -> PathBuf {
+        derived(self.state_dir.as_deref(), file, ".embeddings.json")
+    }
+
+    /// file.failed.json
+    pub fn failed(&self, file: &str) -> PathBuf {
+        derived(self.state_dir.as_deref(), file, ".failed.json")
+    }
+
+    /// file.dignostic_failed_range.json, the failed ranges of an older version
+    pub fn legacy_failed_range(&self, file: &str) -> PathBuf {
+        derived(
+            self.state_dir.as_deref(),
+            file,
+            ".dignostic_failed_range.json",
+        )
+    }
+
+    /// file.translated_xxx.ext, or file.translated_xxx when the file has no extension
+    pub fn translated(&self, file: &str, translator: Translator) -> PathBuf {
+        let mut suffix = OsString::from(format!(".translated_{:?}", translator));
+        if let Some(ext) = Path::new(file).extension() {
+            suffix.push(".");
+            suffix.push(ext);
+        }
+        derived(self.output_dir.as_deref(), file, suffix)
+    }
+
+    /// file.review_xxx.md
+    pub fn report(&self, file: &str, translator: Translator) -> PathBuf {
+        derived(
+            self.output_dir.as_deref(),
+            file,
+            format!(".review_{:?}.md", translator),
+        )
+    }
+
+    /// file.review.csv, of export-review and import-review
+    pub fn review_csv(&self, file: &str) -> PathBuf {
+        derived(self.output_dir.as_deref(), file, ".review.csv")
+    }
+
+    /// file.index.html
+    pub fn index(&self, file: &str) -> PathBuf {
+        derived(self.output_dir.as_deref(), file, ".index.html")
+    }
+
+    /// file.preview.md
+    pub fn preview(&self, file: &str) -> PathBuf {
+        derived(self.output_dir.as_deref(), file, ".preview.md")
+    }
+
+    /// file.consistency.md
+    pub fn consistency(&self, file: &str) -> PathBuf {
+        derived(self.output_dir.as_deref(), file, ".consistency.md")
+    }
+
+    /// file.fixup.md
+    pub fn fixup(&self, file: &str) -> PathBuf {
+        derived(self.output_dir.as_deref(), file, ".fixup.md")
+    }
+
+    /// file.skipped.txt
+    pub fn skipped(&self, file: &str) -> PathBuf {
+        derived(self.output_dir.as_deref(), file, ".skipped.txt")
+    }
+
+    /// file.glossary.toml
+    pub fn glossary(&self, file: &str) -> PathBuf {
+        derived(self.output_dir.as_deref(), file, ".glossary.toml")
+    }
+
+    /// file.tmx
+    pub fn tmx(&self, file: &str) -> PathBuf {
+        derived(self.output_dir.as_deref(), file, ".tmx")
+    }
+
+    /// create the configured directories
+    pub fn create(&self) -> std::io::Result<()> {
+        for dir in [&self.output_dir, &self.state_dir].into_iter().flatten() {
+            std::fs::create_dir_all(dir)?;
+        }
+        Ok(())
+    }
+}
+
+/// the placeholders of the output_name template
+pub const OUTPUT_NAME_PLACEHOLDERS: [&str; 7] =
+    ["dir", "name", "stem", "ext", "from", "to", "translator"];
+
+/// the translated file path of the output_name template, e.g. `{stem}.{to}.{ext}`:
+/// {dir} the directory of the file, {name} the file name, {stem} the file name without extension,
+/// {ext} the extension (`.{ext}` is dropped when the file has none), {from} {to} the iso 639-3
+/// codes of the languages, {translator} the translator. a relative path is relative to the
+/// directory of the file, or output_dir if set
+pub fn output_name(
+    template: &str,
+    file: &str,
+    output_dir: Option<&Path>,
+    vars: [(&str, &str); 3],
+) -> PathBuf {
+    let path = Path::new(file);
+    let dir = path
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let ext = path.extension().map(|e| e.to_string_lossy());
+    let mut name = match ext {
+        Some(_) => template.to_string(),
+        None => template.replace(".{ext}", ""),
+    };
+    let parts = [
+        ("dir", dir.to_string_lossy()),
+        (
+            "name",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+        ),
+        (
+            "stem",
+            path.file_stem().unwrap_or_default().to_string_lossy(),
+        ),
+        ("ext", ext.unwrap_or_default()),
+    ];
+    for (key, value) in parts.iter().map(|(k, v)| (*k, v.as_ref())).chain(vars) {
+        name = name.replace(&format!("{{{}}}", key), value);
+    }
+    let name = PathBuf::from(name);
+    match output_dir {
+        _ if name.is_absolute() || template.starts_with("{dir}") => name,
+        Some(output_dir) => output_dir.join(name),
+        None => dir.join(name),
+    }
+}
+
+/// the file name with the suffix appended, in dir or next to the file
+fn derived<S: AsRef<std::ffi::OsStr>>(dir: Option<&Path>, file: &str, suffix: S) -> PathBuf {
+    let file = Path::new(file);
+    let mut name = file.file_name().map(OsString::from).unwrap_or_default();
+    name.push(suffix);
+    match dir {
+        Some(dir) => dir.join(name),
+        None => file.with_file_name(name),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use super::{output_name, ArtifactDirs};
+    use crate::translators::Translator;
+
+    #[test]
+    fn test_artifact_paths() {
+        let beside = ArtifactDirs::default();
+        assert_eq!(
+            beside.textures("game/a.b.json"),
+            Path::new("game/a.b.json.textures.json")
+        );
+        assert_eq!(
+            beside.translated("game/a.b.json", Translator::ChatGPT),
+            Path::new("game/a.b.json.translated_ChatGPT.json")
+        );
+        assert_eq!(
+            beside.translated("game/script", Translator::ChatGPT),
+            Path::new("game/script.translated_ChatGPT")
+        );
+        let dirs = ArtifactDirs {
+            output_dir: Some(PathBuf::from("out")),
+            state_dir: Some(PathBuf::from("state")),
+            ..Default::default()
+        };
+        assert_eq!(
+            dirs.shard("game/a.ks", 2),
+            Path::new("state/a.ks.textures.2.json")
+        );
+        assert_eq!(
+            dirs.failed("game/a.ks"),
+            Path::new("state/a.ks.failed.json")
+        );
+        assert_eq!(
+            dirs.report("game/a.ks", Translator::ChatGPT),
+            Path::new("out/a.ks.review_ChatGPT.md")
+        );
+        assert_eq!(
+            dirs.review_csv("game/a.ks"),
+            Path::new("out/a.ks.review.csv")
+        );
+    }
+
+    #[test]
+    fn test_is_derived() {
+        let dirs = ArtifactDirs::default();
+        let file = "a.txt";
+        let derived = [
+            dirs.textures(file),
+            dirs.shard(file, 3),
+            dirs.shards_index(file),
+            dirs.embeddings(file),
+            dirs.failed(file),
+            dirs.legacy_failed_range(file),
+            dirs.translated(file, Translator::Gemini),
+            dirs.report(file, Translator::ChatGPT),
+            dirs.review_csv(file),
+            dirs.index(file),
+            dirs.preview(file),
+            dirs.consistency(file),
+            dirs.fixup(file),
+            dirs.skipped(file),
+            dirs.glossary(file),
+            dirs.tmx(file),
+            dirs.shard(file, 3).with_extension("journal.jsonl"),
+        ];
+        for path in derived {
+            assert!(ArtifactDirs::is_derived(&path), "{}", path.display());
+        }
+        let others = [
+            "a.txt.bak",
+            "a.txt.bak.hash",
+            "a.txt.translated_ChatGPT.txt.tmp",
+        ];
+        for name in others {
+            assert!(ArtifactDirs::is_derived(Path::new(name)), "{}", name);
+        }
+        // the inputs with the words of an artifact in their names
+        let inputs = [
+            "game/a.txt",
+            "scene.bak.ks",
+            "a.tmp.json",
+            "notes.review.txt",
+            "x.textures.txt",
+            "a.translated_notes.txt",
+            ".bak",
+        ];
+        for name in inputs {
+            assert!(!ArtifactDirs::is_derived(Path::new(name)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_output_name() {
+        let vars = [("from", "jpn"), ("to", "zho"), ("translator", "ChatGPT")];
+        assert_eq!(
+            output_name("{stem}.{to}.{ext}", "game/a.b.ks", None, vars),
+            Path::new("game/a.b.zho.ks")
+        );
+        assert_eq!(
+            output_name("{stem}.{to}.{ext}", "game/script", None, vars),
+            Path::new("game/script.zho")
+        );
+        assert_eq!(
+            output_name("{dir}/translated/{name}", "game/a.ks", None, vars),
+            Path::new("game/translated/a.ks")
+        );
+        assert_eq!(
+            output_name("{name}", "game/a.ks", Some(Path::new("out")), vars),
+            Path::new("out/a.ks")
+        );
+    }
+}
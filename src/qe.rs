@@ -0,0 +1,212 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use crate::{
+    console::{self, Tone},
+    error::new_regex,
+    i18n::Msg,
+    outputs::LineExtractor,
+    t,
+    textures::Textures,
+    translators::{complete, ChatCompletionMessage, ChatCompletionRole},
+    Configuration,
+};
+
+/// the scoring prompt of the model, {{from}} and {{to}} are replaced by the languages
+const QE_PROMPT: &str = "You are a reviewer of the translations from {{from}} to {{to}}. \
+Every line is `(n) source ||| translation`. Score the quality of all the translations together, \
+from 1 (wrong or untranslated) to 5 (accurate and fluent). Reply only the score, one integer.";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QeOptions {
+    /// ask the model of chatgpt_opt to score the batches, otherwise a heuristic scores them
+    #[serde(default)]
+    pub model: bool,
+    /// the batches scored below are flagged in the output
+    #[serde(default = "default_min_score")]
+    pub min_score: u8,
+    /// the flagged batches are retranslated in the next run
+    #[serde(default)]
+    pub retranslate: bool,
+}
+
+fn default_min_score() -> u8 {
+    3
+}
+
+/// score the translated batches not scored yet, 1 to 5
+pub async fn score_batches(textures: &mut Textures, cfg: &Configuration) -> Result<usize> {
+    let Some(qe) = &cfg.qe_opt else {
+        return Ok(0);
+    };
+    let extractor = LineExtractor::new(cfg)?;
+    let capture = source_capture(cfg)?;
+    // (line index, translated index, the pairs of source and translation or None if mismatched)
+    let mut batches = vec![];
+    for (i, line) in textures.lines.iter().enumerate() {
+        for (k, translated) in line.translated.iter().enumerate() {
+            if translated.score.is_some() || translated.batch_range.0 != i {
+                continue;
+            }
+            let sources = textures
+                .batch_lines(translated.batch_range)
+                .into_iter()
+                .map(|j| source(&capture, &textures.lines[j].content))
+                .collect::<Vec<_>>();
+            let translations = extractor.extract(&translated.content);
+            let pairs = (sources.len() == translations.len())
+                .then(|| sources.into_iter().zip(translations).collect::<Vec<_>>());
+            batches.push((i, k, pairs));
+        }
+    }
+    let scores = match (&cfg.chatgpt_opt, qe.model) {
+        (Some(opt), true) => {
+            let limiter = Arc::new(Semaphore::new(opt.max_concurrent.max(1) as usize));
+            let (from, to) = (cfg.lang_from.to_name(), cfg.lang_to.to_name());
+            let prompt = QE_PROMPT.replace("{{from}}", from).replace("{{to}}", to);
+            let mut tasks = JoinSet::new();
+            for (n, (_, _, pairs)) in batches.iter().enumerate() {
+                let Some(pairs) = pairs.clone() else {
+                    continue;
+                };
+                let api = opt.api_pool[n % opt.api_pool.len()].clone();
+                let model = opt.model.clone();
+                let limiter = limiter.clone();
+                let messages = vec![
+                    ChatCompletionMessage::new(ChatCompletionRole::System, &prompt),
+                    ChatCompletionMessage::new(ChatCompletionRole::User, &numbered(&pairs)),
+                ];
+                tasks.spawn(async move {
+                    let _permit = limiter.acquire().await;
+                    let reply = complete(&api, model.as_deref(), messages).await;
+                    (n, reply)
+                });
+            }
+            let mut scores = vec![Some(1); batches.len()];
+            while let Some(result) = tasks.join_next().await {
+                let (n, reply) = result?;
+                // a failed request leaves the batch unscored, it is scored next time
+                scores[n] = match reply {
+                    Ok(reply) => Some(parse_score(&reply).unwrap_or(1)),
+                    Err(e) => {
+                        let error = t!(Msg::QeError, format!("{:?}", e));
+                        eprintln!("{} {}", console::err(Tone::Error, "[QE]"), error);
+                        None
+                    }
+                };
+            }
+            scores
+        }
+        _ => batches
+            .iter()
+            .map(|(_, _, pairs)| Some(pairs.as_ref().map(|p| heuristic_score(p)).unwrap_or(1)))
+            .collect(),
+    };
+    let mut scored = 0;
+    for ((i, k, _), score) in batches.into_iter().zip(scores) {
+        if score.is_some() {
+            scored += 1;
+        }
+        textures.lines[i].translated[k].score = score;
+    }
+    println!("[QE] {}", t!(Msg::QeScored, scored));
+    Ok(scored)
+}
+
+/// the batch ranges scored below min_score, offset to the whole file
+pub fn low_score_ranges(textures: &Textures, min_score: u8) -> Vec<(usize, usize)> {
+    let offset = textures.offset();
+    let mut ranges = textures
+        .lines
+        .iter()
+        .flat_map(|l| l.translated.iter())
+        .filter(|t| t.score.is_some_and(|s| s < min_score))
+        .map(|t| (t.batch_range.0 + offset, t.batch_range.1 + offset))
+        .collect::<Vec<_>>();
+    ranges.sort();
+    ranges.dedup();
+    ranges
+}
+
+/// 5 when every translation looks fine, down to 1 when none does:
+/// an empty translation, a copied source, garbage characters or an odd length ratio
+pub fn heuristic_score(pairs: &[(String, String)]) -> u8 {
+    if pairs.is_empty() {
+        return 5;
+    }
+    let bad = pairs
+        .iter()
+        .filter(|(source, translation)| {
+            let (source, translation) = (source.trim(), translation.trim());
+            let (s, t) = (source.chars().count(), translation.chars().count());
+            translation.is_empty()
+                || (translation == source && !source.is_ascii())
+                || translation
+                    .chars()
+                    .any(|c| c == '\u{FFFD}' || (c.is_control() && c != '\t'))
+                || (s >= 4 && (t * 5 < s || t > s * 5))
+        })
+        .count();
+    5 - ((4 * bad).div_ceil(pairs.len())) as u8
+}
+
+/// capture_regex when the input lines are the whole lines, see `source`
+pub fn source_capture(cfg: &Configuration) -> Result<Option<Regex>> {
+    Ok(cfg
+        .capture_regex
+        .as_ref()
+        .filter(|_| !cfg.capture_input)
+        .map(|r| new_regex("capture_regex", r))
+        .transpose()?)
+}
+
+/// the source text of a line, the group 1 of capture_regex if it matches
+pub fn source(capture: &Option<Regex>, content: &str) -> String {
+    capture
+        .as_ref()
+        .and_then(|r| r.captures(content))
+        .and_then(|c| c.get(1))
+        .map(|m| m.as_str())
+        .unwrap_or(content)
+        .trim()
+        .to_string()
+}
+
+fn numbered(pairs: &[(String, String)]) -> String {
+    pairs
+        .iter()
+        .enumerate()
+        .map(|(n, (s, t))| format!("({}) {} ||| {}\n", n + 1, s, t.replace('\n', " ")))
+        .collect()
+}
+
+fn parse_score(reply: &str) -> Option<u8> {
+    reply
+        .chars()
+        .find(|c| ('1'..='5').contains(c))
+        .and_then(|c| c.to_digit(10))
+        .map(|d| d as u8)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{heuristic_score, parse_score};
+
+    #[test]
+    fn test_heuristic_score() {
+        let pair = |s: &str, t: &str| (s.to_string(), t.to_string());
+        assert_eq!(heuristic_score(&[pair("你好", "Hello")]), 5);
+        assert_eq!(heuristic_score(&[pair("你好", "你好")]), 1);
+        assert_eq!(
+            heuristic_score(&[pair("你好", "Hello"), pair("再见", "")]),
+            3
+        );
+        assert_eq!(heuristic_score(&[pair("再见", "Bye\u{FFFD}")]), 1);
+        assert_eq!(parse_score("Score: 4"), Some(4));
+        assert_eq!(parse_score("none"), None);
+    }
+}
@@ -0,0 +1,486 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::BufReader,
+};
+
+use anyhow::Result;
+
+use isolang::Language;
+use regex::Regex;
+
+use crate::{
+    console::{self, Tone},
+    error::Error,
+    failed::{FailReason, FailedBatch, FailedReport},
+    i18n::Msg,
+    inputs::{new_input, tpp_cell, Input, TppProject},
+    outputs::translated_lines,
+    qe::{source, source_capture},
+    t,
+    textures::{to_ranges, Textures, TranslatedLine},
+    translators::Translator,
+    Configuration,
+};
+
+/// export every texture line as a row of `id,source,translation` for human post-editing, the id
+/// is the hex of the line id, so the rows are imported into the lines even if the file changed
+pub fn export_review(
+    config: &Configuration,
+    textures: &Textures,
+    path: Option<String>,
+) -> Result<String> {
+    let path = path.unwrap_or_else(|| review_csv(config, textures));
+    let translations = translated_lines(config, textures)?;
+    let mut csv = String::new();
+    csv.push_str(&write_csv_row(&["id", "source", "translation"]));
+    for (i, line) in textures.lines.iter().enumerate() {
+        let tran = translations[i].as_deref().unwrap_or("");
+        csv.push_str(&write_csv_row(&[
+            &format!("{:016x}", line.id),
+            trim_newline(&line.content),
+            tran,
+        ]));
+    }
+    fs::write(&path, csv)?;
+    println!("{}", t!(Msg::ExportReview, path, textures.lines.len()));
+    Ok(path)
+}
+
+/// file.review.csv in the output_dir
+fn review_csv(config: &Configuration, textures: &Textures) -> String {
+    let path = config.dirs().review_csv(&textures.name);
+    path.to_string_lossy().to_string()
+}
+
+/// merge the edited translations of the review csv back into textures,
+/// every batch that has an edited line is rewritten as a numbered list `(1) xxx`,
+/// the same format as the batchizer sends to the translator.
+pub fn import_review(
+    config: &Configuration,
+    textures: &mut Textures,
+    path: Option<String>,
+) -> Result<usize> {
+    let path = path.unwrap_or_else(|| review_csv(config, textures));
+    let mut translations = translated_lines(config, textures)?;
+    let mut edited = HashSet::new();
+    let index = textures.line_index();
+    for row in read_csv(&fs::read_to_string(&path)?).into_iter().skip(1) {
+        let (Some(id), Some(source), Some(tran)) = (row.first(), row.get(1), row.get(2)) else {
+            continue;
+        };
+        // the csv of an older version has the index of the line
+        let line = match id.len() {
+            16 => u64::from_str_radix(id, 16)
+                .ok()
+                .map(|id| index.get(&id).copied()),
+            _ => id.parse::<usize>().ok().map(Some),
+        };
+        let Some(line) = line else {
+            let invalid = t!(Msg::InvalidLineId, id);
+            eprintln!("{} {}", console::err(Tone::Warning, "[Review]"), invalid);
+            continue;
+        };
+        // the duplicated lines are translated by their first occurrence
+        let line = line.map(|i| match textures.lines.get(i) {
+            Some(line) => line.duplicate.unwrap_or(i),
+            None => i,
+        });
+        match line.and_then(|i| Some((i, textures.lines.get(i)?))) {
+            Some((i, line)) if trim_newline(&line.content) == source => {
+                if !tran.is_empty() && translations[i].as_deref() != Some(tran.as_str()) {
+                    translations[i] = Some(tran.clone());
+                    edited.insert(i);
+                }
+            }
+            _ => eprintln!(
+                "{} {}",
+                console::err(Tone::Warning, "[Review]"),
+                t!(Msg::SourceMismatch, id)
+            ),
+        }
+    }
+
+    let translator = config.translator_selection().primary();
+    let batches = write_back(textures, translator, &translations, &edited);
+    println!("{}", t!(Msg::ImportReview, path, edited.len(), batches));
+    Ok(edited.len())
+}
+
+/// pre-fill the untranslated lines with the translations of a previous work: a tmx, a csv of
+/// `source,translation` or of the review `id,source,translation`, a Translator++ project, or a
+/// translated file of the same format and lines. the lines still untranslated are added to
+/// file.failed.json, so the run with --retry-failed only sends them. returns the lines filled
+pub fn import_translations(
+    config: &Configuration,
+    textures: &mut Textures,
+    path: &str,
+) -> Result<usize> {
+    let capture = source_capture(config)?;
+    let pairs = match path.rsplit('.').next().map(|e| e.to_ascii_lowercase()) {
+        Some(ext) if ext == "tmx" => read_tmx(
+            &fs::read_to_string(path).map_err(Error::io(path))?,
+            config.lang_from,
+            config.lang_to,
+        ),
+        // the last translation of every row of a Translator++ project
+        Some(ext) if ext == "trans" => TppProject::read(path)?
+            .rows()
+            .into_iter()
+            .filter_map(|row| {
+                let translation = (1..row.len()).rev().find_map(|c| tpp_cell(row, c))?;
+                Some((tpp_cell(row, 0)?.to_string(), translation.to_string()))
+            })
+            .collect(),
+        Some(ext) if ext == "csv" => {
+            let rows = read_csv(&fs::read_to_string(path).map_err(Error::io(path))?);
+            // the review csv has the id column
+            let column = match rows.first() {
+                Some(header) if header.len() >= 3 && header[0] == "id" => 1,
+                _ => 0,
+            };
+            rows.into_iter()
+                .filter_map(|row| Some((row.get(column)?.clone(), row.get(column + 1)?.clone())))
+                .collect()
+        }
+        _ => {
+            let file = fs::File::open(path).map_err(Error::io(path))?;
+            let translated = new_input(config)?.parse(&mut BufReader::new(file))?;
+            if translated.lines.len() != textures.lines.len() {
+                return Err(Error::Config(format!(
+                    "{} has {} lines to translate, the file has {}, not a translation of it",
+                    path,
+                    translated.lines.len(),
+                    textures.lines.len()
+                ))
+                .into());
+            }
+            textures
+                .lines
+                .iter()
+                .zip(&translated.lines)
+                .map(|(s, t)| (s.content.clone(), t.content.clone()))
+                .collect::<Vec<_>>()
+        }
+    };
+    let mut memory = HashMap::new();
+    for (source_text, tran) in pairs {
+        let tran = tran.trim().to_string();
+        if !tran.is_empty() {
+            memory.entry(source(&capture, &source_text)).or_insert(tran);
+        }
+    }
+
+    let mut translations = translated_lines(config, textures)?;
+    let mut edited = HashSet::new();
+    for (i, line) in textures.lines.iter().enumerate() {
+        if !line.needs_translation() || translations[i].is_some() {
+            continue;
+        }
+        if let Some(tran) = memory.get(&source(&capture, &line.content)) {
+            translations[i] = Some(tran.clone());
+            edited.insert(i);
+        }
+    }
+    let translator = config.translator_selection().primary();
+    write_back(textures, translator, &translations, &edited);
+
+    // the untranslated lines are translated by --retry-failed instead of from curr_index,
+    // which would send the pre-filled lines again
+    let translations = translated_lines(config, textures)?;
+    let untranslated = to_ranges(
+        (0..textures.lines.len())
+            .filter(|&i| textures.lines[i].needs_translation() && translations[i].is_none()),
+    );
+    let mut report = FailedReport::load(&textures.dirs, &textures.name);
+    let batches = untranslated
+        .iter()
+        .map(|&range| FailedBatch::of(textures, range, FailReason::Untranslated))
+        .collect();
+    report.replace(FailReason::Untranslated, batches);
+    report.save(&textures.dirs, &textures.name)?;
+    textures.curr_index = textures.lines.len();
+    let imported = t!(
+        Msg::ImportTranslations,
+        path,
+        edited.len(),
+        untranslated.len()
+    );
+    println!("{}", imported);
+    Ok(edited.len())
+}
+
+/// the (source, translation) pairs of the translation units with both languages
+fn read_tmx(content: &str, from: Language, to: Language) -> Vec<(String, String)> {
+    let tu = Regex::new(r"(?s)<tu[\s>].*?</tu>").unwrap();
+    let tuv = Regex::new(r#"(?s)<tuv[^>]*?lang="([^"]+)"[^>]*>.*?<seg>(.*?)</seg>"#).unwrap();
+    let is_lang = |code: &str, lang: Language| {
+        let code = code
+            .split(['-', '_'])
+            .next()
+            .unwrap_or(code)
+            .to_ascii_lowercase();
+        code == tmx_lang(lang) || code == lang.to_639_3()
+    };
+    tu.find_iter(content)
+        .filter_map(|unit| {
+            let mut source_text = None;
+            let mut tran = None;
+            for caps in tuv.captures_iter(unit.as_str()) {
+                let seg = unescape_xml(&caps[2]);
+                if is_lang(&caps[1], from) {
+                    source_text.get_or_insert(seg);
+                } else if is_lang(&caps[1], to) {
+                    tran.get_or_insert(seg);
+                }
+            }
+            Some((source_text?, tran?))
+        })
+        .collect()
+}
+
+fn unescape_xml(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// rewrite every batch of the translator that contains an edited line as a numbered list
+/// `(1) xxx`, the same format as the batchizer sends to the translator, the translator is the
+/// primary one of the output, returns the count of the batches
+pub fn write_back(
+    textures: &mut Textures,
+    translator: Translator,
+    translations: &[Option<String>],
+    edited: &HashSet<usize>,
+) -> usize {
+    // collect the batches that contain edited lines, the untranslated lines become a single batch
+    let mut batches = vec![];
+    let mut i = 0;
+    while i < textures.lines.len() {
+        let range = textures.lines[i]
+            .translated
+            .iter()
+            .find(|t| t.translator == translator)
+            .map(|t| t.batch_range)
+            .unwrap_or((i, i));
+        if (range.0..=range.1).any(|j| edited.contains(&j)) {
+            batches.push(range);
+        }
+        i = range.1 + 1;
+    }
+    for (start, end) in batches.iter() {
+        let mut content = String::new();
+        for (n, j) in textures.batch_lines((*start, *end)).into_iter().enumerate() {
+            let tran = translations[j].as_deref().unwrap_or("");
+            content.push_str(&format!("({}) {}\n", n + 1, tran));
+        }
+        let curr_index = textures.curr_index;
+        textures.update(TranslatedLine::new(translator, content, *start, *end));
+        textures.curr_index = curr_index;
+    }
+    batches.len()
+}
+
+/// export the source and translation pairs as a tmx translation memory for the cat tools,
+/// the duplicated pairs and the untranslated lines are left out
+pub fn export_tmx(
+    config: &Configuration,
+    textures: &Textures,
+    path: Option<String>,
+) -> Result<String> {
+    let path = path.unwrap_or_else(|| {
+        config
+            .dirs()
+            .tmx(&textures.name)
+            .to_string_lossy()
+            .to_string()
+    });
+    let translations = translated_lines(config, textures)?;
+    let capture = source_capture(config)?;
+    let mut seen = HashSet::new();
+    let pairs = textures
+        .lines
+        .iter()
+        .zip(translations)
+        .filter(|(line, _)| line.needs_translation())
+        .filter_map(|(line, tran)| {
+            let tran = tran?.trim().to_string();
+            let source = source(&capture, &line.content);
+            (!tran.is_empty() && !source.is_empty()).then_some((source, tran))
+        })
+        .filter(|pair| seen.insert(pair.clone()))
+        .collect::<Vec<_>>();
+    fs::write(&path, write_tmx(&pairs, config.lang_from, config.lang_to))?;
+    println!("{}", t!(Msg::ExportTmx, path, pairs.len()));
+    Ok(path)
+}
+
+/// the tmx 1.4 document of the pairs
+fn write_tmx(pairs: &[(String, String)], from: Language, to: Language) -> String {
+    let (from, to) = (tmx_lang(from), tmx_lang(to));
+    let mut tmx =
+        String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<tmx version=\"1.4\">\n");
+    tmx.push_str(&format!(
+        "  <header creationtool=\"lottr\" creationtoolversion=\"{}\" segtype=\"sentence\" o-tmf=\"lottr\" adminlang=\"en\" srclang=\"{}\" datatype=\"plaintext\"/>\n  <body>\n",
+        env!("CARGO_PKG_VERSION"),
+        from
+    ));
+    for (source, tran) in pairs {
+        tmx.push_str("    <tu>\n");
+        for (lang, seg) in [(from, source), (to, tran)] {
+            tmx.push_str(&format!(
+                "      <tuv xml:lang=\"{}\"><seg>{}</seg></tuv>\n",
+                lang,
+                escape_xml(seg)
+            ));
+        }
+        tmx.push_str("    </tu>\n");
+    }
+    tmx.push_str("  </body>\n</tmx>\n");
+    tmx
+}
+
+/// the iso 639-1 code of the language, the iso 639-3 code if it has none
+fn tmx_lang(lang: Language) -> &'static str {
+    lang.to_639_1().unwrap_or(lang.to_639_3())
+}
+
+fn escape_xml(s: &str) -> String {
+    s.chars()
+        // the control characters are not allowed in xml 1.0
+        .filter(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r'))
+        .fold(String::with_capacity(s.len()), |mut escaped, c| {
+            match c {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '"' => escaped.push_str("&quot;"),
+                _ => escaped.push(c),
+            }
+            escaped
+        })
+}
+
+fn trim_newline(s: &str) -> &str {
+    s.trim_end_matches(['\r', '\n'])
+}
+
+fn write_csv_row(fields: &[&str]) -> String {
+    let row = fields
+        .iter()
+        .map(|f| format!("\"{}\"", f.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("{}\r\n", row)
+}
+
+fn read_csv(content: &str) -> Vec<Vec<String>> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = content.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    field.push('"');
+                    chars.next();
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => row.push(std::mem::take(&mut field)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            _ => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_csv_roundtrip() {
+        let mut csv = write_csv_row(&["id", "source", "translation"]);
+        csv.push_str(&write_csv_row(&["0", "「a, \"b\"」", "line1\nline2"]));
+        csv.push_str("1,plain,text");
+        let rows = read_csv(&csv);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[1], vec!["0", "「a, \"b\"」", "line1\nline2"]);
+        assert_eq!(rows[2], vec!["1", "plain", "text"]);
+    }
+
+    #[test]
+    fn test_write_back() {
+        use crate::{
+            paths::ArtifactDirs,
+            textures::{TextureLine, TEXTURES_VERSION},
+        };
+        let mut textures = Textures {
+            lines: ["a", "b"]
+                .iter()
+                .map(|l| TextureLine::new(0, 1, l.to_string(), false))
+                .collect(),
+            curr_index: 2,
+            version: TEXTURES_VERSION,
+            source: None,
+            name: String::new(),
+            shard: None,
+            dirs: ArtifactDirs::default(),
+        };
+        textures.update(TranslatedLine::new(
+            Translator::Gemini,
+            "(1) A\n(2) B".to_string(),
+            0,
+            1,
+        ));
+        let translations = vec![Some("A".to_string()), Some("Bee".to_string())];
+        let edited = HashSet::from([1]);
+        assert_eq!(
+            write_back(&mut textures, Translator::Gemini, &translations, &edited),
+            1
+        );
+        // the batch of the gemini-only setup is rewritten, no chatgpt batch is added
+        let translated = &textures.lines[0].translated;
+        assert_eq!(translated.len(), 1);
+        assert_eq!(translated[0].translator, Translator::Gemini);
+        assert_eq!(translated[0].content, "(1) A\n(2) Bee\n");
+    }
+
+    #[test]
+    fn test_read_tmx() {
+        let pairs = vec![(
+            "ピノ & <ゼペット>".to_string(),
+            "Pino & <Geppetto>".to_string(),
+        )];
+        let tmx = write_tmx(&pairs, Language::Jpn, Language::Eng).replace("\"en\"", "\"EN-US\"");
+        assert_eq!(read_tmx(&tmx, Language::Jpn, Language::Eng), pairs);
+        assert!(read_tmx(&tmx, Language::Jpn, Language::Zho).is_empty());
+    }
+
+    #[test]
+    fn test_write_tmx() {
+        let pairs = vec![("「ピノ」<b>&".to_string(), "\"Pino\" <b>&".to_string())];
+        let tmx = write_tmx(&pairs, Language::Jpn, Language::Eng);
+        assert!(tmx.contains("srclang=\"ja\""));
+        assert!(tmx.contains("<tuv xml:lang=\"ja\"><seg>「ピノ」&lt;b&gt;&amp;</seg></tuv>"));
+        assert!(
+            tmx.contains("<tuv xml:lang=\"en\"><seg>&quot;Pino&quot; &lt;b&gt;&amp;</seg></tuv>")
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// the ruby markup of the three common formats:
+/// kirikiri `[ruby text="かみ"]神`, aozora `|神様《かみさま》` or `神様《かみさま》`,
+/// html `<ruby>神様<rt>かみさま</rt></ruby>`
+const RUBY_REGEX: &str = r#"\[ruby text="?(?P<k_reading>[^"\]]+)"?\](?P<k_base>[^\[\r\n])|[|｜](?P<a_base>[^|｜《》\r\n]+)《(?P<a_reading>[^《》\r\n]+)》|(?P<h_base>[\p{Han}々〆ヵヶ]+)《(?P<h_reading>[^《》\r\n]+)》|<ruby>(?P<html>.*?)</ruby>"#;
+
+/// the tags inside the html ruby, <rt> and <rp> with their text, <rb> without
+const HTML_RUBY_TAGS: &str = r"<rt>(?P<rt>.*?)</rt>|<rp>.*?</rp>|</?rb>";
+
+/// what happens to the readings of the ruby markup, the base text is always sent alone
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RubyMode {
+    /// the translation is output without the readings
+    #[serde(rename = "drop")]
+    Drop,
+    /// the bases kept as they are in the translation are wrapped in their original markup again,
+    /// e.g. the kanji of a japanese to chinese translation
+    #[serde(rename = "keep")]
+    Keep,
+}
+
+/// a ruby markup of the source
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ruby {
+    pub base: String,
+    pub reading: String,
+    /// the whole markup as it is in the source
+    pub markup: String,
+}
+
+pub struct RubyParser {
+    regex: Regex,
+    html: Regex,
+}
+
+impl Default for RubyParser {
+    fn default() -> Self {
+        Self {
+            regex: Regex::new(RUBY_REGEX).unwrap(),
+            html: Regex::new(HTML_RUBY_TAGS).unwrap(),
+        }
+    }
+}
+
+impl RubyParser {
+    /// the content with every ruby replaced by its base, and the rubies in order
+    pub fn parse(&self, content: &str) -> (String, Vec<Ruby>) {
+        let mut rubies = vec![];
+        let plain = self.regex.replace_all(content, |caps: &regex::Captures| {
+            let (base, reading) = match caps.name("html") {
+                Some(html) => {
+                    let base = self.html.replace_all(html.as_str(), "").to_string();
+                    let reading = self
+                        .html
+                        .captures_iter(html.as_str())
+                        .filter_map(|c| c.name("rt").map(|rt| rt.as_str()))
+                        .collect::<String>();
+                    (base, reading)
+                }
+                None => {
+                    let group = |name: &str| {
+                        ["k_", "a_", "h_"]
+                            .iter()
+                            .find_map(|p| caps.name(&format!("{}{}", p, name)))
+                            .map(|m| m.as_str().to_string())
+                            .unwrap_or_default()
+                    };
+                    (group("base"), group("reading"))
+                }
+            };
+            rubies.push(Ruby {
+                base: base.clone(),
+                reading,
+                markup: caps[0].to_string(),
+            });
+            base
+        });
+        (plain.to_string(), rubies)
+    }
+
+    /// the content with every ruby replaced by its base
+    pub fn strip(&self, content: &str) -> String {
+        self.parse(content).0
+    }
+
+    /// wrap the bases found in the translation in the markup of the source, in order,
+    /// the bases translated away are dropped with their readings
+    pub fn restore(&self, translation: &str, source: &str) -> String {
+        let (_, rubies) = self.parse(source);
+        let mut restored = String::with_capacity(translation.len());
+        let mut rest = translation;
+        for ruby in rubies.iter().filter(|r| !r.base.is_empty()) {
+            if let Some(pos) = rest.find(&ruby.base) {
+                restored.push_str(&rest[..pos]);
+                restored.push_str(&ruby.markup);
+                rest = &rest[pos + ruby.base.len()..];
+            }
+        }
+        restored.push_str(rest);
+        restored
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RubyParser;
+
+    #[test]
+    fn test_ruby() {
+        let parser = RubyParser::default();
+        let (plain, rubies) = parser.parse(
+            "[ruby text=\"かみ\"]神と|魔法使い《まほうつかい》、<ruby>剣<rp>(</rp><rt>つるぎ</rt><rp>)</rp></ruby>の勇者《ゆうしゃ》",
+        );
+        assert_eq!(plain, "神と魔法使い、剣の勇者");
+        let readings = rubies
+            .iter()
+            .map(|r| r.reading.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(readings, vec!["かみ", "まほうつかい", "つるぎ", "ゆうしゃ"]);
+        assert_eq!(
+            parser.restore(
+                "神与魔法师、剑的勇者",
+                "[ruby text=かみ]神と|魔法使い《まほうつかい》、勇者《ゆうしゃ》"
+            ),
+            "[ruby text=かみ]神与魔法师、剑的勇者《ゆうしゃ》"
+        );
+        assert_eq!(parser.strip("ピノ"), "ピノ");
+    }
+}
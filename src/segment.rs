@@ -0,0 +1,221 @@
+use serde::{Deserialize, Serialize};
+use unicode_width::UnicodeWidthChar;
+
+use crate::{inputs::is_sentence_end, textures::TextureLine};
+
+/// split overly long lines into sentences for translation, the translated sentences are joined
+/// back into the line, then re-wrapped to line_width
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SegmentOptions {
+    /// a line longer than max_chars characters is split into sentences
+    pub max_chars: usize,
+    /// wrap the translation to the display width, a full-width character counts 2,
+    /// the line_width and width_mode of mtool_opt take precedence for the replace trans_type
+    pub line_width: Option<usize>,
+}
+
+/// split the line into sentences if it is longer than max_chars, the short sentences are merged
+/// as long as they fit in max_chars, the lines of a paragraph are never split
+pub fn split_line(line: TextureLine, options: &SegmentOptions) -> Vec<TextureLine> {
+    if !line.parts.is_empty() || line.content.chars().count() <= options.max_chars {
+        return vec![line];
+    }
+    let sentences = split_sentences(line.content.trim_end_matches(['\r', '\n']));
+    let mut segments: Vec<String> = vec![];
+    for sentence in sentences {
+        match segments.last_mut() {
+            Some(last) if last.chars().count() + sentence.chars().count() <= options.max_chars => {
+                last.push_str(&sentence)
+            }
+            _ => segments.push(sentence),
+        }
+    }
+    if segments.len() < 2 {
+        return vec![line];
+    }
+    let count = segments.len();
+    segments
+        .into_iter()
+        .enumerate()
+        .map(|(k, segment)| {
+            let mut texture_line = line.clone();
+            texture_line.content = segment.trim().to_string();
+            texture_line.sentence = Some((k, count));
+            texture_line
+        })
+        .collect()
+}
+
+/// split after every sentence end, the following closing marks and spaces stay with the sentence
+pub fn split_sentences(text: &str) -> Vec<String> {
+    let mut sentences = vec![];
+    let mut curr = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        curr.push(c);
+        if !is_sentence_end(c) {
+            continue;
+        }
+        while let Some(&next) = chars.peek() {
+            if !is_sentence_end(next) && !next.is_whitespace() {
+                break;
+            }
+            curr.push(next);
+            chars.next();
+        }
+        sentences.push(std::mem::take(&mut curr));
+    }
+    if !curr.is_empty() {
+        sentences.push(curr);
+    }
+    sentences
+}
+
+/// join the translated sentences of a line, CJK sentences are joined without a space
+pub fn join_sentences(sentences: &[&String]) -> String {
+    let mut joined = String::new();
+    for sentence in sentences {
+        let sentence = sentence.trim();
+        let full_width = joined.chars().last().is_some_and(is_full_width)
+            || sentence.chars().next().is_some_and(is_full_width);
+        if !joined.is_empty() && !full_width {
+            joined.push(' ');
+        }
+        joined.push_str(sentence);
+    }
+    joined
+}
+
+pub fn is_full_width(c: char) -> bool {
+    c.width() == Some(2)
+}
+
+/// how the line_width is measured
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum WidthMode {
+    /// display columns, a full-width character counts 2
+    #[default]
+    #[serde(rename = "display")]
+    Display,
+    /// characters, every character counts 1
+    #[serde(rename = "chars")]
+    Chars,
+}
+
+impl WidthMode {
+    /// the width of the character in a text box
+    pub fn char_width(self, c: char) -> usize {
+        match self {
+            WidthMode::Display => c.width().unwrap_or(0),
+            WidthMode::Chars if c == '\r' => 0,
+            WidthMode::Chars => 1,
+        }
+    }
+}
+
+/// wrap the text to the width measured by the mode, the existing line breaks are kept,
+/// a line is broken at the last space if any, so the words are not split
+pub fn wrap(text: &str, width: usize, mode: WidthMode) -> Vec<String> {
+    let mut lines = vec![];
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        let mut line_width = 0;
+        for c in paragraph.chars() {
+            let w = mode.char_width(c);
+            if line_width + w > width && !line.is_empty() {
+                if c == ' ' {
+                    lines.push(std::mem::take(&mut line));
+                    line_width = 0;
+                    continue;
+                }
+                match line.rfind(' ').filter(|&i| i > 0 && !is_full_width(c)) {
+                    Some(i) => {
+                        let rest = line[i + 1..].to_string();
+                        line.truncate(i);
+                        lines.push(std::mem::replace(&mut line, rest));
+                        line_width = line.chars().map(|c| mode.char_width(c)).sum();
+                    }
+                    None => {
+                        lines.push(std::mem::take(&mut line));
+                        line_width = 0;
+                    }
+                }
+            }
+            line.push(c);
+            line_width += w;
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod test {
+    use crate::textures::TextureLine;
+
+    use super::*;
+
+    #[test]
+    fn test_split_line() {
+        let options = SegmentOptions {
+            max_chars: 8,
+            line_width: None,
+        };
+        let line = TextureLine::new(10, 30, "第一句。第二句！「第三句。」\n".to_string(), false);
+        let lines = split_line(line, &options);
+        let contents = lines.iter().map(|l| l.content.as_str()).collect::<Vec<_>>();
+        assert_eq!(contents, vec!["第一句。第二句！", "「第三句。」"]);
+        assert!(lines.iter().all(|l| l.seek == 10 && l.size == 30));
+        assert_eq!(lines[1].sentence, Some((1, 2)));
+        let line = TextureLine::new(0, 6, "short\n".to_string(), false);
+        assert_eq!(split_line(line, &options)[0].sentence, None);
+    }
+
+    #[test]
+    fn test_join_sentences() {
+        let a = "Hello.".to_string();
+        let b = " World. ".to_string();
+        assert_eq!(join_sentences(&[&a, &b]), "Hello. World.");
+        let a = "你好。".to_string();
+        let b = "世界。".to_string();
+        assert_eq!(join_sentences(&[&a, &b]), "你好。世界。");
+    }
+
+    #[test]
+    fn test_wrap() {
+        let display = WidthMode::Display;
+        assert_eq!(
+            wrap("你好世界，再见", 6, display),
+            vec!["你好世", "界，再", "见"]
+        );
+        assert_eq!(
+            wrap("the quick brown fox", 10, display),
+            vec!["the quick", "brown fox"]
+        );
+        assert_eq!(wrap("ab你好", 3, display), vec!["ab", "你", "好"]);
+        assert_eq!(wrap("a\nbcd", 2, display), vec!["a", "bc", "d"]);
+        assert_eq!(wrap("你好世界", 3, WidthMode::Chars), vec!["你好世", "界"]);
+    }
+
+    #[test]
+    fn test_width_mode() {
+        let display = WidthMode::Display;
+        // a combining mark takes no column, an emoji and a full-width letter take two
+        assert_eq!(display.char_width('\u{301}'), 0);
+        assert_eq!(display.char_width('😀'), 2);
+        assert_eq!(display.char_width('Ａ'), 2);
+        assert_eq!(display.char_width('ｱ'), 1);
+        assert_eq!(WidthMode::Chars.char_width('😀'), 1);
+        assert_eq!(
+            wrap("e\u{301}e\u{301}e", 2, display),
+            vec!["e\u{301}e\u{301}", "e"]
+        );
+
+        #[derive(Deserialize)]
+        struct Opt {
+            width_mode: WidthMode,
+        }
+        let opt: Opt = toml::from_str(r#"width_mode = "chars""#).unwrap();
+        assert_eq!(opt.width_mode, WidthMode::Chars);
+    }
+}
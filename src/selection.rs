@@ -0,0 +1,138 @@
+use std::io::{BufRead, Write};
+
+use anyhow::Result;
+
+use crate::{i18n::Msg, t, textures::Textures, translators::estimate_batches, Configuration};
+
+/// the lines shown from the start and the end of the selection
+const PREVIEW_LINES: usize = 5;
+
+/// the lines the run is about to send, printed before the translation so a bad filter regex is
+/// caught before it is paid for
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Selection {
+    /// the lines of the file
+    pub total: usize,
+    /// the lines of the run skipped by filter_regexen or the skip rules
+    pub skipped: usize,
+    /// the lines of the run sharing the translation of an earlier line
+    pub duplicates: usize,
+    /// the lines of the run translated by the dictionary
+    pub local: usize,
+    /// the indexes of the lines of the run sent to the translator
+    pub selected: Vec<usize>,
+    pub batches: Option<usize>,
+}
+
+impl Selection {
+    /// the lines of the specify_range, or from the resume index
+    pub fn new(textures: &Textures, specify_range: &Option<Vec<(usize, usize)>>) -> Self {
+        let len = textures.lines.len();
+        let ranges = specify_range
+            .clone()
+            .unwrap_or_else(|| vec![(textures.curr_index, len.saturating_sub(1))]);
+        let mut selection = Self {
+            total: len,
+            ..Default::default()
+        };
+        for i in ranges
+            .into_iter()
+            .flat_map(|(s, e)| s..=e.min(len.saturating_sub(1)))
+        {
+            let line = &textures.lines[i];
+            if line.skip {
+                selection.skipped += 1;
+            } else if line.duplicate.is_some() {
+                selection.duplicates += 1;
+            } else if line.local.is_some() {
+                selection.local += 1;
+            } else {
+                selection.selected.push(i);
+            }
+        }
+        selection
+    }
+
+    pub fn render(&self, textures: &Textures) -> String {
+        let counts = t!(
+            Msg::Selection,
+            self.total,
+            self.selected.len(),
+            self.skipped,
+            self.duplicates,
+            self.local
+        );
+        let mut out = format!("[Selection] {}", counts);
+        if let Some(batches) = self.batches {
+            out.push_str(&t!(Msg::SelectionBatches, batches));
+        }
+        let head = self.selected.len().min(PREVIEW_LINES);
+        let tail = self.selected.len().saturating_sub(PREVIEW_LINES).max(head);
+        for (k, &i) in self.selected.iter().enumerate() {
+            if k == head && tail > head {
+                out.push_str("\n  ...");
+            }
+            if k < head || k >= tail {
+                let line = textures.offset() + i + 1;
+                out.push_str(&format!("\n  {}: {}", line, textures.lines[i].content));
+            }
+        }
+        out
+    }
+}
+
+/// print the selection of the run, and ask whether to go on when confirm
+pub fn review_selection(cfg: &Configuration, textures: &Textures, confirm: bool) -> Result<bool> {
+    let mut selection = Selection::new(textures, &cfg.specify_range);
+    selection.batches = estimate_batches(textures, cfg)?;
+    println!("{}", selection.render(textures));
+    if !confirm || selection.selected.is_empty() {
+        return Ok(true);
+    }
+    ask(&t!(Msg::ConfirmLines, selection.selected.len()))
+}
+
+/// ask in the terminal, no unless answered yes
+pub fn ask(question: &str) -> Result<bool> {
+    print!("{} [y/N]: ", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::Selection;
+    use crate::textures::{TextureLine, Textures, TEXTURES_VERSION};
+
+    #[test]
+    fn test_selection() {
+        let mut lines = (0..14)
+            .map(|i| TextureLine::new(0, 1, format!("line {}", i), i == 1))
+            .collect::<Vec<_>>();
+        lines[2].duplicate = Some(0);
+        lines[3].local = Some("OK".to_string());
+        let textures = Textures {
+            lines,
+            curr_index: 0,
+            version: TEXTURES_VERSION,
+            source: None,
+            name: "game.txt".to_string(),
+            shard: None,
+            dirs: Default::default(),
+        };
+        let selection = Selection::new(&textures, &None);
+        assert_eq!(
+            (selection.skipped, selection.duplicates, selection.local),
+            (1, 1, 1)
+        );
+        assert_eq!(selection.selected.len(), 11);
+        let out = selection.render(&textures);
+        assert!(out.contains("\n  1: line 0\n  5: line 4\n  6: line 5\n  7: line 6\n  8: line 7\n  ...\n  10: line 9\n"));
+        assert!(out.ends_with("14: line 13"));
+        let selection = Selection::new(&textures, &Some(vec![(0, 2)]));
+        assert_eq!(selection.selected, vec![0]);
+        assert!(!selection.render(&textures).contains("..."));
+    }
+}
@@ -0,0 +1,184 @@
+use std::{fs, path::Path};
+
+use anyhow::Result;
+use regex::Regex;
+
+use crate::{
+    config,
+    console::{self, Tone},
+    inputs::in_put,
+    outputs::out_put,
+    textures::{Textures, TranslatedLine},
+    translators::Translator,
+    Configuration,
+};
+
+/// a sample file of a trans_type, its configuration and the translated file expected
+struct Case {
+    name: &'static str,
+    ext: &'static str,
+    config: &'static str,
+    input: &'static str,
+    golden: &'static str,
+}
+
+macro_rules! case {
+    ($name:literal, $ext:literal) => {
+        Case {
+            name: $name,
+            ext: $ext,
+            config: include_str!(concat!("../assets/selftest/", $name, "/config.toml")),
+            input: include_str!(concat!("../assets/selftest/", $name, "/input.", $ext)),
+            golden: include_str!(concat!("../assets/selftest/", $name, "/golden.", $ext)),
+        }
+    };
+}
+
+/// the cases of assets/selftest, one for every trans_type and the json output of mtool
+const CASES: [Case; 6] = [
+    case!("kirikiri", "ks"),
+    case!("mtool", "json"),
+    case!("mtool_json", "json"),
+    case!("ain", "txt"),
+    case!("key_value", "txt"),
+    case!("tpp", "trans"),
+];
+
+/// run the sample files through the input, a mock translator and the output, the translated
+/// files are compared with the golden ones, the mismatches are printed with the path of the file
+pub fn selftest() -> Result<()> {
+    let root = std::env::temp_dir().join("lottr_selftest");
+    let _ = fs::remove_dir_all(&root);
+    let mut failed = 0;
+    for case in CASES.iter() {
+        match run_case(case, &root.join(case.name)) {
+            Ok(None) => println!("{} ... {}", case.name, console::out(Tone::Ok, "ok")),
+            Ok(Some(mismatch)) => {
+                failed += 1;
+                let tag = console::out(Tone::Error, "FAILED");
+                println!("{} ... {}\n{}", case.name, tag, mismatch);
+            }
+            Err(e) => {
+                failed += 1;
+                let tag = console::out(Tone::Error, "FAILED");
+                println!("{} ... {}\n{:#}", case.name, tag, e);
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!(
+            "{} of {} cases failed, the files are kept in {}",
+            failed,
+            CASES.len(),
+            root.display()
+        ));
+    }
+    let _ = fs::remove_dir_all(&root);
+    println!("{} cases passed", CASES.len());
+    Ok(())
+}
+
+/// the first mismatched line of the translated file, None if it is the golden one
+fn run_case(case: &Case, dir: &Path) -> Result<Option<String>> {
+    fs::create_dir_all(dir)?;
+    let config_path = dir.join("config.toml");
+    fs::write(&config_path, case.config)?;
+    let file = dir.join(format!("input.{}", case.ext));
+    fs::write(&file, case.input)?;
+    let file = file.to_string_lossy().to_string();
+
+    let cfg = config::load_config(&config_path.to_string_lossy(), None, None)?;
+    let mut textures = in_put(&cfg, &file)?;
+    mock_translate(&cfg, &mut textures)?;
+    out_put(&cfg, &textures)?;
+
+    let translated = cfg.output_path(&file, Translator::ChatGPT);
+    let actual = fs::read_to_string(&translated)?;
+    Ok(
+        first_mismatch(&actual, case.golden).map(|(n, actual, golden)| {
+            format!(
+                "  line {} of {}\n  expected: {}\n  actual:   {}",
+                n + 1,
+                translated.display(),
+                golden,
+                actual
+            )
+        }),
+    )
+}
+
+/// translate all the lines as one batch answered in numbered lines, every run of non-ascii
+/// characters is wrapped in <>, the rest of the text is kept like a translator keeps the markup
+fn mock_translate(cfg: &Configuration, textures: &mut Textures) -> Result<()> {
+    if textures.lines.is_empty() {
+        return Ok(());
+    }
+    let text = Regex::new(r"[^\x00-\x7f]+").unwrap();
+    // the batchizer sends the group of capture_regex when the input has not captured it
+    let extract = match cfg.capture_regex.as_ref().filter(|_| !cfg.capture_input) {
+        Some(regex) => Some(Regex::new(regex)?),
+        None => None,
+    };
+    let range = (0, textures.lines.len() - 1);
+    let mut content = String::new();
+    for (n, i) in textures.batch_lines(range).into_iter().enumerate() {
+        let line = &textures.lines[i].content;
+        let line = match &extract {
+            Some(regex) => regex
+                .captures(line)
+                .and_then(|caps| caps.get(1))
+                .map(|m| m.as_str().to_string())
+                .unwrap_or_default(),
+            None => line.clone(),
+        };
+        content.push_str(&format!(
+            "({}) {}\n",
+            n + 1,
+            text.replace_all(&line, "<$0>")
+        ));
+    }
+    textures.update(TranslatedLine::new(
+        Translator::ChatGPT,
+        content,
+        range.0,
+        range.1,
+    ));
+    Ok(())
+}
+
+/// (index, actual, golden) of the first line differing
+fn first_mismatch<'a>(actual: &'a str, golden: &'a str) -> Option<(usize, &'a str, &'a str)> {
+    let mut actual_lines = actual.split_inclusive('\n');
+    let mut golden_lines = golden.split_inclusive('\n');
+    let mut n = 0;
+    loop {
+        match (actual_lines.next(), golden_lines.next()) {
+            (None, None) => return None,
+            (a, g) if a != g => return Some((n, a.unwrap_or("<eof>"), g.unwrap_or("<eof>"))),
+            _ => n += 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{first_mismatch, run_case, CASES};
+
+    #[test]
+    fn test_first_mismatch() {
+        assert_eq!(first_mismatch("a\nb\n", "a\nb\n"), None);
+        assert_eq!(first_mismatch("a\nc\n", "a\nb\n"), Some((1, "c\n", "b\n")));
+        assert_eq!(first_mismatch("a\n", "a\nb\n"), Some((1, "<eof>", "b\n")));
+        // a line ending changed
+        assert_eq!(first_mismatch("a\r\n", "a\n"), Some((0, "a\r\n", "a\n")));
+    }
+
+    #[test]
+    fn test_golden_files() {
+        let root = crate::utils::TempDir::new("golden_files");
+        for case in CASES.iter() {
+            let mismatch = run_case(case, &root.join(case.name)).unwrap();
+            assert!(mismatch.is_none(), "{}: {}", case.name, mismatch.unwrap());
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use hyper::{
+    body::HttpBody,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use crate::{
+    i18n::Msg,
+    outputs::translated_lines,
+    t,
+    textures::{TextureLine, Textures, TEXTURES_VERSION},
+    translators::{translate, Progress},
+    Configuration,
+};
+
+/// a finished job is removed this long after, its lines should have been fetched
+const FINISHED_JOB_TTL: Duration = Duration::from_secs(60 * 60);
+/// a larger request body is rejected before it is buffered
+const MAX_BODY_BYTES: usize = 8 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+struct TranslateRequest {
+    text: Option<String>,
+    lines: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum JobStatus {
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct JobState {
+    job: usize,
+    status: JobStatus,
+    translated: usize,
+    total: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lines: Option<Vec<Option<String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+struct Job {
+    status: JobStatus,
+    progress: watch::Receiver<Progress>,
+    lines: Option<Vec<Option<String>>>,
+    error: Option<String>,
+    /// when the job is done or failed
+    finished: Option<Instant>,
+}
+
+#[derive(Clone)]
+struct ServerState {
+    cfg: Arc<Configuration>,
+    jobs: Arc<Mutex<HashMap<usize, Job>>>,
+    next_id: Arc<Mutex<usize>>,
+}
+
+/// serve a http api, the configuration is loaded once and shared by all jobs:
+///   POST /translate {"text": "..."} or {"lines": ["...", "..."]} -> {"job": 1}
+///   GET /progress/1 -> {"job": 1, "status": "done", "translated": 2, "total": 2, "lines": [...]}
+/// a finished job is found for FINISHED_JOB_TTL, then it is removed, a body over MAX_BODY_BYTES
+/// is rejected
+pub async fn serve(cfg: &Configuration, addr: &str) -> Result<()> {
+    let mut cfg = cfg.clone();
+    // the lines are posted directly, nothing to capture
+    cfg.capture_regex = None;
+    cfg.specify_range = None;
+    let state = ServerState {
+        cfg: Arc::new(cfg),
+        jobs: Arc::new(Mutex::new(HashMap::new())),
+        next_id: Arc::new(Mutex::new(0)),
+    };
+    let addr: SocketAddr = addr.parse()?;
+    let make_svc = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(handle(state, req).await) }
+            }))
+        }
+    });
+    println!("{}", t!(Msg::Serving, addr));
+    Server::bind(&addr).serve(make_svc).await?;
+    Ok(())
+}
+
+async fn handle(state: ServerState, req: Request<Body>) -> Response<Body> {
+    let path = req.uri().path().to_string();
+    match (req.method(), path.as_str()) {
+        (&Method::POST, "/translate") => {
+            let body = match read_body(req.into_body(), MAX_BODY_BYTES).await {
+                Ok(Some(body)) => body,
+                Ok(None) => {
+                    let error = format!("the body is over {} bytes", MAX_BODY_BYTES);
+                    return json_response(StatusCode::PAYLOAD_TOO_LARGE, &error_body(error));
+                }
+                Err(e) => return json_response(StatusCode::BAD_REQUEST, &error_body(e)),
+            };
+            let request = match serde_json::from_slice::<TranslateRequest>(&body) {
+                Ok(request) => request,
+                Err(e) => return json_response(StatusCode::BAD_REQUEST, &error_body(e)),
+            };
+            let lines = match (request.lines, request.text) {
+                (Some(lines), _) => lines,
+                (None, Some(text)) => text.lines().map(|l| l.to_string()).collect(),
+                (None, None) => {
+                    return json_response(
+                        StatusCode::BAD_REQUEST,
+                        &error_body("text or lines is required"),
+                    )
+                }
+            };
+            let id = start_job(&state, lines);
+            json_response(StatusCode::ACCEPTED, &serde_json::json!({ "job": id }))
+        }
+        (&Method::GET, path) if path.starts_with("/progress/") => {
+            let id = path.trim_start_matches("/progress/").parse::<usize>();
+            let jobs = state.jobs.lock().unwrap();
+            match id.ok().and_then(|id| jobs.get(&id).map(|job| (id, job))) {
+                Some((id, job)) => {
+                    let progress = *job.progress.borrow();
+                    let job_state = JobState {
+                        job: id,
+                        status: job.status,
+                        translated: progress.translated,
+                        total: progress.total,
+                        lines: job.lines.clone(),
+                        error: job.error.clone(),
+                    };
+                    json_response(StatusCode::OK, &job_state)
+                }
+                None => json_response(StatusCode::NOT_FOUND, &error_body("job not found")),
+            }
+        }
+        _ => json_response(StatusCode::NOT_FOUND, &error_body("not found")),
+    }
+}
+
+/// the body of the request, None when it is over limit
+async fn read_body(mut body: Body, limit: usize) -> Result<Option<Vec<u8>>, hyper::Error> {
+    if body.size_hint().lower() > limit as u64 {
+        return Ok(None);
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > limit {
+            return Ok(None);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(Some(bytes))
+}
+
+fn start_job(state: &ServerState, lines: Vec<String>) -> usize {
+    let id = {
+        let mut next_id = state.next_id.lock().unwrap();
+        *next_id += 1;
+        *next_id
+    };
+    let (progress_tx, progress_rx) = watch::channel(Progress::default());
+    let mut jobs = state.jobs.lock().unwrap();
+    evict_finished(&mut jobs, Instant::now());
+    jobs.insert(
+        id,
+        Job {
+            status: JobStatus::Running,
+            progress: progress_rx,
+            lines: None,
+            error: None,
+            finished: None,
+        },
+    );
+    drop(jobs);
+    let textures = job_textures(lines);
+    let state = state.clone();
+    tokio::spawn(async move {
+        let mut textures_mut = textures.clone();
+        let result = translate(textures, &mut textures_mut, &state.cfg, Some(&progress_tx))
+            .await
+            .and_then(|_| translated_lines(&state.cfg, &textures_mut));
+        let mut jobs = state.jobs.lock().unwrap();
+        if let Some(job) = jobs.get_mut(&id) {
+            job.finished = Some(Instant::now());
+            match result {
+                Ok(lines) => {
+                    job.status = JobStatus::Done;
+                    job.lines = Some(lines);
+                }
+                Err(e) => {
+                    job.status = JobStatus::Failed;
+                    job.error = Some(e.to_string());
+                }
+            }
+        }
+    });
+    id
+}
+
+/// remove the jobs finished FINISHED_JOB_TTL before now, the running ones are kept
+fn evict_finished(jobs: &mut HashMap<usize, Job>, now: Instant) {
+    jobs.retain(|_, job| {
+        job.finished
+            .is_none_or(|finished| now.duration_since(finished) < FINISHED_JOB_TTL)
+    });
+}
+
+/// the textures of a job are only kept in memory, they have no file name
+fn job_textures(lines: Vec<String>) -> Textures {
+    let mut seek = 0;
+    let lines = lines
+        .into_iter()
+        .map(|line| {
+            let size = line.len() + 1;
+            let texture_line = TextureLine::new(seek, size, line, false);
+            seek += size;
+            texture_line
+        })
+        .collect();
+    let mut textures = Textures {
+        lines,
+        curr_index: 0,
+        version: TEXTURES_VERSION,
+        source: None,
+        name: String::new(),
+        shard: None,
+        dirs: Default::default(),
+    };
+    textures.assign_ids();
+    textures
+}
+
+fn error_body<E: ToString>(e: E) -> serde_json::Value {
+    serde_json::json!({ "error": e.to_string() })
+}
+
+fn json_response<T: Serialize>(status: StatusCode, body: &T) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_string(body).unwrap_or_default()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_job_textures() {
+        let textures = job_textures(vec!["你好".to_string(), "再见".to_string()]);
+        assert_eq!(textures.lines.len(), 2);
+        assert_eq!(textures.lines[1].seek, "你好".len() + 1);
+        assert!(textures.name.is_empty());
+        assert_ne!(textures.lines[0].id, textures.lines[1].id);
+    }
+
+    #[tokio::test]
+    async fn test_read_body() {
+        let body = read_body(Body::from("你好"), 6).await.unwrap();
+        assert_eq!(body.as_deref(), Some("你好".as_bytes()));
+        assert!(read_body(Body::from("你好"), 5).await.unwrap().is_none());
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            for _ in 0..3 {
+                let _ = sender.send_data("abcd".into()).await;
+            }
+        });
+        assert!(read_body(body, 10).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_evict_finished() {
+        let now = Instant::now();
+        let job = |finished: Option<Instant>| Job {
+            status: JobStatus::Done,
+            progress: watch::channel(Progress::default()).1,
+            lines: None,
+            error: None,
+            finished,
+        };
+        let mut jobs = HashMap::new();
+        jobs.insert(1, job(None));
+        jobs.insert(2, job(Some(now)));
+        jobs.insert(3, job(Some(now)));
+        evict_finished(&mut jobs, now + FINISHED_JOB_TTL / 2);
+        assert_eq!(jobs.len(), 3);
+        jobs.get_mut(&3).unwrap().finished = Some(now + FINISHED_JOB_TTL);
+        evict_finished(&mut jobs, now + FINISHED_JOB_TTL);
+        let mut left = jobs.into_keys().collect::<Vec<_>>();
+        left.sort();
+        assert_eq!(left, vec![1, 3]);
+    }
+}
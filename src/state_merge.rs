@@ -0,0 +1,195 @@
+use std::{fs, path::Path, time::SystemTime};
+
+use anyhow::Result;
+use clap::ValueEnum;
+
+use crate::{
+    error::Error,
+    i18n::Msg,
+    t,
+    textures::{match_lines, Textures},
+};
+
+/// which translation is kept when the states translated the same lines with the same translator
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum MergePreference {
+    /// the state given first
+    #[default]
+    Priority,
+    /// the state modified last
+    Recent,
+}
+
+/// merge the translations of the states of the same file, e.g. the ranges translated by two
+/// people or by different backends, into one state written to output, default is the first state.
+/// a state of another revision of the file gives the batches of the lines still in the first.
+/// the batches of different translators are all kept, the output selects among them by
+/// output_translators, the overlapping batches of the same translator are resolved by prefer
+pub fn merge_states(
+    paths: &[String],
+    output: Option<String>,
+    prefer: MergePreference,
+) -> Result<String> {
+    let mut states = paths
+        .iter()
+        .map(|path| {
+            let textures = Textures::load_state(Path::new(path)).map_err(Error::io(path))?;
+            let modified = fs::metadata(path)
+                .and_then(|m| m.modified())
+                .map_err(Error::io(path))?;
+            Ok((textures, modified))
+        })
+        .collect::<Result<Vec<(Textures, SystemTime)>>>()?;
+    if prefer == MergePreference::Recent {
+        // stable, the order given breaks the ties
+        states.sort_by_key(|(_, modified)| std::cmp::Reverse(*modified));
+    }
+    let states = states.into_iter().map(|(t, _)| t).collect::<Vec<_>>();
+    let merged = merge(&states)?;
+    let output = output.unwrap_or_else(|| paths[0].clone());
+    merged
+        .save_as(Path::new(&output))
+        .map_err(Error::io(&output))?;
+    let translated = merged
+        .lines
+        .iter()
+        .map(|l| l.translated.len())
+        .sum::<usize>();
+    let merged = t!(
+        Msg::Merged,
+        states.len(),
+        output,
+        translated,
+        merged.curr_index + 1
+    );
+    println!("{}", merged);
+    Ok(output)
+}
+
+/// the lines of the first state with the batches of all the states, moved to its lines by the
+/// ids of theirs, a batch overlapping one taken from an earlier state with the same translator
+/// is dropped
+fn merge(states: &[Textures]) -> Result<Textures, Error> {
+    let Some(first) = states.first() else {
+        return Err(Error::Config("no state to merge".to_string()));
+    };
+    let mut batches = vec![];
+    for state in states {
+        let map = match_lines(&state.lines, &first.lines);
+        if map.iter().all(Option::is_none) || state.shard != first.shard {
+            return Err(Error::Config(format!(
+                "the state of {} shares no line with the state of {}, not the same file",
+                state.name, first.name
+            )));
+        }
+        batches.push(first.carried(state, &map));
+    }
+    let mut merged = first.clone();
+    merged.lines.iter_mut().for_each(|l| l.translated.clear());
+    // the translators covering every line
+    let mut covered = vec![vec![]; merged.lines.len()];
+    for translated in batches.into_iter().flatten() {
+        let (start, end) = translated.batch_range;
+        if covered[start..=end]
+            .iter()
+            .any(|c: &Vec<_>| c.contains(&translated.translator))
+        {
+            continue;
+        }
+        for c in &mut covered[start..=end] {
+            c.push(translated.translator);
+        }
+        merged.lines[start].translated.push(translated);
+    }
+    // resume from the first line no translator covers
+    merged.curr_index = (0..merged.lines.len())
+        .find(|&i| covered[i].is_empty() && merged.lines[i].needs_translation())
+        .unwrap_or(merged.lines.len());
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod test {
+    use super::merge;
+    use crate::{
+        paths::ArtifactDirs,
+        textures::{TextureLine, Textures, TranslatedLine, TEXTURES_VERSION},
+        translators::Translator,
+    };
+
+    fn state(batches: &[(Translator, &str, usize, usize)]) -> Textures {
+        let mut textures = Textures {
+            lines: ["a", "b", "c", "d"]
+                .iter()
+                .map(|l| TextureLine::new(0, 1, l.to_string(), false))
+                .collect(),
+            curr_index: 0,
+            version: TEXTURES_VERSION,
+            source: None,
+            name: "a.txt".to_string(),
+            shard: None,
+            dirs: ArtifactDirs::default(),
+        };
+        textures.assign_ids();
+        for (translator, content, start, end) in batches {
+            textures.update(TranslatedLine::new(
+                *translator,
+                content.to_string(),
+                *start,
+                *end,
+            ));
+        }
+        textures
+    }
+
+    #[test]
+    fn test_merge_states() {
+        let a = state(&[(Translator::ChatGPT, "a", 0, 1)]);
+        let b = state(&[
+            (Translator::ChatGPT, "b", 1, 2),
+            (Translator::ChatGPT, "b", 3, 3),
+            (Translator::ChatGPTRefined, "b", 0, 1),
+        ]);
+        let merged = merge(&[a.clone(), b.clone()]).unwrap();
+        let batches = |t: &Textures| {
+            t.lines
+                .iter()
+                .flat_map(|l| &l.translated)
+                .map(|t| (t.translator, t.content.clone(), t.batch_range))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            batches(&merged),
+            vec![
+                (Translator::ChatGPT, "a".to_string(), (0, 1)),
+                (Translator::ChatGPTRefined, "b".to_string(), (0, 1)),
+                (Translator::ChatGPT, "b".to_string(), (3, 3)),
+            ]
+        );
+        assert_eq!(merged.curr_index, 2);
+        let merged = merge(&[b, a]).unwrap();
+        assert_eq!(merged.curr_index, 4);
+        // a revision with a line inserted before, its batch of c and d is moved to them
+        let mut revision = state(&[]);
+        revision
+            .lines
+            .insert(0, TextureLine::new(0, 1, "x".to_string(), false));
+        revision.assign_ids();
+        revision.update(TranslatedLine::new(
+            Translator::Gemini,
+            "c".to_string(),
+            3,
+            4,
+        ));
+        let merged = merge(&[state(&[]), revision]).unwrap();
+        assert_eq!(merged.lines[2].translated[0].batch_range, (2, 3));
+        assert!(merge(&[
+            state(&[]),
+            Textures {
+                lines: vec![],
+                ..state(&[])
+            }
+        ])
+        .is_err());
+    }
+}
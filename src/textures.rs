@@ -1,64 +1,805 @@
-use std::fs;
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
 
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-use crate::translators::Translator;
+use crate::{align::realign, i18n::Msg, paths::ArtifactDirs, t, translators::Translator};
+
+/// the schema version of file.textures.json, increased with every change the serde defaults can
+/// not cover, the older states are migrated forward when loaded, see MIGRATIONS
+pub const TEXTURES_VERSION: u32 = 2;
+
+/// the migration of the state of version i to version i + 1
+const MIGRATIONS: [fn(&mut Value); TEXTURES_VERSION as usize] = [migrate_v0, migrate_v1];
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Textures {
+    /// the schema version, 0 for the states written before the version field
+    #[serde(default)]
+    pub version: u32,
     pub lines: Vec<TextureLine>,
     pub curr_index: usize,
     pub name: String,
+    /// set when the textures is one shard of a large file, see `shard_lines`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard: Option<Shard>,
+    /// the input file the lines are parsed from, to tell whether it changed since
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<SourceStamp>,
+    /// where the derived files are written, set when loaded, see `output_dir` and `state_dir`
+    #[serde(skip)]
+    pub dirs: ArtifactDirs,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub struct Shard {
+    pub index: usize,
+    /// the index of the first line of this shard in the whole file
+    pub offset: usize,
+}
+
+/// the size, the modified time and the content hash of the input file
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub struct SourceStamp {
+    pub len: u64,
+    /// milliseconds since the unix epoch
+    pub mtime: u64,
+    /// fnv-1a of the content
+    pub hash: u64,
+}
+
+impl SourceStamp {
+    pub fn of(path: &str) -> Result<Self, std::io::Error> {
+        let content = fs::read(path)?;
+        let mut stamp = Self::metadata(path)?;
+        stamp.hash = fnv(&content);
+        Ok(stamp)
+    }
+    /// the len and the mtime only, hash is 0
+    fn metadata(path: &str) -> Result<Self, std::io::Error> {
+        let metadata = fs::metadata(path)?;
+        let mtime = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        Ok(Self {
+            len: metadata.len(),
+            mtime,
+            hash: 0,
+        })
+    }
+    /// the stamp of the file now, the content is only hashed again when the len or the mtime
+    /// changed, the content changed if the hash differs
+    pub fn refresh(&self, path: &str) -> Result<Self, std::io::Error> {
+        let now = Self::metadata(path)?;
+        if now.len == self.len && now.mtime == self.mtime {
+            return Ok(*self);
+        }
+        Self::of(path)
+    }
+}
+
+/// the fnv-1a of no bytes
+pub(crate) const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+/// fnv-1a, stable across builds
+pub(crate) fn fnv(bytes: &[u8]) -> u64 {
+    fnv_extend(FNV_OFFSET, bytes)
+}
+
+/// the fnv-1a of the bytes hashed after those of hash
+pub(crate) fn fnv_extend(mut hash: u64, bytes: &[u8]) -> u64 {
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// the ids of the lines in the order of the file, see `TextureLine::id`
+#[derive(Debug, Default)]
+struct LineIds {
+    /// the lines seen of every content hash
+    occurrences: HashMap<u64, u64>,
+}
+
+impl LineIds {
+    /// the id of the next line of the file with the content
+    fn next(&mut self, content: &str) -> u64 {
+        let hash = fnv(content.as_bytes());
+        let occurrence = self.occurrences.entry(hash).or_default();
+        let id = fnv_extend(hash, &occurrence.to_le_bytes());
+        *occurrence += 1;
+        id
+    }
+}
+
+/// how the textures are saved, json is readable, json-gz and messagepack are a fraction of its
+/// size and faster to save for the large files. a state of any format is read whatever the option,
+/// the next save converts it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StateFormat {
+    #[default]
+    Json,
+    JsonGz,
+    Messagepack,
+}
+
+impl StateFormat {
+    fn write(self, path: &Path, textures: &Textures) -> Result<(), std::io::Error> {
+        let mut writer = BufWriter::new(fs::File::create(path)?);
+        match self {
+            StateFormat::Json => serde_json::to_writer_pretty(&mut writer, textures)?,
+            StateFormat::JsonGz => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(&mut writer, flate2::Compression::fast());
+                serde_json::to_writer(&mut encoder, textures)?;
+                encoder.finish()?;
+            }
+            // named, the fields are kept for the migrations like in json
+            StateFormat::Messagepack => rmp_serde::encode::write_named(&mut writer, textures)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?,
+        }
+        writer.flush()
+    }
+
+    /// the state and its format, told by the first bytes
+    fn read(path: &Path) -> Result<(Value, Self), std::io::Error> {
+        let bytes = fs::read(path)?;
+        let first = bytes.iter().find(|b| !b.is_ascii_whitespace());
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            let mut json = vec![];
+            flate2::read::GzDecoder::new(&bytes[..]).read_to_end(&mut json)?;
+            Ok((serde_json::from_slice(&json)?, StateFormat::JsonGz))
+        } else if first == Some(&b'{') {
+            Ok((serde_json::from_slice(&bytes)?, StateFormat::Json))
+        } else {
+            let value = rmp_serde::from_slice(&bytes)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            Ok((value, StateFormat::Messagepack))
+        }
+    }
+}
+
+/// file.textures.journal.jsonl beside file.textures.json, one translated batch per line
+fn journal_path(state: &Path) -> PathBuf {
+    state.with_extension("journal.jsonl")
+}
+
+/// the shards of a large file, saved as file.shards.json
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub struct ShardsIndex {
+    pub shards: usize,
+    pub lines: usize,
 }
 
 impl Textures {
+    /// save to file.textures.json in the state_format, the textures without a file name are only
+    /// kept in memory
     pub fn save(&self) -> Result<(), std::io::Error> {
-        println!("Saving textures...");
-        let output = format!("{}.textures.json", self.name);
-        let file = std::fs::File::create(output)?;
-        serde_json::to_writer_pretty(&file, &self)?;
+        if self.name.is_empty() {
+            return Ok(());
+        }
+        println!("{}", t!(Msg::Saving));
+        let output = self.state_path();
+        self.save_as(&output)?;
+        // the journal is in the state now
+        match fs::remove_file(journal_path(&output)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+    /// write the state to path in the state_format, e.g. a merged state
+    pub fn save_as(&self, path: &Path) -> Result<(), std::io::Error> {
+        self.dirs.state_format.write(path, self)
+    }
+    /// file.textures.json, or file.textures.{index}.json of a shard
+    fn state_path(&self) -> PathBuf {
+        match &self.shard {
+            Some(shard) => self.dirs.shard(&self.name, shard.index),
+            None => self.dirs.textures(&self.name),
+        }
+    }
+    /// append the translated batch to the journal beside the state, so the batches done since
+    /// the last save survive a crash, the journal is replayed on load and removed on save
+    pub fn append_journal(&self, line: &TranslatedLine) -> Result<(), std::io::Error> {
+        if self.name.is_empty() {
+            return Ok(());
+        }
+        let state = self.state_path();
+        // the journal is only replayed over a saved state, the new textures are saved first
+        if !state.exists() {
+            self.save()?;
+        }
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(journal_path(&state))?;
+        let mut line = line.clone();
+        if line.ids.is_none() {
+            line.ids = self.range_ids(line.batch_range);
+        }
+        let mut record = serde_json::to_vec(&line)?;
+        record.push(b'\n');
+        file.write_all(&record)?;
+        // the batch is paid for, kept even if the machine goes down
+        file.sync_data()
+    }
+    /// apply the batches of the journal written after the state was saved
+    fn replay_journal(&mut self, path: &Path) -> Result<(), std::io::Error> {
+        let journal = match fs::read_to_string(journal_path(path)) {
+            Ok(journal) => journal,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let start = self.curr_index;
+        let mut replayed = 0;
+        // the last record may be cut by the crash
+        for line in journal
+            .lines()
+            .filter_map(|l| serde_json::from_str::<TranslatedLine>(l).ok())
+        {
+            // the batch of other lines, e.g. of a state replaced since
+            if line.batch_range.1 >= self.lines.len()
+                || line
+                    .ids
+                    .is_some_and(|ids| self.range_ids(line.batch_range) != Some(ids))
+            {
+                continue;
+            }
+            let translator = line.translator;
+            self.update(line);
+            self.curr_index = self.resume_index(translator, start);
+            replayed += 1;
+        }
+        if replayed > 0 {
+            println!("{}", t!(Msg::Recovered, replayed, path.display()));
+        }
         Ok(())
     }
-    pub fn load(file_path: &str) -> Result<Self, std::io::Error> {
-        let file_path = format!("{}.textures.json", file_path);
-        let file = fs::OpenOptions::new().read(true).open(file_path)?;
-        let textures: Textures = serde_json::from_reader(file)?;
+    pub fn load(file_path: &str, dirs: &ArtifactDirs) -> Result<Self, std::io::Error> {
+        Self::read_state(&dirs.textures(file_path), dirs)
+    }
+    pub fn load_shard(
+        file_path: &str,
+        index: usize,
+        dirs: &ArtifactDirs,
+    ) -> Result<Self, std::io::Error> {
+        Self::read_state(&dirs.shard(file_path, index), dirs)
+    }
+    /// read the state file at path, e.g. given on the command line, it is saved in its format
+    pub fn load_state(path: &Path) -> Result<Self, std::io::Error> {
+        let (value, format) = StateFormat::read(path)?;
+        let dirs = ArtifactDirs {
+            state_format: format,
+            ..Default::default()
+        };
+        Self::decode_state(path, value, &dirs)
+    }
+    /// read a saved state, an older one is migrated to TEXTURES_VERSION and backed up as
+    /// xxx.json.v{version}.bak, a newer one is refused instead of being overwritten
+    fn read_state(path: &Path, dirs: &ArtifactDirs) -> Result<Self, std::io::Error> {
+        let (value, _) = StateFormat::read(path)?;
+        Self::decode_state(path, value, dirs)
+    }
+    fn decode_state(
+        path: &Path,
+        mut value: Value,
+        dirs: &ArtifactDirs,
+    ) -> Result<Self, std::io::Error> {
+        let version = migrate(&mut value).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })?;
+        let mut textures: Textures = serde_json::from_value(value)?;
+        if version < TEXTURES_VERSION {
+            let mut backup = path.as_os_str().to_owned();
+            backup.push(format!(".v{}.bak", version));
+            fs::copy(path, &backup)?;
+            let migrated = t!(
+                Msg::Migrated,
+                path.display(),
+                version,
+                TEXTURES_VERSION,
+                Path::new(&backup).display()
+            );
+            println!("{}", migrated);
+        }
+        textures.dirs = dirs.clone();
+        textures.replay_journal(path)?;
         Ok(textures)
     }
-    pub fn update(&mut self, change: TranslatedLine) {
+    /// the ids of the first and the last line of the range, None out of the lines
+    pub fn range_ids(&self, (start, end): (usize, usize)) -> Option<(u64, u64)> {
+        Some((self.lines.get(start)?.id, self.lines.get(end)?.id))
+    }
+    /// the index of every line by its id
+    pub fn line_index(&self) -> HashMap<u64, usize> {
+        self.lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| (line.id, i))
+            .collect()
+    }
+    /// the index of the first line in the whole file
+    pub fn offset(&self) -> usize {
+        self.shard.map(|s| s.offset).unwrap_or(0)
+    }
+    pub fn update(&mut self, mut change: TranslatedLine) {
         self.curr_index = change.batch_range.1;
+        if change.ids.is_none() {
+            change.ids = self.range_ids(change.batch_range);
+        }
+        // a batch of only the lines not sent has nothing to keep
+        if self.batch_lines(change.batch_range).is_empty() {
+            return;
+        }
+        if let Some(reason) = change.refused {
+            for i in self.batch_lines(change.batch_range) {
+                self.lines[i].skip = true;
+                self.lines[i].skip_reason = Some(reason.clone());
+            }
+            return;
+        }
         if let Some(line) = self.lines[change.batch_range.0]
             .translated
             .iter_mut()
             .find(|t| t.translator == change.translator)
         {
-            line.content = change.content;
-            line.batch_range = change.batch_range;
+            *line = change;
         } else {
             self.lines[change.batch_range.0].translated.push(change);
         }
     }
+    /// the first line from start the translator has not translated, the batches are done out of
+    /// order, so the translation resumes from it instead of the end of the last batch done
+    pub fn resume_index(&self, translator: Translator, start: usize) -> usize {
+        let mut i = start;
+        while i < self.lines.len() {
+            match self.lines[i]
+                .translated
+                .iter()
+                .find(|t| t.translator == translator)
+            {
+                Some(translated) => i = translated.batch_range.1.max(i) + 1,
+                None if !self.lines[i].needs_translation() => i += 1,
+                None => return i,
+            }
+        }
+        self.lines.len()
+    }
+    /// set the id of every line, the occurrences are counted in these lines, the whole file or a
+    /// shard
+    pub fn assign_ids(&mut self) {
+        let mut ids = LineIds::default();
+        for line in self.lines.iter_mut() {
+            line.id = ids.next(&line.content);
+        }
+    }
+    /// mark the lines whose content already appeared as duplicates of the first occurrence,
+    /// so every unique content is translated only once
+    pub fn dedup(&mut self) {
+        let mut first = std::collections::HashMap::new();
+        let mut duplicates = 0;
+        for (i, line) in self.lines.iter_mut().enumerate() {
+            match first.get(&line.content) {
+                Some(&k) => {
+                    line.duplicate = Some(k);
+                    duplicates += 1;
+                }
+                None => {
+                    first.insert(line.content.clone(), i);
+                }
+            }
+        }
+        if duplicates > 0 {
+            println!("{}", t!(Msg::Dedup, duplicates, self.lines.len()));
+        }
+    }
+    /// the indexes of the lines in the batch range which are sent to the translator,
+    /// the n-th translated line of the batch belongs to the n-th of them
+    pub fn batch_lines(&self, (start, end): (usize, usize)) -> Vec<usize> {
+        (start..=end.min(self.lines.len().saturating_sub(1)))
+            .filter(|&i| self.lines[i].needs_translation())
+            .collect()
+    }
+    /// the translation of every line from the extracted lines of the batches, indexed like lines,
+    /// the duplicated lines share the translation of their first occurrence, a batch whose size
+    /// mismatches is realigned, failed is called with (batch_range, expected, extracted) for
+    /// the batches unrecoverable
+    pub fn align_translated<E, F>(
+        &self,
+        translator: Translator,
+        extract: E,
+        mut failed: F,
+    ) -> Vec<Option<String>>
+    where
+        E: Fn(&str) -> Vec<String>,
+        F: FnMut((usize, usize), usize, usize),
+    {
+        let mut result = vec![None; self.lines.len()];
+        let mut i = 0;
+        while i < self.lines.len() {
+            let Some(translated) = self.lines[i]
+                .translated
+                .iter()
+                .find(|t| t.translator == translator)
+            else {
+                i += 1;
+                continue;
+            };
+            let tran_lines = extract(&translated.content);
+            let batch_lines = self.batch_lines(translated.batch_range);
+            let aligned = match tran_lines.len() == batch_lines.len() {
+                true => Some(tran_lines.clone()),
+                false => {
+                    let sources = batch_lines
+                        .iter()
+                        .map(|&j| self.lines[j].content.as_str())
+                        .collect::<Vec<_>>();
+                    realign(&translated.content, &tran_lines, &sources, &extract)
+                }
+            };
+            match aligned {
+                Some(aligned) => {
+                    for (j, tran_line) in batch_lines.into_iter().zip(aligned) {
+                        result[j] = Some(tran_line);
+                    }
+                }
+                None => failed(translated.batch_range, batch_lines.len(), tran_lines.len()),
+            }
+            i = translated.batch_range.1 + 1;
+        }
+        for (i, line) in self.lines.iter().enumerate() {
+            if let Some(local) = &line.local {
+                result[i] = Some(local.clone());
+            }
+        }
+        for i in 0..self.lines.len() {
+            if let Some(k) = self.lines[i].duplicate {
+                result[i] = result[k].clone();
+            }
+        }
+        result
+    }
+    /// the batches of the old lines moved to these lines by map, see `match_lines`, only the
+    /// batches whose lines are all unchanged and still in order
+    pub fn carried(&self, old: &Textures, map: &[Option<usize>]) -> Vec<TranslatedLine> {
+        let mut carried = vec![];
+        for line in old.lines.iter() {
+            for translated in &line.translated {
+                let (start, end) = translated.batch_range;
+                let Some(Some(new_start)) = map.get(start).copied() else {
+                    continue;
+                };
+                let unchanged = (start..=end).all(|k| {
+                    map.get(k).copied().flatten() == Some(new_start + k - start)
+                        && old.lines[k].needs_translation()
+                            == self.lines[new_start + k - start].needs_translation()
+                });
+                if !unchanged {
+                    continue;
+                }
+                let mut translated = translated.clone();
+                translated.batch_range = (new_start, new_start + end - start);
+                translated.ids = self.range_ids(translated.batch_range);
+                carried.push(translated);
+            }
+        }
+        carried
+    }
+    /// move the translations of the old state of a changed file to these lines parsed again,
+    /// the batches whose lines are all unchanged are kept, the others are dropped. returns the
+    /// new index of every old line, None for the changed ones, and the ranges to translate again
+    pub fn reconcile(&mut self, old: &Textures) -> (Vec<Option<usize>>, Vec<(usize, usize)>) {
+        let map = match_lines(&old.lines, &self.lines);
+        // the translation resumes from the first unchanged line at or after the old curr_index
+        self.curr_index = map[old.curr_index.min(map.len())..]
+            .iter()
+            .find_map(|m| *m)
+            .unwrap_or(self.lines.len());
+        let mut covered = vec![false; self.lines.len()];
+        for translated in self.carried(old, &map) {
+            let (start, end) = translated.batch_range;
+            covered[start..=end].fill(true);
+            self.lines[start].translated.push(translated);
+        }
+        let mut requeue = to_ranges(
+            (0..self.curr_index).filter(|&i| !covered[i] && self.lines[i].needs_translation()),
+        );
+        if !requeue.is_empty() && self.curr_index < self.lines.len() {
+            requeue.push((self.curr_index, self.lines.len() - 1));
+        }
+        (map, requeue)
+    }
+}
+
+/// the lines at most this many cells of the longest common subsequence table are diffed,
+/// the changed middle of a larger gap between two anchors is translated again as a whole
+const MAX_DIFF_CELLS: usize = 16 * 1024 * 1024;
+
+/// the new index of every old line, in order, None for the changed and the removed ones. the
+/// lines whose content is once in both files are the anchors, found by their ids, the lines
+/// between two anchors, e.g. the repeated blank lines and names, are matched by the longest
+/// common subsequence, so a line inserted or removed does not move the others out of their
+/// batches
+pub fn match_lines(old: &[TextureLine], new: &[TextureLine]) -> Vec<Option<usize>> {
+    let mut map = vec![None; old.len()];
+    let mut from = (0, 0);
+    for (i, j) in anchors(old, new)
+        .into_iter()
+        .chain([(old.len(), new.len())])
+    {
+        diff(old, new, (from.0, i), (from.1, j), &mut map);
+        if let Some(m) = map.get_mut(i) {
+            *m = Some(j);
+        }
+        from = (i + 1, j + 1);
+    }
+    map
+}
+
+/// (old index, new index) of the lines whose content is once in both, the longest run of them
+/// in the same order in both, a moved line is left to the diff
+fn anchors(old: &[TextureLine], new: &[TextureLine]) -> Vec<(usize, usize)> {
+    let mut counts: HashMap<&str, (usize, usize)> = HashMap::new();
+    for line in old {
+        counts.entry(&line.content).or_default().0 += 1;
+    }
+    for line in new {
+        counts.entry(&line.content).or_default().1 += 1;
+    }
+    // the id of a content once in the file is the same in both
+    let unique = new
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| counts[line.content.as_str()] == (1, 1))
+        .map(|(j, line)| (line.id, j))
+        .collect::<HashMap<_, _>>();
+    let pairs = old
+        .iter()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let j = *unique.get(&line.id)?;
+            // the ids of two contents may collide
+            (new[j].content == line.content).then_some((i, j))
+        })
+        .collect::<Vec<_>>();
+    longest_increasing(&pairs)
+}
+
+/// the longest run of the pairs sorted by the first whose seconds increase too
+fn longest_increasing(pairs: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    // tails[k], the pair ending the run of k + 1 pairs with the smallest second
+    let mut tails: Vec<usize> = vec![];
+    let mut prev = vec![None; pairs.len()];
+    for (p, &(_, j)) in pairs.iter().enumerate() {
+        let k = tails.partition_point(|&t| pairs[t].1 < j);
+        if k > 0 {
+            prev[p] = Some(tails[k - 1]);
+        }
+        match tails.get_mut(k) {
+            Some(tail) => *tail = p,
+            None => tails.push(p),
+        }
+    }
+    let mut run = vec![];
+    let mut p = tails.last().copied();
+    while let Some(q) = p {
+        run.push(pairs[q]);
+        p = prev[q];
+    }
+    run.reverse();
+    run
+}
+
+/// match the old lines of the range to the new lines of the range by their longest common
+/// subsequence, none when the gap is larger than MAX_DIFF_CELLS
+fn diff(
+    old: &[TextureLine],
+    new: &[TextureLine],
+    (i0, i1): (usize, usize),
+    (j0, j1): (usize, usize),
+    map: &mut [Option<usize>],
+) {
+    let (old, new) = (&old[i0..i1], &new[j0..j1]);
+    let prefix = old
+        .iter()
+        .zip(new)
+        .take_while(|(o, n)| o.content == n.content)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(o, n)| o.content == n.content)
+        .count();
+    for k in 0..prefix {
+        map[i0 + k] = Some(j0 + k);
+    }
+    for k in 0..suffix {
+        map[i1 - 1 - k] = Some(j1 - 1 - k);
+    }
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+    let (a, b) = (old_mid.len(), new_mid.len());
+    if a == 0 || b == 0 || (a + 1) * (b + 1) > MAX_DIFF_CELLS {
+        return;
+    }
+    // lcs[i][j], the longest common subsequence of old_mid[i..] and new_mid[j..]
+    let mut lcs = vec![0u32; (a + 1) * (b + 1)];
+    for i in (0..a).rev() {
+        for j in (0..b).rev() {
+            lcs[i * (b + 1) + j] = if old_mid[i].content == new_mid[j].content {
+                lcs[(i + 1) * (b + 1) + j + 1] + 1
+            } else {
+                lcs[(i + 1) * (b + 1) + j].max(lcs[i * (b + 1) + j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < a && j < b {
+        if old_mid[i].content == new_mid[j].content {
+            map[i0 + prefix + i] = Some(j0 + prefix + j);
+            i += 1;
+            j += 1;
+        } else if lcs[(i + 1) * (b + 1) + j] >= lcs[i * (b + 1) + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+}
+
+/// the sorted indexes grouped into the ranges of consecutive ones
+pub fn to_ranges(indexes: impl IntoIterator<Item = usize>) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for i in indexes {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == i => *end = i,
+            _ => ranges.push((i, i)),
+        }
+    }
+    ranges
+}
+
+/// migrate the state to TEXTURES_VERSION, the version it was written with
+fn migrate(value: &mut Value) -> Result<u32, String> {
+    let version = value.get("version").and_then(Value::as_u64).unwrap_or(0) as u32;
+    if version > TEXTURES_VERSION {
+        return Err(format!(
+            "the state is written by a newer lottr, version {} (this one reads up to {})",
+            version, TEXTURES_VERSION
+        ));
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(value);
+    }
+    if let Some(state) = value.as_object_mut() {
+        state.insert("version".to_string(), TEXTURES_VERSION.into());
+    }
+    Ok(version)
+}
+
+/// the lines of the first states may lack skip and translated
+fn migrate_v0(value: &mut Value) {
+    let lines = value
+        .get_mut("lines")
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten();
+    for line in lines.filter_map(Value::as_object_mut) {
+        line.entry("skip").or_insert(Value::Bool(false));
+        line.entry("translated")
+            .or_insert_with(|| Value::Array(vec![]));
+    }
+}
+
+/// the lines of the states before the ids are given theirs, a shard counts the occurrences from
+/// its first line like `input_shards`
+fn migrate_v1(value: &mut Value) {
+    let mut ids = LineIds::default();
+    let lines = value
+        .get_mut("lines")
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten();
+    for line in lines.filter_map(Value::as_object_mut) {
+        let content = line.get("content").and_then(Value::as_str).unwrap_or("");
+        let id = ids.next(content);
+        line.insert("id".to_string(), id.into());
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct TextureLine {
+    /// the hash of the content and of its occurrence among the lines of the same content, the
+    /// batches, the failed batches and the review rows are found again by it when the file
+    /// changed, see `match_lines`
+    #[serde(default)]
+    pub id: u64,
     pub seek: usize,
     pub size: usize,
     pub content: String,
     pub skip: bool,
     pub translated: Vec<TranslatedLine>,
+    /// (start, end) bytes of the content in the line, when only a captured group is translated,
+    /// the translation is spliced into the span instead of replacing the whole line
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub span: Option<(usize, usize)>,
+    /// the original lines of a paragraph, the translation is split back across them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<TexturePart>,
+    /// (index, count) of the sentence, when a long line is split into sentences,
+    /// the translations of the sentences are joined back into the line
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sentence: Option<(usize, usize)>,
+    /// the index of the first line with the same content, the line is not sent to the translator,
+    /// the translation of the first line is used instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duplicate: Option<usize>,
+    /// why the line is skipped: filter, numeric, language, protected by the skip rules, or the
+    /// reason the translator refused it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skip_reason: Option<String>,
+    /// the translation of the dictionary, the trivial line is not sent to the translator
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local: Option<String>,
+}
+
+/// a line of a paragraph, seek and size in the file, start and end in the paragraph content
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct TexturePart {
+    pub seek: usize,
+    pub size: usize,
+    pub start: usize,
+    pub end: usize,
 }
 
 impl TextureLine {
     pub fn new(seek: usize, size: usize, content: String, skip: bool) -> Self {
         Self {
+            id: 0,
             seek,
             size,
             content,
             skip,
             translated: vec![],
+            span: None,
+            parts: vec![],
+            sentence: None,
+            duplicate: None,
+            skip_reason: None,
+            local: None,
         }
     }
+
+    /// whether the line is sent to the translator
+    pub fn needs_translation(&self) -> bool {
+        !self.skip && self.duplicate.is_none() && self.local.is_none()
+    }
+
+    /// why the line is not sent to the translator, None if it is sent, offset is the index of
+    /// the first line of the shard
+    pub fn unsent_reason(&self, offset: usize) -> Option<String> {
+        if self.skip {
+            return Some(self.skip_reason.clone().unwrap_or("filter".to_string()));
+        }
+        if let Some(k) = self.duplicate {
+            return Some(format!("duplicate of {}", k + offset));
+        }
+        self.local.as_ref().map(|_| "dictionary".to_string())
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -67,6 +808,48 @@ pub struct TranslatedLine {
     pub content: String,
     // (start, end)
     pub batch_range: (usize, usize),
+    #[serde(default)]
+    pub usage: Option<TokenUsage>,
+    /// masked api key of the client which produced this translation
+    #[serde(default)]
+    pub api: Option<String>,
+    /// the quality estimation of the batch, 1 (unusable) to 5 (good), see qe_opt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<u8>,
+    /// the model and the sampling parameters of the request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<RequestParams>,
+    /// the reason the translator refused the lines of the batch, they are marked skip instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refused: Option<String>,
+    /// the requests of the batch sent again before this one, after a mismatch or an error
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub retries: u32,
+    /// the ids of the first and the last line of the batch, set when it is kept, a journaled
+    /// batch is only replayed over the lines it was translated from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ids: Option<(u64, u64)>,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct RequestParams {
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+pub struct TokenUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
 }
 
 impl TranslatedLine {
@@ -75,6 +858,239 @@ impl TranslatedLine {
             translator,
             content,
             batch_range: (start, end),
+            usage: None,
+            api: None,
+            score: None,
+            params: None,
+            refused: None,
+            retries: 0,
+            ids: None,
+        }
+    }
+
+    /// the lines of the batch refused by the translator, to be marked skip with the reason
+    pub fn refused(translator: Translator, start: usize, end: usize, reason: String) -> Self {
+        let mut line = Self::new(translator, String::new(), start, end);
+        line.refused = Some(reason);
+        line
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{
+        migrate, Shard, StateFormat, TextureLine, Textures, TranslatedLine, TEXTURES_VERSION,
+    };
+    use crate::{paths::ArtifactDirs, translators::Translator};
+
+    fn textures(lines: &[&str]) -> Textures {
+        let mut textures = Textures {
+            lines: lines
+                .iter()
+                .map(|l| TextureLine::new(0, l.len(), l.to_string(), false))
+                .collect(),
+            curr_index: 0,
+            version: TEXTURES_VERSION,
+            source: None,
+            name: String::new(),
+            shard: None,
+            dirs: ArtifactDirs::default(),
+        };
+        textures.assign_ids();
+        textures
+    }
+
+    #[test]
+    fn test_migrate() {
+        let mut state = json!({
+            "name": "a.txt",
+            "curr_index": 0,
+            "lines": [{"index": 0, "seek": 0, "size": 2, "content": "a\n"}]
+        });
+        assert_eq!(migrate(&mut state), Ok(0));
+        assert_eq!(state["version"], TEXTURES_VERSION);
+        assert_eq!(state["lines"][0]["skip"], false);
+        assert_eq!(state["lines"][0]["translated"], json!([]));
+        assert_eq!(state["lines"][0]["id"], textures(&["a\n"]).lines[0].id);
+        assert_eq!(migrate(&mut state), Ok(TEXTURES_VERSION));
+        let mut newer = json!({ "version": TEXTURES_VERSION + 1 });
+        assert!(migrate(&mut newer).is_err());
+    }
+
+    #[test]
+    fn test_journal() {
+        let dir = crate::utils::TempDir::new("journal");
+        let file = dir.join("a.txt").to_string_lossy().to_string();
+        let mut saved = textures(&["a", "b", "c", "d"]);
+        saved.name = file.clone();
+        let batch = |content: &str, start, end| {
+            TranslatedLine::new(Translator::ChatGPT, content.to_string(), start, end)
+        };
+        saved.append_journal(&batch("c", 2, 3)).unwrap();
+        // never saved, the state is saved with the first record
+        assert!(dir.join("a.txt.textures.json").exists());
+        saved.append_journal(&batch("a", 0, 1)).unwrap();
+        // a record cut by the crash
+        let journal = dir.join("a.txt.textures.journal.jsonl");
+        let mut cut = std::fs::read_to_string(&journal).unwrap();
+        cut.push_str("{\"translator\":");
+        std::fs::write(&journal, cut).unwrap();
+        let loaded = Textures::load(&file, &ArtifactDirs::default()).unwrap();
+        assert_eq!(loaded.lines[0].translated[0].content, "a");
+        assert_eq!(loaded.lines[2].translated[0].content, "c");
+        assert_eq!(loaded.curr_index, 4);
+        loaded.save().unwrap();
+        assert!(!journal.exists());
+    }
+
+    #[test]
+    fn test_journal_saves_state_once() {
+        let dir = crate::utils::TempDir::new("journal_state");
+        let file = dir.join("a.txt").to_string_lossy().to_string();
+        let batch = TranslatedLine::new(Translator::ChatGPT, "a".to_string(), 0, 0);
+        // a new shard is saved at its own state path
+        let mut shard = textures(&["a", "b"]);
+        shard.name = file.clone();
+        shard.shard = Some(Shard {
+            index: 1,
+            offset: 2,
+        });
+        shard.append_journal(&batch).unwrap();
+        assert!(ArtifactDirs::default().shard(&file, 1).exists());
+        assert!(!dir.join("a.txt.textures.json").exists());
+        // a saved state is left to the journal, not saved again with every record
+        let mut saved = textures(&["a", "b"]);
+        saved.name = file.clone();
+        saved.save().unwrap();
+        let state = std::fs::read(dir.join("a.txt.textures.json")).unwrap();
+        saved.curr_index = 2;
+        saved.append_journal(&batch).unwrap();
+        assert_eq!(
+            std::fs::read(dir.join("a.txt.textures.json")).unwrap(),
+            state
+        );
+    }
+
+    #[test]
+    fn test_refused() {
+        let mut textures = textures(&["a", "b", "c"]);
+        textures.lines[1].duplicate = Some(0);
+        textures.update(TranslatedLine::refused(
+            Translator::ChatGPT,
+            0,
+            1,
+            "I'm sorry".to_string(),
+        ));
+        assert!(textures.lines[0].skip);
+        assert_eq!(textures.lines[0].skip_reason.as_deref(), Some("I'm sorry"));
+        assert!(textures.lines[0].translated.is_empty());
+        assert!(!textures.lines[1].skip);
+        assert_eq!(textures.resume_index(Translator::ChatGPT, 0), 2);
+    }
+
+    #[test]
+    fn test_state_format() {
+        let dir = crate::utils::TempDir::new("state_format");
+        let path = dir.join("a.txt.textures.json");
+        let mut saved = textures(&["a", "b"]);
+        saved.lines[1].translated.push(TranslatedLine::new(
+            Translator::ChatGPT,
+            "b".to_string(),
+            1,
+            1,
+        ));
+        for format in [
+            StateFormat::JsonGz,
+            StateFormat::Messagepack,
+            StateFormat::Json,
+        ] {
+            saved.dirs.state_format = format;
+            saved.save_as(&path).unwrap();
+            let loaded = Textures::load_state(&path).unwrap();
+            assert_eq!(loaded.dirs.state_format, format);
+            assert_eq!(loaded.lines[1].translated[0].content, "b");
+            assert_eq!(loaded.version, TEXTURES_VERSION);
+        }
+    }
+
+    #[test]
+    fn test_resume_index() {
+        let mut textures = textures(&["a", "b", "c", "d", "e", "f"]);
+        // the larger batch 3-5 is done before 0-2
+        textures.update(TranslatedLine::new(
+            Translator::ChatGPT,
+            "d".to_string(),
+            3,
+            5,
+        ));
+        assert_eq!(textures.resume_index(Translator::ChatGPT, 0), 0);
+        textures.update(TranslatedLine::new(
+            Translator::ChatGPT,
+            "a".to_string(),
+            0,
+            2,
+        ));
+        assert_eq!(textures.resume_index(Translator::ChatGPT, 0), 6);
+        assert_eq!(textures.resume_index(Translator::Gemini, 1), 1);
+    }
+
+    #[test]
+    fn test_reconcile_duplicates() {
+        let mut old = textures(&["……", "a", "……", "b", "……", "c"]);
+        for (start, end) in [(0, 1), (2, 3), (4, 5)] {
+            old.update(TranslatedLine::new(
+                Translator::ChatGPT,
+                String::new(),
+                start,
+                end,
+            ));
+        }
+        old.curr_index = 6;
+        // a repeated line is inserted, the later copies of it keep their batches
+        let mut new = textures(&["……", "y", "……", "a", "……", "b", "……", "c"]);
+        let (map, requeue) = new.reconcile(&old);
+        assert_eq!(
+            map,
+            vec![Some(0), Some(3), Some(4), Some(5), Some(6), Some(7)]
+        );
+        assert_eq!(new.lines[4].translated[0].batch_range, (4, 5));
+        assert_eq!(new.lines[6].translated[0].batch_range, (6, 7));
+        assert_eq!(new.lines[6].translated[0].ids, new.range_ids((6, 7)));
+        assert_eq!(requeue, vec![(0, 3)]);
+    }
+
+    #[test]
+    fn test_line_ids() {
+        let old = textures(&["a", "b", "a"]);
+        // a line inserted before, the ids of the others are kept
+        let new = textures(&["x", "a", "b", "a"]);
+        let ids = |t: &Textures| t.lines.iter().map(|l| l.id).collect::<Vec<_>>();
+        assert_eq!(ids(&old), ids(&new)[1..]);
+        assert_ne!(old.lines[0].id, old.lines[2].id);
+    }
+
+    #[test]
+    fn test_reconcile() {
+        let mut old = textures(&["a", "b", "c", "d", "e", "f"]);
+        for (start, end) in [(0, 1), (2, 3), (4, 5)] {
+            old.update(TranslatedLine::new(
+                Translator::ChatGPT,
+                String::new(),
+                start,
+                end,
+            ));
         }
+        // c is changed, x is inserted, f is not translated yet
+        old.curr_index = 5;
+        let mut new = textures(&["x", "a", "b", "C", "d", "e", "f"]);
+        let (map, requeue) = new.reconcile(&old);
+        assert_eq!(map, vec![Some(1), Some(2), None, Some(4), Some(5), Some(6)]);
+        assert_eq!(new.curr_index, 6);
+        assert_eq!(new.lines[1].translated[0].batch_range, (1, 2));
+        assert!(new.lines[3].translated.is_empty());
+        assert_eq!(new.lines[5].translated[0].batch_range, (5, 6));
+        assert_eq!(requeue, vec![(0, 0), (3, 4), (6, 6)]);
     }
 }
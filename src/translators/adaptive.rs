@@ -0,0 +1,196 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{i18n::Msg, t};
+
+/// the consecutive successful batches before the budget grows
+const GROW_AFTER: usize = 8;
+/// the concurrency grows while the average latency stays within this ratio of the lowest one
+const LATENCY_TOLERANCE: f64 = 1.5;
+/// the weight of the last request in the average latency
+const LATENCY_WEIGHT: f64 = 0.2;
+
+/// the token budget of the batches, halved when a batch mismatches repeatedly,
+/// doubled back toward max_tokens after consecutive successful batches
+#[derive(Debug)]
+pub struct AdaptiveBudget {
+    max_tokens: usize,
+    min_tokens: usize,
+    tokens: usize,
+    successes: usize,
+}
+
+impl AdaptiveBudget {
+    pub fn new(max_tokens: usize) -> Self {
+        Self {
+            max_tokens,
+            min_tokens: (max_tokens / 8).max(1),
+            tokens: max_tokens,
+            successes: 0,
+        }
+    }
+
+    pub fn tokens(&self) -> usize {
+        self.tokens
+    }
+
+    pub fn is_reduced(&self) -> bool {
+        self.tokens < self.max_tokens
+    }
+
+    pub fn shrink(&mut self) {
+        self.successes = 0;
+        let tokens = (self.tokens / 2).max(self.min_tokens);
+        if tokens != self.tokens {
+            println!(
+                "[Adaptive] {}",
+                t!(Msg::AdaptiveBudget, self.tokens, tokens)
+            );
+            self.tokens = tokens;
+        }
+    }
+
+    pub fn succeed(&mut self) {
+        self.successes += 1;
+        if self.successes >= GROW_AFTER && self.is_reduced() {
+            self.successes = 0;
+            let tokens = (self.tokens * 2).min(self.max_tokens);
+            println!(
+                "[Adaptive] {}",
+                t!(Msg::AdaptiveBudget, self.tokens, tokens)
+            );
+            self.tokens = tokens;
+        }
+    }
+}
+
+/// tune the concurrency between min and max instead of the fixed max_concurrent,
+/// example: {min = 2, max = 30}
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ConcurrencyRange {
+    pub min: usize,
+    pub max: usize,
+}
+
+/// the concurrent requests, one more after a round of requests at a stable latency, halved on
+/// a rate limit, a server error or a timeout (additive increase, multiplicative decrease)
+#[derive(Debug)]
+pub struct AdaptiveConcurrency {
+    range: ConcurrencyRange,
+    limit: usize,
+    successes: usize,
+    /// the requests sent before the last back off, their failures do not back off again
+    cooldown: usize,
+    latency: Option<f64>,
+    lowest_latency: Option<f64>,
+}
+
+impl AdaptiveConcurrency {
+    /// start from max_concurrent within the range
+    pub fn new(range: ConcurrencyRange, start: usize) -> Self {
+        let min = range.min.max(1);
+        let range = ConcurrencyRange {
+            min,
+            max: range.max.max(min),
+        };
+        Self {
+            range,
+            limit: start.clamp(range.min, range.max),
+            successes: 0,
+            cooldown: 0,
+            latency: None,
+            lowest_latency: None,
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn max(&self) -> usize {
+        self.range.max
+    }
+
+    pub fn succeed(&mut self, latency: Duration) {
+        self.cooldown = self.cooldown.saturating_sub(1);
+        let latency = latency.as_secs_f64();
+        let average = match self.latency {
+            Some(average) => average + (latency - average) * LATENCY_WEIGHT,
+            None => latency,
+        };
+        self.latency = Some(average);
+        let lowest = self.lowest_latency.map_or(average, |l| l.min(average));
+        self.lowest_latency = Some(lowest);
+        self.successes += 1;
+        if self.successes >= self.limit
+            && self.limit < self.range.max
+            && average <= lowest * LATENCY_TOLERANCE
+        {
+            self.successes = 0;
+            self.cooldown = 0;
+            let grown = t!(Msg::AdaptiveConcurrency, self.limit, self.limit + 1);
+            println!("[Adaptive] {}", grown);
+            self.limit += 1;
+        }
+    }
+
+    pub fn back_off(&mut self) {
+        if self.cooldown > 0 {
+            self.cooldown -= 1;
+            return;
+        }
+        self.successes = 0;
+        self.cooldown = self.limit;
+        let limit = (self.limit / 2).max(self.range.min);
+        if limit != self.limit {
+            println!(
+                "[Adaptive] {}",
+                t!(Msg::AdaptiveConcurrency, self.limit, limit)
+            );
+            self.limit = limit;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{AdaptiveBudget, AdaptiveConcurrency, ConcurrencyRange};
+
+    #[test]
+    fn test_adaptive_budget() {
+        let mut budget = AdaptiveBudget::new(256);
+        budget.shrink();
+        budget.shrink();
+        assert_eq!(budget.tokens(), 64);
+        (0..5).for_each(|_| budget.shrink());
+        assert_eq!(budget.tokens(), 32);
+        (0..8).for_each(|_| budget.succeed());
+        assert_eq!(budget.tokens(), 64);
+        (0..16).for_each(|_| budget.succeed());
+        assert_eq!(budget.tokens(), 256);
+        assert!(!budget.is_reduced());
+    }
+
+    #[test]
+    fn test_adaptive_concurrency() {
+        let mut concurrency = AdaptiveConcurrency::new(ConcurrencyRange { min: 2, max: 8 }, 4);
+        let second = Duration::from_secs(1);
+        (0..4).for_each(|_| concurrency.succeed(second));
+        assert_eq!(concurrency.limit(), 5);
+        // the latency grew, the concurrency holds
+        (0..10).for_each(|_| concurrency.succeed(second * 4));
+        assert_eq!(concurrency.limit(), 5);
+        // the requests in flight fail together, the concurrency is halved once
+        (0..5).for_each(|_| concurrency.back_off());
+        assert_eq!(concurrency.limit(), 2);
+        concurrency.back_off();
+        assert_eq!(concurrency.limit(), 2);
+        assert_eq!(
+            AdaptiveConcurrency::new(ConcurrencyRange { min: 2, max: 8 }, 30).limit(),
+            8
+        );
+    }
+}
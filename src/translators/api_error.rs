@@ -0,0 +1,194 @@
+use std::time::Duration;
+
+use regex::Regex;
+use serde_json::Value;
+
+/// the start of a reply refusing to translate instead of translating, e.g. "I'm sorry, but I
+/// can't assist with that.", only checked when the reply does not match the lines of the batch
+const REFUSAL: &str = r"(?i)^\W*(i'm sorry|i am sorry|sorry, but|i can(not|'t) (assist|help|comply|provide|translate|fulfill)|i'm unable to|i am unable to|i won't be able to|抱歉|对不起|很抱歉|我无法|我不能|申し訳|すみませんが)";
+
+/// a failed response of the api, parsed from the error object of the body, e.g. the openai
+/// `{"error": {"message": "...", "type": "...", "code": "context_length_exceeded"}}`,
+/// the workers react by the kind instead of retrying every failure the same way
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum ApiError {
+    /// the key is rejected or out of quota, the worker moves to another key of the api_pool
+    #[error("status: {status}, invalid api key: {message}")]
+    InvalidApiKey { status: u16, message: String },
+    /// the batch is too long for the model, it is split and the batch budget shrinks
+    #[error("status: {status}, context length exceeded: {message}")]
+    ContextLengthExceeded { status: u16, message: String },
+    /// the worker waits retry_after, or RATE_LIMIT_PAUSE, before its next request
+    #[error("status: {status}, rate limited: {message}")]
+    RateLimit {
+        status: u16,
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    /// the batch or its translation is filtered, it is split to leave the filtered line alone
+    #[error("status: {status}, content filtered: {message}")]
+    ContentFilter { status: u16, message: String },
+    /// retried like before
+    #[error("status: {status}, response: {message}")]
+    Other { status: u16, message: String },
+}
+
+impl ApiError {
+    /// the error of a response which is not a completion, retry_after is the retry-after header
+    pub fn from_response(status: u16, body: &[u8], retry_after: Option<&str>) -> Self {
+        let body = String::from_utf8_lossy(body);
+        let error = serde_json::from_str::<Value>(&body)
+            .ok()
+            .and_then(|v| v.get("error").cloned());
+        let field = |key: &str| {
+            error
+                .as_ref()
+                .and_then(|e| e.get(key))
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        // openai names the error by code and type, gemini by status
+        let (code, kind) = (
+            field("code"),
+            format!("{} {}", field("type"), field("status")),
+        );
+        let message = match field("message") {
+            m if m.is_empty() => body.trim().to_string(),
+            m => m,
+        };
+        let lower = message.to_lowercase();
+        if code == "context_length_exceeded"
+            || field("param") == "max_tokens"
+            || lower.contains("maximum context length")
+        {
+            return ApiError::ContextLengthExceeded { status, message };
+        }
+        if code == "content_filter"
+            || code == "content_policy_violation"
+            || lower.contains("content management policy")
+        {
+            return ApiError::ContentFilter { status, message };
+        }
+        if matches!(status, 401 | 403)
+            || matches!(code.as_str(), "invalid_api_key" | "insufficient_quota")
+            || kind.contains("UNAUTHENTICATED")
+            || lower.contains("api key not valid")
+        {
+            return ApiError::InvalidApiKey { status, message };
+        }
+        if status == 429 || kind.contains("RESOURCE_EXHAUSTED") {
+            let retry_after = retry_after
+                .and_then(|s| s.trim().parse::<f64>().ok())
+                .filter(|s| s.is_finite() && *s >= 0.0)
+                .map(Duration::from_secs_f64);
+            return ApiError::RateLimit {
+                status,
+                message,
+                retry_after,
+            };
+        }
+        ApiError::Other { status, message }
+    }
+
+    /// the translation stopped at max_tokens, finish_reason `length`, the batch is too long too
+    pub fn cut_at_max_tokens((start, end): (usize, usize)) -> Self {
+        ApiError::ContextLengthExceeded {
+            status: 200,
+            message: format!(
+                "the translation of batch {}-{} is cut at max_tokens",
+                start, end
+            ),
+        }
+    }
+
+    /// a reply refusing the batch, it is filtered in plain words
+    pub fn refusal(content: &str) -> Self {
+        let first = content.trim().lines().next().unwrap_or_default();
+        ApiError::ContentFilter {
+            status: 200,
+            message: first.chars().take(80).collect(),
+        }
+    }
+
+    /// the category of the metrics
+    pub fn category(&self) -> &'static str {
+        match self {
+            ApiError::InvalidApiKey { .. } => "auth",
+            ApiError::ContextLengthExceeded { .. } => "context_length",
+            ApiError::RateLimit { .. } => "rate_limit",
+            ApiError::ContentFilter { .. } => "content_filter",
+            ApiError::Other { status: 500.., .. } => "server",
+            ApiError::Other { status: 400.., .. } => "client",
+            ApiError::Other { .. } => "other",
+        }
+    }
+}
+
+/// whether the reply is a refusal instead of a translation
+pub fn is_refusal(content: &str) -> bool {
+    let refusal = Regex::new(REFUSAL).unwrap();
+    refusal.is_match(&content.replace('\u{2019}', "'"))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{is_refusal, ApiError};
+
+    #[test]
+    fn test_api_error() {
+        let openai = |status, code: &str, message: &str| {
+            let body = format!(
+                r#"{{"error": {{"message": "{}", "type": "invalid_request_error", "param": null, "code": "{}"}}}}"#,
+                message, code
+            );
+            ApiError::from_response(status, body.as_bytes(), Some("2"))
+        };
+        assert!(matches!(
+            openai(
+                400,
+                "context_length_exceeded",
+                "This model's maximum context length is 4097 tokens"
+            ),
+            ApiError::ContextLengthExceeded { .. }
+        ));
+        assert!(matches!(
+            openai(401, "invalid_api_key", "Incorrect API key provided"),
+            ApiError::InvalidApiKey { .. }
+        ));
+        assert!(matches!(
+            openai(429, "insufficient_quota", "You exceeded your current quota"),
+            ApiError::InvalidApiKey { .. }
+        ));
+        assert_eq!(
+            openai(429, "rate_limit_exceeded", "Rate limit reached"),
+            ApiError::RateLimit {
+                status: 429,
+                message: "Rate limit reached".to_string(),
+                retry_after: Some(Duration::from_secs(2)),
+            }
+        );
+        let max_tokens = br#"{"error": {"message": "max_tokens is too large: 9000.", "type": "invalid_request_error", "param": "max_tokens", "code": null}}"#;
+        assert!(matches!(
+            ApiError::from_response(400, max_tokens, None),
+            ApiError::ContextLengthExceeded { .. }
+        ));
+        assert_eq!(
+            openai(400, "content_filter", "filtered").category(),
+            "content_filter"
+        );
+        let gemini = br#"{"error": {"code": 400, "message": "API key not valid. Please pass a valid API key.", "status": "INVALID_ARGUMENT"}}"#;
+        assert!(matches!(
+            ApiError::from_response(400, gemini, None),
+            ApiError::InvalidApiKey { .. }
+        ));
+        assert!(is_refusal("I’m sorry, but I can't assist with that."));
+        assert!(is_refusal("抱歉，我无法翻译这段内容。"));
+        assert!(!is_refusal("(1) I'm sorry, I was late.\n(2) It's fine."));
+        let error = ApiError::from_response(502, b"Bad Gateway", None);
+        assert_eq!(error.category(), "server");
+        assert_eq!(error.to_string(), "status: 502, response: Bad Gateway");
+    }
+}
@@ -0,0 +1,115 @@
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::textures::TokenUsage;
+
+/// the limits of the usage of a run, the workers take no more batches once one is crossed, the
+/// batches in flight are finished and saved, the run is resumed by running it again
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BudgetOptions {
+    /// the prompt and completion tokens of the run
+    pub max_total_tokens: Option<u64>,
+    /// the cost of the run, priced by the usd per million tokens below
+    pub max_cost_usd: Option<f64>,
+    #[serde(default)]
+    pub prompt_usd_per_million: f64,
+    #[serde(default)]
+    pub completion_usd_per_million: f64,
+}
+
+impl BudgetOptions {
+    pub fn cost_usd(&self, prompt_tokens: u64, completion_tokens: u64) -> f64 {
+        (prompt_tokens as f64 * self.prompt_usd_per_million
+            + completion_tokens as f64 * self.completion_usd_per_million)
+            / 1_000_000.0
+    }
+
+    pub fn is_exceeded(&self, prompt_tokens: u64, completion_tokens: u64) -> bool {
+        self.max_total_tokens
+            .is_some_and(|max| prompt_tokens + completion_tokens >= max)
+            || self
+                .max_cost_usd
+                .is_some_and(|max| self.cost_usd(prompt_tokens, completion_tokens) >= max)
+    }
+
+    /// the budget left after the usage, for the next shard of the run
+    pub fn remaining(&self, prompt_tokens: u64, completion_tokens: u64) -> Self {
+        Self {
+            max_total_tokens: self
+                .max_total_tokens
+                .map(|max| max.saturating_sub(prompt_tokens + completion_tokens)),
+            max_cost_usd: self
+                .max_cost_usd
+                .map(|max| (max - self.cost_usd(prompt_tokens, completion_tokens)).max(0.0)),
+            ..self.clone()
+        }
+    }
+}
+
+/// the usage of the run shared by the workers, updated after every request, the mismatched and
+/// refused responses are paid too
+#[derive(Debug, Default)]
+pub struct Budget {
+    opt: BudgetOptions,
+    /// the prompt and completion tokens
+    tokens: Mutex<(u64, u64)>,
+}
+
+impl Budget {
+    pub fn new(opt: BudgetOptions) -> Self {
+        Self {
+            opt,
+            tokens: Mutex::new((0, 0)),
+        }
+    }
+
+    pub fn spend(&self, usage: &TokenUsage) {
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.0 += usage.prompt_tokens as u64;
+        tokens.1 += usage.completion_tokens as u64;
+    }
+
+    pub fn is_exceeded(&self) -> bool {
+        let (prompt, completion) = *self.tokens.lock().unwrap();
+        self.opt.is_exceeded(prompt, completion)
+    }
+
+    /// the tokens and the cost spent
+    pub fn spent(&self) -> (u64, f64) {
+        let (prompt, completion) = *self.tokens.lock().unwrap();
+        (prompt + completion, self.opt.cost_usd(prompt, completion))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Budget, BudgetOptions};
+    use crate::textures::TokenUsage;
+
+    #[test]
+    fn test_budget() {
+        let opt = BudgetOptions {
+            max_total_tokens: Some(10_000),
+            max_cost_usd: Some(0.5),
+            prompt_usd_per_million: 10.0,
+            completion_usd_per_million: 30.0,
+        };
+        let budget = Budget::new(opt.clone());
+        budget.spend(&TokenUsage {
+            prompt_tokens: 4000,
+            completion_tokens: 2000,
+        });
+        assert!(!budget.is_exceeded());
+        assert_eq!(budget.spent(), (6000, 0.1));
+        budget.spend(&TokenUsage {
+            prompt_tokens: 3000,
+            completion_tokens: 1000,
+        });
+        assert!(budget.is_exceeded());
+        // the next shard has the rest of the budget
+        let rest = opt.remaining(4000, 2000);
+        assert_eq!(rest.max_total_tokens, Some(4000));
+        assert!(rest.is_exceeded(30_000, 0) && !rest.is_exceeded(3000, 0));
+    }
+}
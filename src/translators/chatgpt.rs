@@ -1,66 +1,296 @@
-use std::{fs, str::FromStr};
+use std::{
+    fs,
+    sync::{Arc, Mutex},
+};
 
 use anyhow::Result;
 use async_trait::async_trait;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use tiktoken_rs::CoreBPE;
+use tokio::sync::Semaphore;
 
-use crate::textures::{TextureLine, Textures, TranslatedLine};
+use crate::error::{new_regex, Error};
+use crate::outputs::LineExtractor;
+use crate::ruby::RubyParser;
+use crate::textures::{RequestParams, TextureLine, Textures, TokenUsage, TranslatedLine};
 
+use super::adaptive::ConcurrencyRange;
+use super::api_error::ApiError;
+use super::guard::ResponseGuard;
+use super::history::Conversation;
+use super::http::{ClientPool, HttpOptions};
+use super::profile::ModelProfile;
+use super::prompt_override::has_own_prompts;
+use super::protect::ProtectedTerms;
+use super::routing::{ContentClassifier, Route, RoutingOptions};
+use super::style::StyleOptions;
 use super::translator::{
-    BatchPackage, Batchizer, ConcurrentTranslate, TranslateClient, Translator,
+    batch_queue, BatchPackage, Batchizer, ConcurrentTranslate, TranslateClient, Translator,
 };
 
+pub const DEFAULT_MODEL: &str = "gpt-3.5-turbo";
+
+/// (model prefix, context window, max output tokens), the longer prefixes first
+const MODEL_LIMITS: [(&str, usize, usize); 14] = [
+    ("gpt-3.5-turbo-instruct", 4096, 4096),
+    ("gpt-3.5-turbo", 16385, 4096),
+    ("gpt-4o-mini", 128000, 16384),
+    ("gpt-4o", 128000, 16384),
+    ("gpt-4-turbo", 128000, 4096),
+    ("gpt-4-32k", 32768, 32768),
+    ("gpt-4.1", 1047576, 32768),
+    ("gpt-4", 8192, 8192),
+    ("gpt-5", 400000, 128000),
+    ("o1", 200000, 100000),
+    ("o3", 200000, 100000),
+    ("o4", 200000, 100000),
+    ("gemini-1.5-pro", 2097152, 8192),
+    ("gemini-", 1048576, 8192),
+];
+
+/// the expected completion tokens per prompt token if expansion_ratio is not specified
+pub const DEFAULT_EXPANSION_RATIO: f32 = 1.5;
+
+/// precedes the context lines of the overlap window in the prompt
+const CONTEXT_HEADER: &str =
+    "The lines marked (cN) are the context before this batch, do not translate or output them.\n";
+
+/// (context window, max output tokens) of the model, conservative for the unknown models
+pub fn model_limits(model: &str) -> (usize, usize) {
+    MODEL_LIMITS
+        .iter()
+        .find(|(prefix, _, _)| model.starts_with(prefix))
+        .map(|(_, context, output)| (*context, *output))
+        .unwrap_or((8192, 4096))
+}
+
+/// the max prompt tokens of a batch, so the expected completion (batch tokens * expansion_ratio)
+/// stays within the output limit, and the prompts + batch + completion fit the context window
+pub fn batch_ceiling(model: &str, expansion_ratio: f32, prompt_tokens: usize) -> usize {
+    let (context, output) = model_limits(model);
+    let by_output = output as f32 / expansion_ratio;
+    let by_context = context.saturating_sub(prompt_tokens) as f32 / (1.0 + expansion_ratio);
+    by_output.min(by_context).max(1.0) as usize
+}
+
+/// the tokenizer to estimate the tokens of a batch, by the model if not specified
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TokenizerKind {
+    #[serde(rename = "cl100k")]
+    Cl100k,
+    #[serde(rename = "o200k")]
+    O200k,
+    #[serde(rename = "p50k")]
+    P50k,
+    /// approximate by the characters, for the backends which are not OpenAI
+    #[serde(rename = "chars")]
+    Chars,
+}
+
+impl TokenizerKind {
+    pub fn from_model(model: &str) -> Self {
+        match tiktoken_rs::tokenizer::get_tokenizer(model) {
+            Some(tiktoken_rs::tokenizer::Tokenizer::O200kBase) => TokenizerKind::O200k,
+            Some(tiktoken_rs::tokenizer::Tokenizer::Cl100kBase) => TokenizerKind::Cl100k,
+            Some(_) => TokenizerKind::P50k,
+            // the newer openai models use o200k
+            None if ["o3", "o4", "gpt-4.1", "gpt-5"]
+                .iter()
+                .any(|prefix| model.starts_with(prefix)) =>
+            {
+                TokenizerKind::O200k
+            }
+            None => TokenizerKind::Chars,
+        }
+    }
+}
+
+/// how the consecutive lines are kept together in a batch, a group can extend the batch beyond
+/// max_tokens, but never beyond the hard ceiling of the batchizer
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum Grouping {
+    #[serde(rename = "off")]
+    Off,
+    /// the lines starting with the same character, e.g. the lines of a dialogue 「xxx」
+    #[default]
+    #[serde(rename = "first_char")]
+    FirstChar,
+    /// the lines of the same speaker, the first capture group (or the whole match) of the regex
+    #[serde(rename = "speaker_tag")]
+    SpeakerTag(String),
+}
+
+pub enum LineGrouping {
+    Off,
+    FirstChar,
+    SpeakerTag(Regex),
+}
+
+impl LineGrouping {
+    pub fn new(grouping: &Grouping) -> Result<Self, Error> {
+        Ok(match grouping {
+            Grouping::Off => LineGrouping::Off,
+            Grouping::FirstChar => LineGrouping::FirstChar,
+            Grouping::SpeakerTag(regex) => {
+                LineGrouping::SpeakerTag(new_regex("batchizer_opt.grouping.speaker_tag", regex)?)
+            }
+        })
+    }
+
+    /// the group of the line, the lines without a group are never kept together
+    fn key(&self, line: &str) -> Option<String> {
+        match self {
+            LineGrouping::Off => None,
+            LineGrouping::FirstChar => line.chars().next().map(String::from),
+            LineGrouping::SpeakerTag(regex) => regex.captures(line).map(|caps| {
+                caps.get(1)
+                    .unwrap_or_else(|| caps.get(0).unwrap())
+                    .as_str()
+                    .to_string()
+            }),
+        }
+    }
+}
+
+pub enum Tokenizer {
+    Bpe(CoreBPE),
+    Chars,
+}
+
+impl Tokenizer {
+    pub fn new(kind: TokenizerKind) -> Self {
+        match kind {
+            TokenizerKind::Cl100k => Tokenizer::Bpe(tiktoken_rs::cl100k_base().unwrap()),
+            TokenizerKind::O200k => Tokenizer::Bpe(tiktoken_rs::o200k_base().unwrap()),
+            TokenizerKind::P50k => Tokenizer::Bpe(tiktoken_rs::p50k_base().unwrap()),
+            TokenizerKind::Chars => Tokenizer::Chars,
+        }
+    }
+
+    /// the count of tokens, approximated as a token per non-ascii character or 4 ascii characters
+    pub fn count(&self, text: &str) -> usize {
+        match self {
+            Tokenizer::Bpe(bpe) => bpe.encode_with_special_tokens(text).len(),
+            Tokenizer::Chars => text
+                .chars()
+                .map(|c| if c.is_ascii() { 1 } else { 4 })
+                .sum::<usize>()
+                .div_ceil(4),
+        }
+    }
+}
+
 pub struct TokenizedBatchizer {
-    pub bep: CoreBPE,
+    pub tokenizer: Tokenizer,
     pub max_tokens: usize,
     pub extract_regex: Option<Regex>,
+    pub protected: ProtectedTerms,
+    /// the count of lines before the batch, sent as context only, marked as `(c1) xxx`
+    pub overlap: usize,
+    pub grouping: LineGrouping,
+    /// the hard ceiling of a batch extended by a group, scaled with the budget when reduced
+    pub group_max_tokens: usize,
+    /// the line protocol of a local model, the lines are sent without numbering or context
+    pub profile: Option<ModelProfile>,
+    /// replace the ruby markup by its base
+    pub ruby: Option<RubyParser>,
+}
+
+impl TokenizedBatchizer {
+    /// the numbered context lines, the last lines to be translated before start
+    fn context(&self, textures: &Textures, start: usize) -> String {
+        let mut lines = textures.lines[..start]
+            .iter()
+            .rev()
+            .filter(|l| l.needs_translation())
+            .filter_map(|l| self.extract(&l.content))
+            .take(self.overlap)
+            .collect::<Vec<_>>();
+        if lines.is_empty() {
+            return String::new();
+        }
+        lines.reverse();
+        let mut content = String::from(CONTEXT_HEADER);
+        for (n, line) in lines.iter().enumerate() {
+            content.push_str(&format!("(c{}) {}\n", n + 1, self.protected.mask(line)));
+        }
+        content
+    }
 }
 
 impl Batchizer<ChatCompletionMessage> for TokenizedBatchizer {
     fn extract(&self, content: &str) -> Option<String> {
-        if let Some(regex) = &self.extract_regex {
-            let caps = regex.captures(content);
-            caps.map(|caps| caps[1].to_string())
-        } else {
-            Some(content.to_string())
+        let content = match &self.extract_regex {
+            Some(regex) => regex
+                .captures(content)
+                .and_then(|caps| caps.get(1))
+                .map(|m| m.as_str().to_string())?,
+            None => content.to_string(),
+        };
+        match &self.ruby {
+            Some(ruby) => Some(ruby.strip(&content)),
+            None => Some(content),
         }
     }
-    fn batchize(
+    fn max_tokens(&self) -> usize {
+        self.max_tokens
+    }
+    fn batchize_with(
         &self,
         textures: &Textures,
         start: usize,
         end: Option<usize>,
+        budget: usize,
     ) -> (Vec<ChatCompletionMessage>, usize) {
-        let mut str_content = String::new();
-        let mut max_tokens = 0;
+        let mut str_content = match self.profile {
+            Some(_) => String::new(),
+            None => self.context(textures, start),
+        };
+        let mut max_tokens = self.tokenizer.count(&str_content);
         let mut size = 0;
-        let mut prefix: Option<char> = None;
+        let mut sent = 0;
+        let mut group: Option<String> = None;
+        let ceiling = self
+            .group_max_tokens
+            .max(self.max_tokens)
+            .saturating_mul(budget)
+            / self.max_tokens.max(1);
         let mut i = start;
         let end = end.unwrap_or(textures.lines.len() - 1);
         while i <= end {
-            let line = self.extract(&textures.lines[i].content);
-            if let Some(line) = line {
-                max_tokens += self.bep.encode_with_special_tokens(&line).len();
-                let prefix_a = line.chars().next();
-                let is_same_suffix = prefix_a == prefix;
-                if !is_same_suffix {
-                    prefix = prefix_a;
-                }
-                if !is_same_suffix && max_tokens > self.max_tokens && !str_content.is_empty() {
-                    break;
-                }
-                str_content.push_str(&format!("({}) {}\n", i - start + 1, &line));
+            // the duplicated lines are consumed by the batch, but not sent
+            if !textures.lines[i].needs_translation() {
                 size += 1;
-            } else {
-                panic!(
-                    "batchizer extract line error, content: {}",
-                    &textures.lines[i].content
-                )
+                i += 1;
+                continue;
+            }
+            // a line the capture_regex misses is sent whole, like the source of the qe
+            let content = &textures.lines[i].content;
+            let line = self.extract(content).unwrap_or_else(|| content.clone());
+            let line = self.protected.mask(&line);
+            max_tokens += self.tokenizer.count(&line);
+            let key = self.grouping.key(&line);
+            let is_same_group = key.is_some() && key == group;
+            group = key;
+            let over = if is_same_group { ceiling } else { budget };
+            if max_tokens > over && sent > 0 {
+                break;
+            }
+            sent += 1;
+            match self.profile {
+                Some(profile) => str_content.push_str(&profile.format_line(&line)),
+                None => str_content.push_str(&format!("({}) {}\n", sent, &line)),
             }
+            size += 1;
             i += 1;
         }
+        if sent == 0 {
+            return (vec![], size);
+        }
+        if let Some(profile) = self.profile {
+            str_content = profile.user_content(&str_content);
+        }
         (
             vec![ChatCompletionMessage::new(
                 ChatCompletionRole::User,
@@ -81,51 +311,279 @@ pub struct ChatGPTAPI {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatGPTOptions {
     pub api_pool: Vec<ChatGPTAPI>,
+    /// the model of the requests, default is gpt-3.5-turbo
+    pub model: Option<String>,
     pub prompt_path: Option<String>,
+    /// the system prompt written in the config instead of a prompt file, {{from}} and {{to}} are
+    /// replaced like in the prompt files, prompt_path is used if both are given
+    pub system_prompt: Option<String>,
     pub max_concurrent: i32,
+    /// tune the concurrency between min and max, starting from max_concurrent, by the latency
+    /// and the rate limits of the api, example: {min = 2, max = 30}
+    pub adaptive_concurrency: Option<ConcurrencyRange>,
+    /// the built-in prompt, line protocol and output stages of a local model, e.g. "sakura",
+    /// the prompt_path or the system_prompt overrides the prompt
+    pub model_profile: Option<ModelProfile>,
+    /// the sampling temperature, default is 0.6 or the one of the model_profile
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    /// the seed of the openai sampling, the same seed and parameters reproduce the translation
+    /// as far as the api allows
+    pub seed: Option<u64>,
+    /// temperature 0, top_p 1 and seed 0 unless given, for the reruns consistent with the first pass
+    #[serde(default)]
+    pub deterministic: bool,
+    /// the stop sequences of the requests, at most 4
+    #[serde(default)]
+    pub stop: Vec<String>,
+    /// the chatter stripped from the start of the replies before the response_cleanup runs,
+    /// e.g. ["翻译为", "Sure, here is"], a lead-in line ending with a colon is dropped as a whole
+    #[serde(default)]
+    pub strip_prefixes: Vec<String>,
+    /// the chatter stripped from the end of the replies, the last lines starting with one of them
+    /// are dropped, e.g. ["是否违规"]
+    #[serde(default)]
+    pub strip_suffixes: Vec<String>,
+    /// every worker sends its previous batches and their replies up to these tokens before the
+    /// next batch, like one conversation, the oldest are dropped first, default is stateless
+    pub history_tokens: Option<usize>,
+    /// the proxy, the connection pool and the http version of the requests
+    pub http: Option<HttpOptions>,
+}
+
+impl ChatGPTOptions {
+    /// apply the sampling parameters to the request, the model_profile first
+    fn apply_sampling(&self, request: &mut ChatCompletionRequest) {
+        if let Some(profile) = self.model_profile {
+            let (temperature, top_p) = profile.sampling();
+            request.temperature = Some(temperature);
+            request.top_p = Some(top_p);
+        }
+        if let Some(temperature) = self.temperature {
+            request.temperature = Some(temperature);
+        }
+        if let Some(top_p) = self.top_p {
+            request.top_p = Some(top_p);
+        }
+        request.seed = self.seed;
+        if self.deterministic {
+            request.temperature = Some(0.0);
+            request.top_p = Some(1.0);
+            request.seed = Some(self.seed.unwrap_or(0));
+        }
+    }
 }
 
 pub struct TranslateChatGPT {
     pub specify_range: Option<Vec<(usize, usize)>>,
-    pub api_pool: Vec<ChatGPTAPI>,
-    pub prompt_path: Option<String>,
     pub max_concurrent: i32,
+    pub adaptive_concurrency: Option<ConcurrencyRange>,
+    pub model: Option<String>,
+    pub request_limiter: Option<Arc<Semaphore>>,
+    pub line_extractor: Option<Arc<LineExtractor>>,
+    /// the tokens of the conversation of every worker
+    history_tokens: Option<usize>,
     client_count: usize,
+    /// a client per api of the api pool, created upfront so a bad api fails early
+    clients: Vec<ChatGPTClient>,
+    /// the http clients shared by the apis
+    pool: ClientPool,
     prompts: Option<Vec<ChatCompletionMessage>>,
 }
 
 impl TranslateChatGPT {
+    /// the tokens of the prompts sent before every batch
+    pub fn prompt_tokens(&self, tokenizer: &Tokenizer) -> usize {
+        self.prompts
+            .iter()
+            .flatten()
+            .map(|m| tokenizer.count(&m.content))
+            .sum()
+    }
+
     pub fn new(
         opt: ChatGPTOptions,
         specify_range: Option<Vec<(usize, usize)>>,
         from: &str,
         to: &str,
-    ) -> Self {
+    ) -> Result<Self, Error> {
         if opt.api_pool.is_empty() {
-            panic!("ChatGPT api pool is empty");
-        }
-        let prompts = if let Some(path) = &opt.prompt_path {
-            let mut prompt_content =
-                fs::read_to_string(path).expect("ChatGPT prompt file is not valid");
-            let replace = Regex::new(r"\{\{from\}\}").unwrap();
-            prompt_content = replace.replace_all(&prompt_content, from).to_string();
-            let replace = Regex::new(r"\{\{to\}\}").unwrap();
-            prompt_content = replace.replace_all(&prompt_content, to).to_string();
-            let prompts = serde_json::from_str::<Vec<ChatCompletionMessage>>(&prompt_content)
-                .expect("ChatGPT prompt file is not valid");
-            Some(prompts)
-        } else {
-            None
-        };
-        Self {
+            return Err(Error::Config("chatgpt_opt.api_pool is empty".to_string()));
+        }
+        let prompts = opt
+            .prompt_path
+            .as_ref()
+            .map(|path| {
+                load_prompts(path, from, to).map_err(|e| Error::Prompt {
+                    path: path.clone(),
+                    reason: e.to_string(),
+                })
+            })
+            .transpose()?
+            .or_else(|| {
+                opt.system_prompt
+                    .as_ref()
+                    .map(|prompt| system_prompts(prompt, from, to))
+            })
+            .or_else(|| opt.model_profile.map(|p| p.prompts()));
+        let pool = ClientPool::new(opt.http.as_ref());
+        let clients = opt
+            .api_pool
+            .iter()
+            .map(|api| {
+                let mut client = ChatGPTClient::new(
+                    &api.api_key,
+                    &api.api_url,
+                    prompts.clone(),
+                    api.org_id.clone(),
+                    &pool,
+                )?;
+                opt.apply_sampling(&mut client.request);
+                client.request.stop = (!opt.stop.is_empty()).then(|| opt.stop.clone());
+                client.guard = ResponseGuard::new(&opt.strip_prefixes, &opt.strip_suffixes);
+                Ok(client)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Self {
             specify_range,
-            api_pool: opt.api_pool,
-            prompt_path: opt.prompt_path,
             max_concurrent: opt.max_concurrent,
+            adaptive_concurrency: opt.adaptive_concurrency,
+            model: opt.model,
+            request_limiter: None,
+            line_extractor: None,
+            history_tokens: opt.history_tokens,
             client_count: 0,
+            clients,
+            pool,
             prompts,
+        })
+    }
+
+    /// replace the prompts sent before every batch
+    pub fn with_prompts(mut self, prompts: Vec<ChatCompletionMessage>) -> Self {
+        for client in self.clients.iter_mut() {
+            client.request.messages = prompts.clone();
         }
+        self.prompts = Some(prompts);
+        self
+    }
+
+    /// render the style into the system prompt
+    pub fn with_style(self, style: Option<&StyleOptions>, to: &str) -> Self {
+        match style {
+            Some(style) => {
+                let prompts = style.apply(self.prompts.clone().unwrap_or_default(), to);
+                self.with_prompts(prompts)
+            }
+            None => self,
+        }
+    }
+
+    /// the translator the translated lines are marked with
+    pub fn with_translator(mut self, translator: Translator) -> Self {
+        for client in self.clients.iter_mut() {
+            client.translator = translator;
+        }
+        self
+    }
+
+    /// the model and the sampling parameters of the requests
+    pub fn params(&self) -> RequestParams {
+        let mut params = self.clients[0].request.params();
+        if let Some(model) = &self.model {
+            params.model = model.clone();
+        }
+        params
+    }
+
+    /// send the explicit batches to the api of routing_opt, with the same prompts,
+    /// call it after the prompts and the translator are set
+    pub fn with_routing(mut self, routing: Option<&RoutingOptions>) -> Result<Self, Error> {
+        let Some(routing) = routing else {
+            return Ok(self);
+        };
+        let first = &self.clients[0];
+        let mut client = ChatGPTClient::new(
+            &routing.api.api_key,
+            &routing.api.api_url,
+            self.prompts.clone(),
+            routing.api.org_id.clone(),
+            &self.pool,
+        )?;
+        client.request.temperature = first.request.temperature;
+        client.request.top_p = first.request.top_p;
+        client.request.seed = first.request.seed;
+        client.request.stop = first.request.stop.clone();
+        client.guard = first.guard.clone();
+        client.translator = first.translator;
+        if let Some(model) = routing.model.as_ref().or(self.model.as_ref()) {
+            client.request.model = model.clone();
+        }
+        let route = Arc::new(Route {
+            classifier: ContentClassifier::new(routing)?,
+            client,
+        });
+        for client in self.clients.iter_mut() {
+            client.route = Some(route.clone());
+        }
+        Ok(self)
+    }
+}
+
+/// read the prompts sent before every batch, {{from}} and {{to}} are replaced by the languages
+pub fn load_prompts(path: &str, from: &str, to: &str) -> Result<Vec<ChatCompletionMessage>> {
+    let prompt_content = fs::read_to_string(path)?
+        .replace("{{from}}", from)
+        .replace("{{to}}", to);
+    Ok(serde_json::from_str::<Vec<ChatCompletionMessage>>(
+        &prompt_content,
+    )?)
+}
+
+/// the system prompt written in the config, as the messages of a prompt file
+pub fn system_prompts(prompt: &str, from: &str, to: &str) -> Vec<ChatCompletionMessage> {
+    vec![ChatCompletionMessage::new(
+        ChatCompletionRole::System,
+        &prompt.replace("{{from}}", from).replace("{{to}}", to),
+    )]
+}
+
+/// send a minimal request with the api, fails if the api rejects it
+pub async fn ping_api(api: &ChatGPTAPI, model: Option<&str>) -> Result<()> {
+    let pool = ClientPool::default();
+    let mut client =
+        ChatGPTClient::new(&api.api_key, &api.api_url, None, api.org_id.clone(), &pool)?;
+    if let Some(model) = model {
+        client.request.model = model.to_string();
     }
+    client.request.max_tokens = Some(1);
+    client
+        .create_chat_completion(vec![ChatCompletionMessage::new(
+            ChatCompletionRole::User,
+            "ping",
+        )])
+        .await?;
+    Ok(())
+}
+
+/// send the messages with the api, the content of the reply
+pub async fn complete(
+    api: &ChatGPTAPI,
+    model: Option<&str>,
+    messages: Vec<ChatCompletionMessage>,
+) -> Result<String> {
+    let pool = ClientPool::default();
+    let mut client =
+        ChatGPTClient::new(&api.api_key, &api.api_url, None, api.org_id.clone(), &pool)?;
+    if let Some(model) = model {
+        client.request.model = model.to_string();
+    }
+    let resp = client.create_chat_completion(messages).await?;
+    resp.choices
+        .into_iter()
+        .next()
+        .map(|c| c.message.content)
+        .ok_or_else(|| anyhow::anyhow!("the reply has no choice"))
 }
 
 fn line_count_batchized(
@@ -169,7 +627,7 @@ impl ConcurrentTranslate<ChatCompletionMessage> for TranslateChatGPT {
 
     fn create_batch_queue<F>(
         &self,
-        batchizer: F,
+        batchizer: &F,
         textures: &Textures,
     ) -> Vec<BatchPackage<ChatCompletionMessage>>
     where
@@ -177,68 +635,57 @@ impl ConcurrentTranslate<ChatCompletionMessage> for TranslateChatGPT {
     {
         let by_line_count = false; //todo
         if !by_line_count {
-            let mut batch_queue = Vec::new();
-            if let Some(specify_range) = &self.specify_range {
-                // specify range
-                for (start, end) in specify_range.iter() {
-                    let mut i = *start;
-                    while i <= *end {
-                        let (batch, size) = batchizer.batchize(textures, i, Some(*end));
-                        println!("specify_range: {}-{}, i: {}, size: {}", start, end, i, size);
-                        if size == 0 {
-                            eprintln!("batch size is 0");
-                            break;
-                        }
-                        batch_queue.push((batch, (i, i + size - 1)));
-                        i += size;
-                    }
-                }
-            } else {
-                // all
-                let mut i = textures.curr_index;
-                while i < textures.lines.len() {
-                    let (batch, size) = batchizer.batchize(textures, i, None);
-                    if size == 0 {
-                        eprintln!("batch size is 0");
-                        break;
-                    }
-                    batch_queue.push((batch, (i, i + size - 1)));
-                    i += size;
-                }
-            }
-            // reverse for pop
-            batch_queue.reverse();
-            batch_queue
+            batch_queue(batchizer, textures, &self.specify_range)
         } else {
             line_count_batchized(textures, &self.specify_range)
         }
     }
 
     fn create_client(&mut self) -> Self::Client {
-        let api = &self.api_pool[self.client_count % self.api_pool.len()];
+        let mut client = self.clients[self.client_count % self.clients.len()].clone();
         self.client_count += 1;
-        ChatGPTClient::new(
-            &api.api_key,
-            &api.api_url,
-            self.prompts.clone(),
-            api.org_id.clone(),
-        )
+        if let Some(model) = &self.model {
+            client.request.model = model.clone();
+        }
+        client.history = self
+            .history_tokens
+            .map(|tokens| Arc::new(Mutex::new(Conversation::new(tokens))));
+        client
     }
 
     fn max_concurrent(&self) -> i32 {
         self.max_concurrent
     }
+
+    fn adaptive_concurrency(&self) -> Option<ConcurrencyRange> {
+        self.adaptive_concurrency
+    }
+
+    fn request_limiter(&self) -> Option<Arc<Semaphore>> {
+        self.request_limiter.clone()
+    }
+
+    fn line_extractor(&self) -> Option<Arc<LineExtractor>> {
+        self.line_extractor.clone()
+    }
 }
 
 #[derive(Clone)]
 pub struct ChatGPTClient {
     pub client: reqwest::Client,
+    /// the authorization and the organization of the api key
+    pub headers: reqwest::header::HeaderMap,
     pub api_key: String,
     pub api_url: String,
-    pub org_id: Option<String>,
-    pub timeout: std::time::Duration,
-    pub proxy: Option<reqwest::Proxy>,
     pub request: ChatCompletionRequest,
+    /// the translator the translated lines are marked with
+    pub translator: Translator,
+    /// the client of the explicit batches
+    pub route: Option<Arc<Route>>,
+    /// strip the chatter around the translation of the replies
+    pub guard: ResponseGuard,
+    /// the conversation of the worker
+    pub history: Option<Arc<Mutex<Conversation>>>,
 }
 
 #[async_trait]
@@ -248,16 +695,85 @@ impl TranslateClient<ChatCompletionMessage> for ChatGPTClient {
         batch_and_range: &BatchPackage<ChatCompletionMessage>,
     ) -> Result<TranslatedLine> {
         let (batch, range) = batch_and_range;
-        let resp = self.create_chat_completion(batch.clone()).await?;
+        let client = self.route(batch);
+        let messages = match &self.history {
+            Some(history) => history.lock().unwrap().with_history(batch),
+            None => batch.clone(),
+        };
+        let resp = client.create_chat_completion(messages).await?;
         // let resp = self.create_chat_completion_test(batch.clone()).await?;
-        let resp_message = resp.choices.into_iter().next().unwrap().message;
-        Ok(TranslatedLine::new(
-            Translator::ChatGPT,
-            resp_message.content.clone(),
+        let usage = TokenUsage {
+            prompt_tokens: resp.usage.prompt_tokens,
+            completion_tokens: resp.usage.completion_tokens,
+        };
+        // an empty choices is retried like the other failed responses
+        let Some(choice) = resp.choices.into_iter().next() else {
+            return Err(ApiError::Other {
+                status: 200,
+                message: format!("batch {}-{} has no choice", range.0, range.1),
+            }
+            .into());
+        };
+        if choice.finish_reason == "length" {
+            return Err(ApiError::cut_at_max_tokens(*range).into());
+        }
+        if choice.finish_reason == "content_filter" {
+            return Err(ApiError::ContentFilter {
+                status: 200,
+                message: format!("batch {}-{} is filtered", range.0, range.1),
+            }
+            .into());
+        }
+        let resp_message = choice.message;
+        let mut translated = TranslatedLine::new(
+            self.translator,
+            client.guard.strip(&resp_message.content),
             range.0,
             range.1,
-        ))
+        );
+        translated.usage = Some(usage);
+        translated.api = Some(mask_api_key(&client.api_key));
+        translated.params = Some(client.request.params());
+        Ok(translated)
+    }
+
+    fn messages(
+        &self,
+        (batch, _): &BatchPackage<ChatCompletionMessage>,
+    ) -> Vec<ChatCompletionMessage> {
+        let messages = match &self.history {
+            Some(history) => history.lock().unwrap().with_history(batch),
+            None => batch.clone(),
+        };
+        self.route(batch).request_messages(messages)
+    }
+
+    fn remember(
+        &self,
+        (batch, _): &BatchPackage<ChatCompletionMessage>,
+        translated: &TranslatedLine,
+    ) {
+        if let Some(history) = &self.history {
+            history.lock().unwrap().push(batch, &translated.content);
+        }
+    }
+
+    fn api(&self) -> String {
+        mask_api_key(&self.api_key)
     }
+
+    fn translator(&self) -> Translator {
+        self.translator
+    }
+}
+
+/// keep only the last 4 characters of the api key, so it can be written into reports
+pub fn mask_api_key(api_key: &str) -> String {
+    let chars = api_key.chars().collect::<Vec<_>>();
+    let tail = chars[chars.len().saturating_sub(4)..]
+        .iter()
+        .collect::<String>();
+    format!("***{}", tail)
 }
 
 impl ChatGPTClient {
@@ -266,44 +782,45 @@ impl ChatGPTClient {
         api_url: &str,
         prompts: Option<Vec<ChatCompletionMessage>>,
         org_id: Option<String>,
-    ) -> Self {
+        pool: &ClientPool,
+    ) -> Result<Self, Error> {
         // check api_key
         if api_key.is_empty() {
-            panic!("api_key is empty");
+            return Err(Error::Config("api_key is empty".to_string()));
         }
         // check api_url
         if api_url.is_empty() {
-            panic!("api_url is empty");
-        }
-        let timeout = std::time::Duration::from_secs(60 * 3);
-        let client = reqwest::ClientBuilder::new()
-            .timeout(timeout)
-            .default_headers({
-                let mut headers = reqwest::header::HeaderMap::new();
-                let mut api_key = api_key.to_string();
-                api_key.insert_str(0, "Bearer ");
-                headers.insert(
-                    reqwest::header::AUTHORIZATION,
-                    reqwest::header::HeaderValue::from_str(&api_key).unwrap(),
-                );
-                if let Some(org_id) = org_id.as_ref() {
-                    headers.insert(
-                        reqwest::header::HeaderName::from_str("OpenAI-Organization").unwrap(),
-                        reqwest::header::HeaderValue::from_str(org_id).unwrap(),
-                    );
-                }
-                headers.insert(
-                    reqwest::header::CONTENT_TYPE,
-                    reqwest::header::HeaderValue::from_str("application/json").unwrap(),
-                );
-                headers.insert(
-                    reqwest::header::ACCEPT,
-                    reqwest::header::HeaderValue::from_str("application/json").unwrap(),
-                );
-                headers
-            })
-            .build()
-            .unwrap();
+            return Err(Error::Config("api_url is empty".to_string()));
+        }
+        let invalid = |name: &str| {
+            Error::Config(format!(
+                "{} of the api {} contains invalid characters",
+                name,
+                mask_api_key(api_key)
+            ))
+        };
+        // the keys are sent with every request, the http client is shared by the keys of the url
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::AUTHORIZATION,
+            reqwest::header::HeaderValue::from_str(&format!("Bearer {}", api_key))
+                .map_err(|_| invalid("api_key"))?,
+        );
+        if let Some(org_id) = org_id.as_ref() {
+            headers.insert(
+                reqwest::header::HeaderName::from_static("openai-organization"),
+                reqwest::header::HeaderValue::from_str(org_id).map_err(|_| invalid("org_id"))?,
+            );
+        }
+        headers.insert(
+            reqwest::header::CONTENT_TYPE,
+            reqwest::header::HeaderValue::from_static("application/json"),
+        );
+        headers.insert(
+            reqwest::header::ACCEPT,
+            reqwest::header::HeaderValue::from_static("application/json"),
+        );
+        let client = pool.client(api_url)?;
 
         // request
         let mut request = ChatCompletionRequest::default();
@@ -311,15 +828,17 @@ impl ChatGPTClient {
             request.messages = prompts;
         }
         request.temperature = Some(0.6);
-        Self {
+        Ok(Self {
             client,
+            headers,
             api_key: api_key.to_string(),
             api_url: api_url.to_string(),
-            org_id,
             request,
-            timeout,
-            proxy: None,
-        }
+            translator: Translator::ChatGPT,
+            route: None,
+            guard: ResponseGuard::default(),
+            history: None,
+        })
     }
 
     #[allow(dead_code)]
@@ -351,22 +870,50 @@ impl ChatGPTClient {
         Ok(response)
     }
 
+    /// the client of the batch, the explicit batches go to the route
+    fn route(&self, batch: &[ChatCompletionMessage]) -> &ChatGPTClient {
+        match &self.route {
+            Some(route) if route.classifier.is_explicit(batch) => &route.client,
+            _ => self,
+        }
+    }
+
+    /// the prompts of the client and the messages, the prompts of an override replace the
+    /// prompts of the client
+    fn request_messages(&self, messages: Vec<ChatCompletionMessage>) -> Vec<ChatCompletionMessage> {
+        let mut request_messages = match has_own_prompts(&messages) {
+            true => vec![],
+            false => self.request.messages.clone(),
+        };
+        request_messages.extend(messages);
+        request_messages
+    }
+
     #[allow(dead_code)]
     pub async fn create_chat_completion(
         &self,
         messages: Vec<ChatCompletionMessage>,
     ) -> Result<ChatCompletionResponse> {
         let mut request = self.request.clone();
-        request.messages.extend(messages);
+        request.messages = self.request_messages(messages);
         // println!("messages :{:?}", request.messages);
         let resp = self
             .client
             .post(&self.api_url)
+            .headers(self.headers.clone())
             .body(&request)
             .send()
             .await?;
         let status = resp.status();
+        let retry_after = resp
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
         match resp.bytes().await {
+            Ok(bs) if !status.is_success() => {
+                Err(ApiError::from_response(status.as_u16(), &bs, retry_after.as_deref()).into())
+            }
             Ok(bs) => match serde_json::from_slice(&bs) {
                 Ok(completion) => Ok(completion),
                 Err(e) => {
@@ -405,10 +952,26 @@ pub struct ChatCompletionRequest {
     pub frequency_penalty: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+impl ChatCompletionRequest {
+    /// the parameters recorded with the translation
+    pub fn params(&self) -> RequestParams {
+        RequestParams {
+            model: self.model.clone(),
+            temperature: self.temperature,
+            top_p: self.top_p,
+            seed: self.seed,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ChatCompletionMessage {
+    /// system if a message of a prompt file has no role
+    #[serde(default)]
     pub role: ChatCompletionRole,
     pub content: String,
 }
@@ -443,9 +1006,10 @@ impl From<&mut TextureLine> for Vec<ChatCompletionMessage> {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Copy, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum ChatCompletionRole {
+    #[default]
     #[serde(rename = "system")]
     System,
     #[serde(rename = "user")]
@@ -467,7 +1031,7 @@ impl AsRef<str> for ChatCompletionRole {
 impl Default for ChatCompletionRequest {
     fn default() -> Self {
         Self {
-            model: "gpt-3.5-turbo".to_string(),
+            model: DEFAULT_MODEL.to_string(),
             messages: Vec::new(),
             temperature: None,
             top_p: None,
@@ -478,6 +1042,7 @@ impl Default for ChatCompletionRequest {
             stream: Some(false),
             stop: None,
             user: None,
+            seed: None,
         }
     }
 }
@@ -519,6 +1084,7 @@ mod test {
     use std::io;
 
     use super::*;
+    use crate::textures::TEXTURES_VERSION;
 
     #[test]
     pub fn test_chat_completion_role_serialize() {
@@ -559,11 +1125,40 @@ mod test {
             presence_penalty: None,
             frequency_penalty: None,
             user: None,
+            seed: None,
         };
         let json = serde_json::to_string(&request).unwrap();
         assert_eq!(json, "{\"model\":\"test\",\"messages\":[]}");
     }
 
+    #[test]
+    pub fn test_apply_sampling() {
+        let mut opt: ChatGPTOptions = toml::from_str(
+            r#"
+            api_pool = []
+            max_concurrent = 1
+            model_profile = "sakura"
+            top_p = 0.5
+            seed = 7
+            "#,
+        )
+        .unwrap();
+        let mut request = ChatCompletionRequest::default();
+        opt.apply_sampling(&mut request);
+        assert_eq!(
+            (request.temperature, request.top_p, request.seed),
+            (Some(0.1), Some(0.5), Some(7))
+        );
+        opt.seed = None;
+        opt.deterministic = true;
+        opt.apply_sampling(&mut request);
+        assert_eq!(
+            (request.temperature, request.top_p, request.seed),
+            (Some(0.0), Some(1.0), Some(0))
+        );
+        assert_eq!(request.params().seed, Some(0));
+    }
+
     #[test]
     pub fn test_chat_completion_request_deserialize() {
         let json = "{\"model\":\"test\",\"messages\":[]}";
@@ -639,6 +1234,32 @@ mod test {
         println!("p50k base len: {}", len);
     }
 
+    #[test]
+    fn test_tokenizer_from_model() {
+        assert_eq!(
+            TokenizerKind::from_model("gpt-4o-mini"),
+            TokenizerKind::O200k
+        );
+        assert_eq!(TokenizerKind::from_model("o3-mini"), TokenizerKind::O200k);
+        assert_eq!(
+            TokenizerKind::from_model("gpt-3.5-turbo"),
+            TokenizerKind::Cl100k
+        );
+        assert_eq!(
+            TokenizerKind::from_model("qwen2.5:7b"),
+            TokenizerKind::Chars
+        );
+        assert_eq!(Tokenizer::new(TokenizerKind::Chars).count("你好 world"), 4);
+    }
+
+    #[test]
+    fn test_batch_ceiling() {
+        assert_eq!(model_limits("gpt-4o-mini-2024-07-18"), (128000, 16384));
+        assert_eq!(model_limits("gpt-4-0613"), (8192, 8192));
+        assert_eq!(batch_ceiling("gpt-3.5-turbo", 2.0, 1000), 2048);
+        assert_eq!(batch_ceiling("gpt-4", 1.0, 2192), 3000);
+    }
+
     #[test]
     pub fn test_tokenized_batchizer_with_specify_range() {
         let bep = tiktoken_rs::cl100k_base().unwrap();
@@ -653,30 +1274,54 @@ mod test {
         let textures = Textures {
             lines,
             curr_index: 0,
+            version: TEXTURES_VERSION,
+            source: None,
             name: "".to_string(),
+            shard: None,
+            dirs: Default::default(),
         };
 
         let batchizer = TokenizedBatchizer {
-            bep: tiktoken_rs::cl100k_base().unwrap(),
+            tokenizer: Tokenizer::new(TokenizerKind::Cl100k),
             max_tokens: len * 3,
             extract_regex: None,
+            protected: ProtectedTerms::default(),
+            overlap: 0,
+            grouping: LineGrouping::FirstChar,
+            group_max_tokens: 10000,
+            profile: None,
+            ruby: None,
         };
         let specify_range = vec![(0, 4), (2, 11)];
         let tor = TranslateChatGPT::new(
             ChatGPTOptions {
                 api_pool: vec![ChatGPTAPI {
-                    api_key: "".to_string(),
-                    api_url: "".to_string(),
+                    api_key: "sk-test".to_string(),
+                    api_url: "http://127.0.0.1".to_string(),
                     org_id: None,
                 }],
                 prompt_path: None,
+                system_prompt: None,
+                history_tokens: None,
+                model: None,
                 max_concurrent: 30,
+                adaptive_concurrency: None,
+                http: None,
+                model_profile: None,
+                temperature: None,
+                top_p: None,
+                seed: None,
+                deterministic: false,
+                stop: vec![],
+                strip_prefixes: vec![],
+                strip_suffixes: vec![],
             },
             Some(specify_range),
             "zho",
             "eng",
-        );
-        let mut batch_queue = tor.create_batch_queue(batchizer, &textures);
+        )
+        .unwrap();
+        let mut batch_queue = tor.create_batch_queue(&batchizer, &textures);
         batch_queue.reverse();
         batch_queue.iter().for_each(|b| {
             println!("batch: {:?}", b);
@@ -687,7 +1332,7 @@ mod test {
 
     #[test]
     pub fn test_tokenized_batchizer() {
-        let lines = vec![
+        let lines = [
             "请原谅我",
             "请原谅我",
             "请原谅我",
@@ -703,13 +1348,23 @@ mod test {
         let textures = Textures {
             lines,
             curr_index: 0,
+            version: TEXTURES_VERSION,
+            source: None,
             name: "".to_string(),
+            shard: None,
+            dirs: Default::default(),
         };
 
         let mut batchizer = TokenizedBatchizer {
-            bep: tiktoken_rs::cl100k_base().unwrap(),
+            tokenizer: Tokenizer::new(TokenizerKind::Cl100k),
             max_tokens: 500,
             extract_regex: None,
+            protected: ProtectedTerms::default(),
+            overlap: 0,
+            grouping: LineGrouping::FirstChar,
+            group_max_tokens: 10000,
+            profile: None,
+            ruby: None,
         };
         let (_, size) = batchizer.batchize(&textures, 0, None);
         assert_eq!(size, 8);
@@ -718,6 +1373,132 @@ mod test {
         assert_eq!(size, 4);
     }
 
+    #[test]
+    fn test_tokenized_batchizer_grouping() {
+        let lines = ["「a」", "「b」", "「c」", "【A】x", "【A】y", "【B】z"]
+            .iter()
+            .map(|s| TextureLine::new(0, 0, s.to_string(), false))
+            .collect::<Vec<_>>();
+        let textures = Textures {
+            lines,
+            curr_index: 0,
+            version: TEXTURES_VERSION,
+            source: None,
+            name: "".to_string(),
+            shard: None,
+            dirs: Default::default(),
+        };
+        let mut batchizer = TokenizedBatchizer {
+            tokenizer: Tokenizer::new(TokenizerKind::Cl100k),
+            max_tokens: 1,
+            extract_regex: None,
+            protected: ProtectedTerms::default(),
+            overlap: 0,
+            grouping: LineGrouping::FirstChar,
+            group_max_tokens: 10000,
+            profile: None,
+            ruby: None,
+        };
+        assert_eq!(batchizer.batchize(&textures, 0, None).1, 3);
+        // the hard ceiling always wins
+        batchizer.group_max_tokens = 1;
+        assert_eq!(batchizer.batchize(&textures, 0, None).1, 1);
+        batchizer.group_max_tokens = 10000;
+        batchizer.grouping = LineGrouping::Off;
+        assert_eq!(batchizer.batchize(&textures, 0, None).1, 1);
+        batchizer.grouping =
+            LineGrouping::new(&Grouping::SpeakerTag("^【(.+?)】".to_string())).unwrap();
+        assert_eq!(batchizer.batchize(&textures, 3, None).1, 2);
+    }
+
+    #[test]
+    fn test_tokenized_batchizer_dedup() {
+        let lines = ["你好", "再见", "你好", "谢谢"]
+            .iter()
+            .map(|s| TextureLine::new(0, 0, s.to_string(), false))
+            .collect::<Vec<_>>();
+        let mut textures = Textures {
+            lines,
+            curr_index: 0,
+            version: TEXTURES_VERSION,
+            source: None,
+            name: "".to_string(),
+            shard: None,
+            dirs: Default::default(),
+        };
+        textures.dedup();
+        assert_eq!(textures.lines[2].duplicate, Some(0));
+        let batchizer = TokenizedBatchizer {
+            tokenizer: Tokenizer::new(TokenizerKind::Cl100k),
+            max_tokens: 500,
+            extract_regex: None,
+            protected: ProtectedTerms::default(),
+            overlap: 0,
+            grouping: LineGrouping::FirstChar,
+            group_max_tokens: 10000,
+            profile: None,
+            ruby: None,
+        };
+        let (batch, size) = batchizer.batchize(&textures, 0, None);
+        assert_eq!(size, 4);
+        assert_eq!(batch[0].content, "(1) 你好\n(2) 再见\n(3) 谢谢\n");
+        let (batch, size) = batchizer.batchize(&textures, 2, Some(2));
+        assert_eq!(size, 1);
+        assert!(batch.is_empty());
+        textures.update(TranslatedLine::new(
+            Translator::ChatGPT,
+            "(1) Hi\n(2) Bye\n(3) Thanks".to_string(),
+            0,
+            3,
+        ));
+        let extract = |content: &str| {
+            content
+                .lines()
+                .map(|l| l[4..].to_string())
+                .collect::<Vec<_>>()
+        };
+        let translated = textures.align_translated(Translator::ChatGPT, extract, |_, _, _| {});
+        assert_eq!(translated[2].as_deref(), Some("Hi"));
+        assert_eq!(translated[3].as_deref(), Some("Thanks"));
+    }
+
+    #[test]
+    fn test_tokenized_batchizer_overlap() {
+        let lines = ["你好", "再见", "谢谢", "晚安"]
+            .iter()
+            .map(|s| TextureLine::new(0, 0, s.to_string(), false))
+            .collect::<Vec<_>>();
+        let textures = Textures {
+            lines,
+            curr_index: 0,
+            version: TEXTURES_VERSION,
+            source: None,
+            name: "".to_string(),
+            shard: None,
+            dirs: Default::default(),
+        };
+        let batchizer = TokenizedBatchizer {
+            tokenizer: Tokenizer::new(TokenizerKind::Cl100k),
+            max_tokens: 500,
+            extract_regex: None,
+            protected: ProtectedTerms::default(),
+            overlap: 2,
+            grouping: LineGrouping::FirstChar,
+            group_max_tokens: 10000,
+            profile: None,
+            ruby: None,
+        };
+        let (batch, size) = batchizer.batchize(&textures, 0, Some(1));
+        assert_eq!(size, 2);
+        assert_eq!(batch[0].content, "(1) 你好\n(2) 再见\n");
+        let (batch, size) = batchizer.batchize(&textures, 3, None);
+        assert_eq!(size, 1);
+        assert_eq!(
+            batch[0].content,
+            format!("{}(c1) 再见\n(c2) 谢谢\n(1) 晚安\n", CONTEXT_HEADER)
+        );
+    }
+
     #[test]
     pub fn test_chat_gpt_create_client() {
         let mut gpt = TranslateChatGPT::new(
@@ -740,12 +1521,26 @@ mod test {
                     },
                 ],
                 prompt_path: None,
+                system_prompt: None,
+                history_tokens: None,
+                model: None,
                 max_concurrent: 10,
+                adaptive_concurrency: None,
+                http: None,
+                model_profile: None,
+                temperature: None,
+                top_p: None,
+                seed: None,
+                deterministic: false,
+                stop: vec![],
+                strip_prefixes: vec![],
+                strip_suffixes: vec![],
             },
             None,
             "Japanese",
             "Chinese",
-        );
+        )
+        .unwrap();
         let client = gpt.create_client();
         assert_eq!(client.api_key, "test1");
         assert_eq!(client.api_url, "test1.html");
@@ -799,12 +1594,26 @@ mod test {
                     org_id: None,
                 }],
                 prompt_path: Some("./assets/prompt_violation_1.json".to_string()),
+                system_prompt: None,
+                history_tokens: None,
+                model: None,
                 max_concurrent: 1,
+                adaptive_concurrency: None,
+                http: None,
+                model_profile: None,
+                temperature: None,
+                top_p: None,
+                seed: None,
+                deterministic: false,
+                stop: vec![],
+                strip_prefixes: vec![],
+                strip_suffixes: vec![],
             },
             None,
             "Japanese",
             "Chinese",
         )
+        .unwrap()
         .create_client();
 
         let response = client.create_chat_completion(messages).await.unwrap();
@@ -822,12 +1631,26 @@ mod test {
                     org_id: None,
                 }],
                 prompt_path: Some("./assets/prompt_violation_3.json".to_string()),
+                system_prompt: None,
+                history_tokens: None,
+                model: None,
                 max_concurrent: 1,
+                adaptive_concurrency: None,
+                http: None,
+                model_profile: None,
+                temperature: None,
+                top_p: None,
+                seed: None,
+                deterministic: false,
+                stop: vec![],
+                strip_prefixes: vec![],
+                strip_suffixes: vec![],
             },
             None,
             "Japanese",
             "Chinese",
         )
+        .unwrap()
         .create_client();
 
         let response = client.create_chat_completion(messages).await.unwrap();
@@ -847,9 +1670,36 @@ mod test {
             .for_each(|m| println!("message: role {:?}\n{}", m.role, m.content));
     }
 
+    #[test]
+    fn test_system_prompt() {
+        let opt: ChatGPTOptions = toml::from_str(
+            r#"
+api_pool = [{api_key = "key", api_url = "https://api.openai.com/v1/chat/completions"}]
+max_concurrent = 1
+system_prompt = """
+Translate {{from}} into {{to}}.
+Keep the (n) numbers."""
+"#,
+        )
+        .unwrap();
+        let gpt = TranslateChatGPT::new(opt, None, "Japanese", "Chinese").unwrap();
+        let prompts = gpt.prompts.unwrap();
+        assert_eq!(prompts.len(), 1);
+        assert_eq!(prompts[0].role, ChatCompletionRole::System);
+        assert_eq!(
+            prompts[0].content,
+            "Translate Japanese into Chinese.\nKeep the (n) numbers."
+        );
+        let messages: Vec<ChatCompletionMessage> =
+            serde_json::from_str(r#"[{"content": "a"}, {"role": "user", "content": "b"}]"#)
+                .unwrap();
+        assert_eq!(messages[0].role, ChatCompletionRole::System);
+        assert_eq!(messages[1].role, ChatCompletionRole::User);
+    }
+
     #[test]
     fn test_batchizer_by_line_count_by_specify_range() {
-        let lines = vec![
+        let lines = [
             "请原谅我1",
             "请原谅我2",
             "请原谅我3",
@@ -882,7 +1732,11 @@ mod test {
         let textures = Textures {
             lines,
             curr_index: 0,
+            version: TEXTURES_VERSION,
+            source: None,
             name: "".to_string(),
+            shard: None,
+            dirs: Default::default(),
         };
 
         let specify_range = vec![(0, 1), (2, 10), (21, 23)];
@@ -898,9 +1752,15 @@ mod test {
     #[test]
     fn test_batchizer_extract_for_mtool() {
         let batchizer = TokenizedBatchizer {
-            bep: tiktoken_rs::cl100k_base().unwrap(),
+            tokenizer: Tokenizer::new(TokenizerKind::Cl100k),
             max_tokens: 256,
             extract_regex: Some(Regex::new(r#":\s"(.+)""#).unwrap()),
+            protected: ProtectedTerms::default(),
+            overlap: 0,
+            grouping: LineGrouping::FirstChar,
+            group_max_tokens: 10000,
+            profile: None,
+            ruby: None,
         };
         let content = r#" "请原谅我": "请原谅我", "#;
         let result = batchizer.extract(content);
@@ -913,9 +1773,15 @@ mod test {
     #[test]
     fn test_batchizer_extract_for_ain() {
         let batchizer = TokenizedBatchizer {
-            bep: tiktoken_rs::cl100k_base().unwrap(),
+            tokenizer: Tokenizer::new(TokenizerKind::Cl100k),
             max_tokens: 256,
             extract_regex: Some(Regex::new(r#"=\s"(.+)""#).unwrap()),
+            protected: ProtectedTerms::default(),
+            overlap: 0,
+            grouping: LineGrouping::FirstChar,
+            group_max_tokens: 10000,
+            profile: None,
+            ruby: None,
         };
         let content = r#";m[300] = "请原谅我""#;
         let result = batchizer.extract(content);
@@ -925,6 +1791,47 @@ mod test {
         assert_eq!(result, Some(r#"请\"原谅\"我"#.to_string()));
     }
 
+    #[test]
+    fn test_batchizer_capture_missed() {
+        let mut batchizer = TokenizedBatchizer {
+            tokenizer: Tokenizer::new(TokenizerKind::Cl100k),
+            max_tokens: 256,
+            extract_regex: Some(Regex::new(r#"=\s"(.+)""#).unwrap()),
+            protected: ProtectedTerms::default(),
+            overlap: 0,
+            grouping: LineGrouping::FirstChar,
+            group_max_tokens: 10000,
+            profile: None,
+            ruby: None,
+        };
+        let lines = [r#";m[1] = "请原谅我""#, "请原谅我2"]
+            .iter()
+            .map(|s| TextureLine::new(0, 0, s.to_string(), false))
+            .collect::<Vec<_>>();
+        let textures = Textures {
+            lines,
+            curr_index: 0,
+            version: TEXTURES_VERSION,
+            source: None,
+            name: "".to_string(),
+            shard: None,
+            dirs: Default::default(),
+        };
+        // the line the capture misses is sent whole
+        let (batch, size) = batchizer.batchize(&textures, 0, None);
+        assert_eq!(size, 2);
+        assert_eq!(batch[0].content, "(1) 请原谅我\n(2) 请原谅我2\n");
+        // a regex without a group captures nothing
+        batchizer.extract_regex = Some(Regex::new(r#"=\s".+""#).unwrap());
+        assert_eq!(batchizer.extract(r#";m[1] = "请原谅我""#), None);
+    }
+
+    #[test]
+    fn test_mask_api_key() {
+        assert_eq!(mask_api_key("sk-1234567890abcd"), "***abcd");
+        assert_eq!(mask_api_key("ab"), "***ab");
+    }
+
     #[test]
     fn test_asdaksdlaj() {
         let regex = Regex::new(r#"(\(\d+\)\s)"#).unwrap();
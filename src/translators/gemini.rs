@@ -0,0 +1,575 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+
+use crate::console::{self, Tone};
+use crate::error::Error;
+use crate::i18n::Msg;
+use crate::outputs::LineExtractor;
+use crate::t;
+use crate::textures::{Textures, TokenUsage, TranslatedLine};
+
+use super::adaptive::ConcurrencyRange;
+use super::api_error::ApiError;
+use super::chatgpt::{
+    load_prompts, mask_api_key, system_prompts, ChatCompletionMessage, ChatCompletionRole,
+    Tokenizer,
+};
+use super::history::Conversation;
+use super::http::{ClientPool, HttpOptions};
+use super::prompt_override::has_own_prompts;
+use super::style::StyleOptions;
+use super::translator::{
+    batch_queue, BatchPackage, Batchizer, ConcurrentTranslate, TranslateClient, Translator,
+};
+
+pub const DEFAULT_GEMINI_MODEL: &str = "gemini-1.5-flash";
+const DEFAULT_GEMINI_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+
+/// the harm categories of the safety settings
+const HARM_CATEGORIES: [&str; 4] = [
+    "HARM_CATEGORY_HARASSMENT",
+    "HARM_CATEGORY_HATE_SPEECH",
+    "HARM_CATEGORY_SEXUALLY_EXPLICIT",
+    "HARM_CATEGORY_DANGEROUS_CONTENT",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum HarmBlockThreshold {
+    BlockNone,
+    BlockOnlyHigh,
+    BlockMediumAndAbove,
+    BlockLowAndAbove,
+    Off,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeminiOptions {
+    /// a client per api key, the batches are spread over them
+    pub api_keys: Vec<String>,
+    /// the model of the requests, default is gemini-1.5-flash
+    pub model: Option<String>,
+    /// default is https://generativelanguage.googleapis.com/v1beta
+    pub api_url: Option<String>,
+    /// the prompts, the same format as chatgpt_opt.prompt_path,
+    /// the system prompts are sent as the system instruction
+    pub prompt_path: Option<String>,
+    /// the system instruction written in the config, see chatgpt_opt.system_prompt
+    pub system_prompt: Option<String>,
+    pub max_concurrent: i32,
+    /// tune the concurrency between min and max, see chatgpt_opt.adaptive_concurrency
+    pub adaptive_concurrency: Option<ConcurrencyRange>,
+    /// the block threshold of every harm category, BLOCK_NONE lets the game dialogue through
+    pub safety_threshold: Option<HarmBlockThreshold>,
+    /// the block threshold of a harm category, overrides safety_threshold,
+    /// example: {HARM_CATEGORY_HARASSMENT = "BLOCK_ONLY_HIGH"}
+    #[serde(default)]
+    pub safety_settings: BTreeMap<String, HarmBlockThreshold>,
+    /// the previous batches and replies of every worker sent before its next batch, see
+    /// chatgpt_opt.history_tokens
+    pub history_tokens: Option<usize>,
+    /// the proxy, the connection pool and the http version of the requests
+    pub http: Option<HttpOptions>,
+}
+
+impl GeminiOptions {
+    pub fn model(&self) -> &str {
+        self.model.as_deref().unwrap_or(DEFAULT_GEMINI_MODEL)
+    }
+
+    fn safety_settings(&self) -> Vec<SafetySetting> {
+        let mut settings = BTreeMap::new();
+        if let Some(threshold) = self.safety_threshold {
+            for category in HARM_CATEGORIES {
+                settings.insert(category.to_string(), threshold);
+            }
+        }
+        settings.extend(self.safety_settings.clone());
+        settings
+            .into_iter()
+            .map(|(category, threshold)| SafetySetting {
+                category,
+                threshold,
+            })
+            .collect()
+    }
+}
+
+pub struct TranslateGemini {
+    pub specify_range: Option<Vec<(usize, usize)>>,
+    pub max_concurrent: i32,
+    pub adaptive_concurrency: Option<ConcurrencyRange>,
+    pub request_limiter: Option<Arc<Semaphore>>,
+    pub line_extractor: Option<Arc<LineExtractor>>,
+    history_tokens: Option<usize>,
+    client_count: usize,
+    clients: Vec<GeminiClient>,
+    prompts: Vec<ChatCompletionMessage>,
+}
+
+impl TranslateGemini {
+    pub fn new(
+        opt: GeminiOptions,
+        specify_range: Option<Vec<(usize, usize)>>,
+        from: &str,
+        to: &str,
+    ) -> Result<Self, Error> {
+        if opt.api_keys.is_empty() {
+            return Err(Error::Config("gemini_opt.api_keys is empty".to_string()));
+        }
+        let prompts = match &opt.prompt_path {
+            Some(path) => load_prompts(path, from, to).map_err(|e| Error::Prompt {
+                path: path.clone(),
+                reason: e.to_string(),
+            })?,
+            None => match &opt.system_prompt {
+                Some(prompt) => system_prompts(prompt, from, to),
+                None => vec![],
+            },
+        };
+        let pool = ClientPool::new(opt.http.as_ref());
+        let clients = opt
+            .api_keys
+            .iter()
+            .map(|key| GeminiClient::new(key, &opt, &pool))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut translator = Self {
+            specify_range,
+            max_concurrent: opt.max_concurrent,
+            adaptive_concurrency: opt.adaptive_concurrency,
+            request_limiter: None,
+            line_extractor: None,
+            history_tokens: opt.history_tokens,
+            client_count: 0,
+            clients,
+            prompts: vec![],
+        };
+        translator.set_prompts(prompts);
+        Ok(translator)
+    }
+
+    /// the tokens of the prompts sent before every batch
+    pub fn prompt_tokens(&self, tokenizer: &Tokenizer) -> usize {
+        self.prompts
+            .iter()
+            .map(|m| tokenizer.count(&m.content))
+            .sum()
+    }
+
+    /// render the style into the system instruction
+    pub fn with_style(mut self, style: Option<&StyleOptions>, to: &str) -> Self {
+        if let Some(style) = style {
+            let prompts = style.apply(self.prompts.clone(), to);
+            self.set_prompts(prompts);
+        }
+        self
+    }
+
+    fn set_prompts(&mut self, prompts: Vec<ChatCompletionMessage>) {
+        let (instruction, contents) = to_contents(&prompts);
+        for client in self.clients.iter_mut() {
+            client.request.system_instruction = instruction.clone();
+            client.request.contents = contents.clone();
+        }
+        self.prompts = prompts;
+    }
+}
+
+#[async_trait]
+impl ConcurrentTranslate<ChatCompletionMessage> for TranslateGemini {
+    type Client = GeminiClient;
+
+    fn create_batch_queue<F>(
+        &self,
+        batchizer: &F,
+        textures: &Textures,
+    ) -> Vec<BatchPackage<ChatCompletionMessage>>
+    where
+        F: Batchizer<ChatCompletionMessage>,
+    {
+        batch_queue(batchizer, textures, &self.specify_range)
+    }
+
+    fn create_client(&mut self) -> Self::Client {
+        let mut client = self.clients[self.client_count % self.clients.len()].clone();
+        self.client_count += 1;
+        client.history = self
+            .history_tokens
+            .map(|tokens| Arc::new(Mutex::new(Conversation::new(tokens))));
+        client
+    }
+
+    fn max_concurrent(&self) -> i32 {
+        self.max_concurrent
+    }
+
+    fn adaptive_concurrency(&self) -> Option<ConcurrencyRange> {
+        self.adaptive_concurrency
+    }
+
+    fn request_limiter(&self) -> Option<Arc<Semaphore>> {
+        self.request_limiter.clone()
+    }
+
+    fn line_extractor(&self) -> Option<Arc<LineExtractor>> {
+        self.line_extractor.clone()
+    }
+}
+
+#[derive(Clone)]
+pub struct GeminiClient {
+    client: reqwest::Client,
+    /// the api key, sent with every request over the shared client
+    headers: reqwest::header::HeaderMap,
+    api_key: String,
+    /// {api_url}/models/{model}:generateContent
+    url: String,
+    request: GenerateContentRequest,
+    /// the conversation of the worker
+    history: Option<Arc<Mutex<Conversation>>>,
+}
+
+impl GeminiClient {
+    pub fn new(api_key: &str, opt: &GeminiOptions, pool: &ClientPool) -> Result<Self, Error> {
+        if api_key.is_empty() {
+            return Err(Error::Config(
+                "gemini_opt.api_keys has an empty key".to_string(),
+            ));
+        }
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::HeaderName::from_static("x-goog-api-key"),
+            reqwest::header::HeaderValue::from_str(api_key).map_err(|_| {
+                Error::Config("gemini_opt.api_keys contains invalid characters".to_string())
+            })?,
+        );
+        headers.insert(
+            reqwest::header::CONTENT_TYPE,
+            reqwest::header::HeaderValue::from_static("application/json"),
+        );
+        let api_url = opt.api_url.as_deref().unwrap_or(DEFAULT_GEMINI_URL);
+        Ok(Self {
+            client: pool.client(api_url)?,
+            headers,
+            api_key: api_key.to_string(),
+            url: format!(
+                "{}/models/{}:generateContent",
+                api_url.trim_end_matches('/'),
+                opt.model()
+            ),
+            request: GenerateContentRequest {
+                contents: vec![],
+                system_instruction: None,
+                safety_settings: opt.safety_settings(),
+                generation_config: Some(GenerationConfig {
+                    temperature: Some(0.6),
+                }),
+            },
+            history: None,
+        })
+    }
+
+    /// the request of the messages, the prompts of an override replace the prompts of the client
+    fn build_request(&self, messages: &[ChatCompletionMessage]) -> GenerateContentRequest {
+        let mut request = self.request.clone();
+        let (instruction, contents) = to_contents(messages);
+        if has_own_prompts(messages) {
+            request.system_instruction = instruction;
+            request.contents.clear();
+        }
+        request.contents.extend(contents);
+        request
+    }
+
+    pub async fn generate_content(
+        &self,
+        messages: &[ChatCompletionMessage],
+    ) -> Result<GenerateContentResponse> {
+        let request = self.build_request(messages);
+        let resp = self
+            .client
+            .post(&self.url)
+            .headers(self.headers.clone())
+            .json(&request)
+            .send()
+            .await?;
+        let status = resp.status();
+        let retry_after = resp
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        let bs = resp.bytes().await?;
+        match serde_json::from_slice(&bs) {
+            Ok(resp) if status.is_success() => Ok(resp),
+            _ => Err(ApiError::from_response(status.as_u16(), &bs, retry_after.as_deref()).into()),
+        }
+    }
+}
+
+#[async_trait]
+impl TranslateClient<ChatCompletionMessage> for GeminiClient {
+    async fn request(
+        &self,
+        batch_and_range: &BatchPackage<ChatCompletionMessage>,
+    ) -> Result<TranslatedLine> {
+        let (batch, range) = batch_and_range;
+        let resp = match &self.history {
+            Some(history) => {
+                let messages = history.lock().unwrap().with_history(batch);
+                self.generate_content(&messages).await?
+            }
+            None => self.generate_content(batch).await?,
+        };
+        let finish_reason = resp
+            .candidates
+            .first()
+            .and_then(|c| c.finish_reason.as_deref());
+        if finish_reason == Some("MAX_TOKENS") {
+            return Err(ApiError::cut_at_max_tokens(*range).into());
+        }
+        // a blocked batch is returned empty, it is retried as a mismatch then recorded as failed
+        let content = match resp.text() {
+            Some(text) => text,
+            None => {
+                let blocked = t!(Msg::GeminiBlocked, range.0, range.1, resp.block_reason());
+                eprintln!("{} {}", console::err(Tone::Warning, "[Gemini]"), blocked);
+                String::new()
+            }
+        };
+        let mut translated = TranslatedLine::new(Translator::Gemini, content, range.0, range.1);
+        translated.usage = resp.usage_metadata.map(|u| TokenUsage {
+            prompt_tokens: u.prompt_token_count,
+            completion_tokens: u.candidates_token_count,
+        });
+        translated.api = Some(mask_api_key(&self.api_key));
+        Ok(translated)
+    }
+
+    fn messages(
+        &self,
+        (batch, _): &BatchPackage<ChatCompletionMessage>,
+    ) -> Vec<ChatCompletionMessage> {
+        let request = match &self.history {
+            Some(history) => self.build_request(&history.lock().unwrap().with_history(batch)),
+            None => self.build_request(batch),
+        };
+        from_contents(request.system_instruction, request.contents)
+    }
+
+    fn remember(
+        &self,
+        (batch, _): &BatchPackage<ChatCompletionMessage>,
+        translated: &TranslatedLine,
+    ) {
+        if let Some(history) = &self.history {
+            history.lock().unwrap().push(batch, &translated.content);
+        }
+    }
+
+    fn api(&self) -> String {
+        mask_api_key(&self.api_key)
+    }
+
+    fn translator(&self) -> Translator {
+        Translator::Gemini
+    }
+}
+
+/// the system prompts become the system instruction, the others the contents of user and model
+fn to_contents(messages: &[ChatCompletionMessage]) -> (Option<Content>, Vec<Content>) {
+    let system = messages
+        .iter()
+        .filter(|m| m.role == ChatCompletionRole::System)
+        .map(|m| Part {
+            text: m.content.clone(),
+        })
+        .collect::<Vec<_>>();
+    let contents = messages
+        .iter()
+        .filter(|m| m.role != ChatCompletionRole::System)
+        .map(|m| Content {
+            role: Some(match m.role {
+                ChatCompletionRole::Assistant => "model".to_string(),
+                _ => "user".to_string(),
+            }),
+            parts: vec![Part {
+                text: m.content.clone(),
+            }],
+        })
+        .collect();
+    let instruction = (!system.is_empty()).then_some(Content {
+        role: None,
+        parts: system,
+    });
+    (instruction, contents)
+}
+
+/// the messages of the contents, the reverse of to_contents
+fn from_contents(
+    instruction: Option<Content>,
+    contents: Vec<Content>,
+) -> Vec<ChatCompletionMessage> {
+    let system = instruction
+        .into_iter()
+        .flat_map(|c| c.parts)
+        .map(|p| ChatCompletionMessage::new(ChatCompletionRole::System, &p.text));
+    let messages = contents.into_iter().map(|c| {
+        let role = match c.role.as_deref() {
+            Some("model") => ChatCompletionRole::Assistant,
+            _ => ChatCompletionRole::User,
+        };
+        let text = c.parts.into_iter().map(|p| p.text).collect::<String>();
+        ChatCompletionMessage::new(role, &text)
+    });
+    system.chain(messages).collect()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerateContentRequest {
+    pub contents: Vec<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_instruction: Option<Content>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub safety_settings: Vec<SafetySetting>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generation_config: Option<GenerationConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Content {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub parts: Vec<Part>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Part {
+    #[serde(default)]
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SafetySetting {
+    pub category: String,
+    pub threshold: HarmBlockThreshold,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GenerationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerateContentResponse {
+    #[serde(default)]
+    pub candidates: Vec<Candidate>,
+    pub prompt_feedback: Option<PromptFeedback>,
+    pub usage_metadata: Option<UsageMetadata>,
+}
+
+impl GenerateContentResponse {
+    /// the text of the first candidate, None if it is blocked
+    pub fn text(&self) -> Option<String> {
+        let content = self.candidates.first()?.content.as_ref()?;
+        Some(content.parts.iter().map(|p| p.text.as_str()).collect())
+    }
+
+    fn block_reason(&self) -> String {
+        self.prompt_feedback
+            .as_ref()
+            .and_then(|f| f.block_reason.clone())
+            .or_else(|| self.candidates.first()?.finish_reason.clone())
+            .unwrap_or_else(|| "no candidate".to_string())
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Candidate {
+    pub content: Option<Content>,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptFeedback {
+    pub block_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageMetadata {
+    #[serde(default)]
+    pub prompt_token_count: u32,
+    #[serde(default)]
+    pub candidates_token_count: u32,
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        from_contents, to_contents, GeminiOptions, GenerateContentRequest, GenerateContentResponse,
+    };
+    use crate::translators::chatgpt::{ChatCompletionMessage, ChatCompletionRole};
+
+    #[test]
+    fn test_gemini_request() {
+        let opt: GeminiOptions = toml::from_str(
+            r#"
+            api_keys = ["key"]
+            max_concurrent = 1
+            safety_threshold = "BLOCK_NONE"
+            safety_settings = { HARM_CATEGORY_HARASSMENT = "BLOCK_ONLY_HIGH" }
+            "#,
+        )
+        .unwrap();
+        let (instruction, contents) = to_contents(&[
+            ChatCompletionMessage::new(ChatCompletionRole::System, "Translate."),
+            ChatCompletionMessage::new(ChatCompletionRole::User, "(1) 你好"),
+            ChatCompletionMessage::new(ChatCompletionRole::Assistant, "(1) Hello"),
+        ]);
+        let request = GenerateContentRequest {
+            contents,
+            system_instruction: instruction,
+            safety_settings: opt.safety_settings(),
+            generation_config: None,
+        };
+        // the preview shows the messages back
+        let messages = from_contents(request.system_instruction.clone(), request.contents.clone());
+        assert_eq!(messages[0].content, "Translate.");
+        assert_eq!(messages[2].role, ChatCompletionRole::Assistant);
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["systemInstruction"]["parts"][0]["text"], "Translate.");
+        assert_eq!(json["contents"][1]["role"], "model");
+        assert_eq!(json["safetySettings"].as_array().unwrap().len(), 4);
+        assert_eq!(
+            json["safetySettings"][0]["category"],
+            "HARM_CATEGORY_DANGEROUS_CONTENT"
+        );
+        assert_eq!(json["safetySettings"][0]["threshold"], "BLOCK_NONE");
+        assert_eq!(json["safetySettings"][1]["threshold"], "BLOCK_ONLY_HIGH");
+
+        let resp: GenerateContentResponse = serde_json::from_str(
+            r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "(1) Hello"}]},
+            "finishReason": "STOP"}], "usageMetadata": {"promptTokenCount": 5, "candidatesTokenCount": 3}}"#,
+        )
+        .unwrap();
+        assert_eq!(resp.text().as_deref(), Some("(1) Hello"));
+        let blocked: GenerateContentResponse =
+            serde_json::from_str(r#"{"promptFeedback": {"blockReason": "SAFETY"}}"#).unwrap();
+        assert_eq!(blocked.text(), None);
+        assert_eq!(blocked.block_reason(), "SAFETY");
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use regex::Regex;
+
+use crate::{
+    error::Error,
+    i18n::Msg,
+    qe::{source, source_capture},
+    t,
+    textures::Textures,
+    Configuration,
+};
+
+use super::chatgpt::{complete, ChatCompletionMessage, ChatCompletionRole, Grouping};
+use super::translator::Batchizer;
+
+/// precedes the glossary of the terms found in the batch
+const GLOSSARY_HEADER: &str = "Glossary:\n";
+
+/// the most frequent candidates sent to the model
+const MAX_CANDIDATES: usize = 300;
+
+const GLOSSARY_PROMPT: &str = "You are building the glossary of a {{from}} game or novel translated to {{to}}. \
+Every line is a term found in the text with its count. For the proper nouns (the names of the characters, \
+places, items and skills), give their {{to}} renderings, transliterated or romanized consistently. \
+Reply every line as `term = rendering`, in the same order, reply `term = -` for the terms that are not proper nouns.";
+
+/// the glossary section of the terms found in the sources, empty if none
+pub fn glossary_section(glossary: &BTreeMap<String, String>, sources: &[String]) -> String {
+    let terms = glossary
+        .iter()
+        .filter(|(term, _)| sources.iter().any(|s| s.contains(term.as_str())))
+        .map(|(term, translation)| format!("{} = {}\n", term, translation))
+        .collect::<String>();
+    if terms.is_empty() {
+        return terms;
+    }
+    format!("{}{}", GLOSSARY_HEADER, terms)
+}
+
+/// the glossary file of the input file, glossary_path or file.glossary.toml
+pub fn glossary_path(cfg: &Configuration, file: &str) -> PathBuf {
+    cfg.glossary_path
+        .clone()
+        .unwrap_or_else(|| cfg.dirs().glossary(file))
+}
+
+/// read the glossary file, the terms without a rendering are left out
+pub fn load_glossary(cfg: &Configuration, file: &str) -> Result<BTreeMap<String, String>> {
+    let glossary = read_glossary(&glossary_path(cfg, file))?;
+    // the terms not rendered yet are left empty in the file
+    Ok(glossary
+        .into_iter()
+        .filter(|(_, v)| !v.is_empty())
+        .collect())
+}
+
+/// the entries of the glossary file, empty if it does not exist
+fn read_glossary(path: &Path) -> Result<BTreeMap<String, String>> {
+    let Ok(content) = fs::read_to_string(path) else {
+        return Ok(BTreeMap::new());
+    };
+    let glossary = toml::from_str::<BTreeMap<String, String>>(&content).map_err(|e| {
+        Error::Config(format!(
+            "the glossary {} is not valid: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    Ok(glossary)
+}
+
+/// extract the probable proper nouns of the file, ask the model for their renderings once, and
+/// write them to the glossary file to be edited, the entries already in the file are kept
+pub async fn build_glossary(
+    cfg: &Configuration,
+    textures: &Textures,
+    output: Option<String>,
+    min_count: usize,
+) -> Result<PathBuf> {
+    let path = output
+        .map(PathBuf::from)
+        .unwrap_or_else(|| glossary_path(cfg, &textures.name));
+    let capture = source_capture(cfg)?;
+    let sources = textures
+        .lines
+        .iter()
+        .filter(|l| l.needs_translation())
+        .map(|l| source(&capture, &l.content))
+        .collect::<Vec<_>>();
+    let speaker = match &cfg.batchizer_opt.grouping {
+        Grouping::SpeakerTag(regex) => Some(crate::error::new_regex("speaker_tag", regex)?),
+        _ => None,
+    };
+    let mut candidates = extract_candidates(&sources, speaker.as_ref(), min_count);
+    candidates.truncate(MAX_CANDIDATES);
+    // the entries edited by the user are kept as they are
+    let existing = read_glossary(&path)?;
+    candidates.retain(|(term, _)| !existing.contains_key(term));
+    println!(
+        "[Glossary] {}",
+        t!(Msg::GlossaryCandidates, candidates.len())
+    );
+
+    let renderings = match (&cfg.chatgpt_opt, candidates.is_empty()) {
+        (Some(opt), false) => {
+            let (from, to) = (cfg.lang_from.to_name(), cfg.lang_to.to_name());
+            let prompt = GLOSSARY_PROMPT
+                .replace("{{from}}", from)
+                .replace("{{to}}", to);
+            let terms = candidates
+                .iter()
+                .map(|(term, count)| format!("{} ({})\n", term, count))
+                .collect::<String>();
+            let messages = vec![
+                ChatCompletionMessage::new(ChatCompletionRole::System, &prompt),
+                ChatCompletionMessage::new(ChatCompletionRole::User, &terms),
+            ];
+            let api = opt
+                .api_pool
+                .first()
+                .ok_or_else(|| Error::Config("chatgpt_opt.api_pool is empty".to_string()))?;
+            parse_renderings(&complete(api, opt.model.as_deref(), messages).await?)
+        }
+        _ => HashMap::new(),
+    };
+
+    let mut content = String::from(
+        "# the glossary sent with the batches containing the terms, `term = rendering`,\n\
+         # edit the renderings, the empty ones are ignored\n",
+    );
+    for (term, rendering) in &existing {
+        content.push_str(&format!("{} = {}\n", quote(term), quote(rendering)));
+    }
+    let mut proposed = 0;
+    for (term, count) in &candidates {
+        match renderings.get(term) {
+            // not a proper noun
+            Some(None) => continue,
+            Some(Some(rendering)) => {
+                proposed += 1;
+                content.push_str(&format!(
+                    "{} = {} # {}\n",
+                    quote(term),
+                    quote(rendering),
+                    count
+                ));
+            }
+            None => content.push_str(&format!("{} = \"\" # {}\n", quote(term), count)),
+        }
+    }
+    fs::write(&path, content)?;
+    let proposed = t!(Msg::GlossaryProposed, proposed, path.display());
+    println!("[Glossary] {}", proposed);
+    Ok(path)
+}
+
+/// the katakana words, the kanji words and the speaker tags found in at least min_count lines,
+/// the most frequent first
+pub fn extract_candidates(
+    sources: &[String],
+    speaker: Option<&Regex>,
+    min_count: usize,
+) -> Vec<(String, usize)> {
+    let katakana = Regex::new(r"[ァ-ヺ][ァ-ヺー・]+").unwrap();
+    let kanji = Regex::new(r"[一-龯々]{2,4}").unwrap();
+    let bracket = Regex::new(r"【([^】]{1,12})】").unwrap();
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let mut order = vec![];
+    for source in sources {
+        // a term is counted once per line
+        let mut terms = katakana
+            .find_iter(source)
+            .chain(kanji.find_iter(source))
+            .map(|m| m.as_str())
+            .collect::<Vec<_>>();
+        for regex in [Some(&bracket), speaker].into_iter().flatten() {
+            for caps in regex.captures_iter(source) {
+                terms.push(caps.get(1).unwrap_or_else(|| caps.get(0).unwrap()).as_str());
+            }
+        }
+        let mut seen = HashSet::new();
+        for term in terms {
+            let term = term.trim_matches(['・', 'ー']);
+            if term.chars().count() < 2 || !seen.insert(term) {
+                continue;
+            }
+            let n = counts.entry(term.to_string()).or_insert_with(|| {
+                order.push(term.to_string());
+                0
+            });
+            *n += 1;
+        }
+    }
+    let mut candidates = order
+        .into_iter()
+        .map(|term| {
+            let n = counts[&term];
+            (term, n)
+        })
+        .filter(|(_, n)| *n >= min_count)
+        .collect::<Vec<_>>();
+    // stable, the first found stays first among the same count
+    candidates.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+    candidates
+}
+
+/// the `term = rendering` lines of the reply, None for the terms answered `-`
+fn parse_renderings(reply: &str) -> HashMap<String, Option<String>> {
+    reply
+        .lines()
+        .filter_map(|line| {
+            let (term, rendering) = line.split_once('=')?;
+            let term = term.trim().trim_matches(['"', '`']);
+            // the count may be echoed back
+            let term = term.split(" (").next().unwrap_or(term).trim();
+            let rendering = rendering.trim().trim_matches(['"', '`']).trim();
+            if term.is_empty() || rendering.is_empty() {
+                return None;
+            }
+            let rendering = (rendering != "-").then(|| rendering.to_string());
+            Some((term.to_string(), rendering))
+        })
+        .collect()
+}
+
+fn quote(s: &str) -> String {
+    toml::Value::String(s.to_string()).to_string()
+}
+
+/// prepend the glossary of the terms found in the batch to every batch of the inner batchizer
+pub struct GlossaryBatchizer<B> {
+    pub inner: B,
+    pub glossary: BTreeMap<String, String>,
+}
+
+impl<B: Batchizer<ChatCompletionMessage>> Batchizer<ChatCompletionMessage>
+    for GlossaryBatchizer<B>
+{
+    fn extract(&self, content: &str) -> Option<String> {
+        self.inner.extract(content)
+    }
+    fn max_tokens(&self) -> usize {
+        self.inner.max_tokens()
+    }
+    fn batchize_with(
+        &self,
+        textures: &Textures,
+        start: usize,
+        end: Option<usize>,
+        budget: usize,
+    ) -> (Vec<ChatCompletionMessage>, usize) {
+        let (mut batch, size) = self.inner.batchize_with(textures, start, end, budget);
+        if batch.is_empty() || self.glossary.is_empty() {
+            return (batch, size);
+        }
+        let sources = textures
+            .batch_lines((start, start + size - 1))
+            .into_iter()
+            .filter_map(|i| self.inner.extract(&textures.lines[i].content))
+            .collect::<Vec<_>>();
+        let section = glossary_section(&self.glossary, &sources);
+        if section.is_empty() {
+            return (batch, size);
+        }
+        if let Some(message) = batch
+            .iter_mut()
+            .find(|m| m.role == ChatCompletionRole::User)
+        {
+            message.content = format!("{}\n{}", section, message.content);
+        }
+        (batch, size)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{extract_candidates, parse_renderings};
+
+    #[test]
+    fn test_extract_candidates() {
+        let sources = [
+            "【ピノ】ゼペットさん、おはよう",
+            "【ピノ】ゼペットの工房へ行こう",
+            "【ゼペット】ピノ、待ちなさい",
+        ]
+        .map(String::from);
+        let candidates = extract_candidates(&sources, None, 2);
+        assert_eq!(
+            candidates,
+            vec![("ピノ".to_string(), 3), ("ゼペット".to_string(), 3)]
+        );
+        let renderings = parse_renderings("ピノ (4) = Pino\n`ゼペット` = \"Geppetto\"\n工房 = -\n");
+        assert_eq!(renderings["ピノ"].as_deref(), Some("Pino"));
+        assert_eq!(renderings["ゼペット"].as_deref(), Some("Geppetto"));
+        assert_eq!(renderings["工房"], None);
+    }
+}
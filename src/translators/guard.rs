@@ -0,0 +1,71 @@
+/// strip the chatter the model puts around the translation, before the response_cleanup runs
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResponseGuard {
+    prefixes: Vec<String>,
+    suffixes: Vec<String>,
+}
+
+impl ResponseGuard {
+    pub fn new(prefixes: &[String], suffixes: &[String]) -> Self {
+        let non_empty = |v: &[String]| v.iter().filter(|s| !s.is_empty()).cloned().collect();
+        Self {
+            prefixes: non_empty(prefixes),
+            suffixes: non_empty(suffixes),
+        }
+    }
+
+    /// a reply starting with a prefix loses it, or its whole first line when the line is a lead-in
+    /// ending with a colon, e.g. `Sure, here is the translation:`. the last lines starting with a
+    /// suffix are dropped, e.g. `是否违规: 否`, or the suffix itself at the end of the reply
+    pub fn strip(&self, reply: &str) -> String {
+        let mut reply = reply.trim_start();
+        while let Some(prefix) = self.prefixes.iter().find(|p| reply.starts_with(p.as_str())) {
+            let (first, rest) = reply.split_once('\n').unwrap_or((reply, ""));
+            reply = match first.trim_end().ends_with([':', '：']) {
+                true => rest,
+                false => &reply[prefix.len()..],
+            }
+            .trim_start();
+        }
+        loop {
+            reply = reply.trim_end();
+            let (rest, last) = reply.rsplit_once('\n').unwrap_or(("", reply));
+            if self
+                .suffixes
+                .iter()
+                .any(|s| last.trim_start().starts_with(s.as_str()))
+            {
+                reply = rest;
+            } else if let Some(suffix) = self.suffixes.iter().find(|s| reply.ends_with(s.as_str()))
+            {
+                reply = &reply[..reply.len() - suffix.len()];
+            } else {
+                break;
+            }
+        }
+        reply.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ResponseGuard;
+
+    #[test]
+    fn test_strip() {
+        let guard = ResponseGuard::new(
+            &["翻译为".to_string(), "Sure, here is".to_string()],
+            &["是否违规".to_string(), "Hope this helps!".to_string()],
+        );
+        assert_eq!(
+            guard.strip("翻译为:\n1. \"......\"\n2. 我非常兴奋。\n是否违规: 否"),
+            "1. \"......\"\n2. 我非常兴奋。"
+        );
+        assert_eq!(
+            guard.strip("Sure, here is the translation:\n\n(1) Good morning. Hope this helps!"),
+            "(1) Good morning."
+        );
+        assert_eq!(guard.strip("翻译为 (1) 早上好"), "(1) 早上好");
+        assert_eq!(guard.strip("(1) 早上好"), "(1) 早上好");
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::VecDeque;
+
+use super::chatgpt::{ChatCompletionMessage, ChatCompletionRole, Tokenizer};
+
+/// the previous batches of a worker and their replies, sent before its next batch so the
+/// translation goes on like one conversation, the oldest exchanges are dropped beyond max_tokens
+#[derive(Debug, Default)]
+pub struct Conversation {
+    max_tokens: usize,
+    /// the user messages and the reply of every batch, with their tokens
+    exchanges: VecDeque<(Vec<ChatCompletionMessage>, usize)>,
+}
+
+impl Conversation {
+    pub fn new(max_tokens: usize) -> Self {
+        Self {
+            max_tokens,
+            exchanges: VecDeque::new(),
+        }
+    }
+
+    /// the batch after the history, the prompts of an override stay before the history
+    pub fn with_history(&self, batch: &[ChatCompletionMessage]) -> Vec<ChatCompletionMessage> {
+        let prompts = batch
+            .iter()
+            .take_while(|m| m.role == ChatCompletionRole::System)
+            .count();
+        let mut messages = batch[..prompts].to_vec();
+        messages.extend(self.exchanges.iter().flat_map(|(e, _)| e.iter().cloned()));
+        messages.extend(batch[prompts..].iter().cloned());
+        messages
+    }
+
+    /// keep the batch and its accepted reply, then drop the oldest exchanges beyond max_tokens
+    pub fn push(&mut self, batch: &[ChatCompletionMessage], reply: &str) {
+        let mut exchange = batch
+            .iter()
+            .filter(|m| m.role == ChatCompletionRole::User)
+            .cloned()
+            .collect::<Vec<_>>();
+        exchange.push(ChatCompletionMessage::new(
+            ChatCompletionRole::Assistant,
+            reply,
+        ));
+        // the approximate tokens, the history is only trimmed by them
+        let tokens = exchange
+            .iter()
+            .map(|m| Tokenizer::Chars.count(&m.content))
+            .sum();
+        self.exchanges.push_back((exchange, tokens));
+        while self.tokens() > self.max_tokens {
+            self.exchanges.pop_front();
+        }
+    }
+
+    fn tokens(&self) -> usize {
+        self.exchanges.iter().map(|(_, tokens)| tokens).sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Conversation;
+    use crate::translators::chatgpt::{ChatCompletionMessage, ChatCompletionRole};
+
+    #[test]
+    fn test_conversation() {
+        let user = |content: &str| ChatCompletionMessage::new(ChatCompletionRole::User, content);
+        let mut conversation = Conversation::new(10);
+        conversation.push(&[user("(1) おはよう")], "(1) Good morning");
+        conversation.push(&[user("(1) また明日")], "(1) See you");
+        let batch = [
+            ChatCompletionMessage::new(ChatCompletionRole::System, "Translate."),
+            user("(1) はい"),
+        ];
+        let messages = conversation.with_history(&batch);
+        let contents = messages
+            .iter()
+            .map(|m| m.content.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            contents,
+            ["Translate.", "(1) また明日", "(1) See you", "(1) はい"]
+        );
+        assert_eq!(messages[2].role, ChatCompletionRole::Assistant);
+        // an exchange over the budget leaves nothing
+        conversation.push(&[user(&"あ".repeat(20))], "a");
+        assert_eq!(conversation.with_history(&[]).len(), 0);
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// the requests time out after 3 minutes by default
+const DEFAULT_TIMEOUT_SECS: u64 = 60 * 3;
+
+/// the connections of the requests, example: {proxy = "http://127.0.0.1:7890", pool_size = 16,
+/// version = "http2"}
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HttpOptions {
+    /// the proxy of the requests, default is the proxy of the environment
+    pub proxy: Option<String>,
+    /// the idle connections kept for reuse per host, default is unlimited
+    pub pool_size: Option<usize>,
+    /// the seconds an idle connection is kept, default is 90
+    pub pool_idle_timeout: Option<u64>,
+    /// the http version, auto negotiated by tls, http1 only, or http2 without negotiation
+    #[serde(default)]
+    pub version: HttpVersion,
+    /// the seconds between the http2 keep-alive pings, for the long requests behind a proxy
+    pub http2_keep_alive: Option<u64>,
+    /// the seconds of a request before it times out, default is 180
+    pub timeout: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpVersion {
+    #[default]
+    Auto,
+    Http1,
+    /// http2 with prior knowledge, e.g. a local server speaking h2c
+    Http2,
+}
+
+impl HttpOptions {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout.unwrap_or(DEFAULT_TIMEOUT_SECS))
+    }
+
+    fn build(&self) -> Result<reqwest::Client, Error> {
+        let mut builder = reqwest::ClientBuilder::new().timeout(self.timeout());
+        if let Some(proxy) = &self.proxy {
+            let proxy = reqwest::Proxy::all(proxy)
+                .map_err(|e| Error::Config(format!("http.proxy {} is not valid: {}", proxy, e)))?;
+            builder = builder.proxy(proxy);
+        }
+        if let Some(size) = self.pool_size {
+            builder = builder.pool_max_idle_per_host(size);
+        }
+        if let Some(secs) = self.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(Duration::from_secs(secs));
+        }
+        builder = match self.version {
+            HttpVersion::Auto => builder,
+            HttpVersion::Http1 => builder.http1_only(),
+            HttpVersion::Http2 => builder.http2_prior_knowledge(),
+        };
+        if let Some(secs) = self.http2_keep_alive {
+            builder = builder
+                .http2_keep_alive_interval(Duration::from_secs(secs))
+                .http2_keep_alive_while_idle(true);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+/// the http clients shared by the api clients of the same api url, so the workers reuse the
+/// connections instead of opening their own, the keys are sent with every request
+#[derive(Debug, Clone, Default)]
+pub struct ClientPool {
+    opt: HttpOptions,
+    clients: Arc<Mutex<HashMap<String, reqwest::Client>>>,
+}
+
+impl ClientPool {
+    pub fn new(opt: Option<&HttpOptions>) -> Self {
+        Self {
+            opt: opt.cloned().unwrap_or_default(),
+            clients: Default::default(),
+        }
+    }
+
+    /// the client of the api url, built on the first use
+    pub fn client(&self, api_url: &str) -> Result<reqwest::Client, Error> {
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(api_url) {
+            return Ok(client.clone());
+        }
+        let client = self.opt.build()?;
+        clients.insert(api_url.to_string(), client.clone());
+        Ok(client)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ClientPool, HttpOptions, HttpVersion};
+
+    #[test]
+    fn test_client_pool() {
+        let pool = ClientPool::new(None);
+        pool.client("https://api.openai.com/v1/chat/completions")
+            .unwrap();
+        pool.client("https://api.openai.com/v1/chat/completions")
+            .unwrap();
+        pool.client("http://127.0.0.1:8080/v1/chat/completions")
+            .unwrap();
+        assert_eq!(pool.clients.lock().unwrap().len(), 2);
+
+        let opt: HttpOptions =
+            toml::from_str("proxy = \"http://127.0.0.1:7890\"\npool_size = 4\nversion = \"http2\"")
+                .unwrap();
+        assert_eq!(opt.version, HttpVersion::Http2);
+        assert!(ClientPool::new(Some(&opt)).client("http://a").is_ok());
+        let bad = HttpOptions {
+            proxy: Some("::".to_string()),
+            ..Default::default()
+        };
+        assert!(ClientPool::new(Some(&bad)).client("http://a").is_err());
+    }
+}
@@ -0,0 +1,315 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use anyhow::Result;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::{error::Error, i18n::Msg, t, textures::TranslatedLine};
+
+use super::api_error::ApiError;
+
+/// the requests of a worker of the run and its api client, to tune max_concurrent and to find
+/// the slow keys of the api pool
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ClientMetrics {
+    pub worker: usize,
+    /// the masked api key
+    pub api: String,
+    pub requests: usize,
+    /// the requests sending a batch again after an error or a mismatch
+    pub retries: usize,
+    /// the count of the failed requests by category: timeout, connect, rate_limit, auth,
+    /// context_length, content_filter, server, client, decode or other
+    pub errors: BTreeMap<String, usize>,
+    pub latency_secs: f64,
+    /// the time from the first request sent to the last one answered
+    pub active_secs: f64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl ClientMetrics {
+    pub fn new(worker: usize, api: String) -> Self {
+        Self {
+            worker,
+            api,
+            ..Default::default()
+        }
+    }
+
+    pub fn avg_latency_secs(&self) -> f64 {
+        match self.requests {
+            0 => 0.0,
+            n => self.latency_secs / n as f64,
+        }
+    }
+
+    pub fn tokens_per_min(&self) -> f64 {
+        match self.active_secs > 0.0 {
+            true => (self.prompt_tokens + self.completion_tokens) as f64 * 60.0 / self.active_secs,
+            false => 0.0,
+        }
+    }
+
+    /// add the metrics of the same worker and api from another run, e.g. the next shard
+    pub fn add(&mut self, other: &ClientMetrics) {
+        self.requests += other.requests;
+        self.retries += other.retries;
+        for (category, count) in &other.errors {
+            *self.errors.entry(category.clone()).or_default() += count;
+        }
+        self.latency_secs += other.latency_secs;
+        self.active_secs += other.active_secs;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
+/// the metrics of the workers shared with the run, updated after every request so an
+/// interrupted run still reports them
+#[derive(Debug, Clone, Default)]
+pub struct Metrics(Arc<Mutex<Vec<ClientMetrics>>>);
+
+impl Metrics {
+    pub fn snapshot(&self) -> Vec<ClientMetrics> {
+        let mut clients = self.0.lock().unwrap().clone();
+        clients.sort_by_key(|c| c.worker);
+        clients
+    }
+
+    fn update(&self, metrics: &ClientMetrics) {
+        let mut clients = self.0.lock().unwrap();
+        match clients.iter_mut().find(|c| c.worker == metrics.worker) {
+            Some(c) => *c = metrics.clone(),
+            None => clients.push(metrics.clone()),
+        }
+    }
+}
+
+/// the recorder of a worker, owned by its task
+pub struct WorkerMetrics {
+    metrics: ClientMetrics,
+    shared: Metrics,
+    started: Option<Instant>,
+}
+
+impl WorkerMetrics {
+    pub fn new(shared: Metrics, worker: usize, api: String) -> Self {
+        Self {
+            metrics: ClientMetrics::new(worker, api),
+            shared,
+            started: None,
+        }
+    }
+
+    /// record a request sent at sent, retry if it sends the batch of the last request again
+    pub fn record(&mut self, sent: Instant, retry: bool, result: &Result<TranslatedLine>) {
+        let now = Instant::now();
+        let started = *self.started.get_or_insert(sent);
+        let m = &mut self.metrics;
+        m.requests += 1;
+        m.retries += retry as usize;
+        m.latency_secs += now.duration_since(sent).as_secs_f64();
+        m.active_secs = now.duration_since(started).as_secs_f64();
+        match result {
+            Ok(translated) => {
+                if let Some(usage) = &translated.usage {
+                    m.prompt_tokens += usage.prompt_tokens as u64;
+                    m.completion_tokens += usage.completion_tokens as u64;
+                }
+            }
+            Err(err) => *m.errors.entry(error_category(err).to_string()).or_default() += 1,
+        }
+        self.shared.update(m);
+    }
+}
+
+/// the category of a failed request, by the api error, the http error or the status of the response
+pub fn error_category(err: &anyhow::Error) -> &'static str {
+    if let Some(e) = err.downcast_ref::<ApiError>() {
+        return e.category();
+    }
+    if let Some(e) = err.downcast_ref::<reqwest::Error>() {
+        if e.is_timeout() {
+            return "timeout";
+        }
+        if e.is_connect() {
+            return "connect";
+        }
+    }
+    if err.downcast_ref::<serde_json::Error>().is_some() {
+        return "decode";
+    }
+    let status = Regex::new(r"status: (\d{3})").unwrap();
+    match status
+        .captures(&err.to_string())
+        .and_then(|cap| cap[1].parse::<u16>().ok())
+    {
+        Some(429) => "rate_limit",
+        Some(500..=599) => "server",
+        Some(400..=499) => "client",
+        _ => "other",
+    }
+}
+
+/// print the table of the clients, and write them to metrics_file if set
+pub fn report_clients(clients: &[ClientMetrics], metrics_file: Option<&Path>) -> Result<()> {
+    if clients.is_empty() {
+        return Ok(());
+    }
+    println!("{}", format_table(clients));
+    if let Some(path) = metrics_file {
+        // written aside then renamed, the collector never reads a partial file
+        let tmp = path.with_extension("prom.tmp");
+        fs::write(&tmp, format_prometheus(clients)).map_err(Error::io(&tmp.to_string_lossy()))?;
+        fs::rename(&tmp, path).map_err(Error::io(&path.to_string_lossy()))?;
+        println!("{}", t!(Msg::Metrics, path.display()));
+    }
+    Ok(())
+}
+
+fn format_errors(errors: &BTreeMap<String, usize>) -> String {
+    match errors.is_empty() {
+        true => "-".to_string(),
+        false => errors
+            .iter()
+            .map(|(category, count)| format!("{} {}", category, count))
+            .collect::<Vec<_>>()
+            .join(", "),
+    }
+}
+
+fn format_table(clients: &[ClientMetrics]) -> String {
+    let mut table = format!(
+        "{:<6} {:<10} {:>8} {:>8} {:>12} {:>10}  errors\n",
+        "worker", "api", "requests", "retries", "avg latency", "tokens/min"
+    );
+    for c in clients {
+        let _ = writeln!(
+            table,
+            "{:<6} {:<10} {:>8} {:>8} {:>11.2}s {:>10.0}  {}",
+            c.worker,
+            c.api,
+            c.requests,
+            c.retries,
+            c.avg_latency_secs(),
+            c.tokens_per_min(),
+            format_errors(&c.errors)
+        );
+    }
+    table.trim_end().to_string()
+}
+
+/// the prometheus text format, for the textfile collector of node_exporter
+fn format_prometheus(clients: &[ClientMetrics]) -> String {
+    let mut text = String::new();
+    let labels = |c: &ClientMetrics| format!("worker=\"{}\",api=\"{}\"", c.worker, c.api);
+    let mut family = |name: &str, kind: &str, help: &str, values: Vec<(String, String)>| {
+        let _ = writeln!(text, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+        for (labels, value) in values {
+            let _ = writeln!(text, "{}{{{}}} {}", name, labels, value);
+        }
+    };
+    let each = |f: &dyn Fn(&ClientMetrics) -> String| {
+        clients
+            .iter()
+            .map(|c| (labels(c), f(c)))
+            .collect::<Vec<_>>()
+    };
+    family(
+        "lottr_requests_total",
+        "counter",
+        "The requests sent by the worker.",
+        each(&|c| c.requests.to_string()),
+    );
+    family(
+        "lottr_retries_total",
+        "counter",
+        "The requests sending a batch again after an error or a mismatch.",
+        each(&|c| c.retries.to_string()),
+    );
+    family(
+        "lottr_request_duration_seconds_sum",
+        "counter",
+        "The total latency of the requests.",
+        each(&|c| c.latency_secs.to_string()),
+    );
+    family(
+        "lottr_tokens_total",
+        "counter",
+        "The prompt and the completion tokens.",
+        clients
+            .iter()
+            .flat_map(|c| {
+                [
+                    ("prompt", c.prompt_tokens),
+                    ("completion", c.completion_tokens),
+                ]
+                .map(|(kind, tokens)| {
+                    (
+                        format!("{},kind=\"{}\"", labels(c), kind),
+                        tokens.to_string(),
+                    )
+                })
+            })
+            .collect(),
+    );
+    family(
+        "lottr_errors_total",
+        "counter",
+        "The failed requests by category.",
+        clients
+            .iter()
+            .flat_map(|c| {
+                c.errors.iter().map(|(category, count)| {
+                    (
+                        format!("{},category=\"{}\"", labels(c), category),
+                        count.to_string(),
+                    )
+                })
+            })
+            .collect(),
+    );
+    text
+}
+
+#[cfg(test)]
+mod test {
+    use super::{error_category, format_prometheus, ClientMetrics};
+
+    #[test]
+    fn test_client_metrics() {
+        let mut c = ClientMetrics::new(0, "***abcd".to_string());
+        c.requests = 4;
+        c.retries = 1;
+        c.latency_secs = 10.0;
+        c.active_secs = 30.0;
+        c.prompt_tokens = 800;
+        c.completion_tokens = 700;
+        c.errors.insert("rate_limit".to_string(), 1);
+        assert_eq!(c.avg_latency_secs(), 2.5);
+        assert_eq!(c.tokens_per_min(), 3000.0);
+        let text = format_prometheus(&[c]);
+        assert!(text.contains("lottr_requests_total{worker=\"0\",api=\"***abcd\"} 4\n"));
+        assert!(text.contains(
+            "lottr_errors_total{worker=\"0\",api=\"***abcd\",category=\"rate_limit\"} 1\n"
+        ));
+        assert!(
+            text.contains("lottr_tokens_total{worker=\"0\",api=\"***abcd\",kind=\"prompt\"} 800\n")
+        );
+
+        let err = anyhow::anyhow!("status: 429 Too Many Requests, response: {{}}");
+        assert_eq!(error_category(&err), "rate_limit");
+        let err = anyhow::anyhow!("status: 502 Bad Gateway, response: ");
+        assert_eq!(error_category(&err), "server");
+        let err: anyhow::Error = serde_json::from_str::<u8>("x").unwrap_err().into();
+        assert_eq!(error_category(&err), "decode");
+    }
+}
@@ -1,6 +1,50 @@
+mod adaptive;
+mod api_error;
+mod budget;
 mod chatgpt;
+mod gemini;
+mod glossary;
+mod guard;
+mod history;
+mod http;
+mod metrics;
+mod profile;
+mod prompt_override;
+mod protect;
+mod queue;
+mod refine;
+mod retrieval;
+mod routing;
+mod shared_cache;
+mod style;
 mod translator;
 
+pub use budget::BudgetOptions;
+pub use chatgpt::complete;
+pub use chatgpt::load_prompts;
+pub use chatgpt::ping_api;
+pub use chatgpt::ChatCompletionMessage;
+pub use chatgpt::ChatCompletionRole;
 pub use chatgpt::ChatGPTOptions;
+pub use chatgpt::Grouping;
+pub use chatgpt::TokenizerKind;
+pub use gemini::GeminiOptions;
+pub use glossary::build_glossary;
+pub use glossary::load_glossary;
+pub use metrics::report_clients;
+pub use metrics::ClientMetrics;
+pub use profile::ModelProfile;
+pub use prompt_override::PromptOverride;
+pub use queue::PriorityOptions;
+pub use refine::RefineOptions;
+pub use retrieval::RetrievalOptions;
+pub use routing::RoutingOptions;
+pub use style::StyleOptions;
+pub use translator::bench_batchizer;
+pub use translator::count_batches;
+pub use translator::count_tokens;
+pub use translator::estimate_batches;
+pub use translator::refine;
 pub use translator::translate;
+pub use translator::Progress;
 pub use translator::Translator;
@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+use toml::Table;
+
+use super::chatgpt::{ChatCompletionMessage, ChatCompletionRole};
+
+const SAKURA_SYSTEM_PROMPT: &str =
+    "你是一个轻小说翻译模型，可以流畅通顺地以日本轻小说的风格将日文翻译成简体中文，\
+并联系上下文正确使用人称代词，不擅自添加原文中没有的代词。";
+
+/// precedes the lines of a batch
+const SAKURA_USER_HEADER: &str = "将下面的日文文本翻译成中文：\n";
+
+/// the translated lines, one per line, without numbering
+const SAKURA_OUTPUT: &str = r#"
+response_cleanup = []
+[[line_capture]]
+usage = {capture = 1}
+regex = '(.+)'
+[[post_replace]]
+usage = {replace = ""}
+regex = '"'
+"#;
+
+/// the built-in prompt and line protocol of the local translation models,
+/// they are trained with a fixed prompt and answer the plain lines
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ModelProfile {
+    /// Sakura-13B and the GalTransl models, japanese to simplified chinese
+    #[serde(rename = "sakura")]
+    Sakura,
+}
+
+impl ModelProfile {
+    /// the system prompt the model is trained with
+    pub fn prompts(&self) -> Vec<ChatCompletionMessage> {
+        match self {
+            ModelProfile::Sakura => vec![ChatCompletionMessage::new(
+                ChatCompletionRole::System,
+                SAKURA_SYSTEM_PROMPT,
+            )],
+        }
+    }
+
+    /// a line of the batch, sent without numbering
+    pub fn format_line(&self, line: &str) -> String {
+        match self {
+            ModelProfile::Sakura => format!("{}\n", line.replace('\n', " ")),
+        }
+    }
+
+    /// the user message of the batch lines
+    pub fn user_content(&self, lines: &str) -> String {
+        match self {
+            ModelProfile::Sakura => format!("{}{}", SAKURA_USER_HEADER, lines.trim_end()),
+        }
+    }
+
+    /// the sampling recommended for the model, (temperature, top_p)
+    pub fn sampling(&self) -> (f32, f32) {
+        match self {
+            ModelProfile::Sakura => (0.1, 0.3),
+        }
+    }
+
+    /// the output stages parsing the answer, override the configured ones
+    pub fn table(&self) -> Table {
+        match self {
+            ModelProfile::Sakura => SAKURA_OUTPUT.parse::<Table>().unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ModelProfile;
+    use crate::{outputs::LineExtractor, Configuration};
+
+    #[test]
+    fn test_sakura_output() {
+        let mut table = include_str!("../../assets/options_text.toml")
+            .parse::<toml::Table>()
+            .unwrap();
+        table.extend(ModelProfile::Sakura.table());
+        let cfg: Configuration = toml::Value::Table(table).try_into().unwrap();
+        let extractor = LineExtractor::new(&cfg).unwrap();
+        assert_eq!(
+            extractor.extract("你好\n再见(1)\n"),
+            vec!["你好", "再见(1)"]
+        );
+        assert_eq!(
+            ModelProfile::Sakura.user_content(&ModelProfile::Sakura.format_line("こんにちは")),
+            "将下面的日文文本翻译成中文：\nこんにちは"
+        );
+    }
+}
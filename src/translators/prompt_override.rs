@@ -0,0 +1,201 @@
+use anyhow::Result;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{textures::Textures, Configuration};
+
+use super::chatgpt::{load_prompts, system_prompts, ChatCompletionMessage, ChatCompletionRole};
+use super::translator::Batchizer;
+
+/// the prompts of the files or the line ranges of other content than the rest, e.g. the menus or
+/// the H-scenes, the first override matching a batch replaces the prompts of the translator
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PromptOverride {
+    /// the globs of the file names, `*` and `?`, e.g. "*menu*", every file if empty
+    #[serde(default)]
+    pub files: Vec<String>,
+    /// the line ranges, a batch starting in one of them uses the prompts, every line if empty
+    #[serde(default)]
+    pub ranges: Vec<(usize, usize)>,
+    /// the prompts, the same format as chatgpt_opt.prompt_path
+    pub prompt_path: Option<String>,
+    /// the system prompt written in the config, see chatgpt_opt.system_prompt
+    pub system_prompt: Option<String>,
+}
+
+impl PromptOverride {
+    /// whether the file name matches one of the globs
+    pub fn matches_file(&self, file: &str) -> bool {
+        let name = std::path::Path::new(file)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| file.to_string());
+        self.files.is_empty()
+            || self
+                .files
+                .iter()
+                .any(|glob| glob_regex(glob).is_match(&name))
+    }
+
+    pub fn prompts(&self, from: &str, to: &str) -> Result<Vec<ChatCompletionMessage>> {
+        match (&self.prompt_path, &self.system_prompt) {
+            (Some(path), _) => load_prompts(path, from, to),
+            (None, Some(prompt)) => Ok(system_prompts(prompt, from, to)),
+            (None, None) => Err(anyhow::anyhow!(
+                "a prompt override needs prompt_path or system_prompt"
+            )),
+        }
+    }
+}
+
+/// the whole name matches the glob
+fn glob_regex(glob: &str) -> Regex {
+    let pattern = regex::escape(glob).replace("\\*", ".*").replace("\\?", ".");
+    Regex::new(&format!("(?i)^{}$", pattern)).unwrap()
+}
+
+/// the line ranges of an override and its prompts
+type RangePrompts = (Vec<(usize, usize)>, Vec<ChatCompletionMessage>);
+
+/// put the prompts of the override matching the batch before it, the translators send them
+/// instead of their own prompts
+pub struct PromptBatchizer<B> {
+    pub inner: B,
+    /// the ranges and the prompts of the overrides matching the file
+    pub overrides: Vec<RangePrompts>,
+}
+
+impl<B> PromptBatchizer<B> {
+    /// the overrides of the file, the style is rendered into their system prompts like into the
+    /// prompts of the translator
+    pub fn new(inner: B, cfg: &Configuration, file: &str) -> Result<Self> {
+        let (from, to) = (cfg.lang_from.to_name(), cfg.lang_to.to_name());
+        let overrides = cfg
+            .prompt_overrides
+            .iter()
+            .filter(|o| o.matches_file(file))
+            .map(|o| {
+                let prompts = o.prompts(from, to)?;
+                let prompts = match &cfg.style {
+                    Some(style) => style.apply(prompts, to),
+                    None => prompts,
+                };
+                Ok((o.ranges.clone(), prompts))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { inner, overrides })
+    }
+}
+
+impl<B: Batchizer<ChatCompletionMessage>> Batchizer<ChatCompletionMessage> for PromptBatchizer<B> {
+    fn extract(&self, content: &str) -> Option<String> {
+        self.inner.extract(content)
+    }
+    fn max_tokens(&self) -> usize {
+        self.inner.max_tokens()
+    }
+    fn batchize_with(
+        &self,
+        textures: &Textures,
+        start: usize,
+        end: Option<usize>,
+        budget: usize,
+    ) -> (Vec<ChatCompletionMessage>, usize) {
+        let (batch, size) = self.inner.batchize_with(textures, start, end, budget);
+        if batch.is_empty() {
+            return (batch, size);
+        }
+        let line = start + textures.offset();
+        let prompts = self.overrides.iter().find(|(ranges, _)| {
+            ranges.is_empty() || ranges.iter().any(|&(s, e)| s <= line && line <= e)
+        });
+        match prompts {
+            Some((_, prompts)) => (prompts.iter().cloned().chain(batch).collect(), size),
+            None => (batch, size),
+        }
+    }
+}
+
+/// the batch carries the prompts of an override, they replace the prompts of the translator
+pub fn has_own_prompts(batch: &[ChatCompletionMessage]) -> bool {
+    batch.iter().any(|m| m.role == ChatCompletionRole::System)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PromptBatchizer, PromptOverride};
+    use crate::{
+        textures::{TextureLine, Textures, TEXTURES_VERSION},
+        translators::{
+            chatgpt::{ChatCompletionMessage, ChatCompletionRole},
+            translator::Batchizer,
+        },
+        Configuration,
+    };
+
+    /// a batch of a line
+    struct OneLine;
+
+    impl Batchizer<ChatCompletionMessage> for OneLine {
+        fn batchize_with(
+            &self,
+            textures: &Textures,
+            index: usize,
+            _: Option<usize>,
+            _: usize,
+        ) -> (Vec<ChatCompletionMessage>, usize) {
+            let content = &textures.lines[index].content;
+            (
+                vec![ChatCompletionMessage::new(
+                    ChatCompletionRole::User,
+                    content,
+                )],
+                1,
+            )
+        }
+        fn max_tokens(&self) -> usize {
+            100
+        }
+        fn extract(&self, content: &str) -> Option<String> {
+            Some(content.to_string())
+        }
+    }
+
+    #[test]
+    fn test_prompt_batchizer() {
+        let menu = PromptOverride {
+            files: vec!["*menu*.txt".to_string()],
+            system_prompt: Some("Translate the menu items into {{to}}.".to_string()),
+            ..Default::default()
+        };
+        assert!(menu.matches_file("game/System_Menu.txt"));
+        assert!(!menu.matches_file("game/scenario.txt"));
+        let scene = PromptOverride {
+            ranges: vec![(1, 1)],
+            system_prompt: Some("The scene is explicit.".to_string()),
+            ..Default::default()
+        };
+        let mut cfg: Configuration =
+            toml::from_str(include_str!("../../assets/options_text.toml")).unwrap();
+        cfg.prompt_overrides = vec![menu, scene];
+        let batchizer = PromptBatchizer::new(OneLine, &cfg, "scenario.txt").unwrap();
+        assert_eq!(batchizer.overrides.len(), 1);
+        let textures = Textures {
+            lines: ["おはよう", "あっ"]
+                .iter()
+                .map(|l| TextureLine::new(0, 1, l.to_string(), false))
+                .collect(),
+            curr_index: 0,
+            version: TEXTURES_VERSION,
+            source: None,
+            name: "scenario.txt".to_string(),
+            shard: None,
+            dirs: Default::default(),
+        };
+        assert_eq!(batchizer.batchize(&textures, 0, None).0.len(), 1);
+        let (batch, _) = batchizer.batchize(&textures, 1, None);
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch[0].role, ChatCompletionRole::System);
+        assert_eq!(batch[0].content, "The scene is explicit.");
+    }
+}
@@ -0,0 +1,54 @@
+/// the terms pass through the translator untranslated, they are replaced by the placeholders
+/// {T0}, {T1}... before sent, and restored in the translated content
+#[derive(Debug, Clone, Default)]
+pub struct ProtectedTerms {
+    /// (placeholder, term), the longer terms first, so a term is never masked by a part of it
+    terms: Vec<(String, String)>,
+}
+
+impl ProtectedTerms {
+    pub fn new(terms: &[String]) -> Self {
+        let mut terms = terms
+            .iter()
+            .filter(|t| !t.is_empty())
+            .cloned()
+            .collect::<Vec<_>>();
+        terms.sort_by_key(|t| std::cmp::Reverse(t.chars().count()));
+        Self {
+            terms: terms
+                .into_iter()
+                .enumerate()
+                .map(|(i, term)| (format!("{{T{}}}", i), term))
+                .collect(),
+        }
+    }
+
+    pub fn mask(&self, content: &str) -> String {
+        let mut content = content.to_string();
+        for (placeholder, term) in self.terms.iter() {
+            content = content.replace(term, placeholder);
+        }
+        content
+    }
+
+    pub fn unmask(&self, content: &str) -> String {
+        let mut content = content.to_string();
+        for (placeholder, term) in self.terms.iter() {
+            content = content.replace(placeholder, term);
+        }
+        content
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ProtectedTerms;
+
+    #[test]
+    fn test_protected_terms() {
+        let terms = ProtectedTerms::new(&["ピノ".to_string(), "ピノキオ".to_string()]);
+        let masked = terms.mask("ピノキオとピノ");
+        assert_eq!(masked, "{T0}と{T1}");
+        assert_eq!(terms.unmask("{T0} and {T1}"), "ピノキオ and ピノ");
+    }
+}
@@ -0,0 +1,187 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::{error::new_regex_set, error::Error, textures::Textures};
+
+use super::translator::BatchPackage;
+
+/// translate the lines of the menus and the system messages first, so `lottr output` writes
+/// them early while the dialogue is still translating
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PriorityOptions {
+    /// the lines of the ranges, the line indexes like specify_range
+    #[serde(default)]
+    pub ranges: Vec<(usize, usize)>,
+    /// the lines matching one of the regexen
+    #[serde(default)]
+    pub regexen: Vec<String>,
+}
+
+/// whether every line is of high priority, indexed like textures.lines, all false without the
+/// priority options
+pub fn priority_lines(
+    opt: Option<&PriorityOptions>,
+    textures: &Textures,
+) -> Result<Vec<bool>, Error> {
+    let mut priority = vec![false; textures.lines.len()];
+    let Some(opt) = opt else {
+        return Ok(priority);
+    };
+    let set = new_regex_set("priority.regexen", &opt.regexen)?;
+    let offset = textures.offset();
+    for (i, line) in textures.lines.iter().enumerate() {
+        let index = i + offset;
+        priority[i] = opt.ranges.iter().any(|&(s, e)| s <= index && index <= e)
+            || set.is_match(&line.content);
+    }
+    Ok(priority)
+}
+
+/// the batches waiting for the workers, the largest first so a huge batch does not finish last,
+/// a failed batch goes back to the front for the next free worker
+pub struct BatchQueue<T> {
+    state: Mutex<QueueState<T>>,
+    notify: Notify,
+}
+
+struct QueueState<T> {
+    /// the batches, and whether they were sent before
+    batches: VecDeque<(BatchPackage<T>, bool)>,
+    /// the batches taken by the workers and not done yet, they may come back
+    in_flight: usize,
+}
+
+impl<T> BatchQueue<T> {
+    /// the batches in the reversed order of create_batch_queue, the largest key first, e.g.
+    /// (priority, size), the batches of the same key keep their order
+    pub fn new<K, S>(mut batches: Vec<BatchPackage<T>>, key: S) -> Self
+    where
+        K: Ord,
+        S: Fn(&BatchPackage<T>) -> K,
+    {
+        batches.reverse();
+        batches.sort_by_cached_key(|b| std::cmp::Reverse(key(b)));
+        Self {
+            state: Mutex::new(QueueState {
+                batches: batches.into_iter().map(|b| (b, false)).collect(),
+                in_flight: 0,
+            }),
+            notify: Notify::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().batches.len()
+    }
+
+    /// whether every batch is done, none waiting and none in flight
+    pub fn is_finished(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.batches.is_empty() && state.in_flight == 0
+    }
+
+    /// take the first batch and whether it is a retry, wait while the queue is empty but some
+    /// batches are in flight, None when every batch is done
+    pub async fn pop(&self) -> Option<(BatchPackage<T>, bool)> {
+        loop {
+            // registered before the check, a batch coming back in between still wakes it
+            let notified = self.notify.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(batch) = state.batches.pop_front() {
+                    state.in_flight += 1;
+                    return Some(batch);
+                }
+                if state.in_flight == 0 {
+                    return None;
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /// the batch taken is done
+    pub fn done(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight = state.in_flight.saturating_sub(1);
+        drop(state);
+        self.notify.notify_waiters();
+    }
+
+    /// put the batch taken back to the front, retry if it was sent
+    pub fn requeue(&self, batch: BatchPackage<T>, retry: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight = state.in_flight.saturating_sub(1);
+        state.batches.push_front((batch, retry));
+        drop(state);
+        self.notify.notify_waiters();
+    }
+
+    /// put the batches split from the batch taken to the front, in order
+    pub fn push_front(&self, batches: Vec<BatchPackage<T>>) {
+        let mut state = self.state.lock().unwrap();
+        for batch in batches.into_iter().rev() {
+            state.batches.push_front((batch, false));
+        }
+        drop(state);
+        self.notify.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{priority_lines, BatchQueue, PriorityOptions};
+    use crate::{
+        paths::ArtifactDirs,
+        textures::{TextureLine, Textures, TEXTURES_VERSION},
+    };
+
+    #[test]
+    fn test_priority_lines() {
+        let textures = Textures {
+            lines: ["はい", "[MENU] Start", "いいえ", "Load"]
+                .iter()
+                .map(|l| TextureLine::new(0, 1, l.to_string(), false))
+                .collect(),
+            curr_index: 0,
+            version: TEXTURES_VERSION,
+            source: None,
+            name: "a.txt".to_string(),
+            shard: None,
+            dirs: ArtifactDirs::default(),
+        };
+        let opt = PriorityOptions {
+            ranges: vec![(3, 5)],
+            regexen: vec![r"^\[MENU\]".to_string()],
+        };
+        assert_eq!(
+            priority_lines(Some(&opt), &textures).unwrap(),
+            vec![false, true, false, true]
+        );
+        assert_eq!(priority_lines(None, &textures).unwrap(), vec![false; 4]);
+    }
+
+    #[tokio::test]
+    async fn test_batch_queue() {
+        let b = |start, end| (Vec::<u8>::new(), (start, end));
+        // the reversed order of create_batch_queue
+        let queue = BatchQueue::new(vec![b(6, 6), b(3, 5), b(0, 2)], |b| b.1 .1 - b.1 .0 + 1);
+        let (first, retry) = queue.pop().await.unwrap();
+        assert_eq!((first.1, retry), ((0, 2), false));
+        queue.requeue(first, true);
+        assert_eq!(queue.pop().await.unwrap(), (b(0, 2), true));
+        queue.push_front(vec![b(0, 0), b(1, 2)]);
+        assert_eq!(queue.pop().await.unwrap().0 .1, (0, 0));
+        queue.done();
+        queue.done();
+        assert_eq!(queue.len(), 3);
+        for range in [(1, 2), (3, 5), (6, 6)] {
+            assert_eq!(queue.pop().await.unwrap().0 .1, range);
+            queue.done();
+        }
+        assert!(queue.is_finished());
+        assert_eq!(queue.pop().await, None);
+    }
+}
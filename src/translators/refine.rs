@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::ruby::RubyParser;
+use crate::textures::Textures;
+
+use super::chatgpt::{
+    load_prompts, system_prompts, ChatCompletionMessage, ChatCompletionRole, Tokenizer,
+};
+use super::glossary::glossary_section;
+use super::protect::ProtectedTerms;
+use super::translator::Batchizer;
+
+/// the proofreading prompt if refine_opt.prompt_path is not specified
+const DEFAULT_REFINE_PROMPT: &str = "You are a proofreader of the translations from {{from}} to {{to}}. \
+Every line is `(n) source ||| translation`. Fix the grammar, the mistranslations and the unnatural wording \
+of the translation, use the terms of the glossary if given, keep the placeholders, tags and symbols as they are. \
+Reply every line as `(n) corrected translation` in {{to}}, one line for each, in the same order, without any explanation.";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RefineOptions {
+    /// the proofreading prompts, the same format as chatgpt_opt.prompt_path
+    pub prompt_path: Option<String>,
+    /// the proofreading system prompt written in the config, see chatgpt_opt.system_prompt
+    pub system_prompt: Option<String>,
+    /// the source terms and their enforced translations
+    #[serde(default)]
+    pub glossary: BTreeMap<String, String>,
+}
+
+impl RefineOptions {
+    pub fn prompts(&self, from: &str, to: &str) -> Result<Vec<ChatCompletionMessage>> {
+        match &self.prompt_path {
+            Some(path) => load_prompts(path, from, to),
+            None => Ok(system_prompts(
+                self.system_prompt
+                    .as_deref()
+                    .unwrap_or(DEFAULT_REFINE_PROMPT),
+                from,
+                to,
+            )),
+        }
+    }
+}
+
+/// batchize the lines with their translations, `(n) source ||| translation`,
+/// the lines without a translation are never sent and never share a batch with the sent lines
+pub struct RefineBatchizer {
+    pub tokenizer: Tokenizer,
+    pub max_tokens: usize,
+    pub extract_regex: Option<Regex>,
+    pub protected: ProtectedTerms,
+    /// the translation to refine of every line, indexed like textures.lines
+    pub translations: Vec<Option<String>>,
+    pub glossary: BTreeMap<String, String>,
+    /// replace the ruby markup of the source by its base
+    pub ruby: Option<RubyParser>,
+}
+
+impl Batchizer<ChatCompletionMessage> for RefineBatchizer {
+    fn extract(&self, content: &str) -> Option<String> {
+        let content = match &self.extract_regex {
+            Some(regex) => regex
+                .captures(content)
+                .and_then(|caps| caps.get(1))
+                .map(|m| m.as_str().to_string())?,
+            None => content.to_string(),
+        };
+        match &self.ruby {
+            Some(ruby) => Some(ruby.strip(&content)),
+            None => Some(content),
+        }
+    }
+    fn max_tokens(&self) -> usize {
+        self.max_tokens
+    }
+    fn batchize_with(
+        &self,
+        textures: &Textures,
+        start: usize,
+        end: Option<usize>,
+        budget: usize,
+    ) -> (Vec<ChatCompletionMessage>, usize) {
+        let mut str_content = String::new();
+        let mut sources = vec![];
+        let mut tokens = 0;
+        let mut size = 0;
+        let mut sent = 0;
+        let mut unsent = false;
+        let end = end.unwrap_or(textures.lines.len() - 1);
+        for i in start..=end {
+            let line = &textures.lines[i];
+            if !line.needs_translation() {
+                size += 1;
+                continue;
+            }
+            let source = self.extract(&line.content);
+            let (Some(source), Some(translation)) = (source, &self.translations[i]) else {
+                // the lines without a translation are consumed by an empty batch
+                if sent > 0 {
+                    break;
+                }
+                unsent = true;
+                size += 1;
+                continue;
+            };
+            if unsent {
+                break;
+            }
+            let pair = format!(
+                "{} ||| {}",
+                self.protected.mask(&source),
+                self.protected.mask(&translation.replace('\n', " "))
+            );
+            tokens += self.tokenizer.count(&pair);
+            if tokens > budget && sent > 0 {
+                break;
+            }
+            sent += 1;
+            str_content.push_str(&format!("({}) {}\n", sent, pair));
+            sources.push(source);
+            size += 1;
+        }
+        if sent == 0 {
+            return (vec![], size);
+        }
+        let glossary = glossary_section(&self.glossary, &sources);
+        if !glossary.is_empty() {
+            str_content = format!("{}{}", glossary, str_content);
+        }
+        (
+            vec![ChatCompletionMessage::new(
+                ChatCompletionRole::User,
+                &str_content,
+            )],
+            size,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::RefineBatchizer;
+    use crate::{
+        textures::{TextureLine, Textures, TEXTURES_VERSION},
+        translators::{
+            chatgpt::Tokenizer, protect::ProtectedTerms, translator::Batchizer, TokenizerKind,
+        },
+    };
+
+    #[test]
+    fn test_refine_batchizer() {
+        let textures = Textures {
+            lines: ["皮诺", "再见", "谢谢", "你好"]
+                .iter()
+                .enumerate()
+                .map(|(i, l)| TextureLine::new(i * 7, 7, l.to_string(), false))
+                .collect(),
+            curr_index: 0,
+            version: TEXTURES_VERSION,
+            source: None,
+            name: String::new(),
+            shard: None,
+            dirs: Default::default(),
+        };
+        let batchizer = RefineBatchizer {
+            tokenizer: Tokenizer::new(TokenizerKind::Chars),
+            max_tokens: 1000,
+            extract_regex: None,
+            protected: ProtectedTerms::new(&[]),
+            translations: vec![
+                Some("Pino".to_string()),
+                Some("Bye".to_string()),
+                None,
+                Some("Hello".to_string()),
+            ],
+            glossary: BTreeMap::from([("皮诺".to_string(), "Pinocchio".to_string())]),
+            ruby: None,
+        };
+        let (batch, size) = batchizer.batchize(&textures, 0, None);
+        assert_eq!(size, 2);
+        assert_eq!(
+            batch[0].content,
+            "Glossary:\n皮诺 = Pinocchio\n(1) 皮诺 ||| Pino\n(2) 再见 ||| Bye\n"
+        );
+        let (batch, size) = batchizer.batchize(&textures, 2, None);
+        assert!(batch.is_empty());
+        assert_eq!(size, 1);
+        let (batch, size) = batchizer.batchize(&textures, 3, None);
+        assert_eq!(size, 1);
+        assert_eq!(batch[0].content, "(1) 你好 ||| Hello\n");
+    }
+}
@@ -0,0 +1,397 @@
+use std::{collections::HashMap, fs, sync::Arc};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::Error,
+    i18n::Msg,
+    outputs::translated_lines,
+    qe::{source, source_capture},
+    t,
+    textures::{fnv_extend, Textures, FNV_OFFSET},
+    Configuration,
+};
+
+use super::chatgpt::{ChatCompletionMessage, ChatCompletionRole};
+use super::translator::Batchizer;
+
+const DEFAULT_EMBEDDING_URL: &str = "https://api.openai.com/v1/embeddings";
+const DEFAULT_EMBEDDING_MODEL: &str = "text-embedding-3-small";
+/// the texts of an embeddings request
+const EMBEDDING_CHUNK: usize = 256;
+/// the dimensions of the local embeddings
+const LOCAL_DIMENSIONS: usize = 512;
+
+/// precedes the similar lines translated before, in the user message of a batch
+const REFERENCE_HEADER: &str =
+    "The similar lines translated before, for reference only, do not translate or output them:\n";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum EmbeddingProvider {
+    /// the embeddings api of openai, or a compatible one
+    #[serde(rename = "openai")]
+    OpenAI,
+    /// the hashed character bigrams, without any request
+    #[default]
+    #[serde(rename = "local")]
+    Local,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetrievalOptions {
+    #[serde(default)]
+    pub provider: EmbeddingProvider,
+    /// the api key of openai, default is the first api of chatgpt_opt.api_pool
+    pub api_key: Option<String>,
+    /// default is https://api.openai.com/v1/embeddings
+    pub api_url: Option<String>,
+    /// default is text-embedding-3-small
+    pub model: Option<String>,
+    /// the count of the similar lines sent with a batch
+    #[serde(default = "default_top_k")]
+    pub top_k: usize,
+    /// the lines less similar than this are never sent, 0 to 1
+    #[serde(default = "default_min_similarity")]
+    pub min_similarity: f32,
+}
+
+fn default_top_k() -> usize {
+    5
+}
+
+fn default_min_similarity() -> f32 {
+    0.75
+}
+
+/// the embeddings of the texts embedded before, saved as file.embeddings.json
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct EmbeddingCache {
+    model: String,
+    vectors: HashMap<String, Vec<f32>>,
+}
+
+/// the translated lines and the embeddings of the lines to be translated
+pub struct Retrieval {
+    /// (source, translation, embedding of the source)
+    pairs: Vec<(String, String, Vec<f32>)>,
+    /// the embedding of every line to be translated, indexed like textures.lines
+    lines: Vec<Option<Vec<f32>>>,
+    top_k: usize,
+    min_similarity: f32,
+}
+
+impl Retrieval {
+    /// embed the translated lines and the lines to be translated, None if retrieval_opt is not set
+    /// or nothing is translated yet
+    pub async fn prepare(cfg: &Configuration, textures: &Textures) -> Result<Option<Arc<Self>>> {
+        let Some(opt) = &cfg.retrieval_opt else {
+            return Ok(None);
+        };
+        let capture = source_capture(cfg)?;
+        let translations = translated_lines(cfg, textures)?;
+        let mut pairs = vec![];
+        let mut pending = vec![];
+        for (i, line) in textures.lines.iter().enumerate() {
+            if !line.needs_translation() {
+                continue;
+            }
+            let text = source(&capture, &line.content);
+            if text.is_empty() {
+                continue;
+            }
+            match &translations[i] {
+                Some(translation) => pairs.push((text, translation.trim().to_string())),
+                None => pending.push((i, text)),
+            }
+        }
+        if pairs.is_empty() || pending.is_empty() {
+            return Ok(None);
+        }
+        let texts = pairs
+            .iter()
+            .map(|(s, _)| s.clone())
+            .chain(pending.iter().map(|(_, s)| s.clone()))
+            .collect::<Vec<_>>();
+        let mut vectors = embed_cached(cfg, opt, textures, &texts).await?;
+        let mut lines = vec![None; textures.lines.len()];
+        for (i, text) in pending {
+            lines[i] = vectors.get(&text).cloned();
+        }
+        let pairs = pairs
+            .into_iter()
+            .filter_map(|(s, t)| {
+                let v = vectors.remove(&s)?;
+                Some((s, t, v))
+            })
+            .collect::<Vec<_>>();
+        println!("[Retrieval] {}", t!(Msg::RetrievalIndexed, pairs.len()));
+        Ok(Some(Arc::new(Self {
+            pairs,
+            lines,
+            top_k: opt.top_k,
+            min_similarity: opt.min_similarity,
+        })))
+    }
+
+    /// the most similar translated lines to any of the lines, the most similar first
+    pub fn similar(&self, lines: &[usize]) -> Vec<(&str, &str)> {
+        let queries = lines
+            .iter()
+            .filter_map(|&i| self.lines.get(i)?.as_ref())
+            .collect::<Vec<_>>();
+        let mut scored = self
+            .pairs
+            .iter()
+            .filter_map(|(s, t, v)| {
+                let score = queries
+                    .iter()
+                    .map(|q| cosine(q, v))
+                    .fold(f32::MIN, f32::max);
+                (score >= self.min_similarity).then_some((score, s.as_str(), t.as_str()))
+            })
+            .collect::<Vec<_>>();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored
+            .into_iter()
+            .take(self.top_k)
+            .map(|(_, s, t)| (s, t))
+            .collect()
+    }
+}
+
+/// prepend the similar translated lines to every batch of the inner batchizer
+pub struct RetrievalBatchizer<B> {
+    pub inner: B,
+    pub retrieval: Option<Arc<Retrieval>>,
+}
+
+impl<B: Batchizer<ChatCompletionMessage>> Batchizer<ChatCompletionMessage>
+    for RetrievalBatchizer<B>
+{
+    fn extract(&self, content: &str) -> Option<String> {
+        self.inner.extract(content)
+    }
+    fn max_tokens(&self) -> usize {
+        self.inner.max_tokens()
+    }
+    fn batchize_with(
+        &self,
+        textures: &Textures,
+        start: usize,
+        end: Option<usize>,
+        budget: usize,
+    ) -> (Vec<ChatCompletionMessage>, usize) {
+        let (mut batch, size) = self.inner.batchize_with(textures, start, end, budget);
+        let Some(retrieval) = &self.retrieval else {
+            return (batch, size);
+        };
+        if batch.is_empty() {
+            return (batch, size);
+        }
+        let lines = textures.batch_lines((start, start + size - 1));
+        let similar = retrieval.similar(&lines);
+        if similar.is_empty() {
+            return (batch, size);
+        }
+        let mut reference = String::from(REFERENCE_HEADER);
+        for (source, translation) in similar {
+            reference.push_str(&format!(
+                "{} => {}\n",
+                source.replace('\n', " "),
+                translation.replace('\n', " ")
+            ));
+        }
+        if let Some(message) = batch
+            .iter_mut()
+            .find(|m| m.role == ChatCompletionRole::User)
+        {
+            message.content = format!("{}\n{}", reference, message.content);
+        }
+        (batch, size)
+    }
+}
+
+/// the embeddings of the texts, the openai embeddings are cached in file.embeddings.json
+async fn embed_cached(
+    cfg: &Configuration,
+    opt: &RetrievalOptions,
+    textures: &Textures,
+    texts: &[String],
+) -> Result<HashMap<String, Vec<f32>>> {
+    if opt.provider == EmbeddingProvider::Local {
+        return Ok(texts
+            .iter()
+            .map(|t| (t.clone(), local_embedding(t)))
+            .collect());
+    }
+    let model = opt.model.as_deref().unwrap_or(DEFAULT_EMBEDDING_MODEL);
+    let path = textures.dirs.embeddings(&textures.name);
+    let mut cache = fs::read(&path)
+        .ok()
+        .and_then(|v| serde_json::from_slice::<EmbeddingCache>(&v).ok())
+        .filter(|c| c.model == model)
+        .unwrap_or_else(|| EmbeddingCache {
+            model: model.to_string(),
+            vectors: HashMap::new(),
+        });
+    let mut missing = texts
+        .iter()
+        .filter(|t| !cache.vectors.contains_key(*t))
+        .cloned()
+        .collect::<Vec<_>>();
+    missing.sort();
+    missing.dedup();
+    if !missing.is_empty() {
+        let api_key = opt
+            .api_key
+            .clone()
+            .or_else(|| {
+                cfg.chatgpt_opt
+                    .as_ref()
+                    .and_then(|o| o.api_pool.first())
+                    .map(|a| a.api_key.clone())
+            })
+            .ok_or_else(|| {
+                Error::Config("retrieval_opt.api_key or chatgpt_opt is required".to_string())
+            })?;
+        let api_url = opt.api_url.as_deref().unwrap_or(DEFAULT_EMBEDDING_URL);
+        let client = reqwest::Client::new();
+        for chunk in missing.chunks(EMBEDDING_CHUNK) {
+            let vectors = request_embeddings(&client, api_url, &api_key, model, chunk).await?;
+            cache.vectors.extend(chunk.iter().cloned().zip(vectors));
+        }
+        println!("[Retrieval] {}", t!(Msg::RetrievalEmbedded, missing.len()));
+        if !textures.name.is_empty() {
+            fs::write(&path, serde_json::to_string(&cache)?)?;
+        }
+    }
+    Ok(texts
+        .iter()
+        .filter_map(|t| Some((t.clone(), cache.vectors.get(t)?.clone())))
+        .collect())
+}
+
+#[derive(Serialize)]
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+async fn request_embeddings(
+    client: &reqwest::Client,
+    api_url: &str,
+    api_key: &str,
+    model: &str,
+    texts: &[String],
+) -> Result<Vec<Vec<f32>>> {
+    let resp = client
+        .post(api_url)
+        .bearer_auth(api_key)
+        .json(&EmbeddingRequest {
+            model,
+            input: texts,
+        })
+        .send()
+        .await?;
+    let status = resp.status();
+    let bs = resp.bytes().await?;
+    let mut resp = match serde_json::from_slice::<EmbeddingResponse>(&bs) {
+        Ok(resp) if status.is_success() => resp,
+        _ => {
+            return Err(anyhow::anyhow!(
+                "embeddings status: {}, response: {}",
+                status,
+                String::from_utf8_lossy(&bs)
+            ))
+        }
+    };
+    if resp.data.len() != texts.len() {
+        return Err(anyhow::anyhow!(
+            "embeddings expected {}, but got {}",
+            texts.len(),
+            resp.data.len()
+        ));
+    }
+    resp.data.sort_by_key(|d| d.index);
+    Ok(resp
+        .data
+        .into_iter()
+        .map(|d| normalize(d.embedding))
+        .collect())
+}
+
+/// the hashed character bigrams (and the single characters), normalized
+fn local_embedding(text: &str) -> Vec<f32> {
+    let mut vector = vec![0.0; LOCAL_DIMENSIONS];
+    let chars = text
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<Vec<_>>();
+    for (i, c) in chars.iter().enumerate() {
+        vector[fnv(&[*c]) % LOCAL_DIMENSIONS] += 0.5;
+        if let Some(next) = chars.get(i + 1) {
+            vector[fnv(&[*c, *next]) % LOCAL_DIMENSIONS] += 1.0;
+        }
+    }
+    normalize(vector)
+}
+
+/// the fnv-1a of the code points
+fn fnv(chars: &[char]) -> usize {
+    chars.iter().fold(FNV_OFFSET, |hash, c| {
+        fnv_extend(hash, &(*c as u32).to_le_bytes())
+    }) as usize
+}
+
+fn normalize(mut vector: Vec<f32>) -> Vec<f32> {
+    let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|v| *v /= norm);
+    }
+    vector
+}
+
+/// the cosine similarity of the normalized vectors
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{cosine, local_embedding, Retrieval};
+
+    #[test]
+    fn test_local_retrieval() {
+        let a = local_embedding("回復薬を使った");
+        let b = local_embedding("回復薬を使う");
+        let c = local_embedding("ここはどこだ");
+        assert!(cosine(&a, &b) > 0.6);
+        assert!(cosine(&a, &c) < 0.3);
+        let retrieval = Retrieval {
+            pairs: vec![
+                ("回復薬を使った".to_string(), "Used a potion".to_string(), a),
+                ("ここはどこだ".to_string(), "Where am I".to_string(), c),
+            ],
+            lines: vec![None, Some(b)],
+            top_k: 5,
+            min_similarity: 0.5,
+        };
+        assert_eq!(
+            retrieval.similar(&[0, 1]),
+            vec![("回復薬を使った", "Used a potion")]
+        );
+        assert!(retrieval.similar(&[0]).is_empty());
+    }
+}
@@ -0,0 +1,89 @@
+use regex::RegexSet;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{new_regex_set, Error};
+
+use super::chatgpt::{ChatCompletionMessage, ChatCompletionRole, ChatGPTAPI, ChatGPTClient};
+
+/// send the batches of explicit content to a designated api, e.g. a local uncensored model,
+/// the other batches keep the api pool of chatgpt_opt
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutingOptions {
+    /// a batch containing one of the keywords is explicit
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    /// a batch matching one of the regexen is explicit
+    #[serde(default)]
+    pub regexen: Vec<String>,
+    /// the api of the explicit batches, an openai compatible api
+    pub api: ChatGPTAPI,
+    /// the model of the explicit batches, default is the model of chatgpt_opt
+    pub model: Option<String>,
+}
+
+/// tell the explicit batches by the keywords and the regexen
+pub struct ContentClassifier {
+    keywords: Vec<String>,
+    set: RegexSet,
+}
+
+impl ContentClassifier {
+    pub fn new(opt: &RoutingOptions) -> Result<Self, Error> {
+        Ok(Self {
+            keywords: opt
+                .keywords
+                .iter()
+                .filter(|k| !k.is_empty())
+                .cloned()
+                .collect(),
+            set: new_regex_set("routing_opt.regexen", &opt.regexen)?,
+        })
+    }
+
+    /// whether the user content of the batch is explicit
+    pub fn is_explicit(&self, batch: &[ChatCompletionMessage]) -> bool {
+        batch
+            .iter()
+            .filter(|m| m.role == ChatCompletionRole::User)
+            .any(|m| {
+                self.keywords.iter().any(|k| m.content.contains(k.as_str()))
+                    || self.set.is_match(&m.content)
+            })
+    }
+}
+
+/// the classifier and the client of the explicit batches
+pub struct Route {
+    pub classifier: ContentClassifier,
+    pub client: ChatGPTClient,
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ContentClassifier, RoutingOptions};
+    use crate::translators::chatgpt::{ChatCompletionMessage, ChatCompletionRole, ChatGPTAPI};
+
+    #[test]
+    fn test_content_classifier() {
+        let opt = RoutingOptions {
+            keywords: vec!["えっち".to_string()],
+            regexen: vec!["(?i)nsfw".to_string()],
+            api: ChatGPTAPI {
+                api_key: "sk-local".to_string(),
+                api_url: "http://127.0.0.1:8080/v1/chat/completions".to_string(),
+                org_id: None,
+            },
+            model: None,
+        };
+        let classifier = ContentClassifier::new(&opt).unwrap();
+        let batch = |content: &str| {
+            vec![
+                ChatCompletionMessage::new(ChatCompletionRole::System, "えっち"),
+                ChatCompletionMessage::new(ChatCompletionRole::User, content),
+            ]
+        };
+        assert!(classifier.is_explicit(&batch("(1) えっちなのはダメ")));
+        assert!(classifier.is_explicit(&batch("(1) [NSFW] scene")));
+        assert!(!classifier.is_explicit(&batch("(1) おはよう")));
+    }
+}
@@ -0,0 +1,147 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use anyhow::Result;
+use isolang::Language;
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+
+use crate::{error::Error, outputs::LineExtractor, textures::Textures};
+
+/// a writer of another process holds the lock, the others wait up to this long
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// the translations shared by the runs of the files of the same game, even the runs at the same
+/// time, in a sqlite file: the lines translated by a run are not sent again by the others
+pub struct SharedCache {
+    conn: Mutex<Connection>,
+    from: String,
+    to: String,
+}
+
+impl SharedCache {
+    pub fn open(path: &str, from: Language, to: Language) -> Result<Self> {
+        let conn = Connection::open(path).map_err(|e| {
+            Error::Config(format!("shared_cache {} can not be opened: {}", path, e))
+        })?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        // the readers do not block the writer of another process
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS translations (
+                source TEXT NOT NULL,
+                lang_from TEXT NOT NULL,
+                lang_to TEXT NOT NULL,
+                translation TEXT NOT NULL,
+                file TEXT NOT NULL,
+                PRIMARY KEY (source, lang_from, lang_to)
+            )",
+        )?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+            from: from.to_639_3().to_string(),
+            to: to.to_639_3().to_string(),
+        })
+    }
+
+    /// the translations of the sources found
+    pub fn get(&self, sources: &[&str]) -> Result<HashMap<String, String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached(
+            "SELECT translation FROM translations WHERE source = ?1 AND lang_from = ?2 AND lang_to = ?3",
+        )?;
+        let mut found = HashMap::new();
+        for source in sources {
+            let translation = stmt
+                .query_row(params![source, self.from, self.to], |row| {
+                    row.get::<_, String>(0)
+                })
+                .optional()?;
+            if let Some(translation) = translation {
+                found.insert(source.to_string(), translation);
+            }
+        }
+        Ok(found)
+    }
+
+    /// add the translations in one transaction, the first translation of a source is kept
+    pub fn put(&self, pairs: &[(&str, &str)], file: &str) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT OR IGNORE INTO translations (source, lang_from, lang_to, translation, file) VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for (source, translation) in pairs {
+                stmt.execute(params![source, self.from, self.to, translation, file])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// the numbered content of the batch when every line of it is in the cache, like the
+    /// translator answers it
+    pub fn batch_content(&self, textures: &Textures, range: (usize, usize)) -> Option<String> {
+        let lines = textures.batch_lines(range);
+        if lines.is_empty() {
+            return None;
+        }
+        let sources = lines
+            .iter()
+            .map(|&i| textures.lines[i].content.as_str())
+            .collect::<Vec<_>>();
+        let found = self.get(&sources).ok()?;
+        let mut content = String::new();
+        for (n, source) in sources.iter().enumerate() {
+            content.push_str(&format!("({}) {}\n", n + 1, found.get(*source)?));
+        }
+        Some(content)
+    }
+
+    /// add the lines of a translated batch, a batch of mismatched lines is not added
+    pub fn put_batch(
+        &self,
+        textures: &Textures,
+        extractor: &LineExtractor,
+        content: &str,
+        range: (usize, usize),
+    ) -> Result<()> {
+        let lines = textures.batch_lines(range);
+        let translations = extractor.extract(content);
+        if translations.len() != lines.len() {
+            return Ok(());
+        }
+        let pairs = lines
+            .iter()
+            .zip(&translations)
+            .filter(|(_, t)| !t.trim().is_empty())
+            .map(|(&i, t)| (textures.lines[i].content.as_str(), t.trim()))
+            .collect::<Vec<_>>();
+        self.put(&pairs, &textures.name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use isolang::Language;
+
+    use super::SharedCache;
+
+    #[test]
+    fn test_shared_cache() {
+        let dir = crate::utils::TempDir::new("shared_cache");
+        let path = dir.join("cache.sqlite");
+        let path = path.to_str().unwrap();
+        let (jpn, zho) = (Language::Jpn, Language::Zho);
+        let a = SharedCache::open(path, jpn, zho).unwrap();
+        // another process of the same game
+        let b = SharedCache::open(path, jpn, zho).unwrap();
+        a.put(&[("はい", "是的"), ("いいえ", "不")], "a.txt")
+            .unwrap();
+        b.put(&[("はい", "好的")], "b.txt").unwrap();
+        let found = b.get(&["はい", "いいえ", "また"]).unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found["はい"], "是的");
+        let eng = SharedCache::open(path, jpn, Language::Eng).unwrap();
+        assert!(eng.get(&["はい"]).unwrap().is_empty());
+    }
+}
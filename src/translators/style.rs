@@ -0,0 +1,160 @@
+use serde::{Deserialize, Serialize};
+
+use super::chatgpt::{ChatCompletionMessage, ChatCompletionRole};
+
+/// the japanese honorifics and their romanized forms
+const HONORIFICS: [(&str, &str); 7] = [
+    ("さん", "san"),
+    ("様", "sama"),
+    ("さま", "sama"),
+    ("ちゃん", "chan"),
+    ("くん", "kun"),
+    ("殿", "dono"),
+    ("先輩", "senpai"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Formality {
+    #[serde(rename = "casual")]
+    Casual,
+    #[serde(rename = "neutral")]
+    Neutral,
+    #[serde(rename = "formal")]
+    Formal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Honorifics {
+    /// keep them romanized, e.g. Pino-san
+    #[serde(rename = "keep")]
+    Keep,
+    /// translate them into the forms of address of the target language, e.g. Mr. Pino
+    #[serde(rename = "localize")]
+    Localize,
+}
+
+/// the style of the translation, rendered into the system prompt
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StyleOptions {
+    pub formality: Option<Formality>,
+    /// the first-person pronoun of the target language, e.g. "俺"
+    pub first_person: Option<String>,
+    /// the second-person pronoun of the target language, e.g. "您"
+    pub second_person: Option<String>,
+    pub honorifics: Option<Honorifics>,
+}
+
+impl StyleOptions {
+    /// the instructions of the style, None if nothing is configured
+    pub fn instructions(&self, to: &str) -> Option<String> {
+        let mut lines = vec![];
+        match self.formality {
+            Some(Formality::Casual) => lines.push("Use a casual, informal register.".to_string()),
+            Some(Formality::Neutral) => lines.push("Use a neutral register.".to_string()),
+            Some(Formality::Formal) => lines.push("Use a formal, polite register.".to_string()),
+            None => {}
+        }
+        if let Some(pronoun) = &self.first_person {
+            lines.push(format!("Use \"{}\" as the first-person pronoun.", pronoun));
+        }
+        if let Some(pronoun) = &self.second_person {
+            lines.push(format!("Use \"{}\" as the second-person pronoun.", pronoun));
+        }
+        match self.honorifics {
+            Some(Honorifics::Keep) => lines.push(
+                "Keep the honorifics romanized after the names, like -san, -sama, -kun.".to_string(),
+            ),
+            Some(Honorifics::Localize) => lines.push(format!(
+                "Translate the honorifics into natural {} forms of address, do not keep -san, -sama, -kun.",
+                to
+            )),
+            None => {}
+        }
+        (!lines.is_empty()).then(|| format!("Style:\n{}", lines.join("\n")))
+    }
+
+    /// append the instructions to the first system prompt, or insert them as the first prompt
+    pub fn apply(
+        &self,
+        mut prompts: Vec<ChatCompletionMessage>,
+        to: &str,
+    ) -> Vec<ChatCompletionMessage> {
+        let Some(instructions) = self.instructions(to) else {
+            return prompts;
+        };
+        match prompts
+            .iter_mut()
+            .find(|m| m.role == ChatCompletionRole::System)
+        {
+            Some(system) => {
+                system.content = format!("{}\n\n{}", system.content.trim_end(), instructions);
+            }
+            None => prompts.insert(
+                0,
+                ChatCompletionMessage::new(ChatCompletionRole::System, &instructions),
+            ),
+        }
+        prompts
+    }
+
+    /// the honorific violation of a translated line, only the honorifics can be validated
+    pub fn check(&self, source: &str, translation: &str) -> Option<String> {
+        match self.honorifics? {
+            Honorifics::Keep => {
+                let (honorific, romanized) = HONORIFICS.iter().find(|(h, _)| source.contains(h))?;
+                let kept = translation.contains(honorific)
+                    || romanized_honorifics(translation).any(|r| r == *romanized);
+                (!kept).then(|| format!("the honorific {} is not kept", honorific))
+            }
+            Honorifics::Localize => romanized_honorifics(translation)
+                .next()
+                .map(|r| format!("the honorific -{} is not localized", r)),
+        }
+    }
+}
+
+/// the romanized honorifics after a hyphen, e.g. san of Pino-san, in lowercase
+fn romanized_honorifics(translation: &str) -> impl Iterator<Item = String> + '_ {
+    translation.split('-').skip(1).filter_map(|part| {
+        let word = part
+            .chars()
+            .take_while(|c| c.is_ascii_alphabetic())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        HONORIFICS.iter().any(|(_, r)| *r == word).then_some(word)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Formality, Honorifics, StyleOptions};
+    use crate::translators::chatgpt::{ChatCompletionMessage, ChatCompletionRole};
+
+    #[test]
+    fn test_style() {
+        let mut style = StyleOptions {
+            formality: Some(Formality::Formal),
+            first_person: Some("I".to_string()),
+            second_person: None,
+            honorifics: Some(Honorifics::Keep),
+        };
+        let prompts = style.apply(
+            vec![ChatCompletionMessage::new(
+                ChatCompletionRole::System,
+                "Translate.",
+            )],
+            "English",
+        );
+        assert_eq!(prompts.len(), 1);
+        assert!(prompts[0]
+            .content
+            .starts_with("Translate.\n\nStyle:\nUse a formal, polite register.\nUse \"I\""));
+        assert_eq!(style.apply(vec![], "English").len(), 1);
+        assert_eq!(style.check("ピノさん", "Pino-san"), None);
+        assert!(style.check("ピノさん", "Mr. Pino").is_some());
+        assert_eq!(style.check("ピノ", "Pino"), None);
+        style.honorifics = Some(Honorifics::Localize);
+        assert!(style.check("ピノさん", "Pino-San").is_some());
+        assert_eq!(StyleOptions::default().instructions("English"), None);
+    }
+}
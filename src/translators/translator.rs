@@ -1,6 +1,8 @@
 use std::{
+    collections::{BTreeSet, HashSet},
     fmt::Debug,
     sync::{Arc, Mutex},
+    time::Instant,
 };
 
 use anyhow::Result;
@@ -9,70 +11,583 @@ use regex::Regex;
 use serde::{Deserialize, Serialize};
 use tokio::{
     select,
-    sync::mpsc::{self, Sender},
+    sync::{
+        mpsc::{self, Sender},
+        watch, Semaphore,
+    },
+    task::JoinHandle,
 };
 
 use crate::{
-    textures::{Textures, TranslatedLine},
-    Configuration, Timer,
+    console::{self, Tone},
+    control::Control,
+    error::{new_regex, Error},
+    i18n::Msg,
+    outputs::{live_output, write_preview, LineExtractor, OutputCache, TranslatorSelection},
+    ruby::RubyParser,
+    t,
+    textures::{RequestParams, Textures, TranslatedLine},
+    Configuration, RunSummary, Timer,
 };
 
-use super::chatgpt::{TokenizedBatchizer, TranslateChatGPT};
+use super::adaptive::{AdaptiveBudget, AdaptiveConcurrency, ConcurrencyRange};
+use super::api_error::{is_refusal, ApiError};
+use super::budget::Budget;
+use super::chatgpt::{
+    batch_ceiling, ChatCompletionMessage, LineGrouping, TokenizedBatchizer, Tokenizer,
+    TokenizerKind, TranslateChatGPT, DEFAULT_EXPANSION_RATIO, DEFAULT_MODEL,
+};
+use super::gemini::TranslateGemini;
+use super::glossary::{load_glossary, GlossaryBatchizer};
+use super::metrics::{error_category, Metrics, WorkerMetrics};
+use super::prompt_override::PromptBatchizer;
+use super::protect::ProtectedTerms;
+use super::queue::{priority_lines, BatchQueue};
+use super::refine::RefineBatchizer;
+use super::retrieval::{Retrieval, RetrievalBatchizer};
+use super::shared_cache::SharedCache;
+
+/// translated lines / total lines of the current run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Progress {
+    pub translated: usize,
+    pub total: usize,
+}
 
 pub async fn translate(
     textures: Textures,
     textures_mut: &mut Textures,
     cfg: &Configuration,
-) -> Result<()> {
+    progress: Option<&watch::Sender<Progress>>,
+) -> Result<RunSummary> {
+    let total = match &cfg.specify_range {
+        Some(ranges) => range_lines(ranges),
+        None => textures.lines.len().saturating_sub(textures.curr_index),
+    };
+    let (from, to) = (cfg.lang_from.to_name(), cfg.lang_to.to_name());
+    let shared_cache = cfg
+        .shared_cache
+        .as_deref()
+        .map(|path| SharedCache::open(path, cfg.lang_from, cfg.lang_to).map(Arc::new))
+        .transpose()?;
+    // todo baidu, deepl
+    if let Some(gemini_opt) = &cfg.gemini_opt {
+        if !cfg.output_translators.contains(&Translator::Gemini) {
+            println!("{}", t!(Msg::AddGemini));
+        }
+        let mut gemini =
+            TranslateGemini::new(gemini_opt.clone(), cfg.specify_range.clone(), from, to)?
+                .with_style(cfg.style.as_ref(), to);
+        gemini.request_limiter = cfg.request_limiter.clone();
+        gemini.line_extractor = LineExtractor::new(cfg).ok().map(Arc::new);
+        let batchizer = tokenized_batchizer(cfg, gemini_opt.model(), |t| gemini.prompt_tokens(t))?;
+        let sample = trial_ranges(&batchizer, &textures, cfg);
+        if let Some((ranges, _)) = &sample {
+            gemini.specify_range = Some(ranges.clone());
+        }
+        let batchizer = GlossaryBatchizer {
+            inner: RetrievalBatchizer {
+                inner: batchizer,
+                retrieval: Retrieval::prepare(cfg, &textures).await?,
+            },
+            glossary: load_glossary(cfg, &textures.name)?,
+        };
+        let batchizer = PromptBatchizer::new(batchizer, cfg, &textures.name)?;
+        let total = sample
+            .as_ref()
+            .map_or(total, |(ranges, _)| range_lines(ranges));
+        let summary = run_translator(
+            gemini,
+            batchizer,
+            textures,
+            textures_mut,
+            cfg,
+            progress,
+            total,
+            shared_cache,
+        )
+        .await?;
+        if let Some((ranges, batches)) = sample.as_ref().filter(|_| cfg.sample.is_some()) {
+            write_preview(cfg, textures_mut, ranges, *batches, &summary)?;
+        }
+        return Ok(summary);
+    }
+    let Some(chatgpt_opt) = &cfg.chatgpt_opt else {
+        textures_mut.save()?;
+        return Ok(RunSummary::default());
+    };
+    let mut chat_gpt =
+        TranslateChatGPT::new(chatgpt_opt.clone(), cfg.specify_range.clone(), from, to)?
+            .with_style(cfg.style.as_ref(), to)
+            .with_routing(cfg.routing_opt.as_ref())?;
+    chat_gpt.request_limiter = cfg.request_limiter.clone();
+    chat_gpt.line_extractor = LineExtractor::new(cfg).ok().map(Arc::new);
+    if cfg.specify_range.is_some() {
+        warn_params_changed(&textures, &chat_gpt.params());
+    }
+    let model = chatgpt_opt.model.as_deref().unwrap_or(DEFAULT_MODEL);
+    let mut batchizer = tokenized_batchizer(cfg, model, |t| chat_gpt.prompt_tokens(t))?;
+    batchizer.profile = chatgpt_opt.model_profile;
+    let sample = trial_ranges(&batchizer, &textures, cfg);
+    if let Some((ranges, _)) = &sample {
+        chat_gpt.specify_range = Some(ranges.clone());
+    }
+    let batchizer = GlossaryBatchizer {
+        inner: RetrievalBatchizer {
+            inner: batchizer,
+            retrieval: Retrieval::prepare(cfg, &textures).await?,
+        },
+        glossary: load_glossary(cfg, &textures.name)?,
+    };
+    let batchizer = PromptBatchizer::new(batchizer, cfg, &textures.name)?;
+    let total = sample
+        .as_ref()
+        .map_or(total, |(ranges, _)| range_lines(ranges));
+    let summary = run_translator(
+        chat_gpt,
+        batchizer,
+        textures,
+        textures_mut,
+        cfg,
+        progress,
+        total,
+        shared_cache,
+    )
+    .await?;
+    if let Some((ranges, batches)) = sample.as_ref().filter(|_| cfg.sample.is_some()) {
+        write_preview(cfg, textures_mut, ranges, *batches, &summary)?;
+    }
+    Ok(summary)
+}
+
+/// a rerun of the ranges with other parameters than the first pass may change the style
+fn warn_params_changed(textures: &Textures, params: &RequestParams) {
+    let first = textures
+        .lines
+        .iter()
+        .flat_map(|l| l.translated.iter())
+        .filter(|t| t.translator == Translator::ChatGPT)
+        .find_map(|t| t.params.as_ref());
+    if let Some(first) = first.filter(|first| *first != params) {
+        let (now, before) = (format!("{:?}", params), format!("{:?}", first));
+        println!("{}", t!(Msg::ParamsDiffer, now, before));
+    }
+}
+
+/// the batches the translation will send, by the batchizer of the translator without the
+/// glossary and the retrieved examples, None without a translator
+pub fn estimate_batches(textures: &Textures, cfg: &Configuration) -> Result<Option<usize>> {
+    let Some(batchizer) = estimate_batchizer(cfg)? else {
+        return Ok(None);
+    };
+    let ranges = cfg
+        .specify_range
+        .clone()
+        .unwrap_or_else(|| vec![(textures.curr_index, textures.lines.len().saturating_sub(1))]);
+    Ok(Some(batch_ranges(&batchizer, textures, ranges).len()))
+}
+
+/// the batchizer of the configured translator, None without a translator
+fn estimate_batchizer(cfg: &Configuration) -> Result<Option<TokenizedBatchizer>> {
+    let (from, to) = (cfg.lang_from.to_name(), cfg.lang_to.to_name());
+    let batchizer = if let Some(gemini_opt) = &cfg.gemini_opt {
+        let gemini = TranslateGemini::new(gemini_opt.clone(), None, from, to)?
+            .with_style(cfg.style.as_ref(), to);
+        tokenized_batchizer(cfg, gemini_opt.model(), |t| gemini.prompt_tokens(t))?
+    } else if let Some(chatgpt_opt) = &cfg.chatgpt_opt {
+        let chat_gpt = TranslateChatGPT::new(chatgpt_opt.clone(), None, from, to)?
+            .with_style(cfg.style.as_ref(), to);
+        let model = chatgpt_opt.model.as_deref().unwrap_or(DEFAULT_MODEL);
+        tokenized_batchizer(cfg, model, |t| chat_gpt.prompt_tokens(t))?
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(batchizer))
+}
+
+/// the batchizer of the bench command, by batchizer_opt alone without a translator
+pub fn bench_batchizer(cfg: &Configuration) -> Result<TokenizedBatchizer> {
+    match estimate_batchizer(cfg)? {
+        Some(batchizer) => Ok(batchizer),
+        None => Ok(tokenized_batchizer(cfg, DEFAULT_MODEL, |_| 0)?),
+    }
+}
+
+/// the tokens of the text sent to the translator, counted by the tokenizer of the batches
+pub fn count_tokens(batchizer: &TokenizedBatchizer, textures: &Textures) -> usize {
+    textures
+        .lines
+        .iter()
+        .filter(|l| l.needs_translation())
+        .filter_map(|l| batchizer.extract(&l.content))
+        .map(|text| batchizer.tokenizer.count(&text))
+        .sum()
+}
+
+/// the count of the batches of all the lines, like the translation would send them
+pub fn count_batches(batchizer: &TokenizedBatchizer, textures: &Textures) -> usize {
+    let ranges = vec![(0, textures.lines.len().saturating_sub(1))];
+    batch_ranges(batchizer, textures, ranges).len()
+}
+
+/// the ranges of the batches of the lines in the ranges, like batch_queue
+fn batch_ranges<T, F: Batchizer<T>>(
+    batchizer: &F,
+    textures: &Textures,
+    ranges: Vec<(usize, usize)>,
+) -> Vec<(usize, usize)> {
+    let mut batches = vec![];
+    for (start, end) in ranges {
+        let mut i = start;
+        while i <= end && i < textures.lines.len() {
+            let (batch, size) = batchizer.batchize(textures, i, Some(end));
+            if size == 0 {
+                break;
+            }
+            if !batch.is_empty() {
+                batches.push((i, i + size - 1));
+            }
+            i += size;
+        }
+    }
+    batches
+}
+
+/// the ranges of a trial run instead of the whole file, and the count of all the batches:
+/// the first batch by --preview, or n batches spread across the file by --sample
+fn trial_ranges<T, F: Batchizer<T>>(
+    batchizer: &F,
+    textures: &Textures,
+    cfg: &Configuration,
+) -> Option<(Vec<(usize, usize)>, usize)> {
+    if cfg.preview {
+        let ranges = cfg
+            .specify_range
+            .clone()
+            .unwrap_or_else(|| vec![(textures.curr_index, textures.lines.len().saturating_sub(1))]);
+        let batches = batch_ranges(batchizer, textures, ranges);
+        return Some((
+            batches.first().into_iter().copied().collect(),
+            batches.len(),
+        ));
+    }
+    cfg.sample
+        .map(|n| sample_ranges(batchizer, textures, &cfg.specify_range, n))
+}
+
+/// the count of lines of the ranges
+fn range_lines(ranges: &[(usize, usize)]) -> usize {
+    ranges.iter().map(|(s, e)| e + 1 - s).sum()
+}
+
+/// the ranges of n batches spread evenly across the file or the specify_range, and the count of
+/// all the batches, the sampled ranges are batchized into the same batches again
+fn sample_ranges<T, F: Batchizer<T>>(
+    batchizer: &F,
+    textures: &Textures,
+    specify_range: &Option<Vec<(usize, usize)>>,
+    n: usize,
+) -> (Vec<(usize, usize)>, usize) {
+    let ranges = specify_range
+        .clone()
+        .unwrap_or_else(|| vec![(0, textures.lines.len().saturating_sub(1))]);
+    let batches = batch_ranges(batchizer, textures, ranges);
+    let count = batches.len();
+    let n = n.min(count);
+    // the middle batch of every n-th part
+    let sampled = (0..n)
+        .map(|k| batches[(2 * k + 1) * count / (2 * n)])
+        .collect::<Vec<_>>();
+    println!("{}", t!(Msg::Sample, sampled.len(), count));
+    (sampled, count)
+}
+
+/// the second pass of the translated lines: the source and the translation are sent together
+/// with a proofreading prompt, the results are stored as the ChatGPTRefined translations.
+/// the lines already refined are skipped, so an interrupted refine can be resumed.
+pub async fn refine(
+    mut textures: Textures,
+    textures_mut: &mut Textures,
+    cfg: &Configuration,
+    progress: Option<&watch::Sender<Progress>>,
+) -> Result<RunSummary> {
+    let Some(chatgpt_opt) = &cfg.chatgpt_opt else {
+        return Err(Error::Config("refine requires chatgpt_opt".to_string()).into());
+    };
+    let refine_opt = cfg.refine_opt.clone().unwrap_or_default();
+    let mut glossary = load_glossary(cfg, &textures.name)?;
+    glossary.extend(refine_opt.glossary.clone());
+    let extractor = LineExtractor::new(cfg)?;
+    let refined = TranslatorSelection::from(Translator::ChatGPTRefined).select(
+        &textures,
+        |c| extractor.extract(c),
+        |_, _, _| {},
+    );
+    let translations = TranslatorSelection::from(Translator::ChatGPT)
+        .select(&textures, |c| extractor.extract(c), |_, _, _| {})
+        .into_iter()
+        .zip(refined)
+        .map(|(translation, refined)| translation.filter(|_| refined.is_none()))
+        .collect::<Vec<_>>();
+    let total = translations.iter().filter(|t| t.is_some()).count();
+    let (from, to) = (cfg.lang_from.to_name(), cfg.lang_to.to_name());
+    let mut opt = chatgpt_opt.clone();
+    opt.prompt_path = None;
+    let mut chat_gpt = TranslateChatGPT::new(opt, None, from, to)?
+        .with_prompts(refine_opt.prompts(from, to)?)
+        .with_style(cfg.style.as_ref(), to)
+        .with_translator(Translator::ChatGPTRefined)
+        .with_routing(cfg.routing_opt.as_ref())?;
+    chat_gpt.request_limiter = cfg.request_limiter.clone();
+    chat_gpt.line_extractor = Some(Arc::new(extractor));
+    let model = chatgpt_opt.model.as_deref().unwrap_or(DEFAULT_MODEL);
+    let tokenizer = Tokenizer::new(
+        cfg.batchizer_opt
+            .tokenizer
+            .unwrap_or_else(|| TokenizerKind::from_model(model)),
+    );
+    let ceiling = max_tokens_ceiling(cfg, model, chat_gpt.prompt_tokens(&tokenizer));
+    let batchizer = RefineBatchizer {
+        tokenizer,
+        max_tokens: cfg.batchizer_opt.max_tokens.min(ceiling),
+        extract_regex: extract_regex(cfg)?,
+        protected: ProtectedTerms::new(&cfg.protected_terms),
+        translations,
+        glossary,
+        ruby: cfg.ruby.map(|_| RubyParser::default()),
+    };
+    // refine from the first line, the resume index of the translation is kept
+    let curr_index = textures_mut.curr_index;
+    textures.curr_index = 0;
+    let summary = run_translator(
+        chat_gpt,
+        batchizer,
+        textures,
+        textures_mut,
+        cfg,
+        progress,
+        total,
+        None,
+    )
+    .await?;
+    textures_mut.curr_index = curr_index;
+    textures_mut.save()?;
+    Ok(summary)
+}
+
+/// the batchizer of the translation, within the limits of the model
+fn tokenized_batchizer<P>(
+    cfg: &Configuration,
+    model: &str,
+    prompt_tokens: P,
+) -> Result<TokenizedBatchizer, Error>
+where
+    P: FnOnce(&Tokenizer) -> usize,
+{
+    let tokenizer = Tokenizer::new(
+        cfg.batchizer_opt
+            .tokenizer
+            .unwrap_or_else(|| TokenizerKind::from_model(model)),
+    );
+    let ceiling = max_tokens_ceiling(cfg, model, prompt_tokens(&tokenizer));
+    Ok(TokenizedBatchizer {
+        tokenizer,
+        max_tokens: cfg.batchizer_opt.max_tokens.min(ceiling),
+        // the input has already captured the content
+        extract_regex: extract_regex(cfg)?,
+        protected: ProtectedTerms::new(&cfg.protected_terms),
+        overlap: cfg.batchizer_opt.overlap,
+        grouping: LineGrouping::new(&cfg.batchizer_opt.grouping)?,
+        group_max_tokens: cfg
+            .batchizer_opt
+            .group_max_tokens
+            .unwrap_or(cfg.batchizer_opt.max_tokens * 2)
+            .min(ceiling),
+        profile: None,
+        ruby: cfg.ruby.map(|_| RubyParser::default()),
+    })
+}
+
+/// the completion of a batch must not be truncated by the output limit of the model
+fn max_tokens_ceiling(cfg: &Configuration, model: &str, prompt_tokens: usize) -> usize {
+    let ceiling = batch_ceiling(
+        model,
+        cfg.batchizer_opt
+            .expansion_ratio
+            .unwrap_or(DEFAULT_EXPANSION_RATIO),
+        prompt_tokens,
+    );
+    if cfg.batchizer_opt.max_tokens > ceiling {
+        let max_tokens = cfg.batchizer_opt.max_tokens;
+        println!("{}", t!(Msg::Capped, max_tokens, ceiling, model));
+    }
+    ceiling
+}
+
+/// the capture regex of the batchizer, None when the input has already captured the content,
+/// the group 1 is sent so the regex must have one
+fn extract_regex(cfg: &Configuration) -> Result<Option<Regex>, Error> {
+    let regex = cfg
+        .capture_regex
+        .as_ref()
+        .filter(|_| !cfg.capture_input)
+        .map(|r| new_regex("capture_regex", r))
+        .transpose()?;
+    if regex.as_ref().is_some_and(|r| r.captures_len() < 2) {
+        return Err(Error::Config(
+            "capture_regex has no capture group".to_string(),
+        ));
+    }
+    Ok(regex)
+}
+
+/// run the translator over the textures, the translated batches are updated into textures_mut
+#[allow(clippy::too_many_arguments)]
+async fn run_translator<M, F>(
+    mut translator: M,
+    batchizer: F,
+    textures: Textures,
+    textures_mut: &mut Textures,
+    cfg: &Configuration,
+    progress: Option<&watch::Sender<Progress>>,
+    total: usize,
+    shared_cache: Option<Arc<SharedCache>>,
+) -> Result<RunSummary>
+where
+    M: Translate<ChatCompletionMessage> + Send + 'static,
+    F: Batchizer<ChatCompletionMessage>,
+{
+    let mut summary = RunSummary::default();
+    let mut curr_progress = Progress {
+        translated: 0,
+        total,
+    };
+    if let Some(progress) = progress {
+        progress.send_replace(curr_progress);
+    }
+    let start_index = textures.curr_index;
+    let priority = priority_lines(cfg.priority.as_ref(), &textures)?;
+    // the priority lines to translate in this run, announced once all translated
+    let mut pending_priority = (0..textures.lines.len())
+        .filter(|&i| priority[i] && textures.lines[i].needs_translation())
+        .filter(|&i| match &cfg.specify_range {
+            Some(ranges) => ranges.iter().any(|&(s, e)| s <= i && i <= e),
+            None => i >= start_index,
+        })
+        .collect::<BTreeSet<_>>();
+    // the shards and the jobs without a file are output at the end only
+    let live = cfg.live_output
+        && !cfg.in_place
+        && cfg.sample.is_none()
+        && textures.shard.is_none()
+        && !textures.name.is_empty();
+    let output_cache = OutputCache::default();
+    let mut output_batches = 0;
     let textures_arc = Arc::new(textures);
 
-    // handle ctrl-c
+    // handle ctrl-c, the listener stops with the run, e.g. the runs of the jobs of serve
     let (close_tx, mut close_rx) = mpsc::channel::<i32>(1);
     let close_tx_c = close_tx.clone();
-    tokio::spawn(async move {
+    let _ctrl_c = AbortOnDrop(tokio::spawn(async move {
         tokio::signal::ctrl_c()
             .await
             .expect("failed to listen for event");
         if let Err(e) = close_tx_c.send(3).await {
             eprintln!("Failed to send close signal: {}", e);
         }
-    });
+    }));
 
     // handle translations
-    let (tx, mut rx) = mpsc::channel::<TranslatedLine>(1);
+    let (tx, mut rx) = mpsc::channel::<TranslatedLine>(RESULT_CHANNEL_CAPACITY);
     let textures_r = textures_arc.clone();
-    let tx_r = tx.clone();
     let close_tx_r = close_tx.clone();
-    let mut wait_for_translations = 0;
-    if let Some(chatgpt_opt) = &cfg.chatgpt_opt {
-        wait_for_translations += 1;
-        let batchizer = TokenizedBatchizer {
-            bep: tiktoken_rs::cl100k_base().unwrap(),
-            max_tokens: cfg.batchizer_opt.max_tokens,
-            extract_regex: cfg.capture_regex.as_ref().map(|r| Regex::new(r).unwrap()),
-        };
-        let mut chat_gpt = TranslateChatGPT::new(
-            chatgpt_opt.clone(),
-            cfg.specify_range.clone(),
-            cfg.lang_from.to_name(),
-            cfg.lang_to.to_name(),
-        );
-        tokio::spawn(async move {
-            chat_gpt.translate(textures_r, batchizer, tx_r).await;
-            if let Err(e) = close_tx_r.send(1).await {
-                eprintln!("Failed to send close signal: {}", e);
-            }
-        });
-    }
-    // todo baidu, deepl
+    let mut wait_for_translations = 1;
+    let metrics = Metrics::default();
+    let mut run = RunOptions {
+        metrics: metrics.clone(),
+        priority,
+        verbose: cfg.verbose,
+        preview: cfg.preview,
+        shared_cache: shared_cache.clone(),
+        budget: None,
+        control: cfg.control.clone(),
+    };
+    let cache_extractor = LineExtractor::new(cfg).ok();
+    let budget = cfg.budget.clone().map(|b| Arc::new(Budget::new(b)));
+    run.budget = budget.clone();
+    tokio::spawn(async move {
+        translator.translate(textures_r, batchizer, tx, run).await;
+        if let Err(e) = close_tx_r.send(1).await {
+            eprintln!("Failed to send close signal: {}", e);
+        }
+    });
 
+    let protected = ProtectedTerms::new(&cfg.protected_terms);
     let mut timer = Timer::new(std::time::Duration::from_secs(60)); // save every 60 seconds
     loop {
         select! {
-            Some(line) = rx.recv() => {
+            // the translations buffered before the close signal are all updated first
+            biased;
+            Some(mut line) = rx.recv() => {
+                line.content = protected.unmask(&line.content);
+                curr_progress.translated += line.batch_range.1 - line.batch_range.0 + 1;
+                if line.refused.is_none() {
+                    summary.batches += 1;
+                    summary.lines += line.batch_range.1 - line.batch_range.0 + 1;
+                }
+                if let Some(usage) = &line.usage {
+                    summary.prompt_tokens += usage.prompt_tokens as u64;
+                    summary.completion_tokens += usage.completion_tokens as u64;
+                }
+                if let Some(progress) = progress {
+                    progress.send_replace(curr_progress);
+                }
+                if let Some(control) = &cfg.control {
+                    control.update(&textures_mut.name, curr_progress);
+                }
+                if let Some(hooks) = cfg.hooks.as_ref().filter(|_| line.refused.is_none()) {
+                    let offset = textures_mut.offset();
+                    let range = (line.batch_range.0 + offset, line.batch_range.1 + offset);
+                    let Progress { translated, total } = curr_progress;
+                    hooks.batch_translated(&textures_mut.name, range, translated, total);
+                }
+                let translator = line.translator;
+                let (start, end) = line.batch_range;
+                if let (Some(cache), Some(extractor)) = (&shared_cache, &cache_extractor) {
+                    if line.refused.is_none() && line.api.as_deref() != Some(SHARED_CACHE_API) {
+                        // the cache is a help, a failure does not stop the run
+                        if let Err(e) = cache.put_batch(textures_mut, extractor, &line.content, line.batch_range) {
+                            let tag = console::err(Tone::Error, "[Cache]");
+                            let e = format!("{:#}", e);
+                            eprintln!("{} {}", tag, t!(Msg::CacheFailed, start, end, e));
+                        }
+                    }
+                }
+                if cfg.preview {
+                    print_parsed(&textures_arc, cache_extractor.as_ref(), &line);
+                }
+                textures_mut.append_journal(&line)?;
                 textures_mut.update(line);
+                if cfg.specify_range.is_none() {
+                    textures_mut.curr_index = textures_mut.resume_index(translator, start_index);
+                }
+                let pending = pending_priority.len();
+                pending_priority.retain(|&i| i < start || i > end);
+                if pending > 0 && pending_priority.is_empty() {
+                    textures_mut.save()?;
+                    println!("[Priority] {}", t!(Msg::PriorityDone));
+                }
                 if timer.finished() {
                     textures_mut.save()?;
+                    if live && summary.batches > output_batches {
+                        output_batches = summary.batches;
+                        // a failed live output is retried with the next save
+                        if let Err(e) = live_output(cfg, textures_mut, &output_cache) {
+                            let tag = console::err(Tone::Error, "[Live]");
+                            let e = format!("{:#}", e);
+                            eprintln!("{} {}", tag, t!(Msg::LiveFailed, e));
+                        }
+                    }
                 }
             }
             Some(n) = close_rx.recv() => {
@@ -87,12 +602,68 @@ pub async fn translate(
             break;
         }
     }
-    Ok(())
+    if let Some(budget) = budget.filter(|b| b.is_exceeded()) {
+        let (tokens, cost) = budget.spent();
+        let cost = format!("{:.2}", cost);
+        println!("[Budget] {}", t!(Msg::BudgetPaused, tokens, cost));
+        summary.paused = true;
+    }
+    summary.clients = metrics.snapshot();
+    Ok(summary)
+}
+
+/// aborts the task when dropped
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// the source lines of the batch beside the translated lines extracted from the response
+fn print_parsed(textures: &Textures, extractor: Option<&LineExtractor>, line: &TranslatedLine) {
+    let Some(extractor) = extractor else {
+        return;
+    };
+    let sources = textures.batch_lines(line.batch_range);
+    let translated = extractor.extract(&line.content);
+    let parsed = t!(Msg::PreviewParsed, translated.len(), sources.len());
+    println!("[Preview] {}", parsed);
+    for (k, i) in sources.iter().enumerate() {
+        let translation = translated.get(k).map_or("(missing)", |t| t.as_str());
+        let n = textures.offset() + i + 1;
+        println!("  {}: {} => {}", n, textures.lines[*i].content, translation);
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Translator {
     ChatGPT,
+    /// the second pass of the ChatGPT translations, see refine
+    ChatGPTRefined,
+    Gemini,
+}
+
+/// the api of the batches taken from the shared cache, they are not added to it again
+const SHARED_CACHE_API: &str = "shared_cache";
+
+/// how the batches of a run are scheduled and reported
+pub struct RunOptions {
+    /// the metrics of the workers, shared with the run
+    pub metrics: Metrics,
+    /// whether every line is of high priority, indexed like the lines, see priority_lines
+    pub priority: Vec<bool>,
+    /// print every request and response
+    pub verbose: bool,
+    /// print the messages sent with the batch as they are, with the prompts and the history
+    pub preview: bool,
+    /// the batches translated by the other runs of the game are taken from it
+    pub shared_cache: Option<Arc<SharedCache>>,
+    /// the workers take no more batches once the usage is over it
+    pub budget: Option<Arc<Budget>>,
+    /// the workers wait while it is paused or beyond its concurrency, see lottr ctl
+    pub control: Option<Arc<Control>>,
 }
 
 #[async_trait]
@@ -102,6 +673,7 @@ pub trait Translate<T> {
         text: Arc<Textures>,
         batchizer: F,
         sender: Sender<TranslatedLine>,
+        run: RunOptions,
     ) where
         F: Batchizer<T>;
 }
@@ -109,12 +681,53 @@ pub trait Translate<T> {
 #[async_trait]
 pub trait ConcurrentTranslate<T>: Translate<T> {
     type Client: TranslateClient<T>;
-    fn create_batch_queue<F>(&self, batchizer: F, textures: &Textures) -> Vec<BatchPackage<T>>
+    fn create_batch_queue<F>(&self, batchizer: &F, textures: &Textures) -> Vec<BatchPackage<T>>
     where
         F: Batchizer<T>;
 
     fn create_client(&mut self) -> Self::Client;
     fn max_concurrent(&self) -> i32;
+    /// tune the concurrency within the range instead of the fixed max_concurrent
+    fn adaptive_concurrency(&self) -> Option<ConcurrencyRange> {
+        None
+    }
+    /// the requests shared limiter across translators, every request must acquire a permit
+    fn request_limiter(&self) -> Option<Arc<Semaphore>> {
+        None
+    }
+    /// split the translated content into lines, to check the batches and adapt the batch budget
+    fn line_extractor(&self) -> Option<Arc<LineExtractor>> {
+        None
+    }
+}
+
+/// the translated batches waiting to be saved, the workers wait when it is full
+const RESULT_CHANNEL_CAPACITY: usize = 64;
+/// retry a mismatched batch this many times before the budget is halved
+const MISMATCH_BEFORE_SHRINK: usize = 2;
+/// accept a mismatched batch after this many times, the output diagnostic will record it
+const MISMATCH_ACCEPTED: usize = 6;
+/// a worker beyond the adaptive concurrency checks again after this pause
+const CONCURRENCY_PAUSE: std::time::Duration = std::time::Duration::from_millis(500);
+/// a rate limited worker waits this long before its next request, unless told by retry-after
+const RATE_LIMIT_PAUSE: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// the concurrency limit of the workers now
+fn concurrency_limit(concurrency: &Mutex<AdaptiveConcurrency>) -> usize {
+    concurrency.lock().unwrap().limit()
+}
+
+/// the client of the next worker after t whose key is not rejected
+fn next_client<C: TranslateClient<T>, T>(
+    clients: &[Arc<C>],
+    t: usize,
+    revoked: &Mutex<HashSet<String>>,
+) -> Option<Arc<C>> {
+    let revoked = revoked.lock().unwrap();
+    (1..=clients.len())
+        .map(|k| &clients[(t + k) % clients.len()])
+        .find(|c| !revoked.contains(&c.api()))
+        .cloned()
 }
 
 #[async_trait]
@@ -128,56 +741,306 @@ where
         textures: Arc<Textures>,
         batchizer: F,
         sender: Sender<TranslatedLine>,
+        run: RunOptions,
     ) where
         F: Batchizer<T>,
     {
-        let batch_queue = self.create_batch_queue(batchizer, textures.as_ref());
+        let RunOptions {
+            metrics,
+            priority,
+            verbose,
+            preview,
+            shared_cache,
+            budget: spending,
+            control,
+        } = run;
+        let batch_queue = BatchQueue::new(
+            self.create_batch_queue(&batchizer, textures.as_ref()),
+            |(_, range)| {
+                let lines = textures.batch_lines(*range);
+                (lines.iter().any(|&i| priority[i]), lines.len())
+            },
+        );
         let batch_len = batch_queue.len();
-        let batch_queue = Arc::new(Mutex::new(batch_queue));
+        let batch_queue = Arc::new(batch_queue);
+        let budget = Arc::new(Mutex::new(AdaptiveBudget::new(batchizer.max_tokens())));
+        let batchizer = Arc::new(batchizer);
+        let line_extractor = self.line_extractor();
         let (close_tx, mut close_rx) = mpsc::channel::<i32>(1);
-        let max_concurrent = self.max_concurrent().min(batch_len as i32);
-        println!(
-            "start translate, batch len: {}, max concurrent {}",
-            batch_len, max_concurrent
+        // the workers up to the max of the range are started, those beyond the limit pause
+        let concurrency = self.adaptive_concurrency().map(|range| {
+            let start = self.max_concurrent().max(1) as usize;
+            Arc::new(Mutex::new(AdaptiveConcurrency::new(range, start)))
+        });
+        let max_concurrent = match &concurrency {
+            Some(c) => c.lock().unwrap().max() as i32,
+            None => self.max_concurrent(),
+        }
+        .min(batch_len as i32);
+        println!("{}", t!(Msg::StartTranslate, batch_len, max_concurrent));
+        if let Some(control) = &control {
+            control.start(&textures.name, max_concurrent as usize, Progress::default());
+        }
+        // a worker whose key is rejected moves to the client of another worker
+        let clients = Arc::new(
+            (0..max_concurrent)
+                .map(|_| Arc::new(self.create_client()))
+                .collect::<Vec<_>>(),
         );
+        let revoked = Arc::new(Mutex::new(HashSet::new()));
         for t in 0..max_concurrent {
             let batch_queue = batch_queue.clone();
             let sender = sender.clone();
-            let client = self.create_client();
+            let mut client = clients[t as usize].clone();
+            let clients = clients.clone();
+            let revoked = revoked.clone();
             let close_tx = close_tx.clone();
+            let request_limiter = self.request_limiter();
+            let budget = budget.clone();
+            let batchizer = batchizer.clone();
+            let textures = textures.clone();
+            let line_extractor = line_extractor.clone();
+            let concurrency = concurrency.clone();
+            let shared_cache = shared_cache.clone();
+            let spending = spending.clone();
+            let control = control.clone();
+            let mut worker_metrics = WorkerMetrics::new(metrics.clone(), t as usize, client.api());
             tokio::spawn(async move {
                 let mut batch_and_range: Option<BatchPackage<T>> = None;
+                let mut mismatches = 0;
+                // the last request failed or mismatched, the next one sends its batch again
+                let mut retry = false;
+                // the requests of the batch sent again, recorded with its translation
+                let mut retries = 0;
                 loop {
+                    if spending.as_ref().is_some_and(|s| s.is_exceeded()) {
+                        // left to the next run
+                        if let Some(br) = batch_and_range.take() {
+                            batch_queue.requeue(br, retry);
+                        }
+                        break;
+                    }
+                    let held = control.as_ref().is_some_and(|c| c.holds(t as usize));
+                    let limited = concurrency
+                        .as_ref()
+                        .is_some_and(|c| t as usize >= concurrency_limit(c));
+                    if held || limited {
+                        // hand the batch over to the workers within the limit
+                        if let Some(br) = batch_and_range.take() {
+                            batch_queue.requeue(br, retry);
+                        }
+                        if batch_queue.is_finished() {
+                            break;
+                        }
+                        tokio::time::sleep(CONCURRENCY_PAUSE).await;
+                        continue;
+                    }
                     if batch_and_range.is_none() {
-                        let mut batch_queue = batch_queue.lock().unwrap();
-                        batch_and_range = batch_queue.pop();
-                        if batch_and_range.is_none() {
+                        let Some((br, sent_before)) = batch_queue.pop().await else {
                             break;
+                        };
+                        batch_and_range = Some(br);
+                        retry = sent_before;
+                        retries = sent_before as u32;
+                        mismatches = 0;
+                    }
+                    let br = batch_and_range.as_ref().unwrap();
+                    // split the batch by the reduced budget, the rest goes back to the queue
+                    let reduced = Some(budget.lock().unwrap())
+                        .filter(|b| b.is_reduced())
+                        .map(|b| b.tokens());
+                    if let Some(tokens) = reduced.filter(|_| br.1 .0 < br.1 .1) {
+                        let mut batches = split_batch(batchizer.as_ref(), &textures, br.1, tokens);
+                        if batches.len() > 1 {
+                            batch_and_range = Some(batches.remove(0));
+                            batch_queue.push_front(batches);
+                            mismatches = 0;
                         }
                     }
                     let br = batch_and_range.as_ref().unwrap();
+                    // translated by another run of the shared cache since the batch was queued
+                    let cached = shared_cache
+                        .as_ref()
+                        .and_then(|cache| cache.batch_content(&textures, br.1));
+                    if let Some(content) = cached {
+                        let mut translated =
+                            TranslatedLine::new(client.translator(), content, br.1 .0, br.1 .1);
+                        translated.api = Some(SHARED_CACHE_API.to_string());
+                        if let Err(err) = sender.send(translated).await {
+                            println!("send change error: {:?}", err);
+                        }
+                        batch_and_range = None;
+                        batch_queue.done();
+                        continue;
+                    }
                     // println!("{} request: {}-{}", t, br.1 .0, br.1 .1);
-                    let result = client.request(br).await;
+                    let _permit = match &request_limiter {
+                        Some(limiter) => Some(limiter.acquire().await.expect("limiter closed")),
+                        None => None,
+                    };
+                    if preview {
+                        let request = t!(Msg::PreviewRequest, br.1 .0, br.1 .1);
+                        println!("[Preview] {}", request);
+                        for message in client.messages(br) {
+                            println!("{:?}", message);
+                        }
+                    }
+                    let sent = Instant::now();
+                    let mut result = client.request(br).await;
+                    if let (Some(spending), Ok(translated)) = (&spending, &result) {
+                        if let Some(usage) = &translated.usage {
+                            spending.spend(usage);
+                        }
+                    }
+                    // a refusal in plain words is a filtered batch
+                    if let Ok(translated) = &result {
+                        let refused = line_extractor.as_ref().is_some_and(|e| {
+                            e.extract(&translated.content).len() != textures.batch_lines(br.1).len()
+                        }) && is_refusal(&translated.content);
+                        if refused {
+                            result = Err(ApiError::refusal(&translated.content).into());
+                        }
+                    }
+                    worker_metrics.record(sent, retry, &result);
+                    retry = true;
+                    if let Some(concurrency) = &concurrency {
+                        let mut concurrency = concurrency.lock().unwrap();
+                        match &result {
+                            Ok(_) => concurrency.succeed(sent.elapsed()),
+                            Err(err) => {
+                                if matches!(
+                                    error_category(err),
+                                    "rate_limit" | "server" | "timeout"
+                                ) {
+                                    concurrency.back_off();
+                                }
+                            }
+                        }
+                    }
                     match result {
-                        Ok(translated) => {
-                            println!(
-                                "{} request: {}-{} total {}\n{:?}\n",
-                                t,
-                                br.1 .0,
-                                br.1 .1,
-                                br.1 .1 - br.1 .0 + 1,
-                                br.0[0]
-                            );
-                            println!("{} response:\n{}\n", t, translated.content);
+                        Ok(mut translated) => {
+                            if verbose {
+                                println!(
+                                    "{} request: {}-{} total {}\n{:?}\n",
+                                    t,
+                                    br.1 .0,
+                                    br.1 .1,
+                                    br.1 .1 - br.1 .0 + 1,
+                                    br.0[0]
+                                );
+                                println!("{} response:\n{}\n", t, translated.content);
+                            }
+                            if preview {
+                                let response = t!(Msg::PreviewResponse);
+                                println!("[Preview] {}\n{}\n", response, translated.content);
+                            }
+                            let mismatched = line_extractor.as_ref().is_some_and(|e| {
+                                e.extract(&translated.content).len()
+                                    != textures.batch_lines(br.1).len()
+                            });
+                            if mismatched {
+                                mismatches += 1;
+                                if mismatches % MISMATCH_BEFORE_SHRINK == 0 {
+                                    budget.lock().unwrap().shrink();
+                                }
+                                if mismatches < MISMATCH_ACCEPTED {
+                                    let (start, end) = br.1;
+                                    println!("{}", t!(Msg::Mismatch, t, start, end, mismatches));
+                                    retries += 1;
+                                    continue;
+                                }
+                            } else {
+                                budget.lock().unwrap().succeed();
+                                client.remember(br, &translated);
+                            }
+                            translated.retries = retries;
                             if let Err(err) = sender.send(translated).await {
                                 println!("send change error: {:?}", err);
                             }
                             // set batch_and_range to None, so that we can pop a new batch from the queue
                             batch_and_range = None;
+                            batch_queue.done();
                         }
                         Err(err) => {
-                            println!("{} request error: {:?}", t, err);
-                            // keep batch_and_range not changed, so that it will be retried
+                            let error = format!("{:?}", err);
+                            println!("{}", t!(Msg::RequestError, t, error));
+                            retries += 1;
+                            let (start, end) = br.1;
+                            match err.downcast_ref::<ApiError>() {
+                                Some(ApiError::InvalidApiKey { .. }) => {
+                                    let api = client.api();
+                                    revoked.lock().unwrap().insert(api.clone());
+                                    let Some(next) = next_client(&clients, t as usize, &revoked)
+                                    else {
+                                        let tag = console::err(Tone::Error, "[Api]");
+                                        eprintln!("{} {}", tag, t!(Msg::KeysRejected, t));
+                                        if let Some(br) = batch_and_range.take() {
+                                            batch_queue.requeue(br, true);
+                                        }
+                                        break;
+                                    };
+                                    let next_api = next.api();
+                                    println!("[Api] {}", t!(Msg::KeyRejected, api, t, next_api));
+                                    client = next;
+                                }
+                                Some(
+                                    e @ (ApiError::ContextLengthExceeded { .. }
+                                    | ApiError::ContentFilter { .. }),
+                                ) => {
+                                    if matches!(e, ApiError::ContextLengthExceeded { .. }) {
+                                        budget.lock().unwrap().shrink();
+                                    }
+                                    // sent again the batch fails the same, the halves go on and
+                                    // are halved again until they fit
+                                    let tokens = budget.lock().unwrap().tokens();
+                                    let mut halves =
+                                        split_halves(batchizer.as_ref(), &textures, br.1, tokens);
+                                    if halves.is_empty() {
+                                        match e {
+                                            // the line alone is refused, skipped with the reason
+                                            ApiError::ContentFilter { message, .. } => {
+                                                let tag = console::err(Tone::Warning, "[Refused]");
+                                                let skipped = t!(Msg::Refused, start, end, message);
+                                                eprintln!("{} {}", tag, skipped);
+                                                let refused = TranslatedLine::refused(
+                                                    client.translator(),
+                                                    start,
+                                                    end,
+                                                    message.clone(),
+                                                );
+                                                if let Err(err) = sender.send(refused).await {
+                                                    println!("send change error: {:?}", err);
+                                                }
+                                            }
+                                            _ => eprintln!(
+                                                "{} {}",
+                                                console::err(Tone::Warning, "[Api]"),
+                                                t!(Msg::LeftUntranslated, start, end, e)
+                                            ),
+                                        }
+                                        batch_and_range = None;
+                                        batch_queue.done();
+                                        continue;
+                                    }
+                                    batch_and_range = Some(halves.remove(0));
+                                    batch_queue.push_front(halves);
+                                    mismatches = 0;
+                                    retry = false;
+                                }
+                                Some(ApiError::RateLimit { retry_after, .. }) => {
+                                    let pause = retry_after.unwrap_or(RATE_LIMIT_PAUSE);
+                                    if let Some(br) = batch_and_range.take() {
+                                        batch_queue.requeue(br, true);
+                                    }
+                                    tokio::time::sleep(pause).await;
+                                }
+                                _ => {
+                                    // back to the front of the queue, the next free worker retries it
+                                    if let Some(br) = batch_and_range.take() {
+                                        batch_queue.requeue(br, true);
+                                    }
+                                }
+                            }
                         }
                     }
                 }
@@ -199,15 +1062,119 @@ where
     }
 }
 
+/// batchize the range again within max_tokens
+fn split_batch<T, F: Batchizer<T>>(
+    batchizer: &F,
+    textures: &Textures,
+    (start, end): (usize, usize),
+    max_tokens: usize,
+) -> Vec<BatchPackage<T>> {
+    let mut batches = vec![];
+    let mut i = start;
+    while i <= end {
+        let (batch, size) = batchizer.batchize_with(textures, i, Some(end), max_tokens);
+        if size == 0 {
+            break;
+        }
+        if !batch.is_empty() {
+            batches.push((batch, (i, i + size - 1)));
+        }
+        i += size;
+    }
+    batches
+}
+
+/// batchize the two halves of the range, nothing for a single line
+fn split_halves<T, F: Batchizer<T>>(
+    batchizer: &F,
+    textures: &Textures,
+    (start, end): (usize, usize),
+    max_tokens: usize,
+) -> Vec<BatchPackage<T>> {
+    if start >= end {
+        return vec![];
+    }
+    let mid = start + (end - start) / 2;
+    [(start, mid), (mid + 1, end)]
+        .into_iter()
+        .flat_map(|range| split_batch(batchizer, textures, range, max_tokens))
+        .collect()
+}
+
 pub type BatchPackage<T> = (Vec<T>, (usize, usize));
 
+/// batchize the specify_range, or all the lines from curr_index, in the reversed order for pop
+pub fn batch_queue<T, F: Batchizer<T>>(
+    batchizer: &F,
+    textures: &Textures,
+    specify_range: &Option<Vec<(usize, usize)>>,
+) -> Vec<BatchPackage<T>> {
+    let mut batch_queue = Vec::new();
+    if let Some(specify_range) = specify_range {
+        // specify range
+        for (start, end) in specify_range.iter() {
+            let mut i = *start;
+            while i <= *end {
+                let (batch, size) = batchizer.batchize(textures, i, Some(*end));
+                println!("specify_range: {}-{}, i: {}, size: {}", start, end, i, size);
+                if size == 0 {
+                    eprintln!("batch size is 0");
+                    break;
+                }
+                if !batch.is_empty() {
+                    batch_queue.push((batch, (i, i + size - 1)));
+                }
+                i += size;
+            }
+        }
+    } else {
+        // all
+        let mut i = textures.curr_index;
+        while i < textures.lines.len() {
+            let (batch, size) = batchizer.batchize(textures, i, None);
+            if size == 0 {
+                eprintln!("batch size is 0");
+                break;
+            }
+            if !batch.is_empty() {
+                batch_queue.push((batch, (i, i + size - 1)));
+            }
+            i += size;
+        }
+    }
+    // reverse for pop
+    batch_queue.reverse();
+    batch_queue
+}
+
 #[async_trait]
 pub trait TranslateClient<T>: Send + Sync + 'static {
     async fn request(&self, batch_and_range: &BatchPackage<T>) -> Result<TranslatedLine>;
+    /// the messages of the request of the batch, with the prompts and the history
+    fn messages(&self, batch_and_range: &BatchPackage<T>) -> Vec<T>;
+    /// keep the accepted translation of the batch in the conversation of the worker
+    fn remember(&self, _batch_and_range: &BatchPackage<T>, _translated: &TranslatedLine) {}
+    /// the masked api key of the client, to tell the clients apart in the metrics
+    fn api(&self) -> String;
+    /// the translator the translated lines are marked with
+    fn translator(&self) -> Translator;
 }
 
 pub trait Batchizer<T>: Send + Sync + 'static {
-    fn batchize(&self, textures: &Textures, index: usize, end: Option<usize>) -> (Vec<T>, usize);
+    /// the batch from index and the count of the lines it consumed,
+    /// the batch is empty when none of the consumed lines needs translation
+    fn batchize(&self, textures: &Textures, index: usize, end: Option<usize>) -> (Vec<T>, usize) {
+        self.batchize_with(textures, index, end, self.max_tokens())
+    }
+    /// batchize within the max_tokens instead of the configured one
+    fn batchize_with(
+        &self,
+        textures: &Textures,
+        index: usize,
+        end: Option<usize>,
+        max_tokens: usize,
+    ) -> (Vec<T>, usize);
+    fn max_tokens(&self) -> usize;
     fn extract(&self, content: &str) -> Option<String>;
 }
 
@@ -257,4 +1224,195 @@ mod test {
         let wrapper: Wrapper = toml::from_str(en_str).unwrap();
         assert_eq!(wrapper.lang.to_name(), "Chinese");
     }
+
+    /// ten lines per batch
+    struct TenLines;
+
+    impl super::Batchizer<String> for TenLines {
+        fn batchize_with(
+            &self,
+            textures: &crate::textures::Textures,
+            index: usize,
+            end: Option<usize>,
+            _: usize,
+        ) -> (Vec<String>, usize) {
+            let end = end.unwrap_or(textures.lines.len() - 1).min(index + 9);
+            (vec![String::new()], end + 1 - index)
+        }
+        fn max_tokens(&self) -> usize {
+            100
+        }
+        fn extract(&self, content: &str) -> Option<String> {
+            Some(content.to_string())
+        }
+    }
+
+    /// answers every line as its own batch at once, more than the result channel holds
+    struct Instant;
+
+    #[async_trait::async_trait]
+    impl super::Translate<super::ChatCompletionMessage> for Instant {
+        async fn translate<F>(
+            &mut self,
+            textures: std::sync::Arc<crate::textures::Textures>,
+            _: F,
+            sender: tokio::sync::mpsc::Sender<crate::textures::TranslatedLine>,
+            _: super::RunOptions,
+        ) where
+            F: super::Batchizer<super::ChatCompletionMessage>,
+        {
+            for i in 0..textures.lines.len() {
+                let content = format!("(1) {}", i);
+                let line =
+                    crate::textures::TranslatedLine::new(super::Translator::ChatGPT, content, i, i);
+                sender.send(line).await.unwrap();
+            }
+        }
+    }
+
+    struct OneLine;
+
+    impl super::Batchizer<super::ChatCompletionMessage> for OneLine {
+        fn batchize_with(
+            &self,
+            _: &crate::textures::Textures,
+            _: usize,
+            _: Option<usize>,
+            _: usize,
+        ) -> (Vec<super::ChatCompletionMessage>, usize) {
+            (vec![], 1)
+        }
+        fn max_tokens(&self) -> usize {
+            100
+        }
+        fn extract(&self, content: &str) -> Option<String> {
+            Some(content.to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_translator_all_received() {
+        let table = include_str!("../../assets/options_text.toml")
+            .parse::<toml::Table>()
+            .unwrap();
+        let cfg: crate::Configuration = toml::Value::Table(table).try_into().unwrap();
+        let textures = crate::textures::Textures {
+            lines: (0..super::RESULT_CHANNEL_CAPACITY * 4)
+                .map(|i| crate::textures::TextureLine::new(i, 1, i.to_string(), false))
+                .collect(),
+            curr_index: 0,
+            version: crate::textures::TEXTURES_VERSION,
+            source: None,
+            name: String::new(),
+            shard: None,
+            dirs: Default::default(),
+        };
+        let mut textures_mut = textures.clone();
+        let total = textures.lines.len();
+        let summary = super::run_translator(
+            Instant,
+            OneLine,
+            textures,
+            &mut textures_mut,
+            &cfg,
+            None,
+            total,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(summary.batches, total);
+        assert!(textures_mut.lines.iter().all(|l| !l.translated.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn test_abort_on_drop() {
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let task = super::AbortOnDrop(tokio::spawn(async move {
+            std::future::pending::<()>().await;
+            let _ = tx.send(());
+        }));
+        drop(task);
+        // the aborted task drops its sender without sending
+        assert!(rx.await.is_err());
+    }
+
+    #[test]
+    fn test_sample_ranges() {
+        let textures = crate::textures::Textures {
+            lines: (0..100)
+                .map(|i| crate::textures::TextureLine::new(i, 1, i.to_string(), false))
+                .collect(),
+            curr_index: 0,
+            version: crate::textures::TEXTURES_VERSION,
+            source: None,
+            name: String::new(),
+            shard: None,
+            dirs: Default::default(),
+        };
+        let (ranges, batches) = super::sample_ranges(&TenLines, &textures, &None, 3);
+        assert_eq!(batches, 10);
+        assert_eq!(ranges, vec![(10, 19), (50, 59), (80, 89)]);
+        let (ranges, batches) = super::sample_ranges(&TenLines, &textures, &Some(vec![(0, 24)]), 5);
+        assert_eq!(batches, 3);
+        assert_eq!(ranges, vec![(0, 9), (10, 19), (20, 24)]);
+    }
+
+    #[test]
+    fn test_trial_ranges() {
+        let table = include_str!("../../assets/options_text.toml")
+            .parse::<toml::Table>()
+            .unwrap();
+        let mut cfg: crate::Configuration = toml::Value::Table(table).try_into().unwrap();
+        let textures = crate::textures::Textures {
+            lines: (0..100)
+                .map(|i| crate::textures::TextureLine::new(i, 1, i.to_string(), false))
+                .collect(),
+            curr_index: 30,
+            version: crate::textures::TEXTURES_VERSION,
+            source: None,
+            name: String::new(),
+            shard: None,
+            dirs: Default::default(),
+        };
+        assert_eq!(super::trial_ranges(&TenLines, &textures, &cfg), None);
+        cfg.sample = Some(3);
+        let (ranges, _) = super::trial_ranges(&TenLines, &textures, &cfg).unwrap();
+        assert_eq!(ranges.len(), 3);
+        // the preview is the first batch from the resume index, it takes precedence
+        cfg.preview = true;
+        assert_eq!(
+            super::trial_ranges(&TenLines, &textures, &cfg),
+            Some((vec![(30, 39)], 7))
+        );
+        cfg.specify_range = Some(vec![(55, 70)]);
+        assert_eq!(
+            super::trial_ranges(&TenLines, &textures, &cfg),
+            Some((vec![(55, 64)], 2))
+        );
+    }
+
+    #[test]
+    fn test_split_halves() {
+        let textures = crate::textures::Textures {
+            lines: (0..20)
+                .map(|i| crate::textures::TextureLine::new(i, 1, i.to_string(), false))
+                .collect(),
+            curr_index: 0,
+            version: crate::textures::TEXTURES_VERSION,
+            source: None,
+            name: String::new(),
+            shard: None,
+            dirs: Default::default(),
+        };
+        let ranges = |range| {
+            super::split_halves(&TenLines, &textures, range, 100)
+                .into_iter()
+                .map(|(_, range)| range)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ranges((0, 9)), vec![(0, 4), (5, 9)]);
+        assert_eq!(ranges((6, 7)), vec![(6, 6), (7, 7)]);
+        assert_eq!(ranges((3, 3)), vec![]);
+    }
 }
@@ -0,0 +1,354 @@
+use std::io::stdout;
+
+use anyhow::Result;
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Direction, Layout},
+    style::{Modifier, Style},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
+    Frame, Terminal,
+};
+
+use crate::{
+    failed::{self, FailReason, FailedReport},
+    i18n::Msg,
+    outputs::LineExtractor,
+    t,
+    textures::{Textures, TranslatedLine},
+    translators::Translator,
+    Configuration,
+};
+
+struct FailedBatch {
+    range: (usize, usize),
+    /// the lines of the range sent to the translator
+    lines: Vec<usize>,
+    extracted: usize,
+    requeue: bool,
+}
+
+impl FailedBatch {
+    fn expected(&self) -> usize {
+        self.lines.len()
+    }
+}
+
+struct App {
+    textures: Textures,
+    /// the primary translator of the output, whose batches are reviewed
+    translator: Translator,
+    extractor: LineExtractor,
+    batches: Vec<FailedBatch>,
+    state: ListState,
+    editing: Option<String>,
+    message: String,
+}
+
+impl App {
+    fn new(config: &Configuration, textures: Textures) -> Result<Self> {
+        let extractor = LineExtractor::new(config)?;
+        let translator = config.translator_selection().primary();
+        let mut batches = vec![];
+        for line in textures.lines.iter() {
+            if let Some(translated) = find_translated(&line.translated, translator) {
+                let extracted = extractor.extract(&translated.content).len();
+                let lines = textures.batch_lines(translated.batch_range);
+                if extracted != lines.len() {
+                    batches.push(FailedBatch {
+                        range: translated.batch_range,
+                        lines,
+                        extracted,
+                        requeue: false,
+                    });
+                }
+            }
+        }
+        let mut state = ListState::default();
+        if !batches.is_empty() {
+            state.select(Some(0));
+        }
+        Ok(Self {
+            textures,
+            translator,
+            extractor,
+            batches,
+            state,
+            editing: None,
+            message: "↑/↓ select, e edit, r re-queue, s save, q quit".to_string(),
+        })
+    }
+
+    fn selected(&self) -> Option<&FailedBatch> {
+        self.state.selected().and_then(|i| self.batches.get(i))
+    }
+
+    fn selected_content(&self) -> String {
+        self.selected()
+            .and_then(|b| {
+                find_translated(&self.textures.lines[b.range.0].translated, self.translator)
+            })
+            .map(|t| t.content.clone())
+            .unwrap_or_default()
+    }
+
+    fn move_selection(&mut self, step: isize) {
+        if self.batches.is_empty() {
+            return;
+        }
+        let i = self.state.selected().unwrap_or(0) as isize + step;
+        let i = i.clamp(0, self.batches.len() as isize - 1);
+        self.state.select(Some(i as usize));
+    }
+
+    fn apply_edit(&mut self, content: String) {
+        let Some(i) = self.state.selected() else {
+            return;
+        };
+        let batch = &mut self.batches[i];
+        let line = &mut self.textures.lines[batch.range.0];
+        let translated = match line
+            .translated
+            .iter_mut()
+            .find(|t| t.translator == self.translator)
+        {
+            Some(translated) => translated,
+            None => return,
+        };
+        translated.content = content;
+        batch.extracted = self.extractor.extract(&translated.content).len();
+        self.message = if batch.extracted == batch.expected() {
+            format!("batch {}-{} aligned", batch.range.0, batch.range.1)
+        } else {
+            format!(
+                "batch {}-{} still mismatch, expected {} got {}",
+                batch.range.0,
+                batch.range.1,
+                batch.expected(),
+                batch.extracted
+            )
+        };
+    }
+
+    fn save(&mut self) -> Result<()> {
+        self.textures.save()?;
+        let requeue = self
+            .batches
+            .iter()
+            .filter(|b| b.requeue)
+            .map(|b| failed::FailedBatch::of(&self.textures, b.range, FailReason::Requeued))
+            .collect::<Vec<_>>();
+        let count = requeue.len();
+        let (dirs, name) = (&self.textures.dirs, &self.textures.name);
+        let mut report = FailedReport::load(dirs, name);
+        report.replace(FailReason::Requeued, requeue);
+        report.save(dirs, name)?;
+        self.message = match count {
+            0 => "saved".to_string(),
+            n => format!("saved, {} batches re-queued for --retry-failed", n),
+        };
+        Ok(())
+    }
+}
+
+fn find_translated(
+    translated: &[TranslatedLine],
+    translator: Translator,
+) -> Option<&TranslatedLine> {
+    translated.iter().find(|t| t.translator == translator)
+}
+
+/// review the batches that failed the diagnostic in a terminal ui,
+/// fix the model output by hand or mark the batches to be translated again.
+pub fn review(config: &Configuration, textures: Textures) -> Result<()> {
+    let mut app = App::new(config, textures)?;
+    if app.batches.is_empty() {
+        println!("{}", t!(Msg::NoFailedBatches));
+        return Ok(());
+    }
+    enable_raw_mode()?;
+    execute!(stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+    let result = run(&mut terminal, &mut app);
+    disable_raw_mode()?;
+    execute!(stdout(), LeaveAlternateScreen)?;
+    result
+}
+
+fn run(terminal: &mut Terminal<CrosstermBackend<std::io::Stdout>>, app: &mut App) -> Result<()> {
+    loop {
+        terminal.draw(|f| draw(f, app))?;
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        if let Some(editing) = app.editing.as_mut() {
+            match key.code {
+                KeyCode::Esc => {
+                    let content = app.editing.take().unwrap();
+                    app.apply_edit(content);
+                }
+                KeyCode::Enter => editing.push('\n'),
+                KeyCode::Backspace => {
+                    editing.pop();
+                }
+                KeyCode::Char(c) => editing.push(c),
+                _ => {}
+            }
+            continue;
+        }
+        match key.code {
+            KeyCode::Char('q') => return Ok(()),
+            KeyCode::Up | KeyCode::Char('k') => app.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => app.move_selection(1),
+            KeyCode::Char('r') => {
+                if let Some(i) = app.state.selected() {
+                    app.batches[i].requeue = !app.batches[i].requeue;
+                }
+            }
+            KeyCode::Char('e') => {
+                app.editing = Some(app.selected_content());
+                app.message = "editing, Esc to finish".to_string();
+            }
+            KeyCode::Char('s') => app.save()?,
+            _ => {}
+        }
+    }
+}
+
+fn draw(f: &mut Frame, app: &mut App) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(3), Constraint::Length(1)])
+        .split(f.size());
+    let cols = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage(20),
+            Constraint::Percentage(40),
+            Constraint::Percentage(40),
+        ])
+        .split(rows[0]);
+
+    let items = app
+        .batches
+        .iter()
+        .map(|b| {
+            let mark = if b.requeue { "R" } else { " " };
+            let status = if b.extracted == b.expected() {
+                "✓"
+            } else {
+                "✗"
+            };
+            ListItem::new(format!(
+                "{}{} {}-{} {}/{}",
+                mark,
+                status,
+                b.range.0,
+                b.range.1,
+                b.extracted,
+                b.expected()
+            ))
+        })
+        .collect::<Vec<_>>();
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title("Failed"))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    f.render_stateful_widget(list, cols[0], &mut app.state);
+
+    let source = app
+        .selected()
+        .map(|b| {
+            b.lines
+                .iter()
+                .enumerate()
+                .map(|(n, &i)| format!("({}) {}", n + 1, app.textures.lines[i].content.trim_end()))
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .unwrap_or_default();
+    f.render_widget(
+        Paragraph::new(source)
+            .wrap(Wrap { trim: false })
+            .block(Block::default().borders(Borders::ALL).title("Source")),
+        cols[1],
+    );
+    let (title, output) = match &app.editing {
+        Some(editing) => ("Output (editing)", editing.clone()),
+        None => ("Output", app.selected_content()),
+    };
+    f.render_widget(
+        Paragraph::new(output)
+            .wrap(Wrap { trim: false })
+            .block(Block::default().borders(Borders::ALL).title(title)),
+        cols[2],
+    );
+    f.render_widget(Paragraph::new(app.message.as_str()), rows[1]);
+}
+
+#[cfg(test)]
+mod test {
+    use super::App;
+    use crate::{
+        paths::ArtifactDirs,
+        textures::{TextureLine, Textures, TranslatedLine, TEXTURES_VERSION},
+        translators::Translator,
+        Configuration,
+    };
+
+    #[test]
+    fn test_review_failed_batch() {
+        let mut textures = Textures {
+            lines: ["a", "b", "c", "d"]
+                .iter()
+                .map(|l| TextureLine::new(0, 1, l.to_string(), false))
+                .collect(),
+            curr_index: 4,
+            version: TEXTURES_VERSION,
+            source: None,
+            name: String::new(),
+            shard: None,
+            dirs: ArtifactDirs::default(),
+        };
+        textures.update(TranslatedLine::new(
+            Translator::ChatGPT,
+            "(1) A\n(2) B".to_string(),
+            0,
+            2,
+        ));
+        textures.update(TranslatedLine::new(
+            Translator::ChatGPT,
+            "(1) D".to_string(),
+            3,
+            3,
+        ));
+        let config: Configuration =
+            toml::from_str(include_str!("../assets/options_text.toml")).unwrap();
+        let mut app = App::new(&config, textures).unwrap();
+        // only the mismatched batch is listed
+        assert_eq!(app.batches.len(), 1);
+        assert_eq!(app.batches[0].range, (0, 2));
+        assert_eq!(
+            (app.batches[0].extracted, app.batches[0].expected()),
+            (2, 3)
+        );
+        assert_eq!(app.selected_content(), "(1) A\n(2) B");
+
+        app.apply_edit("(1) A\n(2) B\n(3) C".to_string());
+        assert_eq!(app.batches[0].extracted, 3);
+        assert_eq!(app.message, "batch 0-2 aligned");
+        assert_eq!(
+            app.textures.lines[0].translated[0].content,
+            "(1) A\n(2) B\n(3) C"
+        );
+
+        app.move_selection(1);
+        assert_eq!(app.state.selected(), Some(0));
+    }
+}
@@ -46,6 +46,38 @@ impl RateLimit {
     }
 }
 
+/// a directory of a test under the temp dir, named by the test and the process so the tests and
+/// the parallel runs never share one, removed when dropped, also when the test panics
+#[cfg(test)]
+pub struct TempDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let name = format!("lottr_test_{}_{}", name, std::process::id());
+        let path = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.0
+    }
+
+    /// the path of the file in the directory
+    pub fn join<P: AsRef<std::path::Path>>(&self, name: P) -> std::path::PathBuf {
+        self.0.join(name)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::time;
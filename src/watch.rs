@@ -0,0 +1,106 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use notify::{EventKind, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+
+use crate::{
+    console::{self, Tone},
+    i18n::Msg,
+    paths::ArtifactDirs,
+    run_file, t, Configuration,
+};
+
+/// watch the directory, translate the new or modified files one by one,
+/// the events are debounced, a file is translated after it has not been changed for 2 seconds.
+pub async fn watch(cfg: &Configuration, dir: &str, exts: &[String]) -> Result<()> {
+    let (tx, mut rx) = mpsc::channel::<PathBuf>(1024);
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res {
+            if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                for path in event.paths {
+                    let _ = tx.blocking_send(path);
+                }
+            }
+        }
+    })?;
+    watcher.watch(Path::new(dir), RecursiveMode::Recursive)?;
+    println!("{}", t!(Msg::Watching, dir));
+
+    // the modified time of the files when they were translated last time
+    let mut translated_at: HashMap<PathBuf, SystemTime> = HashMap::new();
+    let mut pending = BTreeSet::new();
+    let mut outputs = HashSet::new();
+    loop {
+        match tokio::time::timeout(Duration::from_secs(2), rx.recv()).await {
+            Ok(Some(path)) => {
+                if should_translate(&path, exts) {
+                    pending.insert(path);
+                }
+            }
+            Ok(None) => break,
+            Err(_) => {
+                for path in std::mem::take(&mut pending) {
+                    let Ok(modified) = path.metadata().and_then(|m| m.modified()) else {
+                        continue;
+                    };
+                    if translated_at.get(&path) == Some(&modified) || outputs.contains(&path) {
+                        continue;
+                    }
+                    let file = path.to_string_lossy().to_string();
+                    println!("[Watch] {}", t!(Msg::WatchTranslate, file));
+                    if let Err(e) = run_file(cfg, &file, None).await {
+                        let error = t!(Msg::WatchError, file, format!("{:?}", e));
+                        eprintln!("{} {}", console::err(Tone::Error, "[Watch]"), error);
+                    }
+                    // the translated file of output_name may look like an input file
+                    let translator = cfg.translator_selection().primary();
+                    outputs.insert(cfg.output_path(&file, translator));
+                    // the file may be rewritten in place, remember the latest modified time
+                    if let Ok(modified) = path.metadata().and_then(|m| m.modified()) {
+                        translated_at.insert(path, modified);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn should_translate(path: &Path, exts: &[String]) -> bool {
+    if !path.is_file() {
+        return false;
+    }
+    // the files written by lottr itself must not trigger a translation
+    if ArtifactDirs::is_derived(path) {
+        return false;
+    }
+    if exts.is_empty() {
+        return true;
+    }
+    path.extension()
+        .map(|ext| exts.iter().any(|e| ext.eq_ignore_ascii_case(e.as_str())))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_should_translate() {
+        let dir = crate::utils::TempDir::new("watch");
+        let file = dir.join("a.json");
+        std::fs::write(&file, "{}").unwrap();
+        assert!(should_translate(&file, &[]));
+        assert!(should_translate(&file, &["JSON".to_string()]));
+        assert!(!should_translate(&file, &["txt".to_string()]));
+        let derived = dir.join("a.json.skipped.txt");
+        std::fs::write(&derived, "{}").unwrap();
+        assert!(!should_translate(&derived, &[]));
+    }
+}